@@ -0,0 +1,397 @@
+//! Converts CAN logs between formats
+//!
+//! Reads candump, Vector ASC and BLF, PEAK TRC and pcap/pcapng logs and
+//! writes any of them, optionally keeping only some ids, interfaces or a time
+//! window and moving timestamps to a new start. Text logs may be compressed
+//! with gzip, xz or zstd.
+//!
+//! ```text
+//! canconvert trace.blf trace.log
+//! canconvert --id 701,18FF0102 --interface can1 --start +10 --end +12 trace.log.zst window.asc
+//! canconvert --rebase 0 --to pcapng trace.trc capture.out
+//! ```
+
+use clap::{App, Arg, ArgMatches};
+use socketcan::dump::{self, CanDumpRecord, ParseError};
+use socketcan::{asc, blf, compress, pcap, trc};
+use std::fs;
+use std::io::{self, BufRead, BufWriter};
+use std::path::Path;
+use std::process;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Candump,
+    Asc,
+    Blf,
+    Trc,
+    Pcap,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "candump" | "log" => Some(Format::Candump),
+            "asc" => Some(Format::Asc),
+            "blf" => Some(Format::Blf),
+            "trc" => Some(Format::Trc),
+            "pcap" | "pcapng" => Some(Format::Pcap),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the extension, looking past the one of a
+    /// compressed file.
+    fn from_path(path: &str) -> Option<Format> {
+        let path = Path::new(path);
+        let ext = path.extension()?.to_str()?;
+        match ext.to_lowercase().as_str() {
+            "gz" | "xz" | "zst" => Format::from_path(path.file_stem()?.to_str()?),
+            ext => Format::from_name(ext),
+        }
+    }
+}
+
+/// A reader of any format
+trait Source {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError>;
+}
+
+impl<R: BufRead> Source for dump::Reader<R> {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        dump::Reader::next_record(self)
+    }
+}
+
+impl<R: BufRead> Source for asc::Reader<R> {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        asc::Reader::next_record(self)
+    }
+}
+
+impl<R: BufRead> Source for blf::Reader<R> {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        blf::Reader::next_record(self)
+    }
+}
+
+impl<R: BufRead> Source for trc::Reader<R> {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        trc::Reader::next_record(self)
+    }
+}
+
+impl<R: BufRead> Source for pcap::Reader<R> {
+    fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        pcap::Reader::next_record(self)
+    }
+}
+
+fn open_source(path: &str, format: Format) -> io::Result<Box<dyn Source>> {
+    let rdr = compress::open(path)?;
+    Ok(match format {
+        Format::Candump => Box::new(dump::Reader::new(rdr)),
+        Format::Asc => Box::new(asc::Reader::from_reader(rdr)),
+        Format::Blf => Box::new(blf::Reader::from_reader(rdr)),
+        Format::Trc => Box::new(trc::Reader::from_reader(rdr)),
+        Format::Pcap => Box::new(pcap::Reader::from_reader(rdr)),
+    })
+}
+
+/// A writer of any format
+enum Sink {
+    Candump(dump::Writer<BufWriter<fs::File>>),
+    Asc(asc::Writer<BufWriter<fs::File>>),
+    Blf(blf::Writer<BufWriter<fs::File>>),
+    Trc(trc::Writer<BufWriter<fs::File>>),
+    Pcap(pcap::Writer<BufWriter<fs::File>>),
+}
+
+impl Sink {
+    fn create(path: &str, format: Format) -> io::Result<Sink> {
+        Ok(match format {
+            Format::Candump => Sink::Candump(dump::Writer::from_file(path)?),
+            Format::Asc => Sink::Asc(asc::Writer::from_file(path)?),
+            Format::Blf => Sink::Blf(blf::Writer::from_file(path)?),
+            Format::Trc => Sink::Trc(trc::Writer::from_file(path)?),
+            Format::Pcap => Sink::Pcap(pcap::Writer::from_file(path)?),
+        })
+    }
+
+    fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        match *self {
+            Sink::Candump(ref mut w) => w.write_record(record),
+            Sink::Asc(ref mut w) => w.write_record(record),
+            Sink::Blf(ref mut w) => w.write_record(record),
+            Sink::Trc(ref mut w) => w.write_record(record),
+            Sink::Pcap(ref mut w) => w.write_record(record),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Candump(mut w) => w.flush(),
+            Sink::Asc(w) => w.finish().map(|_| ()),
+            Sink::Blf(w) => w.finish().map(|_| ()),
+            Sink::Trc(w) => w.finish().map(|_| ()),
+            Sink::Pcap(w) => w.finish().map(|_| ()),
+        }
+    }
+}
+
+/// A point in time, in microseconds
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Time {
+    Absolute(u64),
+
+    /// relative to the first record of the input
+    Relative(u64),
+}
+
+impl Time {
+    fn resolve(self, first_us: u64) -> u64 {
+        match self {
+            Time::Absolute(t_us) => t_us,
+            Time::Relative(offset) => first_us.saturating_add(offset),
+        }
+    }
+}
+
+/// Parses seconds with up to six decimals, a leading `+` makes them relative
+/// to the first record.
+fn parse_time(s: &str) -> Option<Time> {
+    let (relative, s) = if s.starts_with('+') { (true, &s[1..]) } else { (false, s) };
+
+    let mut parts = s.splitn(2, '.');
+    let secs: u64 = parts.next()?.parse().ok()?;
+    let frac = match parts.next() {
+        Some(digits) if !digits.is_empty() && digits.len() <= 6 && digits.bytes().all(|c| c.is_ascii_digit()) => {
+            digits.parse::<u64>().ok()? * 10u64.pow(6 - digits.len() as u32)
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    let t_us = secs.checked_mul(1_000_000)?.checked_add(frac)?;
+    Some(if relative { Time::Relative(t_us) } else { Time::Absolute(t_us) })
+}
+
+/// Which records to convert and how
+#[derive(Debug, Default)]
+struct Options {
+    ids: Vec<u32>,
+    interfaces: Vec<String>,
+    start: Option<Time>,
+    end: Option<Time>,
+
+    /// new timestamp of the first converted record
+    rebase: Option<u64>,
+}
+
+impl Options {
+    fn from_matches(matches: &ArgMatches) -> Result<Options, String> {
+        let mut opts = Options::default();
+
+        for ids in matches.values_of("id").into_iter().flatten() {
+            for id in ids.split(',') {
+                let id = id.trim_start_matches("0x");
+                opts.ids.push(u32::from_str_radix(id, 16).map_err(|_| format!("invalid id {:?}", id))?);
+            }
+        }
+
+        for interfaces in matches.values_of("interface").into_iter().flatten() {
+            opts.interfaces.extend(interfaces.split(',').map(str::to_owned));
+        }
+
+        let time = |name| -> Result<Option<Time>, String> {
+            match matches.value_of(name) {
+                Some(s) => parse_time(s).map(Some).ok_or_else(|| format!("invalid time {:?}", s)),
+                None => Ok(None),
+            }
+        };
+        opts.start = time("start")?;
+        opts.end = time("end")?;
+        opts.rebase = match time("rebase")? {
+            Some(Time::Absolute(t_us)) => Some(t_us),
+            Some(Time::Relative(_)) => return Err("the new start time must be absolute".to_owned()),
+            None => None,
+        };
+
+        Ok(opts)
+    }
+
+    fn matches(&self, record: &CanDumpRecord, first_us: u64) -> bool {
+        (self.ids.is_empty() || self.ids.contains(&record.frame.id())) &&
+        (self.interfaces.is_empty() || self.interfaces.iter().any(|i| i == record.device)) &&
+        self.start.map_or(true, |start| record.t_us >= start.resolve(first_us)) &&
+        self.end.map_or(true, |end| record.t_us <= end.resolve(first_us))
+    }
+}
+
+/// Copies matching records, returning how many there were.
+fn convert(source: &mut dyn Source, sink: &mut Sink, opts: &Options) -> Result<u64, String> {
+    let mut first_us = None;
+    let mut shift = None;
+    let mut count = 0;
+
+    while let Some(mut record) = source.next_record().map_err(|e| format!("cannot read input: {}", e))? {
+        let first_us = *first_us.get_or_insert(record.t_us);
+        if !opts.matches(&record, first_us) {
+            continue;
+        }
+
+        if let Some(rebase) = opts.rebase {
+            let shift = *shift.get_or_insert(i128::from(rebase) - i128::from(record.t_us));
+            record.t_us = (i128::from(record.t_us) + shift).max(0) as u64;
+        }
+
+        sink.write_record(&record).map_err(|e| format!("cannot write output: {}", e))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let input = matches.value_of("INPUT").unwrap();
+    let output = matches.value_of("OUTPUT").unwrap();
+
+    let format = |arg, path| match matches.value_of(arg) {
+        Some(name) => Format::from_name(name).ok_or_else(|| format!("unknown format {:?}", name)),
+        None => Format::from_path(path).ok_or_else(|| format!("cannot tell the format of {}, use --{}", path, arg)),
+    };
+    let from = format("from", input)?;
+    let to = format("to", output)?;
+    let opts = Options::from_matches(matches)?;
+
+    let mut source = open_source(input, from).map_err(|e| format!("cannot open {}: {}", input, e))?;
+    let mut sink = Sink::create(output, to).map_err(|e| format!("cannot create {}: {}", output, e))?;
+    let count = convert(&mut *source, &mut sink, &opts)?;
+    sink.finish().map_err(|e| format!("cannot write output: {}", e))?;
+
+    if !matches.is_present("quiet") {
+        eprintln!("{} records converted", count);
+    }
+    Ok(())
+}
+
+fn main() {
+    let formats = ["candump", "asc", "blf", "trc", "pcapng"];
+    let matches = App::new("canconvert")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Converts CAN logs between the candump, ASC, BLF, TRC and pcapng formats")
+        .arg(Arg::with_name("INPUT").help("Log to read, text logs may be compressed").required(true))
+        .arg(Arg::with_name("OUTPUT").help("Log to write").required(true))
+        .arg(Arg::with_name("from")
+                 .long("from")
+                 .takes_value(true)
+                 .possible_values(&formats)
+                 .help("Format of the input, instead of guessing it from the extension"))
+        .arg(Arg::with_name("to")
+                 .long("to")
+                 .takes_value(true)
+                 .possible_values(&formats)
+                 .help("Format of the output, instead of guessing it from the extension"))
+        .arg(Arg::with_name("id")
+                 .long("id")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .value_name("ID[,ID...]")
+                 .help("Only convert frames with one of these hex ids"))
+        .arg(Arg::with_name("interface")
+                 .long("interface")
+                 .short("i")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .value_name("NAME[,NAME...]")
+                 .help("Only convert frames of one of these interfaces"))
+        .arg(Arg::with_name("start")
+                 .long("start")
+                 .takes_value(true)
+                 .value_name("SECONDS")
+                 .help("Skip frames before this time, relative to the first frame if prefixed with +"))
+        .arg(Arg::with_name("end")
+                 .long("end")
+                 .takes_value(true)
+                 .value_name("SECONDS")
+                 .help("Skip frames after this time, relative to the first frame if prefixed with +"))
+        .arg(Arg::with_name("rebase")
+                 .long("rebase")
+                 .takes_value(true)
+                 .value_name("SECONDS")
+                 .help("Shift timestamps so the first converted frame is at this time, e.g. 0"))
+        .arg(Arg::with_name("quiet").long("quiet").short("q").help("Do not report the number of frames"))
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("canconvert: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{convert, open_source, parse_time, Format, Options, Sink, Time};
+    use std::{env, fs};
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(Format::from_path("trace.log"), Some(Format::Candump));
+        assert_eq!(Format::from_path("dir/trace.ASC"), Some(Format::Asc));
+        assert_eq!(Format::from_path("trace.blf"), Some(Format::Blf));
+        assert_eq!(Format::from_path("trace.trc.zst"), Some(Format::Trc));
+        assert_eq!(Format::from_path("capture.pcapng"), Some(Format::Pcap));
+        assert_eq!(Format::from_path("trace.gz"), None);
+        assert_eq!(Format::from_path("trace"), None);
+    }
+
+    #[test]
+    fn test_times() {
+        assert_eq!(parse_time("1469439874.299591"), Some(Time::Absolute(1_469_439_874_299_591)));
+        assert_eq!(parse_time("+2.5"), Some(Time::Relative(2_500_000)));
+        assert_eq!(parse_time("0"), Some(Time::Absolute(0)));
+        assert_eq!(parse_time("1.1234567"), None);
+        assert_eq!(parse_time("-1"), None);
+        assert_eq!(Time::Relative(5).resolve(10), 15);
+    }
+
+    #[test]
+    fn test_convert_filter_rebase() {
+        let dir = env::temp_dir().join(format!("canconvert-test-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.log");
+        let blf = dir.join("out.blf");
+        let output = dir.join("out.log");
+
+        fs::write(&input,
+                  "(100.000000) can0 123#01\n\
+                   (100.500000) can1 123#02\n\
+                   (101.000000) can0 456#03\n\
+                   (101.500000) can0 123#04\n\
+                   (103.000000) can0 123#05\n")
+            .unwrap();
+
+        // through BLF and back, so both directions are covered
+        let opts = Options {
+            ids: vec![0x123],
+            interfaces: vec!["can0".to_owned()],
+            start: Some(Time::Relative(500_000)),
+            end: Some(Time::Absolute(102_000_000)),
+            rebase: Some(0),
+        };
+        let mut sink = Sink::create(blf.to_str().unwrap(), Format::Blf).unwrap();
+        let mut source = open_source(input.to_str().unwrap(), Format::Candump).unwrap();
+        assert_eq!(convert(&mut *source, &mut sink, &opts).unwrap(), 1);
+        sink.finish().unwrap();
+
+        let mut sink = Sink::create(output.to_str().unwrap(), Format::Candump).unwrap();
+        let mut source = open_source(blf.to_str().unwrap(), Format::Blf).unwrap();
+        assert_eq!(convert(&mut *source, &mut sink, &Options::default()).unwrap(), 1);
+        sink.finish().unwrap();
+
+        assert_eq!(fs::read_to_string(&output).unwrap(), "(0000000000.000000) can0 123#04 R\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
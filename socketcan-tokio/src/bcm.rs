@@ -12,7 +12,7 @@ use nix::net::if_::if_nametoindex;
 use std::collections::VecDeque;
 use std::fmt;
 use std::io::{Error, ErrorKind};
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::{io, slice, time};
 use tokio::reactor::PollEvented2;
 
@@ -485,6 +485,71 @@ impl CanBCMSocket {
         Ok(())
     }
 
+    /// Create or update a cyclic transmission task, sending `frames` in turn
+    /// every `ival`.
+    ///
+    /// The kernel keeps sending until the task is deleted or the socket is
+    /// closed. The ids of the frames are replaced by `can_id`.
+    pub fn tx_setup(&self, can_id: CanMessageId, frames: &[CanFrame], ival: time::Duration) -> io::Result<()> {
+        if frames.is_empty() || frames.len() > MAX_NFRAMES as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid number of frames"));
+        }
+
+        let mut _frames = [CanFrame::new(CanMessageId::SFF(0u16), &[], false, false).unwrap(); MAX_NFRAMES as usize];
+        _frames[..frames.len()].copy_from_slice(frames);
+
+        let msg = BcmMsgHeadFrameLess {
+            _opcode: TX_SETUP,
+            _flags: SETTIMER | STARTTIMER | TX_CP_CAN_ID,
+            _count: 0,
+            #[cfg(all(target_pointer_width = "32"))]
+            _pad: 0,
+            _ival1: c_timeval_new(time::Duration::new(0, 0)),
+            _ival2: c_timeval_new(ival),
+            _can_id: can_id.with_eff_bit(),
+            _nframes: frames.len() as u32,
+        };
+
+        let tx_msg = &TxMsg {
+            _msg_head: msg,
+            _frames: _frames,
+        };
+
+        let tx_msg_ptr = tx_msg as *const TxMsg;
+        let size = size_of::<BcmMsgHeadFrameLess>() + size_of_val(frames);
+        let write_rv = unsafe { write(self.fd, tx_msg_ptr as *const c_void, size) };
+
+        if write_rv < 0 {
+            return Err(Error::new(ErrorKind::WriteZero, io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Remove a cyclic transmission task.
+    pub fn tx_delete(&self, can_id: CanMessageId) -> io::Result<()> {
+        let msg = BcmMsgHeadFrameLess {
+            _opcode: TX_DELETE,
+            _flags: 0,
+            _count: 0,
+            #[cfg(all(target_pointer_width = "32"))]
+            _pad: 0,
+            _ival1: c_timeval_new(time::Duration::new(0, 0)),
+            _ival2: c_timeval_new(time::Duration::new(0, 0)),
+            _can_id: can_id.with_eff_bit(),
+            _nframes: 0,
+        };
+
+        let msg_ptr = &msg as *const BcmMsgHeadFrameLess;
+        let write_rv = unsafe { write(self.fd, msg_ptr as *const c_void, size_of::<BcmMsgHeadFrameLess>()) };
+
+        if write_rv < 0 {
+            return Err(Error::new(ErrorKind::WriteZero, io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     /// Read a single can frame.
     pub fn read_msg(&self) -> io::Result<BcmMsgHead> {
         let ival1 = c_timeval_new(time::Duration::from_millis(0));
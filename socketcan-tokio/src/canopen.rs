@@ -0,0 +1,211 @@
+use futures::try_ready;
+use futures::{Async, Poll, Stream};
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::reactor::PollEvented2;
+use tokio::timer::Interval;
+
+use socketcan::canopen::{CanOpenMessage, HeartbeatMonitor, NodeEvent, COB_SYNC};
+use socketcan::{CanFrame, CanMessageId, CanSocket, CanSocketOpenError};
+
+use crate::bcm::CanBCMSocket;
+use crate::evented::{poll_frame, EventedCanSocket};
+
+/// Stream of the CANopen messages on a bus, frames outside of the
+/// predefined connection set are skipped.
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate tokio;
+///
+/// use futures::stream::Stream;
+/// use socketcan::canopen::CanOpenMessage;
+/// use socketcan_tokio::canopen::CanOpenStream;
+///
+/// let f = CanOpenStream::open("vcan0").unwrap()
+///        .filter(|msg| match msg {
+///            CanOpenMessage::Emergency { .. } => true,
+///            _ => false,
+///        })
+///        .map_err(|err| eprintln!("IO error {:?}", err))
+///        .for_each(|msg| {
+///            println!("{:?}", msg);
+///            Ok(())
+///        });
+/// tokio::run(f);
+/// ```
+pub struct CanOpenStream {
+    io: PollEvented2<EventedCanSocket>,
+}
+
+impl CanOpenStream {
+    pub fn open(ifname: &str) -> Result<CanOpenStream, CanSocketOpenError> {
+        Ok(CanOpenStream::new(CanSocket::open(ifname)?)?)
+    }
+
+    /// Turns an existing socket into a stream, switching it to non-blocking
+    /// mode.
+    pub fn new(sock: CanSocket) -> io::Result<CanOpenStream> {
+        sock.set_nonblocking(true)?;
+        Ok(CanOpenStream {
+            io: PollEvented2::new(EventedCanSocket(sock)),
+        })
+    }
+}
+
+impl Stream for CanOpenStream {
+    type Item = CanOpenMessage;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let frame = try_ready!(poll_frame(&mut self.io));
+            if let Some(msg) = CanOpenMessage::decode(&frame) {
+                return Ok(Async::Ready(Some(msg)));
+            }
+        }
+    }
+}
+
+/// Stream of the events of a `HeartbeatMonitor`, checking for lost nodes
+/// every `period`.
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate tokio;
+///
+/// use std::time::Duration;
+/// use futures::stream::Stream;
+/// use socketcan::canopen::HeartbeatMonitor;
+/// use socketcan_tokio::canopen::NodeEventStream;
+///
+/// let mut monitor = HeartbeatMonitor::new();
+/// monitor.watch_heartbeat(0x10, Duration::from_millis(1500));
+///
+/// let f = NodeEventStream::open("vcan0", monitor, Duration::from_millis(100)).unwrap()
+///        .map_err(|err| eprintln!("IO error {:?}", err))
+///        .for_each(|event| {
+///            println!("{:?}", event);
+///            Ok(())
+///        });
+/// tokio::run(f);
+/// ```
+pub struct NodeEventStream {
+    io: PollEvented2<EventedCanSocket>,
+    monitor: HeartbeatMonitor,
+    interval: Interval,
+    events: VecDeque<NodeEvent>,
+}
+
+impl NodeEventStream {
+    pub fn open(
+        ifname: &str,
+        monitor: HeartbeatMonitor,
+        period: Duration,
+    ) -> Result<NodeEventStream, CanSocketOpenError> {
+        Ok(NodeEventStream::new(CanSocket::open(ifname)?, monitor, period)?)
+    }
+
+    /// Turns an existing socket into a stream, switching it to non-blocking
+    /// mode.
+    pub fn new(
+        sock: CanSocket,
+        monitor: HeartbeatMonitor,
+        period: Duration,
+    ) -> io::Result<NodeEventStream> {
+        sock.set_nonblocking(true)?;
+        Ok(NodeEventStream {
+            io: PollEvented2::new(EventedCanSocket(sock)),
+            monitor: monitor,
+            interval: Interval::new(Instant::now() + period, period),
+            events: VecDeque::new(),
+        })
+    }
+
+    /// The monitor, to watch more nodes or query their state
+    pub fn monitor(&mut self) -> &mut HeartbeatMonitor {
+        &mut self.monitor
+    }
+}
+
+impl Stream for NodeEventStream {
+    type Item = NodeEvent;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            match poll_frame(&mut self.io)? {
+                Async::Ready(frame) => {
+                    if let Some(event) = self.monitor.push(&frame, Instant::now()) {
+                        self.events.push_back(event);
+                    }
+                    continue;
+                }
+                Async::NotReady => (),
+            }
+
+            match self
+                .interval
+                .poll()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            {
+                Async::Ready(Some(now)) => self.events.extend(self.monitor.check(now)),
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Produces SYNC messages with a cyclic transmission task of the broadcast
+/// manager, so the kernel keeps their timing.
+///
+/// The messages carry a counter from 1 up to `counter_overflow` if it is
+/// 2-240, as configured by object 0x1019, and no data otherwise. They are
+/// sent until the producer is dropped.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use socketcan_tokio::canopen::SyncProducer;
+///
+/// let sync = SyncProducer::open("vcan0", Duration::from_millis(10), 0).unwrap();
+/// sync.set_period(Duration::from_millis(20), 0).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SyncProducer {
+    bcm: CanBCMSocket,
+}
+
+impl SyncProducer {
+    pub fn open(
+        ifname: &str,
+        period: Duration,
+        counter_overflow: u8,
+    ) -> Result<SyncProducer, CanSocketOpenError> {
+        Ok(SyncProducer::new(CanBCMSocket::open_nb(ifname)?, period, counter_overflow)?)
+    }
+
+    /// Start sending SYNC messages on a broadcast manager socket.
+    pub fn new(bcm: CanBCMSocket, period: Duration, counter_overflow: u8) -> io::Result<SyncProducer> {
+        let producer = SyncProducer { bcm: bcm };
+        producer.set_period(period, counter_overflow)?;
+        Ok(producer)
+    }
+
+    /// Change the period and counter, restarting the counter.
+    pub fn set_period(&self, period: Duration, counter_overflow: u8) -> io::Result<()> {
+        let id = CanMessageId::SFF(COB_SYNC as u16);
+        let frames = match counter_overflow {
+            2..=240 => (1..=counter_overflow)
+                .map(|counter| CanFrame::new(id, &[counter], false, false).unwrap())
+                .collect(),
+            _ => vec![CanFrame::new(id, &[], false, false).unwrap()],
+        };
+        self.bcm.tx_setup(id, &frames, period)
+    }
+}
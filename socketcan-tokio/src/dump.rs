@@ -0,0 +1,88 @@
+//! Asynchronous candump log reading
+//!
+//! `CanDumpStream` yields the records of a candump log as owned records, so
+//! they can be passed along a pipeline freely.
+//!
+//! The source is any `BufRead`. Sources implementing `AsyncRead` signal that
+//! no data is available yet with `WouldBlock` and notify the task once there
+//! is, which ends the current poll with `NotReady`. Blocking sources, such as
+//! compressed files opened by `socketcan::compress::open`, never do so:
+//!
+//! ```no_run
+//! use futures::{Future, Stream};
+//! use socketcan_tokio::dump::CanDumpStream;
+//!
+//! let stream = CanDumpStream::open("candump.log.gz").unwrap();
+//! let count = stream.fold(0, |n, _record| Ok::<_, socketcan::dump::ParseError>(n + 1));
+//! println!("{} records", count.wait().unwrap());
+//! ```
+
+use futures::{Async, Poll, Stream};
+use std::io::{self, BufRead};
+use std::path::Path;
+use tokio::io::AsyncRead;
+
+use socketcan::compress;
+use socketcan::dump::{self, OwnedCanDumpRecord, ParseError};
+
+/// Stream of the records of a candump log.
+#[derive(Debug)]
+pub struct CanDumpStream<R> {
+    rdr: R,
+
+    /// holds a partial line while the source is not ready
+    line_buf: Vec<u8>,
+}
+
+impl<R: BufRead> CanDumpStream<R> {
+    pub fn new(rdr: R) -> CanDumpStream<R> {
+        CanDumpStream {
+            rdr: rdr,
+            line_buf: Vec::new(),
+        }
+    }
+}
+
+impl<R: AsyncRead> CanDumpStream<io::BufReader<R>> {
+    /// Buffer an unbuffered source.
+    pub fn from_async_read(rdr: R) -> CanDumpStream<io::BufReader<R>> {
+        CanDumpStream::new(io::BufReader::new(rdr))
+    }
+}
+
+impl CanDumpStream<Box<dyn BufRead + Send>> {
+    /// Open a log file, which may be compressed.
+    ///
+    /// The file is read with blocking reads.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CanDumpStream<Box<dyn BufRead + Send>>> {
+        Ok(CanDumpStream::new(compress::open(path)?))
+    }
+}
+
+impl<R: BufRead> Stream for CanDumpStream<R> {
+    type Item = OwnedCanDumpRecord;
+    type Error = ParseError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            // on errors, everything read so far is kept in the buffer
+            let bytes_read = match self.rdr.read_until(b'\n', &mut self.line_buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e.into()),
+            };
+
+            if bytes_read == 0 && self.line_buf.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+
+            let record = dump::parse_line(&self.line_buf).map(|r| r.map(OwnedCanDumpRecord::from));
+            self.line_buf.clear();
+
+            // blank lines are skipped
+            if let Some(record) = record? {
+                return Ok(Async::Ready(Some(record)));
+            }
+        }
+    }
+}
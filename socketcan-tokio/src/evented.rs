@@ -0,0 +1,53 @@
+use futures::try_ready;
+use futures::{Async, Poll};
+use mio::unix::EventedFd;
+use mio::{Evented, PollOpt, Ready, Token};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use tokio::reactor::PollEvented2;
+
+use socketcan::{CanFrame, CanSocket};
+
+/// Wraps a `CanSocket` to register it with the reactor.
+pub(crate) struct EventedCanSocket(pub(crate) CanSocket);
+
+impl Evented for EventedCanSocket {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+/// Non-blocking read of the next frame.
+pub(crate) fn poll_frame(io: &mut PollEvented2<EventedCanSocket>) -> Poll<CanFrame, io::Error> {
+    let ready = Ready::readable();
+    try_ready!(io.poll_read_ready(ready));
+
+    match io.get_ref().0.read_frame() {
+        Ok(frame) => Ok(Async::Ready(frame)),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            io.clear_read_ready(ready)?;
+            Ok(Async::NotReady)
+        }
+        Err(e) => Err(e),
+    }
+}
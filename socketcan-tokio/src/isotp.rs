@@ -0,0 +1,178 @@
+use futures::try_ready;
+use futures::{Async, Future, Poll, Stream};
+use mio::unix::EventedFd;
+use mio::{Evented, PollOpt, Ready, Token};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use tokio::reactor::PollEvented2;
+
+use socketcan::isotp::{IsoTpOptions, IsoTpSocket};
+use socketcan::{CanMessageId, CanSocketOpenError};
+
+/// Wraps an `IsoTpSocket` to register it with the reactor.
+struct EventedIsoTpSocket(IsoTpSocket);
+
+impl Evented for EventedIsoTpSocket {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+/// Non-blocking ISO-TP connection, see `socketcan::isotp`.
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate tokio;
+///
+/// use futures::Future;
+/// use socketcan::CanMessageId;
+/// use socketcan::isotp::IsoTpOptions;
+/// use socketcan_tokio::isotp::AsyncIsoTpSocket;
+///
+/// let sock = AsyncIsoTpSocket::open("vcan0", CanMessageId::SFF(0x7e8),
+///                                   CanMessageId::SFF(0x7e0), &IsoTpOptions::default())
+///     .unwrap();
+///
+/// let f = sock.write_pdu(vec![0x3e, 0x00])
+///        .and_then(|sock| sock.read_pdu())
+///        .map(|(_sock, pdu)| println!("Response {:02x?}", pdu))
+///        .map_err(|err| eprintln!("IO error {:?}", err));
+/// tokio::run(f);
+/// ```
+pub struct AsyncIsoTpSocket {
+    io: PollEvented2<EventedIsoTpSocket>,
+}
+
+impl AsyncIsoTpSocket {
+    /// Open an ISO-TP connection, see `IsoTpSocket::open`.
+    pub fn open(ifname: &str,
+                rx_id: CanMessageId,
+                tx_id: CanMessageId,
+                options: &IsoTpOptions)
+                -> Result<AsyncIsoTpSocket, CanSocketOpenError> {
+        let sock = IsoTpSocket::open(ifname, rx_id, tx_id, options)?;
+        Ok(AsyncIsoTpSocket::new(sock)?)
+    }
+
+    /// Turns an existing socket into an asynchronous one, switching it to
+    /// non-blocking mode.
+    pub fn new(sock: IsoTpSocket) -> io::Result<AsyncIsoTpSocket> {
+        sock.set_nonblocking(true)?;
+        Ok(AsyncIsoTpSocket {
+            io: PollEvented2::new(EventedIsoTpSocket(sock)),
+        })
+    }
+
+    /// Attempt to read a PDU.
+    pub fn poll_read_pdu(&mut self) -> Poll<Vec<u8>, io::Error> {
+        let ready = Ready::readable();
+        try_ready!(self.io.poll_read_ready(ready));
+
+        match self.io.get_ref().0.read_pdu() {
+            Ok(pdu) => Ok(Async::Ready(pdu)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.io.clear_read_ready(ready)?;
+                Ok(Async::NotReady)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attempt to write a PDU.
+    pub fn poll_write_pdu(&mut self, pdu: &[u8]) -> Poll<(), io::Error> {
+        try_ready!(self.io.poll_write_ready());
+
+        match self.io.get_ref().0.write(pdu) {
+            Ok(()) => Ok(Async::Ready(())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.io.clear_write_ready()?;
+                Ok(Async::NotReady)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a single PDU, returning the socket along with it.
+    pub fn read_pdu(self) -> ReadPdu {
+        ReadPdu { sock: Some(self) }
+    }
+
+    /// Write a single PDU, returning the socket when done.
+    pub fn write_pdu(self, pdu: Vec<u8>) -> WritePdu {
+        WritePdu {
+            sock: Some(self),
+            pdu: pdu,
+        }
+    }
+
+    /// Stream of all received PDUs.
+    pub fn incoming(self) -> IsoTpStream {
+        IsoTpStream { sock: self }
+    }
+}
+
+/// Future of `AsyncIsoTpSocket::read_pdu`.
+pub struct ReadPdu {
+    sock: Option<AsyncIsoTpSocket>,
+}
+
+impl Future for ReadPdu {
+    type Item = (AsyncIsoTpSocket, Vec<u8>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let pdu = try_ready!(self.sock.as_mut().expect("polled after completion").poll_read_pdu());
+        Ok(Async::Ready((self.sock.take().unwrap(), pdu)))
+    }
+}
+
+/// Future of `AsyncIsoTpSocket::write_pdu`.
+pub struct WritePdu {
+    sock: Option<AsyncIsoTpSocket>,
+    pdu: Vec<u8>,
+}
+
+impl Future for WritePdu {
+    type Item = AsyncIsoTpSocket;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.sock.as_mut().expect("polled after completion").poll_write_pdu(&self.pdu));
+        Ok(Async::Ready(self.sock.take().unwrap()))
+    }
+}
+
+/// Stream of the PDUs received on an ISO-TP connection.
+pub struct IsoTpStream {
+    sock: AsyncIsoTpSocket,
+}
+
+impl Stream for IsoTpStream {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let pdu = try_ready!(self.sock.poll_read_pdu());
+        Ok(Async::Ready(Some(pdu)))
+    }
+}
@@ -1 +1,7 @@
-pub mod bcm;
\ No newline at end of file
+pub mod bcm;
+pub mod canopen;
+pub mod dump;
+mod evented;
+pub mod isotp;
+pub mod link;
+pub mod replay;
@@ -0,0 +1,99 @@
+use futures::try_ready;
+use futures::{Async, Poll, Stream};
+use mio::unix::EventedFd;
+use mio::{Evented, PollOpt, Ready, Token};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use tokio::reactor::PollEvented2;
+
+use socketcan::{LinkEvent, LinkMonitor};
+
+/// Wraps a `LinkMonitor` to register its netlink socket with the reactor.
+struct EventedLinkMonitor(LinkMonitor);
+
+impl Evented for EventedLinkMonitor {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+/// Stream of link events of CAN devices.
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate tokio;
+///
+/// use futures::stream::Stream;
+/// use socketcan_tokio::link::LinkEventStream;
+///
+/// let f = LinkEventStream::open().unwrap()
+///        .map_err(|err| eprintln!("IO error {:?}", err))
+///        .for_each(|event| {
+///            println!("Link event {:?}", event);
+///            Ok(())
+///        });
+/// tokio::run(f);
+/// ```
+pub struct LinkEventStream {
+    io: PollEvented2<EventedLinkMonitor>,
+}
+
+impl LinkEventStream {
+    /// Start monitoring CAN devices
+    pub fn open() -> io::Result<LinkEventStream> {
+        LinkEventStream::new(LinkMonitor::open()?)
+    }
+
+    /// Turns an existing monitor into a stream, switching it to non-blocking
+    /// mode.
+    pub fn new(monitor: LinkMonitor) -> io::Result<LinkEventStream> {
+        monitor.set_nonblocking(true)?;
+        Ok(LinkEventStream {
+            io: PollEvented2::new(EventedLinkMonitor(monitor)),
+        })
+    }
+}
+
+impl Stream for LinkEventStream {
+    type Item = LinkEvent;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let ready = Ready::readable();
+
+        // a single datagram can yield several events, which stay queued in
+        // the monitor; readiness is only cleared once it runs dry
+        try_ready!(self.io.poll_read_ready(ready));
+
+        match self.io.get_mut().0.next_event() {
+            Ok(event) => Ok(Async::Ready(Some(event))),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    self.io.clear_read_ready(ready)?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e)
+            }
+        }
+    }
+}
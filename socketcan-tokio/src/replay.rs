@@ -0,0 +1,97 @@
+use futures::try_ready;
+use futures::{Async, Future, Poll};
+use libc::ENOBUFS;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::reactor::PollEvented2;
+use tokio::timer::Delay;
+
+use socketcan::replay::{self, Player, ReplayError, Schedule, ScheduledFrame};
+
+use crate::evented::EventedCanSocket;
+
+/// Replays a log without blocking, see `socketcan::replay`.
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate tokio;
+///
+/// use futures::Future;
+/// use socketcan::dump::Reader;
+/// use socketcan::replay::Player;
+/// use socketcan_tokio::replay::ReplayFuture;
+///
+/// let mut reader = Reader::from_file("candump.log").unwrap();
+/// let player = Player::from_reader(&mut reader).unwrap();
+///
+/// let f = ReplayFuture::new(&player).unwrap()
+///        .map_err(|err| eprintln!("Replay failed {}", err));
+/// tokio::run(f);
+/// ```
+pub struct ReplayFuture {
+    schedule: Schedule,
+    sockets: Vec<PollEvented2<EventedCanSocket>>,
+    start: Option<Instant>,
+
+    /// the next frame and the timer it is sent on
+    pending: Option<(Delay, ScheduledFrame)>,
+}
+
+impl ReplayFuture {
+    /// Open the interfaces the log is played on. Timing starts with the
+    /// first poll.
+    pub fn new(player: &Player) -> Result<ReplayFuture, ReplayError> {
+        let schedule = player.schedule();
+        let mut sockets = Vec::new();
+
+        for socket in schedule.open_sockets()? {
+            socket.set_nonblocking(true)?;
+            sockets.push(PollEvented2::new(EventedCanSocket(socket)));
+        }
+
+        Ok(ReplayFuture {
+            schedule: schedule,
+            sockets: sockets,
+            start: None,
+            pending: None,
+        })
+    }
+}
+
+impl Future for ReplayFuture {
+    type Item = ();
+    type Error = ReplayError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+
+        loop {
+            if self.pending.is_none() {
+                match self.schedule.next() {
+                    Some(frame) => self.pending = Some((Delay::new(start + frame.at), frame)),
+                    None => return Ok(Async::Ready(())),
+                }
+            }
+
+            let (ref mut delay, ref frame) = *self.pending.as_mut().unwrap();
+            try_ready!(delay.poll().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+
+            let io = &mut self.sockets[frame.interface];
+            try_ready!(io.poll_write_ready());
+
+            match replay::write_frame(&io.get_ref().0, &frame.frame) {
+                Ok(()) => self.pending = None,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    io.clear_write_ready()?;
+                    return Ok(Async::NotReady);
+                }
+                Err(ref e) if e.raw_os_error() == Some(ENOBUFS) => {
+                    // the transmit queue of the device is full, which does
+                    // not affect readiness; try again shortly
+                    delay.reset(Instant::now() + Duration::from_millis(1));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -5,9 +5,17 @@ extern crate socketcan_tokio;
 extern crate tokio;
 
 use futures::stream::Stream;
+use futures::{Async, Future};
 use socketcan::{CanFrame, CanSocket};
 use socketcan_tokio::bcm::*;
+use socketcan_tokio::dump::CanDumpStream;
+use socketcan_tokio::isotp::AsyncIsoTpSocket;
+use socketcan_tokio::replay::ReplayFuture;
+use socketcan::dump::Reader;
+use socketcan::isotp::IsoTpOptions;
+use socketcan::replay::Player;
 use tokio::runtime::Runtime;
+use std::io::{self, BufRead, Read};
 use std::time;
 
 fn send_frame() {
@@ -53,4 +61,91 @@ fn vcan0_bcm_filter_delete() {
 fn vcan0_bcm_filter_delete_err() {
     let cbs = CanBCMSocket::open_nb("vcan0").unwrap();
     assert!(cbs.filter_delete(0x124.into()).is_err())
-}
\ No newline at end of file
+}
+
+#[test]
+fn vcan0_replay_keeps_timing() {
+    let log: &[u8] = b"(1.000000) can1 123#01\n\
+                       (1.050000) can1 123#02\n";
+    let mut player = Player::from_reader(&mut Reader::from_reader(log)).unwrap();
+    player.add_mapping("vcan0=can1").unwrap();
+
+    let cs = CanSocket::open("vcan0").unwrap();
+    let start = time::Instant::now();
+    Runtime::new()
+        .unwrap()
+        .block_on(ReplayFuture::new(&player).unwrap())
+        .unwrap();
+
+    assert!(start.elapsed() >= time::Duration::from_millis(50));
+    assert_eq!(cs.read_frame().unwrap().data(), &[0x01]);
+    assert_eq!(cs.read_frame().unwrap().data(), &[0x02]);
+}
+
+/// Hands out one chunk per read, with `WouldBlock` in between.
+struct Trickle(Vec<&'static [u8]>, bool);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.0[0][..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Trickle {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.1 = !self.1;
+        if self.1 && !self.0.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(self.0.first().cloned().unwrap_or(&[]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt == 0 {
+            return;
+        }
+        self.0[0] = &self.0[0][amt..];
+        if self.0[0].is_empty() {
+            self.0.remove(0);
+        }
+    }
+}
+
+#[test]
+fn dump_stream_resumes_partial_lines() {
+    let src = Trickle(vec![b"(1.000000) can1 12", b"3#01\n\n(1.5", b"00000) can0 701#7F"], false);
+    let mut stream = CanDumpStream::new(src);
+
+    let mut records = Vec::new();
+    loop {
+        match stream.poll().unwrap() {
+            Async::Ready(Some(record)) => records.push(record),
+            Async::Ready(None) => break,
+            Async::NotReady => (),
+        }
+    }
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].device, "can1");
+    assert_eq!(records[0].frame.id(), 0x123);
+    assert_eq!(records[1].t_us, 1_500_000);
+    assert_eq!(records[1].frame.data(), &[0x7f]);
+}
+
+#[test]
+fn vcan0_isotp_request_response() {
+    let options = IsoTpOptions::default();
+    let client = AsyncIsoTpSocket::open("vcan0", 0x7e8.into(), 0x7e0.into(), &options).unwrap();
+    let server = AsyncIsoTpSocket::open("vcan0", 0x7e0.into(), 0x7e8.into(), &options).unwrap();
+
+    let response: Vec<u8> = (0..100).collect();
+    let reply = response.clone();
+    let serve = server.read_pdu().and_then(move |(server, _request)| server.write_pdu(reply));
+    let request = client.write_pdu(vec![0x22, 0xf1, 0x90]).and_then(|client| client.read_pdu());
+
+    let (_, (_, pdu)) = Runtime::new().unwrap().block_on(serve.join(request)).unwrap();
+    assert_eq!(pdu, response);
+}
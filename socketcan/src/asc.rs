@@ -0,0 +1,695 @@
+//! Vector ASC format parsing
+//!
+//! Parses the text format written by Vector CANalyzer/CANoe, usually found in
+//! files with an `.asc` extension.
+//!
+//! Example:
+//!
+//! ```text
+//! date Wed Jun 26 02:46:21.735 pm 2024
+//! base hex  timestamps absolute
+//! internal events logged
+//! Begin Triggerblock Wed Jun 26 02:46:21.735 pm 2024
+//!    0.000000 Start of measurement
+//!    0.015991 1  123             Rx   d 2 01 02
+//!    0.016002 1  12345678x       Tx   r 4
+//!    0.017000 1  ErrorFrame
+//!    0.018000 CANFD   1 Rx      701                                  1 0 9 12 01 02 03 04 05 06 07 08 09 0A 0B 0C
+//! End TriggerBlock
+//! ```
+//!
+//! Records are returned as `dump::CanDumpRecord`s, so both formats can be
+//! converted into each other. Channels are named `can0` for channel 1,
+//! `can1` for channel 2 and so forth, unless named otherwise using
+//! `Reader::set_channel_name`. Timestamps are microseconds since the epoch,
+//! taken from the `date` header as if it were UTC; logs without a valid date
+//! start at zero.
+
+use crate::dump::{CanDumpFrame, CanDumpRecord, Direction, ParseError};
+use crate::util::{civil_from_days, days_from_civil};
+use crate::{canfd_dlc_to_len, canfd_len_to_dlc, CanFdFrame, CanFrame, CanMessageId, FdFlags};
+use std::collections::BTreeMap;
+use std::{fs, io, path};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// flags of CANFD lines
+const FD_FLAG_RTR: u32 = 0x0010;
+const FD_FLAG_EDL: u32 = 0x1000;
+const FD_FLAG_BRS: u32 = 0x2000;
+const FD_FLAG_ESI: u32 = 0x4000;
+
+/// Parses the `date` header, e.g. `Wed Jun 26 02:46:21.735 pm 2024`, into
+/// microseconds since the epoch.
+fn parse_date(s: &str) -> Option<u64> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+
+    // weekday, month, day, time, [am|pm], year
+    let (time, meridiem, year) = match fields.len() {
+        5 => (fields[3], None, fields[4]),
+        6 => (fields[3], Some(fields[4].to_lowercase()), fields[5]),
+        _ => return None,
+    };
+
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(fields[1]))? as u32 + 1;
+    let day: u32 = fields[2].parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+
+    let mut hms = time.splitn(3, ':');
+    let mut hour: u64 = hms.next()?.parse().ok()?;
+    let min: u64 = hms.next()?.parse().ok()?;
+    let (sec, frac) = parse_seconds(hms.next()?)?;
+
+    match meridiem.as_ref().map(String::as_str) {
+        Some("am") if hour == 12 => hour = 0,
+        Some("pm") if hour < 12 => hour += 12,
+        Some("am") | Some("pm") | None => (),
+        Some(_) => return None,
+    }
+
+    if day < 1 || day > 31 || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    let secs = days as u64 * 86_400 + hour * 3600 + min * 60 + sec;
+    Some(secs * 1_000_000 + frac)
+}
+
+/// Formats microseconds since the epoch as a `date` header.
+fn format_date(t_us: u64) -> String {
+    let secs = t_us / 1_000_000;
+    let days = (secs / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let (hour, min, sec) = ((secs % 86_400) / 3600, (secs % 3600) / 60, secs % 60);
+
+    let (hour12, meridiem) = match hour {
+        0 => (12, "am"),
+        1..=11 => (hour, "am"),
+        12 => (12, "pm"),
+        _ => (hour - 12, "pm"),
+    };
+
+    format!("{} {} {:02} {:02}:{:02}:{:02}.{:03} {} {}",
+            WEEKDAYS[((days + 4) % 7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            hour12,
+            min,
+            sec,
+            (t_us % 1_000_000) / 1000,
+            meridiem,
+            year)
+}
+
+/// Parses seconds with an optional fraction, returning the fraction in
+/// microseconds.
+fn parse_seconds(s: &str) -> Option<(u64, u64)> {
+    let mut parts = s.splitn(2, '.');
+    let secs = parts.next()?.parse().ok()?;
+    let frac = match parts.next() {
+        Some(digits) if !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit()) => {
+            // normalize to six digits
+            let mut us = 0;
+            for i in 0..6 {
+                us = us * 10 + digits.as_bytes().get(i).map_or(0, |&c| u64::from(c - b'0'));
+            }
+            us
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some((secs, frac))
+}
+
+/// Parses an identifier, extended ones have an `x` appended.
+fn parse_id(s: &str, radix: u32) -> Option<CanMessageId> {
+    if s.ends_with('x') || s.ends_with('X') {
+        let id = u32::from_str_radix(&s[..s.len() - 1], radix).ok()?;
+        if id > crate::EFF_MASK {
+            return None;
+        }
+        Some(CanMessageId::EFF(id))
+    } else {
+        let id = u16::from_str_radix(s, radix).ok()?;
+        if u32::from(id) > crate::SFF_MASK {
+            return None;
+        }
+        Some(CanMessageId::SFF(id))
+    }
+}
+
+fn parse_direction(s: &str) -> Option<Direction> {
+    match s {
+        "Rx" => Some(Direction::Rx),
+        "Tx" => Some(Direction::Tx),
+        _ => None,
+    }
+}
+
+fn parse_data<'a, I>(fields: &mut I, len: usize, radix: u32) -> Result<Vec<u8>, ParseError>
+    where I: Iterator<Item = &'a str>
+{
+    let mut data = Vec::with_capacity(len);
+    for _ in 0..len {
+        let byte = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        data.push(u8::from_str_radix(byte, radix).map_err(|_| ParseError::InvalidCanFrame)?);
+    }
+    Ok(data)
+}
+
+#[derive(Debug)]
+/// An ASC log reader.
+pub struct Reader<R> {
+    rdr: R,
+    line_buf: String,
+
+    /// radix of ids and data
+    radix: u32,
+
+    /// timestamps are relative to the previous event
+    relative: bool,
+    start_us: u64,
+
+    /// offset of the previous event, for relative timestamps
+    last_us: u64,
+
+    /// device names, by channel number
+    channels: BTreeMap<u32, String>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            line_buf: String::new(),
+            radix: 16,
+            relative: false,
+            start_us: 0,
+            last_us: 0,
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+impl Reader<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<fs::File>>> {
+        Ok(Reader::from_reader(fs::File::open(path)?))
+    }
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct AscRecords<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+type Line = (u64, u32, CanDumpFrame, Option<Direction>);
+
+impl<R: io::BufRead> Reader<R> {
+    /// Returns an iterator over all records
+    pub fn records(&mut self) -> AscRecords<'_, R> {
+        AscRecords { src: self }
+    }
+
+    /// Set the device name used for records of `channel`, which starts at 1.
+    ///
+    /// Fails with `InvalidDeviceName` for channel 0.
+    pub fn set_channel_name(&mut self, channel: u32, name: &str) -> Result<(), ParseError> {
+        *self.channel_name(channel)? = name.to_owned();
+        Ok(())
+    }
+
+    fn channel_name(&mut self, channel: u32) -> Result<&mut String, ParseError> {
+        let idx = channel.checked_sub(1).ok_or(ParseError::InvalidDeviceName)?;
+        Ok(self.channels.entry(channel).or_insert_with(|| format!("can{}", idx)))
+    }
+
+    /// Advance state, returning next record.
+    ///
+    /// Headers are evaluated as they appear, other lines that do not hold a
+    /// frame (comments, statistics, events) are skipped.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        loop {
+            self.line_buf.clear();
+            if self.rdr.read_line(&mut self.line_buf)? == 0 {
+                return Ok(None);
+            }
+
+            let line = ::std::mem::replace(&mut self.line_buf, String::new());
+            let parsed = self.parse_line(&line);
+            self.line_buf = line;
+
+            if let Some((t_us, channel, frame, direction)) = parsed? {
+                return Ok(Some(CanDumpRecord {
+                    t_us: t_us,
+                    device: self.channel_name(channel)?,
+                    frame: frame,
+                    direction: direction,
+                }));
+            }
+        }
+    }
+
+    // returns `None` for lines without a frame
+    fn parse_line(&mut self, line: &str) -> Result<Option<Line>, ParseError> {
+        let mut fields = line.split_whitespace();
+
+        let first = match fields.next() {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+
+        match first {
+            "date" => {
+                let date = line.trim_start()[4..].trim();
+                self.start_us = parse_date(date).unwrap_or(0);
+                return Ok(None);
+            }
+            "base" => {
+                self.radix = match fields.next() {
+                    Some("hex") => 16,
+                    Some("dec") => 10,
+                    _ => return Err(ParseError::InvalidHeader),
+                };
+                self.relative = match (fields.next(), fields.next()) {
+                    (Some("timestamps"), Some("absolute")) | (None, None) => false,
+                    (Some("timestamps"), Some("relative")) => true,
+                    _ => return Err(ParseError::InvalidHeader),
+                };
+                return Ok(None);
+            }
+            _ => (),
+        }
+
+        // everything else of interest starts with a timestamp
+        let (secs, frac) = match parse_seconds(first) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let mut offset = secs.saturating_mul(1_000_000).saturating_add(frac);
+        if self.relative {
+            offset = offset.saturating_add(self.last_us);
+        }
+        self.last_us = offset;
+        let t_us = self.start_us.saturating_add(offset);
+
+        let parsed = match fields.next() {
+            Some("CANFD") => Some(self.parse_fd(&mut fields)?),
+            Some(channel) => match channel.parse::<u32>() {
+                Ok(channel) if channel > 0 => {
+                    self.parse_classic(&mut fields)?.map(|(frame, direction)| (channel, frame, direction))
+                }
+                _ => None,
+            },
+            None => None,
+        };
+
+        Ok(parsed.map(|(channel, frame, direction)| (t_us, channel, frame, direction)))
+    }
+
+    // <id> <dir> d <dlc> <data>... | <id> <dir> r [<dlc>] | ErrorFrame
+    fn parse_classic<'a, I>(&self, fields: &mut I) -> Result<Option<(CanDumpFrame, Option<Direction>)>, ParseError>
+        where I: Iterator<Item = &'a str>
+    {
+        let id = match fields.next() {
+            Some("ErrorFrame") => return Ok(Some((CanDumpFrame::Error(CanFrame::new_error(0, &[])?), None))),
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        // other events on a channel, e.g. statistics or chip state
+        let (id, direction) = match (parse_id(id, self.radix), fields.next().and_then(parse_direction)) {
+            (Some(id), Some(direction)) => (id, direction),
+            _ => return Ok(None),
+        };
+
+        let rtr = match fields.next() {
+            Some("d") | Some("D") => false,
+            Some("r") | Some("R") => true,
+            Some(_) => return Err(ParseError::InvalidCanFrame),
+            None => return Err(ParseError::UnexpectedEndOfLine),
+        };
+
+        let dlc = match fields.next() {
+            Some(dlc) => usize::from_str_radix(dlc, 16).map_err(|_| ParseError::InvalidCanFrame)?,
+            None if rtr => 0,
+            None => return Err(ParseError::UnexpectedEndOfLine),
+        };
+
+        // data length codes above 8 still carry 8 bytes
+        let len = dlc.min(8);
+        let data = if rtr { vec![0; len] } else { parse_data(fields, len, self.radix)? };
+        let frame = CanFrame::new(id, &data, rtr, false)?;
+        Ok(Some((CanDumpFrame::Classic(frame), Some(direction))))
+    }
+
+    // <channel> <dir> <id> [<name>] <brs> <esi> <dlc> <len> <data>...
+    //     <duration> <bits> <flags> ...
+    fn parse_fd<'a, I>(&self, fields: &mut I) -> Result<(u32, CanDumpFrame, Option<Direction>), ParseError>
+        where I: Iterator<Item = &'a str>
+    {
+        let channel = fields.next()
+            .and_then(|c| c.parse::<u32>().ok())
+            .filter(|&c| c > 0)
+            .ok_or(ParseError::InvalidCanFrame)?;
+        let direction = fields.next().and_then(parse_direction).ok_or(ParseError::InvalidCanFrame)?;
+
+        let id = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        if id == "ErrorFrame" {
+            return Ok((channel, CanDumpFrame::Error(CanFrame::new_error(0, &[])?), Some(direction)));
+        }
+        let id = parse_id(id, self.radix).ok_or(ParseError::InvalidCanFrame)?;
+
+        // the symbolic name is optional
+        let mut brs = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        if brs != "0" && brs != "1" {
+            brs = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        }
+        let esi = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        let dlc = fields.next()
+            .and_then(|dlc| u8::from_str_radix(dlc, 16).ok())
+            .filter(|&dlc| dlc <= 15)
+            .ok_or(ParseError::InvalidCanFrame)?;
+        let len: usize = fields.next()
+            .and_then(|len| len.parse().ok())
+            .ok_or(ParseError::InvalidCanFrame)?;
+        if len != canfd_dlc_to_len(dlc) && !(dlc > 8 && len == 8) {
+            return Err(ParseError::InvalidCanFrame);
+        }
+
+        let data = parse_data(fields, len, self.radix)?;
+
+        // classic frames on a CAN FD channel lack the EDL flag
+        let flags = fields.nth(2).and_then(|f| u32::from_str_radix(f, 16).ok());
+        let frame = match flags {
+            Some(flags) if flags & FD_FLAG_EDL == 0 => {
+                let rtr = flags & FD_FLAG_RTR != 0;
+                let data = if rtr { vec![0; len.min(8)] } else { data };
+                CanDumpFrame::Classic(CanFrame::new(id, &data, rtr, false)?)
+            }
+            _ => {
+                let mut fd_flags = FdFlags::empty();
+                fd_flags.set(FdFlags::BRS, brs == "1");
+                fd_flags.set(FdFlags::ESI, esi == "1");
+                CanDumpFrame::Fd(CanFdFrame::new(id, &data, fd_flags)?)
+            }
+        };
+
+        Ok((channel, frame, Some(direction)))
+    }
+}
+
+impl<'a, R: io::BufRead> Iterator for AscRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// An ASC log writer.
+///
+/// Writes hex based, absolute timestamps. The header is written along with
+/// the first record, whose time is taken as the start of the measurement.
+/// Call `finish` to terminate the log properly.
+pub struct Writer<W> {
+    wtr: W,
+    start_us: Option<u64>,
+
+    /// device names, by channel number minus one
+    channels: Vec<String>,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            start_us: None,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Assign a device the next free channel number.
+    ///
+    /// Devices get channel numbers in the order they first appear unless
+    /// they are assigned one in advance.
+    pub fn add_channel(&mut self, device: &str) -> u32 {
+        match self.channels.iter().position(|d| d == device) {
+            Some(idx) => idx as u32 + 1,
+            None => {
+                self.channels.push(device.to_owned());
+                self.channels.len() as u32
+            }
+        }
+    }
+
+    /// Write a record as returned by one of the readers.
+    ///
+    /// Records without a direction are written as received. ASC logs cannot
+    /// hold CAN XL frames, trying to write one fails with `InvalidInput`.
+    pub fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        if let CanDumpFrame::Xl(_) = record.frame {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN XL frames cannot be written to ASC logs"));
+        }
+
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                // the date header only holds milliseconds
+                let start_us = record.t_us - record.t_us % 1000;
+                self.write_header(start_us)?;
+                start_us
+            }
+        };
+
+        let channel = self.add_channel(record.device);
+        let offset = record.t_us.saturating_sub(start_us);
+        let dir = match record.direction {
+            Some(Direction::Tx) => "Tx",
+            _ => "Rx",
+        };
+
+        write!(self.wtr, "{:>4}.{:06} ", offset / 1_000_000, offset % 1_000_000)?;
+
+        match record.frame {
+            CanDumpFrame::Error(_) => write!(self.wtr, "{}  ErrorFrame", channel)?,
+            CanDumpFrame::Classic(ref frame) => {
+                let id = format_id(frame.id(), frame.is_extended());
+                if frame.is_rtr() {
+                    write!(self.wtr, "{}  {:<15} {:<4} r {:X}", channel, id, dir, frame.data().len())?;
+                } else {
+                    write!(self.wtr, "{}  {:<15} {:<4} d {:X}", channel, id, dir, frame.data().len())?;
+                    self.write_data(frame.data())?;
+                }
+            }
+            CanDumpFrame::Fd(ref frame) => {
+                let mut flags = FD_FLAG_EDL;
+                if frame.is_brs() {
+                    flags |= FD_FLAG_BRS;
+                }
+                if frame.is_esi() {
+                    flags |= FD_FLAG_ESI;
+                }
+
+                write!(self.wtr,
+                       "CANFD {:>3} {:<4} {:>8} {:>32} {} {} {:x} {:>2}",
+                       channel,
+                       dir,
+                       format_id(frame.id(), frame.is_extended()),
+                       "",
+                       frame.is_brs() as u8,
+                       frame.is_esi() as u8,
+                       canfd_len_to_dlc(frame.data().len()),
+                       frame.data().len())?;
+                self.write_data(frame.data())?;
+
+                // duration, bit count, flags, crc and bit timings are unknown
+                write!(self.wtr, " {:>8} {:>4} {:>8X} {:>8} {:>8} {:>8} {:>8} {:>8}", 0, 0, flags, 0, 0, 0, 0, 0)?;
+            }
+            CanDumpFrame::Xl(_) => unreachable!(),
+        }
+
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Terminate the log, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.start_us.is_some() {
+            self.wtr.write_all(b"End TriggerBlock\n")?;
+        }
+        self.wtr.flush()?;
+        Ok(self.wtr)
+    }
+
+    fn write_header(&mut self, start_us: u64) -> io::Result<()> {
+        let date = format_date(start_us);
+        write!(self.wtr,
+               "date {}\n\
+                base hex  timestamps absolute\n\
+                internal events logged\n\
+                Begin Triggerblock {}\n   \
+                0.000000 Start of measurement\n",
+               date,
+               date)?;
+        self.start_us = Some(start_us);
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        for byte in data {
+            write!(self.wtr, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+fn format_id(id: u32, extended: bool) -> String {
+    if extended {
+        format!("{:X}x", id)
+    } else {
+        format!("{:X}", id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_date, parse_date, Reader, Writer};
+    use crate::dump::{self, CanDumpFrame, Direction};
+
+    const ASC: &str = "date Wed Jun 26 02:46:21.735 pm 2024\n\
+                       base hex  timestamps absolute\n\
+                       internal events logged\n\
+                       // version 9.0.0\n\
+                       Begin Triggerblock Wed Jun 26 02:46:21.735 pm 2024\n   \
+                       0.000000 Start of measurement\n   \
+                       0.015991 1  123             Rx   d 2 01 02  Length = 272000 BitCount = 140 ID = 291\n   \
+                       0.016002 2  12345678x       Tx   r 4\n   \
+                       0.016500 1  Statistic: D 0 R 0 XD 0 XR 0 E 0 O 0 B 0.00%\n   \
+                       0.017000 1  ErrorFrame\n   \
+                       0.018000 CANFD   1 Rx      701  Name 1 0 9 12 01 02 03 04 05 06 07 08 09 0A 0B 0C    \
+                       130000  130     3000 1234 0 0 0 0\n   \
+                       0.019000 CANFD   1 Tx      702                                  0 0 2  2 AA BB    \
+                       130000  130        0 1234 0 0 0 0\n\
+                       End TriggerBlock\n";
+
+    const START_US: u64 = 1_719_413_181_735_000;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("Wed Jun 26 02:46:21.735 pm 2024"), Some(START_US));
+        assert_eq!(parse_date("Wed Jun 26 14:46:21.735 2024"), Some(START_US));
+        assert_eq!(parse_date("Thu Jan 01 12:00:00 am 1970"), Some(0));
+        assert_eq!(parse_date("Mi Jun 26 2024"), None);
+        assert_eq!(format_date(START_US), "Wed Jun 26 02:46:21.735 pm 2024");
+        assert_eq!(format_date(0), "Thu Jan 01 12:00:00.000 am 1970");
+    }
+
+    #[test]
+    fn test_read_frames() {
+        let mut reader = Reader::from_reader(ASC.as_bytes());
+        reader.set_channel_name(2, "vcan1").unwrap();
+        assert!(reader.set_channel_name(0, "vcan0").is_err());
+        reader.set_channel_name(u32::max_value(), "vcan9").unwrap();
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec.t_us, START_US + 15_991);
+            assert_eq!(rec.device, "can0");
+            assert_eq!(rec.direction, Some(Direction::Rx));
+            assert_eq!(rec.frame.id(), 0x123);
+            assert_eq!(rec.frame.data(), &[1, 2]);
+        }
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec.device, "vcan1");
+            assert_eq!(rec.direction, Some(Direction::Tx));
+            assert!(rec.frame.is_rtr());
+            assert!(rec.frame.is_extended());
+            assert_eq!(rec.frame.id(), 0x12345678);
+            assert_eq!(rec.frame.data().len(), 4);
+        }
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec.t_us, START_US + 17_000);
+            assert!(rec.frame.is_error());
+        }
+
+        match reader.next_record().unwrap().unwrap().frame {
+            CanDumpFrame::Fd(frame) => {
+                assert!(frame.is_brs());
+                assert!(!frame.is_esi());
+                assert_eq!(frame.id(), 0x701);
+                assert_eq!(frame.data().len(), 12);
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+
+        // no EDL flag
+        match reader.next_record().unwrap().unwrap().frame {
+            CanDumpFrame::Classic(frame) => assert_eq!(frame.data(), &[0xaa, 0xbb]),
+            other => panic!("unexpected frame {:?}", other),
+        }
+
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_relative_decimal() {
+        let input = "base dec  timestamps relative\n\
+                     1.000000 1  291 Rx d 2 1 255\n\
+                     0.5 1  291 Rx d 0\n";
+
+        let records: Vec<_> = Reader::from_reader(input.as_bytes()).records().map(|r| r.unwrap()).collect();
+        assert_eq!(records[0].0, 1_000_000);
+        assert_eq!(records[0].1.id(), 0x123);
+        assert_eq!(records[0].1.data(), &[1, 255]);
+        assert_eq!(records[1].0, 1_500_000);
+
+        assert!(Reader::from_reader("base oct\n".as_bytes()).next_record().is_err());
+        assert!(Reader::from_reader("1.0 1  123 Rx d 2 01\n".as_bytes()).next_record().is_err());
+    }
+
+    #[test]
+    fn test_candump_to_asc_and_back() {
+        let candump = "(1719413181.750991) can0 123#0102 R\n\
+                       (1719413181.751002) can1 12345678#R4 T\n\
+                       (1719413181.752000) can0 20000000#\n\
+                       (1719413181.753000) can0 701##1010203040506070809000000 R\n";
+
+        let mut asc = Writer::from_writer(Vec::new());
+        let mut reader = dump::Reader::from_reader(candump.as_bytes());
+        while let Some(record) = reader.next_record().unwrap() {
+            asc.write_record(&record).unwrap();
+        }
+        let asc = asc.finish().unwrap();
+
+        let mut dump = dump::Writer::from_writer(Vec::new());
+        let mut reader = Reader::from_reader(&asc[..]);
+        while let Some(record) = reader.next_record().unwrap() {
+            dump.write_record(&record).unwrap();
+        }
+
+        assert_eq!(::std::str::from_utf8(&dump.into_inner()).unwrap(), candump);
+    }
+}
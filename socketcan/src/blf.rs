@@ -0,0 +1,851 @@
+//! Vector BLF format parsing
+//!
+//! Reads and writes the Binary Logging Format of Vector CANalyzer/CANoe,
+//! usually found in files with a `.blf` extension.
+//!
+//! A BLF file consists of a file header followed by objects, most of them
+//! log containers holding zlib compressed streams of further objects. The
+//! following objects are turned into records, all others are skipped:
+//!
+//! * `CAN_MESSAGE` and `CAN_MESSAGE2`, classic CAN frames
+//! * `CAN_FD_MESSAGE` and `CAN_FD_MESSAGE_64`, CAN FD frames or classic
+//!   frames sent on a CAN FD channel
+//! * `CAN_ERROR_EXT`, error frames
+//!
+//! Like with `asc`, records are `dump::CanDumpRecord`s, channels are named
+//! `can0` for channel 1 and so forth and timestamps are microseconds since
+//! the epoch, based on the start time found in the file header.
+
+use crate::dump::{CanDumpFrame, CanDumpRecord, Direction, ParseError};
+use crate::util::{civil_from_days, days_from_civil};
+use crate::{canfd_len_to_dlc, CanFdFrame, CanFrame, CanMessageId, EFF_MASK, ERR_MASK, SFF_MASK};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, SeekFrom, Write};
+use std::{fs, io, path};
+
+const FILE_SIGNATURE: &[u8] = b"LOGG";
+const FILE_HEADER_SIZE: usize = 144;
+const OBJ_SIGNATURE: &[u8] = b"LOBJ";
+const OBJ_HEADER_BASE_SIZE: usize = 16;
+const OBJ_HEADER_V1_SIZE: usize = 32;
+const LOG_CONTAINER_HEADER_SIZE: usize = 16;
+
+// object types
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+// timestamp resolution, object header flags
+const TIME_TEN_MICS: u32 = 0x1;
+const TIME_ONE_NANS: u32 = 0x2;
+
+// log container compression methods
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+// CAN_MESSAGE and CAN_FD_MESSAGE flags
+const DIR_TX: u8 = 0x01;
+const REMOTE_FLAG: u8 = 0x80;
+const EXT_ID: u32 = 0x8000_0000;
+
+// CAN_FD_MESSAGE fd_flags
+const FD_EDL: u8 = 0x01;
+const FD_BRS: u8 = 0x02;
+const FD_ESI: u8 = 0x04;
+
+// CAN_FD_MESSAGE_64 flags
+const FD64_REMOTE: u32 = 0x0010;
+const FD64_EDL: u32 = 0x1000;
+const FD64_BRS: u32 = 0x2000;
+const FD64_ESI: u32 = 0x4000;
+
+/// uncompressed size at which a log container is written
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+
+/// largest object read, well above the containers written by any logger
+const MAX_OBJ_SIZE: usize = 16 * 1024 * 1024;
+
+fn le_u16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([b[off], b[off + 1]])
+}
+
+fn le_u32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]])
+}
+
+fn le_u64(b: &[u8], off: usize) -> u64 {
+    u64::from(le_u32(b, off)) | u64::from(le_u32(b, off + 4)) << 32
+}
+
+/// Converts a `SYSTEMTIME` (year, month, day of week, day, hour, minute,
+/// second, milliseconds) to microseconds since the epoch.
+fn parse_systemtime(b: &[u8]) -> u64 {
+    let f: Vec<u16> = (0..8).map(|i| le_u16(b, i * 2)).collect();
+    if f[0] < 1970 || f[1] < 1 || f[1] > 12 || f[3] < 1 {
+        return 0;
+    }
+
+    let days = days_from_civil(i64::from(f[0]), u32::from(f[1]), u32::from(f[3])) as u64;
+    let secs = days * 86_400 + u64::from(f[4]) * 3600 + u64::from(f[5]) * 60 + u64::from(f[6]);
+    secs * 1_000_000 + u64::from(f[7]) * 1000
+}
+
+fn write_systemtime(buf: &mut Vec<u8>, t_us: u64) {
+    let secs = t_us / 1_000_000;
+    let days = (secs / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let fields = [year as u16,
+                  month as u16,
+                  ((days + 4) % 7) as u16,
+                  day as u16,
+                  ((secs % 86_400) / 3600) as u16,
+                  ((secs % 3600) / 60) as u16,
+                  (secs % 60) as u16,
+                  ((t_us % 1_000_000) / 1000) as u16];
+    for field in &fields {
+        buf.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+fn message_id(raw: u32) -> CanMessageId {
+    if raw & EXT_ID != 0 {
+        CanMessageId::EFF(raw & EFF_MASK)
+    } else {
+        CanMessageId::SFF((raw & SFF_MASK) as u16)
+    }
+}
+
+fn direction(tx: bool) -> Option<Direction> {
+    Some(if tx { Direction::Tx } else { Direction::Rx })
+}
+
+type Object = (u64, u16, CanDumpFrame, Option<Direction>);
+
+#[derive(Debug)]
+/// A BLF log reader.
+pub struct Reader<R> {
+    rdr: R,
+
+    /// measurement start, `None` until the file header has been read
+    start_us: Option<u64>,
+
+    /// uncompressed objects, starting at `pos`
+    buf: Vec<u8>,
+    pos: usize,
+
+    /// device names, by channel number minus one
+    channels: Vec<String>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            start_us: None,
+            buf: Vec::new(),
+            pos: 0,
+            channels: Vec::new(),
+        }
+    }
+}
+
+impl Reader<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<fs::File>>> {
+        Ok(Reader::from_reader(fs::File::open(path)?))
+    }
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct BlfRecords<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+impl<R: io::BufRead> Reader<R> {
+    /// Returns an iterator over all records
+    pub fn records(&mut self) -> BlfRecords<'_, R> {
+        BlfRecords { src: self }
+    }
+
+    /// Set the device name used for records of `channel`, which starts at 1.
+    pub fn set_channel_name(&mut self, channel: u16, name: &str) {
+        let idx = self.channel_index(channel);
+        self.channels[idx] = name.to_owned();
+    }
+
+    fn channel_index(&mut self, channel: u16) -> usize {
+        let idx = usize::from(channel.max(1)) - 1;
+        while self.channels.len() <= idx {
+            let name = format!("can{}", self.channels.len());
+            self.channels.push(name);
+        }
+        idx
+    }
+
+    /// The start of the measurement, in microseconds since the epoch.
+    pub fn start_time(&mut self) -> Result<u64, ParseError> {
+        match self.start_us {
+            Some(start_us) => Ok(start_us),
+            None => self.read_file_header(),
+        }
+    }
+
+    fn read_file_header(&mut self) -> Result<u64, ParseError> {
+        let mut header = [0; 72];
+        self.rdr.read_exact(&mut header)?;
+        if &header[..4] != FILE_SIGNATURE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        // the header is usually larger than the fields we know of
+        let header_size = le_u32(&header, 4) as usize;
+        if header_size < header.len() {
+            return Err(ParseError::InvalidHeader);
+        }
+        io::copy(&mut (&mut self.rdr).take((header_size - header.len()) as u64), &mut io::sink())?;
+
+        let start_us = parse_systemtime(&header[40..56]);
+        self.start_us = Some(start_us);
+        Ok(start_us)
+    }
+
+    /// Advance state, returning next record.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        let start_us = self.start_time()?;
+
+        loop {
+            let avail = &self.buf[self.pos..];
+            if avail.len() >= OBJ_HEADER_BASE_SIZE && le_u32(avail, 8) as usize > MAX_OBJ_SIZE {
+                return Err(ParseError::InvalidHeader);
+            }
+            if avail.len() < OBJ_HEADER_BASE_SIZE || avail.len() < le_u32(avail, 8) as usize {
+                // objects may span containers
+                if !self.read_container()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            if &avail[..4] != OBJ_SIGNATURE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let obj_size = le_u32(avail, 8) as usize;
+            let obj_type = le_u32(avail, 12);
+            if obj_size < OBJ_HEADER_BASE_SIZE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let parsed = parse_object(&avail[..obj_size], obj_type)?;
+
+            self.pos += obj_size;
+            if obj_type != CAN_FD_MESSAGE_64 {
+                // padding
+                self.pos = (self.pos + obj_size % 4).min(self.buf.len());
+            }
+
+            if let Some((offset_us, channel, frame, direction)) = parsed {
+                let idx = self.channel_index(channel);
+                return Ok(Some(CanDumpRecord {
+                    t_us: start_us.saturating_add(offset_us),
+                    device: &self.channels[idx],
+                    frame: frame,
+                    direction: direction,
+                }));
+            }
+        }
+    }
+
+    // appends the content of the next log container, returns false on EOF
+    fn read_container(&mut self) -> Result<bool, ParseError> {
+        loop {
+            let mut header = [0; OBJ_HEADER_BASE_SIZE];
+            match self.rdr.read_exact(&mut header) {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+
+            if &header[..4] != OBJ_SIGNATURE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let obj_size = le_u32(&header, 8) as usize;
+            let obj_type = le_u32(&header, 12);
+            if obj_size < OBJ_HEADER_BASE_SIZE || obj_size > MAX_OBJ_SIZE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let mut body = vec![0; obj_size - OBJ_HEADER_BASE_SIZE];
+            self.rdr.read_exact(&mut body)?;
+
+            // padding, possibly missing at the end of the file
+            let mut padding = [0; 4];
+            match self.rdr.read_exact(&mut padding[..obj_size % 4]) {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => (),
+                Err(e) => return Err(e.into()),
+            }
+
+            // drop consumed data
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+
+            if obj_type != LOG_CONTAINER {
+                // objects outside of containers are read as they are
+                self.buf.extend_from_slice(&header);
+                self.buf.extend_from_slice(&body);
+                self.buf.extend_from_slice(&padding[..obj_size % 4]);
+                return Ok(true);
+            }
+
+            if body.len() < LOG_CONTAINER_HEADER_SIZE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let method = le_u16(&body, 0);
+            let data = &body[LOG_CONTAINER_HEADER_SIZE..];
+            match method {
+                NO_COMPRESSION => self.buf.extend_from_slice(data),
+                ZLIB_DEFLATE => {
+                    let len = ZlibDecoder::new(data).take(MAX_OBJ_SIZE as u64 + 1).read_to_end(&mut self.buf)?;
+                    if len > MAX_OBJ_SIZE {
+                        return Err(ParseError::InvalidHeader);
+                    }
+                }
+                // unknown compression, skip the container
+                _ => continue,
+            }
+            return Ok(true);
+        }
+    }
+}
+
+/// Parses a single object, returning `None` for those that do not hold a
+/// frame.
+fn parse_object(obj: &[u8], obj_type: u32) -> Result<Option<Object>, ParseError> {
+    match obj_type {
+        CAN_MESSAGE | CAN_MESSAGE2 | CAN_ERROR_EXT | CAN_FD_MESSAGE | CAN_FD_MESSAGE_64 => (),
+        _ => return Ok(None),
+    }
+
+    let header_size = le_u16(obj, 4) as usize;
+    let header_version = le_u16(obj, 6);
+    if header_size < OBJ_HEADER_V1_SIZE || header_size > obj.len() {
+        return Err(ParseError::InvalidHeader);
+    }
+
+    // both header versions start with flags, the timestamp differs in place
+    let flags = le_u32(obj, 16);
+    let timestamp = match header_version {
+        1 => le_u64(obj, 24),
+        2 if header_size >= 40 => le_u64(obj, 24),
+        _ => return Err(ParseError::InvalidHeader),
+    };
+    let offset_us = match flags {
+        TIME_TEN_MICS => timestamp.saturating_mul(10),
+        TIME_ONE_NANS => timestamp / 1000,
+        _ => timestamp,
+    };
+
+    let b = &obj[header_size..];
+    let (channel, frame, direction) = match obj_type {
+        CAN_MESSAGE | CAN_MESSAGE2 => {
+            if b.len() < 16 {
+                return Err(ParseError::InvalidCanFrame);
+            }
+
+            let flags = b[2];
+            let len = usize::from(b[3]).min(8);
+            let rtr = flags & REMOTE_FLAG != 0;
+            let data = if rtr { [0; 8] } else { [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]] };
+            let frame = CanFrame::new(message_id(le_u32(b, 4)), &data[..len], rtr, false)?;
+            (le_u16(b, 0), CanDumpFrame::Classic(frame), direction(flags & DIR_TX != 0))
+        }
+        CAN_ERROR_EXT => {
+            if b.len() < 32 {
+                return Err(ParseError::InvalidCanFrame);
+            }
+
+            // the error class is kept in the id
+            let len = usize::from(b[10]).min(8);
+            let frame = CanFrame::new_error(le_u32(b, 16) & ERR_MASK, &b[24..24 + len])?;
+            (le_u16(b, 0), CanDumpFrame::Error(frame), None)
+        }
+        CAN_FD_MESSAGE => {
+            if b.len() < 84 {
+                return Err(ParseError::InvalidCanFrame);
+            }
+
+            let flags = b[2];
+            let id = message_id(le_u32(b, 4));
+            let fd_flags = b[13];
+            let len = usize::from(b[14]).min(64);
+            let data = &b[20..20 + len];
+
+            let frame = if fd_flags & FD_EDL != 0 {
+                fd_frame(id, data, fd_flags & FD_BRS != 0, fd_flags & FD_ESI != 0)?
+            } else {
+                classic_frame(id, data, flags & REMOTE_FLAG != 0, b[3])?
+            };
+            (le_u16(b, 0), frame, direction(flags & DIR_TX != 0))
+        }
+        _ => {
+            // CAN_FD_MESSAGE_64
+            if b.len() < 40 {
+                return Err(ParseError::InvalidCanFrame);
+            }
+
+            let id = message_id(le_u32(b, 4));
+            let flags = le_u32(b, 12);
+            let len = usize::from(b[2]);
+            if b.len() < 40 + len {
+                return Err(ParseError::InvalidCanFrame);
+            }
+            let data = &b[40..40 + len];
+
+            let frame = if flags & FD64_EDL != 0 {
+                fd_frame(id, data, flags & FD64_BRS != 0, flags & FD64_ESI != 0)?
+            } else {
+                classic_frame(id, data, flags & FD64_REMOTE != 0, b[1])?
+            };
+            // 0 is Rx, 1 is Tx, 2 a transmit request
+            (u16::from(b[0]), frame, direction(b[34] != 0))
+        }
+    };
+
+    Ok(Some((offset_us, channel, frame, direction)))
+}
+
+fn fd_frame(id: CanMessageId, data: &[u8], brs: bool, esi: bool) -> Result<CanDumpFrame, ParseError> {
+    let mut flags = crate::FdFlags::empty();
+    flags.set(crate::FdFlags::BRS, brs);
+    flags.set(crate::FdFlags::ESI, esi);
+    Ok(CanDumpFrame::Fd(CanFdFrame::new(id, data, flags)?))
+}
+
+fn classic_frame(id: CanMessageId, data: &[u8], rtr: bool, dlc: u8) -> Result<CanDumpFrame, ParseError> {
+    let frame = if rtr {
+        CanFrame::new(id, &[0; 8][..usize::from(dlc).min(8)], true, false)?
+    } else {
+        CanFrame::new(id, &data[..data.len().min(8)], false, false)?
+    };
+    Ok(CanDumpFrame::Classic(frame))
+}
+
+impl<'a, R: io::BufRead> Iterator for BlfRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A BLF log writer.
+///
+/// Objects are collected in zlib compressed log containers. Since the file
+/// header holds the object count and the time of the last record, it is
+/// written last; call `finish` to complete the file.
+pub struct Writer<W> {
+    wtr: W,
+
+    /// uncompressed objects of the current container
+    container: Vec<u8>,
+    start_us: Option<u64>,
+    stop_us: u64,
+    object_count: u32,
+    uncompressed_size: u64,
+
+    /// device names, by channel number minus one
+    channels: Vec<String>,
+}
+
+impl<W: io::Write + io::Seek> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            container: Vec::new(),
+            start_us: None,
+            stop_us: 0,
+            object_count: 0,
+            uncompressed_size: 0,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Assign a device the next free channel number, see
+    /// `asc::Writer::add_channel`.
+    pub fn add_channel(&mut self, device: &str) -> u16 {
+        match self.channels.iter().position(|d| d == device) {
+            Some(idx) => idx as u16 + 1,
+            None => {
+                self.channels.push(device.to_owned());
+                self.channels.len() as u16
+            }
+        }
+    }
+
+    /// Write a record as returned by one of the readers.
+    ///
+    /// Classic frames are written as `CAN_MESSAGE`, CAN FD frames as
+    /// `CAN_FD_MESSAGE` and error frames as `CAN_ERROR_EXT` objects. BLF
+    /// cannot hold CAN XL frames, trying to write one fails with
+    /// `InvalidInput`.
+    pub fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                // leave room for the header
+                self.wtr.write_all(&[0; FILE_HEADER_SIZE])?;
+
+                // the start time only holds milliseconds
+                let start_us = record.t_us - record.t_us % 1000;
+                self.start_us = Some(start_us);
+                start_us
+            }
+        };
+
+        let channel = self.add_channel(record.device);
+        let tx = record.direction == Some(Direction::Tx);
+        let mut body = Vec::with_capacity(84);
+        body.extend_from_slice(&channel.to_le_bytes());
+
+        let obj_type = match record.frame {
+            CanDumpFrame::Classic(ref frame) => {
+                let mut flags = if tx { DIR_TX } else { 0 };
+                if frame.is_rtr() {
+                    flags |= REMOTE_FLAG;
+                }
+                body.extend_from_slice(&[flags, frame.data().len() as u8]);
+                body.extend_from_slice(&raw_id(frame.id(), frame.is_extended()).to_le_bytes());
+
+                let mut data = [0; 8];
+                if !frame.is_rtr() {
+                    data[..frame.data().len()].copy_from_slice(frame.data());
+                }
+                body.extend_from_slice(&data);
+                CAN_MESSAGE
+            }
+            CanDumpFrame::Fd(ref frame) => {
+                let mut fd_flags = FD_EDL;
+                if frame.is_brs() {
+                    fd_flags |= FD_BRS;
+                }
+                if frame.is_esi() {
+                    fd_flags |= FD_ESI;
+                }
+
+                let len = frame.data().len();
+                body.extend_from_slice(&[if tx { DIR_TX } else { 0 }, canfd_len_to_dlc(len)]);
+                body.extend_from_slice(&raw_id(frame.id(), frame.is_extended()).to_le_bytes());
+
+                // frame length and arbitration bit count are unknown
+                body.extend_from_slice(&[0; 5]);
+                body.extend_from_slice(&[fd_flags, len as u8, 0, 0, 0, 0, 0]);
+
+                let mut data = [0; 64];
+                data[..len].copy_from_slice(frame.data());
+                body.extend_from_slice(&data);
+                CAN_FD_MESSAGE
+            }
+            CanDumpFrame::Error(ref frame) => {
+                // length, flags, ecc, position, dlc, frame length, id, extended
+                // flags and data, the error class is kept in the id
+                body.extend_from_slice(&[0; 30]);
+                body[10] = frame.data().len() as u8;
+                body[16..20].copy_from_slice(&frame.err().to_le_bytes());
+                body[24..24 + frame.data().len()].copy_from_slice(frame.data());
+                CAN_ERROR_EXT
+            }
+            CanDumpFrame::Xl(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN XL frames cannot be written to BLF logs"));
+            }
+        };
+
+        let offset_ns = record.t_us.saturating_sub(start_us).saturating_mul(1000);
+        let obj_size = OBJ_HEADER_V1_SIZE + body.len();
+
+        let c = &mut self.container;
+        c.extend_from_slice(OBJ_SIGNATURE);
+        c.extend_from_slice(&(OBJ_HEADER_V1_SIZE as u16).to_le_bytes());
+        c.extend_from_slice(&1u16.to_le_bytes());
+        c.extend_from_slice(&(obj_size as u32).to_le_bytes());
+        c.extend_from_slice(&obj_type.to_le_bytes());
+        c.extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+
+        // client index and object version
+        c.extend_from_slice(&[0; 4]);
+        c.extend_from_slice(&offset_ns.to_le_bytes());
+        c.extend_from_slice(&body);
+        c.extend_from_slice(&[0; 4][..obj_size % 4]);
+
+        self.object_count += 1;
+        self.stop_us = self.stop_us.max(record.t_us);
+
+        if self.container.len() >= MAX_CONTAINER_SIZE {
+            self.write_container()?;
+        }
+        Ok(())
+    }
+
+    /// Write the remaining objects and the file header, returning the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                self.wtr.write_all(&[0; FILE_HEADER_SIZE])?;
+                0
+            }
+        };
+        self.write_container()?;
+
+        let file_size = self.wtr.seek(SeekFrom::Current(0))?;
+
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_SIGNATURE);
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+
+        // application id and version, BLF version
+        header.extend_from_slice(&[5, 0, 0, 0, 2, 6, 8, 1]);
+        header.extend_from_slice(&file_size.to_le_bytes());
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u64 + self.uncompressed_size).to_le_bytes());
+        header.extend_from_slice(&self.object_count.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        write_systemtime(&mut header, start_us);
+        write_systemtime(&mut header, self.stop_us.max(start_us));
+        header.resize(FILE_HEADER_SIZE, 0);
+
+        self.wtr.seek(SeekFrom::Start(0))?;
+        self.wtr.write_all(&header)?;
+        self.wtr.seek(SeekFrom::Start(file_size))?;
+        self.wtr.flush()?;
+        Ok(self.wtr)
+    }
+
+    fn write_container(&mut self) -> io::Result<()> {
+        if self.container.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.container)?;
+        let compressed = encoder.finish()?;
+
+        let obj_size = OBJ_HEADER_BASE_SIZE + LOG_CONTAINER_HEADER_SIZE + compressed.len();
+        let mut header = Vec::with_capacity(OBJ_HEADER_BASE_SIZE + LOG_CONTAINER_HEADER_SIZE);
+        header.extend_from_slice(OBJ_SIGNATURE);
+        header.extend_from_slice(&(OBJ_HEADER_BASE_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(obj_size as u32).to_le_bytes());
+        header.extend_from_slice(&LOG_CONTAINER.to_le_bytes());
+        header.extend_from_slice(&ZLIB_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&(self.container.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+
+        self.wtr.write_all(&header)?;
+        self.wtr.write_all(&compressed)?;
+        self.wtr.write_all(&[0; 4][..obj_size % 4])?;
+
+        self.uncompressed_size += self.container.len() as u64;
+        self.container.clear();
+        Ok(())
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+fn raw_id(id: u32, extended: bool) -> u32 {
+    if extended {
+        id | EXT_ID
+    } else {
+        id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{le_u32, Reader, Writer, CAN_FD_MESSAGE_64};
+    use crate::dump::{self, CanDumpFrame, Direction};
+    use std::io::{self, Cursor, Read};
+
+    const CANDUMP: &str = "(1719413181.750991) can0 123#0102 R\n\
+                           (1719413181.751002) can1 12345678#R4 T\n\
+                           (1719413181.752000) can0 20000004#0004000000000000\n\
+                           (1719413181.753000) can0 701##30102030405060708090A0B0C R\n";
+
+    fn to_blf(candump: &str) -> Vec<u8> {
+        let mut writer = Writer::from_writer(Cursor::new(Vec::new()));
+        let mut reader = dump::Reader::from_reader(candump.as_bytes());
+        while let Some(record) = reader.next_record().unwrap() {
+            writer.write_record(&record).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_write_header() {
+        let blf = to_blf(CANDUMP);
+        assert_eq!(&blf[..4], b"LOGG");
+        assert_eq!(le_u32(&blf, 4), 144);
+        assert_eq!(le_u32(&blf, 16) as usize, blf.len());
+        assert_eq!(le_u32(&blf, 32), 4);
+        assert_eq!(&blf[144..148], b"LOBJ");
+    }
+
+    #[test]
+    fn test_candump_to_blf_and_back() {
+        let blf = to_blf(CANDUMP);
+
+        let mut reader = Reader::from_reader(&blf[..]);
+        assert_eq!(reader.start_time().unwrap(), 1_719_413_181_750_000);
+
+        let mut dump = dump::Writer::from_writer(Vec::new());
+        while let Some(record) = reader.next_record().unwrap() {
+            dump.write_record(&record).unwrap();
+        }
+
+        assert_eq!(::std::str::from_utf8(&dump.into_inner()).unwrap(), CANDUMP);
+    }
+
+    /// An uncompressed CAN_FD_MESSAGE_64 object, Tx on channel 2
+    fn fd_message_64() -> Vec<u8> {
+        let mut obj = Vec::new();
+        obj.extend_from_slice(b"LOBJ");
+        obj.extend_from_slice(&[32, 0, 1, 0]);
+        obj.extend_from_slice(&(32u32 + 40 + 12).to_le_bytes());
+        obj.extend_from_slice(&CAN_FD_MESSAGE_64.to_le_bytes());
+        obj.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        obj.extend_from_slice(&150u64.to_le_bytes());
+        obj.extend_from_slice(&[2, 9, 12, 0]);
+        obj.extend_from_slice(&0x8000_0123u32.to_le_bytes());
+        obj.extend_from_slice(&0u32.to_le_bytes());
+        obj.extend_from_slice(&0x3000u32.to_le_bytes());
+        obj.extend_from_slice(&[0; 16]);
+        obj.extend_from_slice(&[0, 0, 1, 0, 0, 0, 0, 0]);
+        obj.extend_from_slice(&[0xaa; 12]);
+        obj
+    }
+
+    #[test]
+    fn test_read_fd_message_64() {
+        let mut blf = to_blf("");
+        blf.extend_from_slice(&fd_message_64());
+
+        let mut reader = Reader::from_reader(&blf[..]);
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(record.t_us, 1500);
+        assert_eq!(record.device, "can1");
+        assert_eq!(record.direction, Some(Direction::Tx));
+        match record.frame {
+            CanDumpFrame::Fd(frame) => {
+                assert!(frame.is_extended());
+                assert!(frame.is_brs());
+                assert_eq!(frame.id(), 0x123);
+                assert_eq!(frame.data(), &[0xaa; 12]);
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+
+    /// Returns a single byte per read.
+    struct ShortReads<'a>(&'a [u8]);
+
+    impl<'a> Read for ShortReads<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(1);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_short_reads() {
+        // an unknown object of 18 bytes, followed by 2 bytes of padding
+        let mut blf = to_blf("");
+        blf.extend_from_slice(b"LOBJ");
+        blf.extend_from_slice(&[16, 0, 1, 0]);
+        blf.extend_from_slice(&18u32.to_le_bytes());
+        blf.extend_from_slice(&999u32.to_le_bytes());
+        blf.extend_from_slice(&[0xee, 0xee, 0, 0]);
+        blf.extend_from_slice(&fd_message_64());
+
+        let mut reader = Reader::from_reader(io::BufReader::with_capacity(1, ShortReads(&blf)));
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(record.t_us, 1500);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_oversized_object() {
+        let mut blf = to_blf("");
+        blf.extend_from_slice(b"LOBJ");
+        blf.extend_from_slice(&[16, 0, 1, 0]);
+        blf.extend_from_slice(&0xffff_fff0u32.to_le_bytes());
+        blf.extend_from_slice(&10u32.to_le_bytes());
+        assert!(Reader::from_reader(&blf[..]).next_record().is_err());
+
+        // within an uncompressed container
+        let mut blf = to_blf("");
+        blf.extend_from_slice(b"LOBJ");
+        blf.extend_from_slice(&[16, 0, 1, 0]);
+        blf.extend_from_slice(&48u32.to_le_bytes());
+        blf.extend_from_slice(&10u32.to_le_bytes());
+        blf.extend_from_slice(&[0; 16]);
+        blf.extend_from_slice(b"LOBJ");
+        blf.extend_from_slice(&[16, 0, 1, 0]);
+        blf.extend_from_slice(&0xffff_fff0u32.to_le_bytes());
+        blf.extend_from_slice(&999u32.to_le_bytes());
+        assert!(Reader::from_reader(&blf[..]).next_record().is_err());
+    }
+
+    #[test]
+    fn test_oversized_container() {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        // unknown objects that would be skipped, inflating beyond the limit
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut obj = b"LOBJ".to_vec();
+        obj.extend_from_slice(&[16, 0, 1, 0, 16, 0, 0, 0]);
+        obj.extend_from_slice(&999u32.to_le_bytes());
+        for _ in 0..super::MAX_OBJ_SIZE / obj.len() + 1 {
+            zlib.write_all(&obj).unwrap();
+        }
+        let data = zlib.finish().unwrap();
+
+        let mut blf = to_blf("");
+        blf.extend_from_slice(b"LOBJ");
+        blf.extend_from_slice(&[16, 0, 1, 0]);
+        blf.extend_from_slice(&(32 + data.len() as u32).to_le_bytes());
+        blf.extend_from_slice(&10u32.to_le_bytes());
+        blf.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        blf.extend_from_slice(&data);
+        assert!(Reader::from_reader(&blf[..]).next_record().is_err());
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut blf = to_blf(CANDUMP);
+        blf[0] = b'X';
+        assert!(Reader::from_reader(&blf[..]).next_record().is_err());
+    }
+}
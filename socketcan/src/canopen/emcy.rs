@@ -0,0 +1,95 @@
+//! Emergency messages
+
+/// An emergency message of a node
+///
+/// An error code of 0 signals that the node recovered from all errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Emergency {
+    pub error_code: u16,
+
+    /// the error register, object 0x1001
+    pub error_register: u8,
+
+    /// manufacturer specific error information
+    pub data: [u8; 5],
+}
+
+impl Emergency {
+    /// Decode the data of an emergency message, `None` if it is not 8
+    /// bytes long.
+    pub fn decode(data: &[u8]) -> Option<Emergency> {
+        if data.len() != 8 {
+            return None;
+        }
+        let mut info = [0u8; 5];
+        info.copy_from_slice(&data[3..]);
+
+        Some(Emergency {
+            error_code: u16::from_le_bytes([data[0], data[1]]),
+            error_register: data[2],
+            data: info,
+        })
+    }
+
+    /// Check if this message ends the error state.
+    pub fn is_reset(&self) -> bool {
+        self.error_code == 0
+    }
+
+    /// The class of the error code, as listed by CiA 301
+    pub fn description(&self) -> &'static str {
+        match self.error_code >> 8 {
+            0x00 => "error reset or no error",
+            0x10 => "generic error",
+            0x20..=0x23 => "current",
+            0x30..=0x33 => "voltage",
+            0x40..=0x42 => "temperature",
+            0x50 => "device hardware",
+            0x60..=0x63 => "device software",
+            0x70 => "additional modules",
+            0x80 => "monitoring",
+            0x81 => {
+                match self.error_code {
+                    0x8110 => "CAN overrun (objects lost)",
+                    0x8120 => "CAN in error passive mode",
+                    0x8130 => "life guard error or heartbeat error",
+                    0x8140 => "recovered from bus off",
+                    0x8150 => "CAN-ID collision",
+                    _ => "communication",
+                }
+            }
+            0x82 => {
+                match self.error_code {
+                    0x8210 => "PDO not processed due to length error",
+                    0x8220 => "PDO length exceeded",
+                    0x8230 => "DAM MPDO not processed, destination object not available",
+                    0x8240 => "unexpected SYNC data length",
+                    0x8250 => "RPDO timeout",
+                    _ => "protocol error",
+                }
+            }
+            0x90 => "external error",
+            0xf0 => "additional functions",
+            0xff => "device specific",
+            _ => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let emcy = Emergency::decode(&[0x30, 0x81, 0x11, 1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(emcy.error_code, 0x8130);
+        assert_eq!(emcy.error_register, 0x11);
+        assert_eq!(emcy.data, [1, 2, 3, 4, 5]);
+        assert_eq!(emcy.description(), "life guard error or heartbeat error");
+        assert!(!emcy.is_reset());
+
+        assert_eq!(Emergency::decode(&[0; 8]).unwrap().description(), "error reset or no error");
+        assert_eq!(Emergency::decode(&[0; 7]), None);
+    }
+}
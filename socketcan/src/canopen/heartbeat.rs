@@ -0,0 +1,208 @@
+//! Monitoring of heartbeats and node guarding responses
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::{CanOpenMessage, NmtState};
+use crate::CanFrame;
+
+/// A change of a node, as seen by a `HeartbeatMonitor`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NodeEvent {
+    /// the node booted, or was reset
+    BootUp(u8),
+
+    /// the node reports a state different from before, or reports again
+    /// after it was lost
+    StateChanged { node: u8, state: NmtState },
+
+    /// no heartbeat or guarding response arrived in time
+    Lost(u8),
+
+    /// the toggle bit of a guarding response did not alternate
+    ToggleError(u8),
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    state: Option<NmtState>,
+    last_seen: Option<Instant>,
+
+    /// time after which the node is lost, `None` if it is not watched
+    timeout: Option<Duration>,
+    lost: bool,
+
+    /// node guarding is used, expecting this toggle bit next
+    guarding: bool,
+    toggle: Option<bool>,
+}
+
+/// Tracks the state of nodes from their heartbeats or node guarding
+/// responses.
+///
+/// Feed it all frames with `push` and call `check` periodically to detect
+/// lost nodes. Nodes show up as soon as they send a heartbeat, only those
+/// watched with `watch_heartbeat` or `watch_guarding` can be lost. A lost
+/// node is reported once, until it responds again.
+#[derive(Debug, Default)]
+pub struct HeartbeatMonitor {
+    nodes: BTreeMap<u8, Node>,
+}
+
+impl HeartbeatMonitor {
+    pub fn new() -> HeartbeatMonitor {
+        HeartbeatMonitor::default()
+    }
+
+    /// Expect heartbeats of `node` at least every `timeout`, the heartbeat
+    /// consumer time.
+    pub fn watch_heartbeat(&mut self, node: u8, timeout: Duration) {
+        let entry = self.nodes.entry(node).or_default();
+        entry.timeout = Some(timeout);
+        entry.guarding = false;
+    }
+
+    /// Expect guarding responses of `node` within its life time, the guard
+    /// time multiplied by the life time factor, saturating on overflow.
+    ///
+    /// The guarding requests have to be sent every guard time, using
+    /// `NmtMaster::node_guard`.
+    pub fn watch_guarding(&mut self, node: u8, guard_time: Duration, life_time_factor: u8) {
+        let entry = self.nodes.entry(node).or_default();
+        let life_time = guard_time.checked_mul(u32::from(life_time_factor.max(1)))
+            .unwrap_or_else(|| Duration::new(u64::max_value(), 999_999_999));
+        entry.timeout = Some(life_time);
+        entry.guarding = true;
+        entry.toggle = None;
+    }
+
+    /// Stop watching `node` and forget its state.
+    pub fn unwatch(&mut self, node: u8) {
+        self.nodes.remove(&node);
+    }
+
+    /// The last state `node` reported, `None` if unknown or lost
+    pub fn state(&self, node: u8) -> Option<NmtState> {
+        self.nodes.get(&node).and_then(|n| n.state)
+    }
+
+    /// The nodes seen and their states
+    pub fn nodes(&self) -> Vec<(u8, Option<NmtState>)> {
+        self.nodes.iter().map(|(&node, n)| (node, n.state)).collect()
+    }
+
+    /// Process a frame received at `now`, returning the event it causes,
+    /// if any.
+    pub fn push(&mut self, frame: &CanFrame, now: Instant) -> Option<NodeEvent> {
+        let (node, state, toggle) = match CanOpenMessage::decode(frame)? {
+            CanOpenMessage::Heartbeat { node, state, toggle } => (node, state, toggle),
+            _ => return None,
+        };
+
+        let entry = self.nodes.entry(node).or_default();
+        entry.last_seen = Some(now);
+        entry.lost = false;
+
+        if state == NmtState::BootUp {
+            // the toggle bit starts over after a reset
+            entry.state = Some(state);
+            entry.toggle = None;
+            return Some(NodeEvent::BootUp(node));
+        }
+
+        let toggle_error = if entry.guarding {
+            let error = entry.toggle.is_some() && entry.toggle != Some(toggle);
+            entry.toggle = Some(!toggle);
+            error
+        } else {
+            false
+        };
+
+        let changed = entry.state != Some(state);
+        entry.state = Some(state);
+
+        if toggle_error {
+            Some(NodeEvent::ToggleError(node))
+        } else if changed {
+            Some(NodeEvent::StateChanged { node: node, state: state })
+        } else {
+            None
+        }
+    }
+
+    /// Detect the nodes lost at `now`.
+    ///
+    /// Watched nodes that never responded are lost after their timeout,
+    /// counted from the first check.
+    pub fn check(&mut self, now: Instant) -> Vec<NodeEvent> {
+        let mut events = Vec::new();
+        for (&node, entry) in &mut self.nodes {
+            let timeout = match entry.timeout {
+                Some(timeout) if !entry.lost => timeout,
+                _ => continue,
+            };
+
+            let last_seen = *entry.last_seen.get_or_insert(now);
+            if now.duration_since(last_seen) > timeout {
+                entry.lost = true;
+                entry.state = None;
+                entry.toggle = None;
+                events.push(NodeEvent::Lost(node));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CanMessageId;
+
+    fn heartbeat(node: u8, state: u8) -> CanFrame {
+        CanFrame::new(CanMessageId::SFF(0x700 + u16::from(node)), &[state], false, false).unwrap()
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        let mut monitor = HeartbeatMonitor::new();
+        monitor.watch_heartbeat(0x10, Duration::from_millis(150));
+        assert_eq!(monitor.check(ms(0)), vec![]);
+
+        assert_eq!(monitor.push(&heartbeat(0x10, 0x00), ms(10)), Some(NodeEvent::BootUp(0x10)));
+        assert_eq!(monitor.push(&heartbeat(0x10, 0x7f), ms(100)),
+                   Some(NodeEvent::StateChanged {
+                       node: 0x10,
+                       state: NmtState::PreOperational,
+                   }));
+        assert_eq!(monitor.push(&heartbeat(0x10, 0x7f), ms(200)), None);
+        assert_eq!(monitor.check(ms(300)), vec![]);
+
+        assert_eq!(monitor.check(ms(400)), vec![NodeEvent::Lost(0x10)]);
+        assert_eq!(monitor.check(ms(500)), vec![]);
+        assert_eq!(monitor.state(0x10), None);
+
+        // unwatched nodes are tracked, but never lost
+        monitor.push(&heartbeat(0x20, 0x05), ms(500));
+        assert_eq!(monitor.state(0x20), Some(NmtState::Operational));
+        assert_eq!(monitor.check(ms(10_000)), vec![]);
+    }
+
+    #[test]
+    fn test_guarding() {
+        let start = Instant::now();
+        let mut monitor = HeartbeatMonitor::new();
+        monitor.watch_guarding(0x05, Duration::from_millis(100), 3);
+
+        assert!(monitor.push(&heartbeat(0x05, 0x05), start).is_some());
+        assert_eq!(monitor.push(&heartbeat(0x05, 0x85), start), None);
+        assert_eq!(monitor.push(&heartbeat(0x05, 0x85), start), Some(NodeEvent::ToggleError(0x05)));
+        assert_eq!(monitor.check(start + Duration::from_millis(301)), vec![NodeEvent::Lost(0x05)]);
+
+        monitor.watch_guarding(0x06, Duration::from_secs(u64::max_value()), 2);
+        assert_eq!(monitor.check(start + Duration::from_millis(301)), vec![]);
+    }
+}
@@ -0,0 +1,281 @@
+//! CANopen (CiA 301) on top of `CanSocket`
+//!
+//! CANopen identifies its messages by the function code in the upper bits
+//! of an 11 bit CAN id and the node id (1-127) in the lower 7 bits.
+//! `CanOpenMessage::decode` turns frames into messages, so the protocol can
+//! be followed on any stream of frames.
+//!
+//! `NmtMaster` commands the state of nodes, `HeartbeatMonitor` turns their
+//! heartbeats and node guarding responses into `NodeEvent`s, and `SdoClient`
+//! reads and writes the object dictionary of a node:
+//!
+//! ```no_run
+//! use socketcan::canopen::{NmtMaster, SdoClient};
+//!
+//! let mut nmt = NmtMaster::open("vcan0").unwrap();
+//! nmt.enter_pre_operational(0).unwrap();
+//!
+//! let mut sdo = SdoClient::open("vcan0", 0x10).unwrap();
+//! let name = sdo.upload(0x1008, 0).unwrap();
+//! println!("device name {}", String::from_utf8_lossy(&name));
+//! sdo.download(0x1017, 0, &1000u16.to_le_bytes()).unwrap();
+//!
+//! nmt.start(0x10).unwrap();
+//! ```
+//!
+//! The objects of a device are described by its EDS or DCF file, parsed into
+//! an `ObjectDictionary`. It gives names and types to the objects mapped into
+//! `Pdo`s, which can be configured on a node over SDO:
+//!
+//! ```no_run
+//! use socketcan::canopen::{ObjectDictionary, Pdo, PdoEntry, PdoKind, SdoClient, Value};
+//!
+//! let od = ObjectDictionary::from_file("io-module.eds").unwrap();
+//! let mut sdo = SdoClient::open("vcan0", 0x10).unwrap();
+//!
+//! let mut tpdo = Pdo::new(0x190);
+//! tpdo.entries.push(PdoEntry::new(0x6000, 1, 8));
+//! tpdo.configure(&mut sdo, PdoKind::Transmit, 1).unwrap();
+//!
+//! let values = tpdo.decode(&od, &[0x01]).unwrap();
+//! assert_eq!(values[0].1, Value::Unsigned(1));
+//! ```
+
+use std::io;
+use std::time::Duration;
+
+mod emcy;
+mod heartbeat;
+mod nmt;
+mod od;
+mod pdo;
+mod sdo;
+mod value;
+
+pub use self::emcy::Emergency;
+pub use self::heartbeat::{HeartbeatMonitor, NodeEvent};
+pub use self::nmt::{NmtCommand, NmtMaster, NmtState};
+pub use self::od::{AccessType, EdsError, Object, ObjectDictionary, ObjectType, Variable};
+pub use self::pdo::{Pdo, PdoEntry, PdoKind, TRANSMISSION_EVENT_MANUFACTURER, TRANSMISSION_EVENT_PROFILE};
+pub use self::sdo::{SdoAbortCode, SdoClient, SdoError};
+pub use self::value::{DataType, Value};
+
+use crate::{CanFrame, CanSocket, SFF_MASK};
+
+// function codes of the predefined connection set
+pub const COB_NMT: u32 = 0x000;
+pub const COB_SYNC: u32 = 0x080;
+pub const COB_EMCY: u32 = 0x080;
+pub const COB_TIME: u32 = 0x100;
+pub const COB_TPDO1: u32 = 0x180;
+pub const COB_RPDO1: u32 = 0x200;
+pub const COB_TSDO: u32 = 0x580;
+pub const COB_RSDO: u32 = 0x600;
+pub const COB_HEARTBEAT: u32 = 0x700;
+
+/// Sends and receives frames for the CANopen protocols
+///
+/// Implemented by `CanSocket`, other implementations may run the protocols
+/// over gateways or in tests.
+pub trait CanOpenTransport {
+    fn send(&mut self, frame: &CanFrame) -> io::Result<()>;
+
+    /// Blocking receive of a frame, failing if none arrives within
+    /// `timeout`.
+    fn recv(&mut self, timeout: Duration) -> io::Result<CanFrame>;
+}
+
+impl CanOpenTransport for CanSocket {
+    fn send(&mut self, frame: &CanFrame) -> io::Result<()> {
+        self.write_frame_insist(frame)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<CanFrame> {
+        self.set_read_timeout(timeout)?;
+        self.read_frame()
+    }
+}
+
+/// A message of the predefined connection set
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanOpenMessage {
+    /// network management, to node 0 for all nodes
+    Nmt { command: NmtCommand, node: u8 },
+
+    /// synchronization, with an optional counter
+    Sync(Option<u8>),
+
+    Emergency { node: u8, emergency: Emergency },
+
+    /// time stamp, in ms after midnight and days since 1984-01-01
+    Time { ms: u32, days: u16 },
+
+    /// process data sent by a node, `pdo` 1-4
+    Tpdo { pdo: u8, node: u8, data: Vec<u8> },
+
+    /// process data received by a node, `pdo` 1-4
+    Rpdo { pdo: u8, node: u8, data: Vec<u8> },
+
+    /// SDO response of a server
+    SdoResponse { node: u8, data: [u8; 8] },
+
+    /// SDO request to a server
+    SdoRequest { node: u8, data: [u8; 8] },
+
+    /// heartbeat or node guarding response, the toggle bit is only used by
+    /// node guarding
+    Heartbeat { node: u8, state: NmtState, toggle: bool },
+
+    /// node guarding request (a remote frame)
+    NodeGuard { node: u8 },
+}
+
+/// The 8 bytes of an SDO frame, `None` for other lengths.
+fn sdo_data(data: &[u8]) -> Option<[u8; 8]> {
+    if data.len() != 8 {
+        return None;
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(data);
+    Some(buf)
+}
+
+/// Parse a sum of integers and `$NODEID`.
+fn parse_integer(s: &str, node: u8) -> Option<i128> {
+    s.split('+')
+        .map(|term| {
+            let term = term.trim();
+            let (negative, term) = if term.starts_with('-') { (true, &term[1..]) } else { (false, term) };
+            let n = if term.eq_ignore_ascii_case("$NODEID") {
+                i128::from(node)
+            } else if term.starts_with("0x") || term.starts_with("0X") {
+                i128::from_str_radix(&term[2..], 16).ok()?
+            } else if term.len() > 1 && term.starts_with('0') {
+                i128::from_str_radix(&term[1..], 8).ok()?
+            } else {
+                term.parse().ok()?
+            };
+            if negative { n.checked_neg() } else { Some(n) }
+        })
+        .try_fold(0i128, |sum, n| sum.checked_add(n?))
+}
+
+impl CanOpenMessage {
+    /// Decode a frame, `None` if it is not part of the predefined
+    /// connection set.
+    pub fn decode(frame: &CanFrame) -> Option<CanOpenMessage> {
+        if frame.is_extended() || frame.is_error() || frame.id() > SFF_MASK {
+            return None;
+        }
+
+        let id = frame.id();
+        let (function, node) = (id & 0x780, (id & 0x7f) as u8);
+        let data = frame.data();
+
+        if frame.is_rtr() {
+            return match function {
+                COB_HEARTBEAT if node != 0 => Some(CanOpenMessage::NodeGuard { node: node }),
+                _ => None,
+            };
+        }
+
+        let message = match function {
+            COB_NMT if node == 0 => {
+                if data.len() != 2 {
+                    return None;
+                }
+                CanOpenMessage::Nmt {
+                    command: NmtCommand::from_u8(data[0])?,
+                    node: data[1],
+                }
+            }
+            COB_SYNC if node == 0 => CanOpenMessage::Sync(data.first().cloned()),
+            COB_EMCY => {
+                CanOpenMessage::Emergency {
+                    node: node,
+                    emergency: Emergency::decode(data)?,
+                }
+            }
+            COB_TIME if node == 0 && data.len() == 6 => {
+                CanOpenMessage::Time {
+                    ms: u32::from_le_bytes([data[0], data[1], data[2], data[3] & 0x0f]),
+                    days: u16::from_le_bytes([data[4], data[5]]),
+                }
+            }
+            0x180 | 0x280 | 0x380 | 0x480 if node != 0 => {
+                CanOpenMessage::Tpdo {
+                    pdo: ((function - COB_TPDO1) >> 8) as u8 + 1,
+                    node: node,
+                    data: data.to_vec(),
+                }
+            }
+            0x200 | 0x300 | 0x400 | 0x500 if node != 0 => {
+                CanOpenMessage::Rpdo {
+                    pdo: ((function - COB_RPDO1) >> 8) as u8 + 1,
+                    node: node,
+                    data: data.to_vec(),
+                }
+            }
+            COB_TSDO if node != 0 => {
+                CanOpenMessage::SdoResponse {
+                    node: node,
+                    data: sdo_data(data)?,
+                }
+            }
+            COB_RSDO if node != 0 => {
+                CanOpenMessage::SdoRequest {
+                    node: node,
+                    data: sdo_data(data)?,
+                }
+            }
+            COB_HEARTBEAT if node != 0 && data.len() == 1 => {
+                CanOpenMessage::Heartbeat {
+                    node: node,
+                    state: NmtState::from(data[0] & 0x7f),
+                    toggle: data[0] & 0x80 != 0,
+                }
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CanMessageId;
+
+    fn frame(id: u16, data: &[u8]) -> CanFrame {
+        CanFrame::new(CanMessageId::SFF(id), data, false, false).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(CanOpenMessage::decode(&frame(0x000, &[0x01, 0x00])),
+                   Some(CanOpenMessage::Nmt {
+                       command: NmtCommand::Start,
+                       node: 0,
+                   }));
+        assert_eq!(CanOpenMessage::decode(&frame(0x080, &[])), Some(CanOpenMessage::Sync(None)));
+        assert_eq!(CanOpenMessage::decode(&frame(0x28a, &[1, 2])),
+                   Some(CanOpenMessage::Tpdo {
+                       pdo: 2,
+                       node: 0x0a,
+                       data: vec![1, 2],
+                   }));
+        assert_eq!(CanOpenMessage::decode(&frame(0x70a, &[0x85])),
+                   Some(CanOpenMessage::Heartbeat {
+                       node: 0x0a,
+                       state: NmtState::Operational,
+                       toggle: true,
+                   }));
+
+        let guard = CanFrame::new(CanMessageId::SFF(0x70a), &[], true, false).unwrap();
+        assert_eq!(CanOpenMessage::decode(&guard), Some(CanOpenMessage::NodeGuard { node: 0x0a }));
+
+        // SDOs are always 8 bytes, LSS is not part of the connection set
+        assert_eq!(CanOpenMessage::decode(&frame(0x58a, &[0x60, 0, 0])), None);
+        assert_eq!(CanOpenMessage::decode(&frame(0x7e5, &[0])), None);
+    }
+}
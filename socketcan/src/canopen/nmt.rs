@@ -0,0 +1,142 @@
+//! Network management
+
+use std::io;
+
+use super::{CanOpenTransport, COB_HEARTBEAT, COB_NMT};
+use crate::{CanFrame, CanMessageId, CanSocket, CanSocketOpenError};
+
+/// Commands of the NMT master
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NmtCommand {
+    Start,
+    Stop,
+    EnterPreOperational,
+    ResetNode,
+    ResetCommunication,
+}
+
+impl NmtCommand {
+    pub fn from_u8(command: u8) -> Option<NmtCommand> {
+        match command {
+            0x01 => Some(NmtCommand::Start),
+            0x02 => Some(NmtCommand::Stop),
+            0x80 => Some(NmtCommand::EnterPreOperational),
+            0x81 => Some(NmtCommand::ResetNode),
+            0x82 => Some(NmtCommand::ResetCommunication),
+            _ => None,
+        }
+    }
+}
+
+impl From<NmtCommand> for u8 {
+    fn from(command: NmtCommand) -> u8 {
+        match command {
+            NmtCommand::Start => 0x01,
+            NmtCommand::Stop => 0x02,
+            NmtCommand::EnterPreOperational => 0x80,
+            NmtCommand::ResetNode => 0x81,
+            NmtCommand::ResetCommunication => 0x82,
+        }
+    }
+}
+
+/// States of a node, as reported by heartbeats
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NmtState {
+    /// sent once after initialization
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+    Unknown(u8),
+}
+
+impl From<u8> for NmtState {
+    fn from(state: u8) -> NmtState {
+        match state {
+            0x00 => NmtState::BootUp,
+            0x04 => NmtState::Stopped,
+            0x05 => NmtState::Operational,
+            0x7f => NmtState::PreOperational,
+            state => NmtState::Unknown(state),
+        }
+    }
+}
+
+impl From<NmtState> for u8 {
+    fn from(state: NmtState) -> u8 {
+        match state {
+            NmtState::BootUp => 0x00,
+            NmtState::Stopped => 0x04,
+            NmtState::Operational => 0x05,
+            NmtState::PreOperational => 0x7f,
+            NmtState::Unknown(state) => state,
+        }
+    }
+}
+
+/// Sends NMT commands and node guarding requests.
+///
+/// Commands to node 0 address all nodes.
+#[derive(Debug)]
+pub struct NmtMaster<T = CanSocket> {
+    transport: T,
+}
+
+impl NmtMaster<CanSocket> {
+    pub fn open(ifname: &str) -> Result<NmtMaster<CanSocket>, CanSocketOpenError> {
+        Ok(NmtMaster::new(CanSocket::open(ifname)?))
+    }
+}
+
+impl<T: CanOpenTransport> NmtMaster<T> {
+    pub fn new(transport: T) -> NmtMaster<T> {
+        NmtMaster { transport: transport }
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Send `command` to `node`, 0 for all nodes.
+    pub fn send(&mut self, command: NmtCommand, node: u8) -> io::Result<()> {
+        let frame = CanFrame::new(CanMessageId::SFF(COB_NMT as u16), &[command.into(), node], false, false)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.transport.send(&frame)
+    }
+
+    pub fn start(&mut self, node: u8) -> io::Result<()> {
+        self.send(NmtCommand::Start, node)
+    }
+
+    pub fn stop(&mut self, node: u8) -> io::Result<()> {
+        self.send(NmtCommand::Stop, node)
+    }
+
+    pub fn enter_pre_operational(&mut self, node: u8) -> io::Result<()> {
+        self.send(NmtCommand::EnterPreOperational, node)
+    }
+
+    pub fn reset_node(&mut self, node: u8) -> io::Result<()> {
+        self.send(NmtCommand::ResetNode, node)
+    }
+
+    pub fn reset_communication(&mut self, node: u8) -> io::Result<()> {
+        self.send(NmtCommand::ResetCommunication, node)
+    }
+
+    /// Request the state of a guarded node.
+    ///
+    /// Send this every guard time, the responses are checked by a
+    /// `HeartbeatMonitor`.
+    pub fn node_guard(&mut self, node: u8) -> io::Result<()> {
+        let id = CanMessageId::SFF((COB_HEARTBEAT | u32::from(node & 0x7f)) as u16);
+        let frame = CanFrame::new(id, &[], true, false).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.transport.send(&frame)
+    }
+}
@@ -0,0 +1,427 @@
+//! Object dictionaries, parsed from EDS and DCF files (CiA 306)
+//!
+//! Both file types share an INI like format: a section per object, named by
+//! its index in hex, and one per sub-index of arrays and records:
+//!
+//! ```text
+//! [1017]
+//! ParameterName=Producer heartbeat time
+//! ObjectType=0x7
+//! DataType=0x0006
+//! AccessType=rw
+//! DefaultValue=0
+//! PDOMapping=0
+//!
+//! [1800sub1]
+//! ParameterName=COB-ID used by TPDO
+//! DataType=0x0007
+//! AccessType=rw
+//! DefaultValue=$NODEID+0x180
+//! ```
+//!
+//! DCF files configure a device, adding the node id in the
+//! `[DeviceComissioning]` section and a `ParameterValue` to the objects.
+//! Objects using `CompactSubObj` are not supported, their sub-indices are
+//! missing from the dictionary.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::{collections::btree_map, error, fmt, fs, io, path};
+
+use super::{parse_integer, DataType, Value};
+
+/// Kinds of objects
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ObjectType {
+    /// a single value, at sub-index 0
+    Var,
+    Array,
+    Record,
+    /// a large block of data, at sub-index 0
+    Domain,
+    /// type definitions and others
+    Other(u8),
+}
+
+impl From<u8> for ObjectType {
+    fn from(code: u8) -> ObjectType {
+        match code {
+            0x02 => ObjectType::Domain,
+            0x07 => ObjectType::Var,
+            0x08 => ObjectType::Array,
+            0x09 => ObjectType::Record,
+            code => ObjectType::Other(code),
+        }
+    }
+}
+
+/// Access rights of a variable
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AccessType {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+    /// read only, never changes
+    Const,
+}
+
+impl AccessType {
+    /// Parse the `AccessType` of EDS files, `rwr` and `rww` are `ReadWrite`.
+    pub fn parse(s: &str) -> Option<AccessType> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ro" => Some(AccessType::ReadOnly),
+            "wo" => Some(AccessType::WriteOnly),
+            "rw" | "rwr" | "rww" => Some(AccessType::ReadWrite),
+            "const" => Some(AccessType::Const),
+            _ => None,
+        }
+    }
+
+    pub fn is_readable(self) -> bool {
+        self != AccessType::WriteOnly
+    }
+
+    pub fn is_writable(self) -> bool {
+        self == AccessType::WriteOnly || self == AccessType::ReadWrite
+    }
+}
+
+/// A sub-index of an object
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub index: u16,
+    pub sub: u8,
+
+    /// the name of the object, followed by the name of the sub-index for
+    /// arrays and records, as in `Identity object.Vendor-ID`
+    pub name: String,
+    pub data_type: DataType,
+    pub access: AccessType,
+
+    /// whether it can be mapped into PDOs
+    pub pdo_mapping: bool,
+    pub default_value: Option<String>,
+
+    /// the configured value of a DCF
+    pub parameter_value: Option<String>,
+}
+
+impl Variable {
+    /// The configured value, or else the default value, with `$NODEID` as
+    /// `node`
+    pub fn value(&self, node: u8) -> Option<Value> {
+        let value = self.parameter_value.as_ref().or(self.default_value.as_ref())?;
+        Value::parse(self.data_type, value, node)
+    }
+}
+
+/// An entry of the object dictionary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub index: u16,
+    pub name: String,
+    pub object_type: ObjectType,
+
+    /// the variables by sub-index, only sub-index 0 for `Var` and `Domain`
+    pub subs: BTreeMap<u8, Variable>,
+}
+
+/// Errors of parsing EDS and DCF files
+#[derive(Debug)]
+pub enum EdsError {
+    Io(io::Error),
+
+    /// a line that is neither a section, a key or a comment
+    InvalidLine(usize),
+
+    /// a key of a section is missing or has an invalid value
+    InvalidEntry { section: String, key: &'static str },
+}
+
+impl fmt::Display for EdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EdsError::Io(ref e) => write!(f, "{}", e),
+            EdsError::InvalidLine(line) => write!(f, "invalid line {}", line),
+            EdsError::InvalidEntry { ref section, key } => write!(f, "invalid or missing {} in [{}]", key, section),
+        }
+    }
+}
+
+impl error::Error for EdsError {}
+
+impl From<io::Error> for EdsError {
+    fn from(e: io::Error) -> EdsError {
+        EdsError::Io(e)
+    }
+}
+
+/// The keys of a section, lower case
+type Section = BTreeMap<String, String>;
+
+/// Parse the sections of an INI file, names and keys in lower case.
+fn parse_sections<R: io::BufRead>(rdr: R) -> Result<BTreeMap<String, Section>, EdsError> {
+    let mut sections: BTreeMap<String, Section> = BTreeMap::new();
+    let mut current = None;
+
+    for (i, line) in rdr.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim().to_ascii_lowercase();
+            sections.entry(name.clone()).or_default();
+            current = Some(name);
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+        match (current.as_ref(), kv.next(), kv.next()) {
+            (Some(section), Some(key), Some(value)) => {
+                sections.get_mut(section)
+                    .unwrap()
+                    .insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+            _ => return Err(EdsError::InvalidLine(i + 1)),
+        }
+    }
+    Ok(sections)
+}
+
+/// Parse `key` of a section as an integer of type `T`.
+fn parse_key<T: TryFrom<i128>>(name: &str, section: &Section, key: &'static str) -> Result<Option<T>, EdsError> {
+    match section.get(&key.to_ascii_lowercase()) {
+        Some(value) => {
+            let n = parse_integer(value, 0).and_then(|n| T::try_from(n).ok());
+            n.map(Some).ok_or_else(|| invalid_entry(name, key))
+        }
+        None => Ok(None),
+    }
+}
+
+fn invalid_entry(name: &str, key: &'static str) -> EdsError {
+    EdsError::InvalidEntry {
+        section: name.to_string(),
+        key: key,
+    }
+}
+
+/// Parse the section of a variable, its data type may be omitted for domains.
+fn parse_variable(index: u16,
+                  sub: u8,
+                  name: String,
+                  section_name: &str,
+                  section: &Section,
+                  default_type: Option<DataType>)
+                  -> Result<Variable, EdsError> {
+    let data_type = match parse_key::<u16>(section_name, section, "DataType")? {
+        Some(data_type) => DataType::from(data_type),
+        None => default_type.ok_or_else(|| invalid_entry(section_name, "DataType"))?,
+    };
+    let access = section.get("accesstype")
+        .and_then(|a| AccessType::parse(a))
+        .ok_or_else(|| invalid_entry(section_name, "AccessType"))?;
+    let pdo_mapping = parse_key::<u8>(section_name, section, "PDOMapping")?.unwrap_or(0) != 0;
+
+    Ok(Variable {
+        index: index,
+        sub: sub,
+        name: name,
+        data_type: data_type,
+        access: access,
+        pdo_mapping: pdo_mapping,
+        default_value: section.get("defaultvalue").cloned(),
+        parameter_value: section.get("parametervalue").cloned(),
+    })
+}
+
+/// The objects of a device, by index
+#[derive(Debug, Clone, Default)]
+pub struct ObjectDictionary {
+    objects: BTreeMap<u16, Object>,
+    node_id: Option<u8>,
+}
+
+impl ObjectDictionary {
+    pub fn new() -> ObjectDictionary {
+        ObjectDictionary::default()
+    }
+
+    /// Parse an EDS or DCF file.
+    pub fn from_reader<R: io::Read>(rdr: R) -> Result<ObjectDictionary, EdsError> {
+        let sections = parse_sections(io::BufReader::new(rdr))?;
+        let mut od = ObjectDictionary::new();
+
+        for (section_name, section) in &sections {
+            let index = match u16::from_str_radix(section_name, 16) {
+                Ok(index) if section_name.len() == 4 => index,
+                _ => continue,
+            };
+
+            let name = section.get("parametername").ok_or_else(|| invalid_entry(section_name, "ParameterName"))?;
+            let object_type = ObjectType::from(parse_key::<u8>(section_name, section, "ObjectType")?.unwrap_or(0x07));
+
+            let mut subs = BTreeMap::new();
+            match object_type {
+                ObjectType::Var | ObjectType::Domain => {
+                    let default_type = if object_type == ObjectType::Domain { Some(DataType::Domain) } else { None };
+                    let var = parse_variable(index, 0, name.clone(), section_name, section, default_type)?;
+                    subs.insert(0, var);
+                }
+                ObjectType::Array | ObjectType::Record => {
+                    let prefix = format!("{}sub", section_name);
+                    for (sub_name, sub_section) in sections.range(prefix.clone()..) {
+                        if !sub_name.starts_with(&prefix) {
+                            break;
+                        }
+                        let sub = match u8::from_str_radix(&sub_name[prefix.len()..], 16) {
+                            Ok(sub) => sub,
+                            Err(_) => continue,
+                        };
+                        let var_name = sub_section.get("parametername")
+                            .ok_or_else(|| invalid_entry(sub_name, "ParameterName"))?;
+                        let var_name = format!("{}.{}", name, var_name);
+                        subs.insert(sub, parse_variable(index, sub, var_name, sub_name, sub_section, None)?);
+                    }
+                }
+                ObjectType::Other(_) => (),
+            }
+
+            od.insert(Object {
+                index: index,
+                name: name.clone(),
+                object_type: object_type,
+                subs: subs,
+            });
+        }
+
+        let comissioning = sections.get("devicecomissioning").or_else(|| sections.get("devicecommissioning"));
+        if let Some(section) = comissioning {
+            od.node_id = parse_key::<u8>("DeviceComissioning", section, "NodeID")?;
+        }
+        Ok(od)
+    }
+
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> Result<ObjectDictionary, EdsError> {
+        ObjectDictionary::from_reader(fs::File::open(path)?)
+    }
+
+    /// The node id of a DCF
+    pub fn node_id(&self) -> Option<u8> {
+        self.node_id
+    }
+
+    pub fn set_node_id(&mut self, node_id: Option<u8>) {
+        self.node_id = node_id;
+    }
+
+    /// Add an object, replacing one with the same index.
+    pub fn insert(&mut self, object: Object) {
+        self.objects.insert(object.index, object);
+    }
+
+    pub fn object(&self, index: u16) -> Option<&Object> {
+        self.objects.get(&index)
+    }
+
+    pub fn objects(&self) -> btree_map::Values<'_, u16, Object> {
+        self.objects.values()
+    }
+
+    pub fn get(&self, index: u16, sub: u8) -> Option<&Variable> {
+        self.objects.get(&index).and_then(|o| o.subs.get(&sub))
+    }
+
+    /// Look up a variable by its name.
+    pub fn find(&self, name: &str) -> Option<&Variable> {
+        self.objects.values().flat_map(|o| o.subs.values()).find(|v| v.name == name)
+    }
+
+    /// The value of a variable, with the node id of the dictionary.
+    pub fn value(&self, index: u16, sub: u8) -> Option<Value> {
+        self.get(index, sub)?.value(self.node_id.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DCF: &str = "\
+[DeviceInfo]
+VendorName=Test
+
+[1000]
+ParameterName=Device type
+ObjectType=0x7
+DataType=0x0007
+AccessType=ro
+DefaultValue=0x00020192
+PDOMapping=0
+
+[1800]
+ParameterName=TPDO communication parameter
+ObjectType=0x9
+SubNumber=2
+
+[1800sub0]
+ParameterName=Highest sub-index supported
+DataType=0x0005
+AccessType=const
+DefaultValue=2
+
+[1800sub1]
+ParameterName=COB-ID used by TPDO
+DataType=0x0007
+AccessType=rw
+DefaultValue=$NODEID+0x180
+ParameterValue=0x40000000+$NODEID+0x180
+
+; a comment
+[1F50]
+ParameterName=Program data
+ObjectType=0x2
+AccessType=wo
+
+[DeviceComissioning]
+NodeID=0x0A
+";
+
+    #[test]
+    fn test_parse() {
+        let od = ObjectDictionary::from_reader(DCF.as_bytes()).unwrap();
+        assert_eq!(od.node_id(), Some(0x0a));
+        assert_eq!(od.objects().count(), 3);
+
+        let var = od.get(0x1000, 0).unwrap();
+        assert_eq!(var.name, "Device type");
+        assert_eq!(var.data_type, DataType::Unsigned32);
+        assert_eq!(var.access, AccessType::ReadOnly);
+        assert_eq!(od.value(0x1000, 0), Some(Value::Unsigned(0x20192)));
+
+        let object = od.object(0x1800).unwrap();
+        assert_eq!(object.object_type, ObjectType::Record);
+        assert_eq!(object.subs.len(), 2);
+
+        let cob_id = od.find("TPDO communication parameter.COB-ID used by TPDO").unwrap();
+        assert_eq!((cob_id.index, cob_id.sub), (0x1800, 1));
+        assert_eq!(cob_id.value(0x0a), Some(Value::Unsigned(0x4000_018a)));
+
+        assert_eq!(od.get(0x1f50, 0).unwrap().data_type, DataType::Domain);
+    }
+
+    #[test]
+    fn test_errors() {
+        match ObjectDictionary::from_reader("[1000]\nParameterName=x\nDataType=7\n".as_bytes()) {
+            Err(EdsError::InvalidEntry { key: "AccessType", .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match ObjectDictionary::from_reader("[1000]\nDataType\n".as_bytes()) {
+            Err(EdsError::InvalidLine(2)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! Process data objects, their configuration and mapping
+
+use super::{CanOpenTransport, ObjectDictionary, SdoAbortCode, SdoClient, SdoError, Value};
+use crate::{CanFrame, CanMessageId, EFF_MASK, SFF_MASK};
+
+// flags of the COB-ID entries
+const COB_ID_INVALID: u32 = 0x8000_0000;
+const COB_ID_NO_RTR: u32 = 0x4000_0000;
+const COB_ID_EXTENDED: u32 = 0x2000_0000;
+
+/// transmission type of event driven PDOs, as defined by the device profile
+pub const TRANSMISSION_EVENT_PROFILE: u8 = 0xff;
+
+/// transmission type of event driven PDOs, manufacturer specific
+pub const TRANSMISSION_EVENT_MANUFACTURER: u8 = 0xfe;
+
+/// Direction of a PDO, as seen by the node it belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PdoKind {
+    /// RPDO, configured by objects 0x1400 and 0x1600
+    Receive,
+
+    /// TPDO, configured by objects 0x1800 and 0x1a00
+    Transmit,
+}
+
+impl PdoKind {
+    /// The index of the communication parameters of PDO `num`, 1-512
+    pub fn communication_index(self, num: u16) -> u16 {
+        match self {
+            PdoKind::Receive => 0x1400 + num - 1,
+            PdoKind::Transmit => 0x1800 + num - 1,
+        }
+    }
+
+    /// The index of the mapping parameters of PDO `num`, 1-512
+    pub fn mapping_index(self, num: u16) -> u16 {
+        self.communication_index(num) + 0x200
+    }
+
+    /// The COB-ID of the predefined connection set, PDOs 1-4 only
+    pub fn default_cob_id(self, num: u16, node: u8) -> Option<u32> {
+        let base = match self {
+            PdoKind::Receive => 0x200,
+            PdoKind::Transmit => 0x180,
+        };
+        match num {
+            1..=4 => Some(base + (u32::from(num) - 1) * 0x100 + u32::from(node & 0x7f)),
+            _ => None,
+        }
+    }
+}
+
+/// An object mapped into a PDO
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PdoEntry {
+    pub index: u16,
+    pub sub: u8,
+
+    /// the number of bits used in the PDO
+    pub bits: u8,
+}
+
+impl PdoEntry {
+    pub fn new(index: u16, sub: u8, bits: u8) -> PdoEntry {
+        PdoEntry {
+            index: index,
+            sub: sub,
+            bits: bits,
+        }
+    }
+
+    /// Dummy entries map a data type, to skip bits of the PDO.
+    pub fn is_dummy(&self) -> bool {
+        self.index < 0x1000
+    }
+}
+
+impl From<u32> for PdoEntry {
+    fn from(entry: u32) -> PdoEntry {
+        PdoEntry::new((entry >> 16) as u16, (entry >> 8) as u8, entry as u8)
+    }
+}
+
+impl From<PdoEntry> for u32 {
+    fn from(entry: PdoEntry) -> u32 {
+        u32::from(entry.index) << 16 | u32::from(entry.sub) << 8 | u32::from(entry.bits)
+    }
+}
+
+/// Read `bits` bits of `data`, starting at bit `offset`.
+fn get_bits(data: &[u8], offset: usize, bits: usize) -> u64 {
+    (0..bits).fold(0, |raw, i| {
+        let bit = offset + i;
+        raw | u64::from((data[bit / 8] >> (bit % 8)) & 0x01) << i
+    })
+}
+
+/// Write the lower `bits` bits of `raw` into `data`, starting at bit
+/// `offset`.
+fn set_bits(data: &mut [u8], offset: usize, bits: usize, raw: u64) {
+    for i in 0..bits {
+        let bit = offset + i;
+        if (raw >> i) & 0x01 != 0 {
+            data[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+}
+
+/// Read an unsigned integer of up to 8 bytes.
+fn upload_unsigned<T: CanOpenTransport>(sdo: &mut SdoClient<T>, index: u16, sub: u8) -> Result<u64, SdoError> {
+    let data = sdo.upload(index, sub)?;
+    if data.is_empty() || data.len() > 8 {
+        return Err(SdoError::LocalAbort(SdoAbortCode::LengthMismatch));
+    }
+    let mut buf = [0u8; 8];
+    buf[..data.len()].copy_from_slice(&data);
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read an optional sub-index, 0 if the node does not implement it.
+fn upload_optional<T: CanOpenTransport>(sdo: &mut SdoClient<T>, index: u16, sub: u8) -> Result<u64, SdoError> {
+    match upload_unsigned(sdo, index, sub) {
+        Err(SdoError::Abort(SdoAbortCode::SubIndexDoesNotExist)) => Ok(0),
+        result => result,
+    }
+}
+
+/// The communication and mapping parameters of a PDO
+///
+/// Mapped objects are packed into the data in order, least significant bit
+/// first, as CiA 301 requires. `decode` and `encode` look up their names and
+/// types in an `ObjectDictionary`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pdo {
+    /// the CAN id, 11 or 29 bits
+    pub cob_id: u32,
+    pub extended: bool,
+
+    /// disabled PDOs are neither sent nor received
+    pub enabled: bool,
+
+    /// 0 for acyclic and 1-240 for every nth SYNC, 254 and 255 for event
+    /// driven PDOs
+    pub transmission_type: u8,
+
+    /// the minimum time between transmissions in multiples of 100 µs, 0
+    /// for none
+    pub inhibit_time: u16,
+
+    /// the time between transmissions of event driven PDOs in ms, 0 for
+    /// none
+    pub event_timer: u16,
+    pub entries: Vec<PdoEntry>,
+}
+
+impl Pdo {
+    /// An enabled, event driven PDO without mapped objects
+    pub fn new(cob_id: u32) -> Pdo {
+        Pdo {
+            cob_id: cob_id,
+            extended: cob_id > SFF_MASK,
+            enabled: true,
+            transmission_type: TRANSMISSION_EVENT_PROFILE,
+            inhibit_time: 0,
+            event_timer: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Decode the COB-ID entry of the communication parameters.
+    fn set_cob_id(&mut self, cob_id: u32) {
+        self.extended = cob_id & COB_ID_EXTENDED != 0;
+        self.enabled = cob_id & COB_ID_INVALID == 0;
+        self.cob_id = cob_id & if self.extended { EFF_MASK } else { SFF_MASK };
+    }
+
+    /// The COB-ID entry of the communication parameters, with `enabled` set
+    /// as given
+    fn cob_id_entry(&self, enabled: bool) -> u32 {
+        let mut entry = self.cob_id | COB_ID_NO_RTR;
+        if self.extended {
+            entry |= COB_ID_EXTENDED;
+        }
+        if !enabled {
+            entry |= COB_ID_INVALID;
+        }
+        entry
+    }
+
+    /// The configuration of PDO `num` of `kind` in a dictionary, with
+    /// `$NODEID` as `node`
+    ///
+    /// `None` if the dictionary lacks the communication parameters or has
+    /// invalid mapping entries.
+    pub fn from_dictionary(od: &ObjectDictionary, node: u8, kind: PdoKind, num: u16) -> Option<Pdo> {
+        let unsigned = |index: u16, sub: u8| match od.get(index, sub).and_then(|v| v.value(node)) {
+            Some(Value::Unsigned(n)) => Some(n),
+            _ => None,
+        };
+
+        let comm = kind.communication_index(num);
+        let mut pdo = Pdo::new(0);
+        pdo.set_cob_id(unsigned(comm, 1)? as u32);
+        pdo.transmission_type = unsigned(comm, 2).unwrap_or(u64::from(TRANSMISSION_EVENT_PROFILE)) as u8;
+        pdo.inhibit_time = unsigned(comm, 3).unwrap_or(0) as u16;
+        pdo.event_timer = unsigned(comm, 5).unwrap_or(0) as u16;
+
+        let mapping = kind.mapping_index(num);
+        for sub in 1..=unsigned(mapping, 0).unwrap_or(0) as u8 {
+            pdo.entries.push(PdoEntry::from(unsigned(mapping, sub)? as u32));
+        }
+        Some(pdo)
+    }
+
+    /// Read the configuration of PDO `num` of `kind` from a node.
+    pub fn upload<T: CanOpenTransport>(sdo: &mut SdoClient<T>, kind: PdoKind, num: u16) -> Result<Pdo, SdoError> {
+        let comm = kind.communication_index(num);
+        let mut pdo = Pdo::new(0);
+        pdo.set_cob_id(upload_unsigned(sdo, comm, 1)? as u32);
+        pdo.transmission_type = upload_unsigned(sdo, comm, 2)? as u8;
+        if kind == PdoKind::Transmit {
+            pdo.inhibit_time = upload_optional(sdo, comm, 3)? as u16;
+        }
+        pdo.event_timer = upload_optional(sdo, comm, 5)? as u16;
+
+        let mapping = kind.mapping_index(num);
+        for sub in 1..=upload_unsigned(sdo, mapping, 0)? as u8 {
+            pdo.entries.push(PdoEntry::from(upload_unsigned(sdo, mapping, sub)? as u32));
+        }
+        Ok(pdo)
+    }
+
+    /// Configure PDO `num` of `kind` of a node, which has to be
+    /// pre-operational.
+    ///
+    /// The PDO is disabled while its mapping is written, as CiA 301
+    /// requires. The inhibit time and event timer are only written if set.
+    pub fn configure<T: CanOpenTransport>(&self,
+                                          sdo: &mut SdoClient<T>,
+                                          kind: PdoKind,
+                                          num: u16)
+                                          -> Result<(), SdoError> {
+        let comm = kind.communication_index(num);
+        let mapping = kind.mapping_index(num);
+
+        sdo.download(comm, 1, &self.cob_id_entry(false).to_le_bytes())?;
+        sdo.download(comm, 2, &[self.transmission_type])?;
+        if self.inhibit_time != 0 && kind == PdoKind::Transmit {
+            sdo.download(comm, 3, &self.inhibit_time.to_le_bytes())?;
+        }
+        if self.event_timer != 0 {
+            sdo.download(comm, 5, &self.event_timer.to_le_bytes())?;
+        }
+
+        sdo.download(mapping, 0, &[0])?;
+        for (i, &entry) in self.entries.iter().enumerate() {
+            sdo.download(mapping, i as u8 + 1, &u32::from(entry).to_le_bytes())?;
+        }
+        sdo.download(mapping, 0, &[self.entries.len() as u8])?;
+
+        if self.enabled {
+            sdo.download(comm, 1, &self.cob_id_entry(true).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The CAN id of the PDO
+    pub fn id(&self) -> CanMessageId {
+        if self.extended {
+            CanMessageId::EFF(self.cob_id)
+        } else {
+            CanMessageId::SFF(self.cob_id as u16)
+        }
+    }
+
+    /// Check if `frame` carries this PDO.
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        self.enabled && frame.id() == self.cob_id && frame.is_extended() == self.extended && !frame.is_rtr()
+            && !frame.is_error()
+    }
+
+    /// The length of the PDO in bits
+    pub fn bits(&self) -> usize {
+        self.entries.iter().map(|e| e.bits as usize).sum()
+    }
+
+    /// Decode the mapped objects, returning their names and values.
+    ///
+    /// `None` if `data` is too short or objects are missing from `od`.
+    pub fn decode(&self, od: &ObjectDictionary, data: &[u8]) -> Option<Vec<(String, Value)>> {
+        if data.len() * 8 < self.bits() {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        let mut offset = 0;
+        for entry in &self.entries {
+            let bits = entry.bits as usize;
+            if !entry.is_dummy() {
+                let var = od.get(entry.index, entry.sub)?;
+                if bits > 64 {
+                    return None;
+                }
+                let raw = get_bits(data, offset, bits);
+                values.push((var.name.clone(), Value::from_raw(var.data_type, raw, bits as u32)));
+            }
+            offset += bits;
+        }
+        Some(values)
+    }
+
+    /// Encode the mapped objects from named values, taking the value of the
+    /// dictionary for those not given.
+    ///
+    /// `None` if objects are missing from `od`, or a value does not fit.
+    pub fn encode(&self, od: &ObjectDictionary, values: &[(&str, Value)]) -> Option<Vec<u8>> {
+        let mut data = vec![0u8; (self.bits() + 7) / 8];
+        let mut offset = 0;
+        for entry in &self.entries {
+            let bits = entry.bits as usize;
+            if !entry.is_dummy() {
+                let var = od.get(entry.index, entry.sub)?;
+                let value = match values.iter().find(|(name, _)| *name == var.name) {
+                    Some((_, value)) => value.clone(),
+                    None => od.value(entry.index, entry.sub)?,
+                };
+
+                let bytes = value.encode(var.data_type)?;
+                if bits > 64 || bytes.len() > 8 {
+                    return None;
+                }
+                let mut buf = [0u8; 8];
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                let raw = u64::from_le_bytes(buf);
+
+                // the mapped bits have to hold the same value
+                let mask = if bits == 64 { !0 } else { (1 << bits) - 1 };
+                let type_bits = bytes.len() as u32 * 8;
+                let mapped = Value::from_raw(var.data_type, raw & mask, bits as u32);
+                if mapped != Value::from_raw(var.data_type, raw, type_bits) {
+                    return None;
+                }
+                set_bits(&mut data, offset, bits, raw);
+            }
+            offset += bits;
+        }
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canopen::{AccessType, DataType, Object, ObjectType, Variable};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::io;
+    use std::time::Duration;
+
+    fn dictionary() -> ObjectDictionary {
+        let mut od = ObjectDictionary::new();
+        let vars = [(0x6000, "Switch", DataType::Boolean, "0"),
+                    (0x6001, "Mode", DataType::Unsigned8, "3"),
+                    (0x6002, "Temperature", DataType::Integer16, "0"),
+                    (0x1800, "COB-ID", DataType::Unsigned32, "$NODEID+0x180")];
+        for &(index, name, data_type, default) in &vars {
+            let mut subs = BTreeMap::new();
+            let sub = if index == 0x1800 { 1 } else { 0 };
+            subs.insert(sub,
+                        Variable {
+                            index: index,
+                            sub: sub,
+                            name: name.to_string(),
+                            data_type: data_type,
+                            access: AccessType::ReadWrite,
+                            pdo_mapping: true,
+                            default_value: Some(default.to_string()),
+                            parameter_value: None,
+                        });
+            od.insert(Object {
+                index: index,
+                name: name.to_string(),
+                object_type: ObjectType::Var,
+                subs: subs,
+            });
+        }
+        od
+    }
+
+    fn pdo() -> Pdo {
+        let mut pdo = Pdo::new(0x185);
+        pdo.entries = vec![PdoEntry::new(0x6000, 0, 1),
+                           PdoEntry::new(0x0001, 0, 3),
+                           PdoEntry::new(0x6001, 0, 4),
+                           PdoEntry::new(0x6002, 0, 16)];
+        pdo
+    }
+
+    #[test]
+    fn test_decode_encode() {
+        let od = dictionary();
+        let pdo = pdo();
+        assert_eq!(pdo.bits(), 24);
+
+        let values = pdo.decode(&od, &[0x51, 0xfe, 0xff]).unwrap();
+        assert_eq!(values,
+                   vec![("Switch".to_string(), Value::Boolean(true)),
+                        ("Mode".to_string(), Value::Unsigned(5)),
+                        ("Temperature".to_string(), Value::Integer(-2))]);
+        assert_eq!(pdo.decode(&od, &[0x51, 0xfe]), None);
+
+        // the mode is taken from the dictionary
+        let data = pdo.encode(&od, &[("Temperature", Value::Integer(-2)), ("Switch", Value::Boolean(true))])
+            .unwrap();
+        assert_eq!(data, vec![0x31, 0xfe, 0xff]);
+        assert_eq!(pdo.encode(&od, &[("Mode", Value::Unsigned(300))]), None);
+        assert_eq!(pdo.encode(&od, &[("Mode", Value::Unsigned(0x1f))]), None);
+        assert_eq!(pdo.encode(&od, &[("Mode", Value::Unsigned(0x0f))]), Some(vec![0xf0, 0x00, 0x00]));
+
+        // signed values have to fit the bits as well
+        let mut pdo = Pdo::new(0x185);
+        pdo.entries = vec![PdoEntry::new(0x6002, 0, 8)];
+        assert_eq!(pdo.encode(&od, &[("Temperature", Value::Integer(-2))]), Some(vec![0xfe]));
+        assert_eq!(pdo.encode(&od, &[("Temperature", Value::Integer(-200))]), None);
+    }
+
+    #[test]
+    fn test_from_dictionary() {
+        let od = dictionary();
+        let pdo = Pdo::from_dictionary(&od, 5, PdoKind::Transmit, 1).unwrap();
+        assert_eq!(pdo.cob_id, 0x185);
+        assert!(pdo.enabled && !pdo.extended);
+        assert_eq!(pdo.transmission_type, TRANSMISSION_EVENT_PROFILE);
+        assert_eq!(pdo.entries, vec![]);
+        assert_eq!(Pdo::from_dictionary(&od, 5, PdoKind::Transmit, 2), None);
+
+        assert_eq!(PdoKind::Receive.default_cob_id(4, 5), Some(0x505));
+    }
+
+    /// An SDO server answering expedited transfers from a map
+    struct Server {
+        objects: BTreeMap<(u16, u8), Vec<u8>>,
+        writes: Vec<(u16, u8, Vec<u8>)>,
+        responses: VecDeque<CanFrame>,
+    }
+
+    impl CanOpenTransport for Server {
+        fn send(&mut self, frame: &CanFrame) -> io::Result<()> {
+            let req = frame.data();
+            let (index, sub) = (u16::from_le_bytes([req[1], req[2]]), req[3]);
+            let mut resp = [0u8; 8];
+            resp[1..4].copy_from_slice(&req[1..4]);
+            match req[0] >> 5 {
+                1 => {
+                    let data = req[4..8 - ((req[0] >> 2) & 0x03) as usize].to_vec();
+                    self.objects.insert((index, sub), data.clone());
+                    self.writes.push((index, sub, data));
+                    resp[0] = 0x60;
+                }
+                2 => {
+                    match self.objects.get(&(index, sub)) {
+                        Some(data) => {
+                            resp[0] = 0x43 | ((4 - data.len() as u8) << 2);
+                            resp[4..4 + data.len()].copy_from_slice(data);
+                        }
+                        None => {
+                            resp[0] = 0x80;
+                            resp[4..].copy_from_slice(&0x0609_0011u32.to_le_bytes());
+                        }
+                    }
+                }
+                _ => panic!("unexpected request {:02x?}", req),
+            }
+            self.responses.push_back(CanFrame::new(CanMessageId::SFF(0x585), &resp, false, false).unwrap());
+            Ok(())
+        }
+
+        fn recv(&mut self, _timeout: Duration) -> io::Result<CanFrame> {
+            self.responses.pop_front().ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+        }
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut sdo = SdoClient::new(Server {
+                                         objects: BTreeMap::new(),
+                                         writes: Vec::new(),
+                                         responses: VecDeque::new(),
+                                     },
+                                     5);
+        let mut pdo = pdo();
+        pdo.event_timer = 100;
+        pdo.configure(&mut sdo, PdoKind::Transmit, 1).unwrap();
+
+        let writes = &sdo.transport().writes;
+        assert_eq!(writes[0], (0x1800, 1, vec![0x85, 0x01, 0x00, 0xc0]));
+        assert_eq!(writes[2], (0x1800, 5, vec![100, 0]));
+        assert_eq!(writes[3], (0x1a00, 0, vec![0]));
+        assert_eq!(writes[4], (0x1a00, 1, vec![0x01, 0x00, 0x00, 0x60]));
+        assert_eq!(writes[8], (0x1a00, 0, vec![4]));
+        assert_eq!(writes[9], (0x1800, 1, vec![0x85, 0x01, 0x00, 0x40]));
+
+        // sub-index 3 is not implemented by the server
+        assert_eq!(Pdo::upload(&mut sdo, PdoKind::Transmit, 1).unwrap(), pdo);
+    }
+}
@@ -0,0 +1,730 @@
+//! SDO client, accessing the object dictionary of a node
+
+use std::time::{Duration, Instant};
+use std::{error, fmt, io};
+
+use super::{CanOpenTransport, COB_RSDO, COB_TSDO};
+use crate::{
+    CanFilter, CanFrame, CanMessageId, CanSocket, CanSocketOpenError, FrameFlags, ShouldRetry, EFF_MASK,
+};
+
+// client command specifiers
+const CCS_DOWNLOAD_SEGMENT: u8 = 0;
+const CCS_INITIATE_DOWNLOAD: u8 = 1;
+const CCS_INITIATE_UPLOAD: u8 = 2;
+const CCS_UPLOAD_SEGMENT: u8 = 3;
+const CS_ABORT: u8 = 4;
+const CCS_BLOCK_UPLOAD: u8 = 5;
+const CCS_BLOCK_DOWNLOAD: u8 = 6;
+
+// server command specifiers
+const SCS_UPLOAD_SEGMENT: u8 = 0;
+const SCS_DOWNLOAD_SEGMENT: u8 = 1;
+const SCS_INITIATE_UPLOAD: u8 = 2;
+const SCS_INITIATE_DOWNLOAD: u8 = 3;
+const SCS_BLOCK_DOWNLOAD: u8 = 5;
+const SCS_BLOCK_UPLOAD: u8 = 6;
+
+// sub-commands of block transfers
+const BLOCK_INITIATE: u8 = 0;
+const BLOCK_END: u8 = 1;
+const BLOCK_ACK: u8 = 2;
+const BLOCK_START_UPLOAD: u8 = 3;
+
+/// flag of block transfers, the sender supports CRCs
+const BLOCK_CRC: u8 = 0x04;
+
+/// flag of initiate requests and responses, the size is given
+const SIZE_INDICATED: u8 = 0x01;
+
+/// flag of initiate requests and responses, an expedited transfer
+const EXPEDITED: u8 = 0x02;
+
+/// last segment of a block
+const BLOCK_LAST_SEGMENT: u8 = 0x80;
+
+/// default time to wait for a response
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// largest number of segments of a block
+const MAX_BLOCK_SIZE: u8 = 127;
+
+/// CRC-16-CCITT of block transfers, polynomial 0x1021 starting with 0
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, &b| {
+        crc ^= u16::from(b) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Reasons to abort an SDO transfer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SdoAbortCode {
+    ToggleBitNotAlternated,
+    ProtocolTimedOut,
+    InvalidCommandSpecifier,
+    InvalidBlockSize,
+    InvalidSequenceNumber,
+    CrcError,
+    OutOfMemory,
+    UnsupportedAccess,
+    ReadWriteOnlyObject,
+    WriteReadOnlyObject,
+    ObjectDoesNotExist,
+    ObjectCannotBeMapped,
+    PdoLengthExceeded,
+    ParameterIncompatibility,
+    InternalIncompatibility,
+    HardwareError,
+    LengthMismatch,
+    LengthTooHigh,
+    LengthTooLow,
+    SubIndexDoesNotExist,
+    InvalidValue,
+    ValueTooHigh,
+    ValueTooLow,
+    MaxLessThanMin,
+    ResourceNotAvailable,
+    GeneralError,
+    CannotTransfer,
+    CannotTransferLocalControl,
+    CannotTransferDeviceState,
+    NoObjectDictionary,
+    NoDataAvailable,
+    /// codes without a variant, such as manufacturer specific ones
+    Other(u32),
+}
+
+impl From<u32> for SdoAbortCode {
+    fn from(code: u32) -> SdoAbortCode {
+        use self::SdoAbortCode::*;
+
+        match code {
+            0x0503_0000 => ToggleBitNotAlternated,
+            0x0504_0000 => ProtocolTimedOut,
+            0x0504_0001 => InvalidCommandSpecifier,
+            0x0504_0002 => InvalidBlockSize,
+            0x0504_0003 => InvalidSequenceNumber,
+            0x0504_0004 => CrcError,
+            0x0504_0005 => OutOfMemory,
+            0x0601_0000 => UnsupportedAccess,
+            0x0601_0001 => ReadWriteOnlyObject,
+            0x0601_0002 => WriteReadOnlyObject,
+            0x0602_0000 => ObjectDoesNotExist,
+            0x0604_0041 => ObjectCannotBeMapped,
+            0x0604_0042 => PdoLengthExceeded,
+            0x0604_0043 => ParameterIncompatibility,
+            0x0604_0047 => InternalIncompatibility,
+            0x0606_0000 => HardwareError,
+            0x0607_0010 => LengthMismatch,
+            0x0607_0012 => LengthTooHigh,
+            0x0607_0013 => LengthTooLow,
+            0x0609_0011 => SubIndexDoesNotExist,
+            0x0609_0030 => InvalidValue,
+            0x0609_0031 => ValueTooHigh,
+            0x0609_0032 => ValueTooLow,
+            0x0609_0036 => MaxLessThanMin,
+            0x060a_0023 => ResourceNotAvailable,
+            0x0800_0000 => GeneralError,
+            0x0800_0020 => CannotTransfer,
+            0x0800_0021 => CannotTransferLocalControl,
+            0x0800_0022 => CannotTransferDeviceState,
+            0x0800_0023 => NoObjectDictionary,
+            0x0800_0024 => NoDataAvailable,
+            code => Other(code),
+        }
+    }
+}
+
+impl From<SdoAbortCode> for u32 {
+    fn from(code: SdoAbortCode) -> u32 {
+        use self::SdoAbortCode::*;
+
+        match code {
+            ToggleBitNotAlternated => 0x0503_0000,
+            ProtocolTimedOut => 0x0504_0000,
+            InvalidCommandSpecifier => 0x0504_0001,
+            InvalidBlockSize => 0x0504_0002,
+            InvalidSequenceNumber => 0x0504_0003,
+            CrcError => 0x0504_0004,
+            OutOfMemory => 0x0504_0005,
+            UnsupportedAccess => 0x0601_0000,
+            ReadWriteOnlyObject => 0x0601_0001,
+            WriteReadOnlyObject => 0x0601_0002,
+            ObjectDoesNotExist => 0x0602_0000,
+            ObjectCannotBeMapped => 0x0604_0041,
+            PdoLengthExceeded => 0x0604_0042,
+            ParameterIncompatibility => 0x0604_0043,
+            InternalIncompatibility => 0x0604_0047,
+            HardwareError => 0x0606_0000,
+            LengthMismatch => 0x0607_0010,
+            LengthTooHigh => 0x0607_0012,
+            LengthTooLow => 0x0607_0013,
+            SubIndexDoesNotExist => 0x0609_0011,
+            InvalidValue => 0x0609_0030,
+            ValueTooHigh => 0x0609_0031,
+            ValueTooLow => 0x0609_0032,
+            MaxLessThanMin => 0x0609_0036,
+            ResourceNotAvailable => 0x060a_0023,
+            GeneralError => 0x0800_0000,
+            CannotTransfer => 0x0800_0020,
+            CannotTransferLocalControl => 0x0800_0021,
+            CannotTransferDeviceState => 0x0800_0022,
+            NoObjectDictionary => 0x0800_0023,
+            NoDataAvailable => 0x0800_0024,
+            Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for SdoAbortCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SdoAbortCode::*;
+
+        let msg = match *self {
+            ToggleBitNotAlternated => "toggle bit not alternated",
+            ProtocolTimedOut => "SDO protocol timed out",
+            InvalidCommandSpecifier => "client/server command specifier not valid or unknown",
+            InvalidBlockSize => "invalid block size",
+            InvalidSequenceNumber => "invalid sequence number",
+            CrcError => "CRC error",
+            OutOfMemory => "out of memory",
+            UnsupportedAccess => "unsupported access to an object",
+            ReadWriteOnlyObject => "attempt to read a write only object",
+            WriteReadOnlyObject => "attempt to write a read only object",
+            ObjectDoesNotExist => "object does not exist in the object dictionary",
+            ObjectCannotBeMapped => "object cannot be mapped to the PDO",
+            PdoLengthExceeded => "the number and length of the objects to be mapped would exceed the PDO length",
+            ParameterIncompatibility => "general parameter incompatibility",
+            InternalIncompatibility => "general internal incompatibility in the device",
+            HardwareError => "access failed due to a hardware error",
+            LengthMismatch => "data type does not match, length of service parameter does not match",
+            LengthTooHigh => "data type does not match, length of service parameter too high",
+            LengthTooLow => "data type does not match, length of service parameter too low",
+            SubIndexDoesNotExist => "sub-index does not exist",
+            InvalidValue => "invalid value for parameter",
+            ValueTooHigh => "value of parameter written too high",
+            ValueTooLow => "value of parameter written too low",
+            MaxLessThanMin => "maximum value is less than minimum value",
+            ResourceNotAvailable => "resource not available: SDO connection",
+            GeneralError => "general error",
+            CannotTransfer => "data cannot be transferred or stored to the application",
+            CannotTransferLocalControl => {
+                "data cannot be transferred or stored to the application because of local control"
+            }
+            CannotTransferDeviceState => {
+                "data cannot be transferred or stored to the application because of the present device state"
+            }
+            NoObjectDictionary => "object dictionary dynamic generation fails or no object dictionary is present",
+            NoDataAvailable => "no data available",
+            Other(code) => return write!(f, "abort code {:08x}", code),
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+/// Errors of SDO transfers
+#[derive(Debug)]
+pub enum SdoError {
+    /// The transport failed
+    IOError(io::Error),
+
+    /// The server did not respond in time, the transfer was aborted
+    Timeout,
+
+    /// The server aborted the transfer
+    Abort(SdoAbortCode),
+
+    /// The client aborted the transfer on an invalid response
+    LocalAbort(SdoAbortCode),
+}
+
+impl fmt::Display for SdoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SdoError::IOError(ref e) => write!(f, "IO: {}", e),
+            SdoError::Timeout => write!(f, "no response"),
+            SdoError::Abort(code) => write!(f, "aborted by the server: {}", code),
+            SdoError::LocalAbort(code) => write!(f, "aborted: {}", code),
+        }
+    }
+}
+
+impl error::Error for SdoError {}
+
+impl From<io::Error> for SdoError {
+    fn from(e: io::Error) -> SdoError {
+        SdoError::IOError(e)
+    }
+}
+
+/// An SDO client, using the default SDO of a node.
+///
+/// Transfers block until they complete. `upload` and `download` choose
+/// between expedited and segmented transfers by the size of the data, block
+/// transfers are used by `block_upload` and `block_download` only. Protocol
+/// errors and timeouts abort the transfer, so the server can start over.
+#[derive(Debug)]
+pub struct SdoClient<T = CanSocket> {
+    transport: T,
+    node: u8,
+    timeout: Duration,
+    block_size: u8,
+
+    /// index and sub-index of the current transfer
+    mux: [u8; 3],
+}
+
+impl SdoClient<CanSocket> {
+    /// Open a socket receiving the SDO responses of `node`.
+    pub fn open(ifname: &str, node: u8) -> Result<SdoClient<CanSocket>, CanSocketOpenError> {
+        let sock = CanSocket::open(ifname)?;
+        let flags = (FrameFlags::EFF_FLAG | FrameFlags::RTR_FLAG).bits();
+        let filter = CanFilter::new(COB_TSDO | u32::from(node & 0x7f), flags | EFF_MASK).unwrap();
+        sock.set_filters(&[filter])?;
+        Ok(SdoClient::new(sock, node))
+    }
+}
+
+impl<T: CanOpenTransport> SdoClient<T> {
+    /// A client of `node`, 1-127.
+    pub fn new(transport: T, node: u8) -> SdoClient<T> {
+        SdoClient {
+            transport: transport,
+            node: node & 0x7f,
+            timeout: DEFAULT_TIMEOUT,
+            block_size: MAX_BLOCK_SIZE,
+            mux: [0; 3],
+        }
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    pub fn node(&self) -> u8 {
+        self.node
+    }
+
+    /// Sets the time to wait for each response.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the number of segments per block of block uploads, 1-127.
+    pub fn set_block_size(&mut self, block_size: u8) {
+        self.block_size = if block_size == 0 { 1 } else { block_size.min(MAX_BLOCK_SIZE) };
+    }
+
+    fn send(&mut self, data: [u8; 8]) -> Result<(), SdoError> {
+        let id = CanMessageId::SFF((COB_RSDO | u32::from(self.node)) as u16);
+        let frame = CanFrame::new(id, &data, false, false).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(self.transport.send(&frame)?)
+    }
+
+    /// Abort the current transfer, returning the error to report.
+    fn abort(&mut self, code: SdoAbortCode) -> SdoError {
+        let mut data = [CS_ABORT << 5, self.mux[0], self.mux[1], self.mux[2], 0, 0, 0, 0];
+        data[4..].copy_from_slice(&u32::from(code).to_le_bytes());
+
+        // the transfer failed already, the abort is sent on a best effort
+        // basis
+        let _ = self.send(data);
+        match code {
+            SdoAbortCode::ProtocolTimedOut => SdoError::Timeout,
+            code => SdoError::LocalAbort(code),
+        }
+    }
+
+    /// Receive the next frame of the server, aborting the transfer if none
+    /// arrives in time.
+    fn recv_raw(&mut self) -> Result<[u8; 8], SdoError> {
+        let id = COB_TSDO | u32::from(self.node);
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            // a zero timeout would block forever
+            let now = Instant::now();
+            if now >= deadline || deadline - now < Duration::from_micros(1) {
+                return Err(self.abort(SdoAbortCode::ProtocolTimedOut));
+            }
+
+            let frame = match self.transport.recv(deadline - now) {
+                Ok(frame) => frame,
+                Err(ref e) if e.should_retry() => return Err(self.abort(SdoAbortCode::ProtocolTimedOut)),
+                Err(e) => return Err(e.into()),
+            };
+            if frame.id() == id && !frame.is_extended() && !frame.is_rtr() && frame.data().len() == 8 {
+                let mut data = [0u8; 8];
+                data.copy_from_slice(frame.data());
+                return Ok(data);
+            }
+        }
+    }
+
+    /// Receive a response, failing on aborts of the server.
+    fn recv(&mut self) -> Result<[u8; 8], SdoError> {
+        let data = self.recv_raw()?;
+        if data[0] == CS_ABORT << 5 {
+            let code = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            return Err(SdoError::Abort(code.into()));
+        }
+        Ok(data)
+    }
+
+    /// Receive a response with the server command specifier `scs`, and the
+    /// index and sub-index of the transfer if `mux` is set.
+    fn expect(&mut self, scs: u8, mux: bool) -> Result<[u8; 8], SdoError> {
+        let data = self.recv()?;
+        if data[0] >> 5 != scs || mux && data[1..4] != self.mux {
+            return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+        }
+        Ok(data)
+    }
+
+    /// A request starting a transfer of `index` and `sub`.
+    fn initiate(&mut self, command: u8, index: u16, sub: u8) -> [u8; 8] {
+        let index = index.to_le_bytes();
+        self.mux = [index[0], index[1], sub];
+        [command, index[0], index[1], sub, 0, 0, 0, 0]
+    }
+
+    /// Read an object.
+    pub fn upload(&mut self, index: u16, sub: u8) -> Result<Vec<u8>, SdoError> {
+        let request = self.initiate(CCS_INITIATE_UPLOAD << 5, index, sub);
+        self.send(request)?;
+        let response = self.expect(SCS_INITIATE_UPLOAD, true)?;
+
+        if response[0] & EXPEDITED != 0 {
+            let len = if response[0] & SIZE_INDICATED != 0 { 4 - ((response[0] >> 2) & 0x03) as usize } else { 4 };
+            return Ok(response[4..4 + len].to_vec());
+        }
+
+        let size = if response[0] & SIZE_INDICATED != 0 {
+            Some(u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize)
+        } else {
+            None
+        };
+
+        let mut data = Vec::new();
+        let mut toggle = 0;
+        loop {
+            self.send([CCS_UPLOAD_SEGMENT << 5 | toggle << 4, 0, 0, 0, 0, 0, 0, 0])?;
+            let response = self.expect(SCS_UPLOAD_SEGMENT, false)?;
+            if (response[0] >> 4) & 0x01 != toggle {
+                return Err(self.abort(SdoAbortCode::ToggleBitNotAlternated));
+            }
+
+            let unused = ((response[0] >> 1) & 0x07) as usize;
+            data.extend_from_slice(&response[1..8 - unused]);
+            if response[0] & 0x01 != 0 {
+                break;
+            }
+            toggle ^= 1;
+        }
+
+        match size {
+            Some(size) if size != data.len() => Err(SdoError::LocalAbort(SdoAbortCode::LengthMismatch)),
+            _ => Ok(data),
+        }
+    }
+
+    /// Write an object.
+    pub fn download(&mut self, index: u16, sub: u8, data: &[u8]) -> Result<(), SdoError> {
+        if data.len() <= 4 {
+            let command = CCS_INITIATE_DOWNLOAD << 5 | ((4 - data.len()) as u8) << 2 | EXPEDITED | SIZE_INDICATED;
+            let mut request = self.initiate(command, index, sub);
+            request[4..4 + data.len()].copy_from_slice(data);
+            self.send(request)?;
+            self.expect(SCS_INITIATE_DOWNLOAD, true)?;
+            return Ok(());
+        }
+
+        let mut request = self.initiate(CCS_INITIATE_DOWNLOAD << 5 | SIZE_INDICATED, index, sub);
+        request[4..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.send(request)?;
+        self.expect(SCS_INITIATE_DOWNLOAD, true)?;
+
+        let segments = data.chunks(7).len();
+        let mut toggle = 0;
+        for (i, segment) in data.chunks(7).enumerate() {
+            let last = (i + 1 == segments) as u8;
+            let mut request = [CCS_DOWNLOAD_SEGMENT << 5 | toggle << 4 | ((7 - segment.len()) as u8) << 1 | last,
+                               0, 0, 0, 0, 0, 0, 0];
+            request[1..1 + segment.len()].copy_from_slice(segment);
+            self.send(request)?;
+
+            let response = self.expect(SCS_DOWNLOAD_SEGMENT, false)?;
+            if (response[0] >> 4) & 0x01 != toggle {
+                return Err(self.abort(SdoAbortCode::ToggleBitNotAlternated));
+            }
+            toggle ^= 1;
+        }
+        Ok(())
+    }
+
+    /// Read an object with a block transfer, checking its CRC if the server
+    /// supports it.
+    pub fn block_upload(&mut self, index: u16, sub: u8) -> Result<Vec<u8>, SdoError> {
+        let mut request = self.initiate(CCS_BLOCK_UPLOAD << 5 | BLOCK_CRC | BLOCK_INITIATE, index, sub);
+        request[4] = self.block_size;
+        self.send(request)?;
+
+        let response = self.expect(SCS_BLOCK_UPLOAD, true)?;
+        if response[0] & 0x01 != BLOCK_INITIATE {
+            return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+        }
+        let crc = response[0] & BLOCK_CRC != 0;
+        let size = if response[0] & 0x02 != 0 {
+            Some(u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize)
+        } else {
+            None
+        };
+
+        self.send([CCS_BLOCK_UPLOAD << 5 | BLOCK_START_UPLOAD, 0, 0, 0, 0, 0, 0, 0])?;
+
+        let mut data = Vec::new();
+        loop {
+            // segments after a lost one are dropped, the server repeats
+            // them in the next block
+            let mut ackseq = 0;
+            let mut done = false;
+            loop {
+                let segment = self.recv_raw()?;
+                if segment[0] == CS_ABORT << 5 {
+                    let code = u32::from_le_bytes([segment[4], segment[5], segment[6], segment[7]]);
+                    return Err(SdoError::Abort(code.into()));
+                }
+
+                let seq = segment[0] & 0x7f;
+                let last = segment[0] & BLOCK_LAST_SEGMENT != 0;
+                if seq == ackseq + 1 {
+                    data.extend_from_slice(&segment[1..]);
+                    ackseq = seq;
+                    done = last;
+                }
+                if last || seq >= self.block_size {
+                    break;
+                }
+            }
+
+            self.send([CCS_BLOCK_UPLOAD << 5 | BLOCK_ACK, ackseq, self.block_size, 0, 0, 0, 0, 0])?;
+            if done {
+                break;
+            }
+        }
+
+        let response = self.expect(SCS_BLOCK_UPLOAD, false)?;
+        if response[0] & 0x03 != BLOCK_END {
+            return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+        }
+        let unused = ((response[0] >> 2) & 0x07) as usize;
+        let len = data.len().saturating_sub(unused);
+        data.truncate(len);
+
+        if crc && crc16(&data) != u16::from_le_bytes([response[1], response[2]]) {
+            return Err(self.abort(SdoAbortCode::CrcError));
+        }
+        self.send([CCS_BLOCK_UPLOAD << 5 | BLOCK_END, 0, 0, 0, 0, 0, 0, 0])?;
+
+        match size {
+            Some(size) if size != data.len() => Err(SdoError::LocalAbort(SdoAbortCode::LengthMismatch)),
+            _ => Ok(data),
+        }
+    }
+
+    /// Write an object with a block transfer, sending its CRC if the server
+    /// supports it.
+    pub fn block_download(&mut self, index: u16, sub: u8, data: &[u8]) -> Result<(), SdoError> {
+        let mut request = self.initiate(CCS_BLOCK_DOWNLOAD << 5 | BLOCK_CRC | 0x02 | BLOCK_INITIATE, index, sub);
+        request[4..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.send(request)?;
+
+        let response = self.expect(SCS_BLOCK_DOWNLOAD, true)?;
+        if response[0] & 0x03 != BLOCK_INITIATE {
+            return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+        }
+        let crc = response[0] & BLOCK_CRC != 0;
+        let mut block_size = response[4];
+
+        let segments: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(7).collect() };
+        let mut next = 0;
+        while next < segments.len() {
+            if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+                return Err(self.abort(SdoAbortCode::InvalidBlockSize));
+            }
+
+            let count = (block_size as usize).min(segments.len() - next);
+            for seq in 1..=count {
+                let i = next + seq - 1;
+                let last = if i + 1 == segments.len() { BLOCK_LAST_SEGMENT } else { 0 };
+                let mut request = [last | seq as u8, 0, 0, 0, 0, 0, 0, 0];
+                request[1..1 + segments[i].len()].copy_from_slice(segments[i]);
+                self.send(request)?;
+            }
+
+            // segments after the acknowledged one are sent again
+            let response = self.expect(SCS_BLOCK_DOWNLOAD, false)?;
+            if response[0] & 0x03 != BLOCK_ACK {
+                return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+            }
+            if response[1] as usize > count {
+                return Err(self.abort(SdoAbortCode::InvalidSequenceNumber));
+            }
+            next += response[1] as usize;
+            block_size = response[2];
+        }
+
+        let unused = (7 - segments[segments.len() - 1].len()) as u8;
+        let crc = if crc { crc16(data) } else { 0 }.to_le_bytes();
+        self.send([CCS_BLOCK_DOWNLOAD << 5 | unused << 2 | BLOCK_END, crc[0], crc[1], 0, 0, 0, 0, 0])?;
+
+        let response = self.expect(SCS_BLOCK_DOWNLOAD, false)?;
+        if response[0] & 0x03 != BLOCK_END {
+            return Err(self.abort(SdoAbortCode::InvalidCommandSpecifier));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Replays the frames of a server, recording the requests.
+    struct Script {
+        responses: VecDeque<[u8; 8]>,
+        requests: Vec<[u8; 8]>,
+    }
+
+    impl CanOpenTransport for Script {
+        fn send(&mut self, frame: &CanFrame) -> io::Result<()> {
+            assert_eq!(frame.id(), 0x60a);
+            let mut data = [0u8; 8];
+            data.copy_from_slice(frame.data());
+            self.requests.push(data);
+            Ok(())
+        }
+
+        fn recv(&mut self, _timeout: Duration) -> io::Result<CanFrame> {
+            let data = self.responses.pop_front().ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+            Ok(CanFrame::new(CanMessageId::SFF(0x58a), &data, false, false).unwrap())
+        }
+    }
+
+    fn client(responses: &[[u8; 8]]) -> SdoClient<Script> {
+        SdoClient::new(Script {
+                           responses: responses.iter().cloned().collect(),
+                           requests: Vec::new(),
+                       },
+                       0x0a)
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn test_expedited() {
+        let mut sdo = client(&[[0x4b, 0x17, 0x10, 0x00, 0xe8, 0x03, 0, 0], [0x60, 0x17, 0x10, 0x00, 0, 0, 0, 0]]);
+        assert_eq!(sdo.upload(0x1017, 0).unwrap(), vec![0xe8, 0x03]);
+        sdo.download(0x1017, 0, &[0xd0, 0x07]).unwrap();
+
+        let requests = &sdo.transport().requests;
+        assert_eq!(requests[0], [0x40, 0x17, 0x10, 0x00, 0, 0, 0, 0]);
+        assert_eq!(requests[1], [0x2b, 0x17, 0x10, 0x00, 0xd0, 0x07, 0, 0]);
+    }
+
+    #[test]
+    fn test_segmented() {
+        let mut sdo = client(&[[0x41, 0x08, 0x10, 0x00, 10, 0, 0, 0],
+                               [0x00, b'C', b'A', b'N', b'o', b'p', b'e', b'n'],
+                               [0x19, b' ', b'I', b'O', 0, 0, 0, 0],
+                               [0x60, 0x08, 0x10, 0x00, 0, 0, 0, 0],
+                               [0x20, 0, 0, 0, 0, 0, 0, 0],
+                               [0x30, 0, 0, 0, 0, 0, 0, 0]]);
+        assert_eq!(sdo.upload(0x1008, 0).unwrap(), b"CANopen IO");
+        sdo.download(0x1008, 0, b"CANopen IO").unwrap();
+
+        let requests = &sdo.transport().requests;
+        assert_eq!(requests[1][0], 0x60);
+        assert_eq!(requests[2][0], 0x70);
+        assert_eq!(requests[3], [0x21, 0x08, 0x10, 0x00, 10, 0, 0, 0]);
+        assert_eq!(requests[4], [0x00, b'C', b'A', b'N', b'o', b'p', b'e', b'n']);
+        assert_eq!(requests[5], [0x19, b' ', b'I', b'O', 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_aborts() {
+        let mut sdo = client(&[[0x80, 0x00, 0x20, 0x00, 0x00, 0x00, 0x02, 0x06]]);
+        match sdo.upload(0x2000, 0) {
+            Err(SdoError::Abort(SdoAbortCode::ObjectDoesNotExist)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(SdoAbortCode::from(0x0602_0000).to_string(),
+                   "object does not exist in the object dictionary");
+
+        // the toggle bit of the second segment is wrong
+        let mut sdo = client(&[[0x41, 0x08, 0x10, 0x00, 0, 0, 0, 0], [0x10, 0, 0, 0, 0, 0, 0, 0]]);
+        match sdo.upload(0x1008, 0) {
+            Err(SdoError::LocalAbort(SdoAbortCode::ToggleBitNotAlternated)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(sdo.transport().requests[2], [0x80, 0x08, 0x10, 0x00, 0x00, 0x00, 0x03, 0x05]);
+
+        // timeouts are aborted as well
+        let mut sdo = client(&[]);
+        match sdo.download(0x1017, 0, &[0]) {
+            Err(SdoError::Timeout) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(sdo.transport().requests[1], [0x80, 0x17, 0x10, 0x00, 0x00, 0x00, 0x04, 0x05]);
+    }
+
+    #[test]
+    fn test_block_download() {
+        let data: Vec<u8> = (0..20).collect();
+        let crc = crc16(&data).to_le_bytes();
+        let mut sdo = client(&[[0xa4, 0x00, 0x1f, 0x01, 2, 0, 0, 0],
+                               // the second segment is lost
+                               [0xa2, 1, 2, 0, 0, 0, 0, 0],
+                               [0xa2, 2, 2, 0, 0, 0, 0, 0],
+                               [0xa1, 0, 0, 0, 0, 0, 0, 0]]);
+        sdo.block_download(0x1f00, 1, &data).unwrap();
+
+        let requests = &sdo.transport().requests;
+        assert_eq!(requests[0], [0xc6, 0x00, 0x1f, 0x01, 20, 0, 0, 0]);
+        assert_eq!(requests[1], [0x01, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(requests[2], [0x02, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(requests[3], [0x01, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(requests[4], [0x82, 14, 15, 16, 17, 18, 19, 0]);
+        assert_eq!(requests[5], [0xc5, crc[0], crc[1], 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_block_upload() {
+        let data: Vec<u8> = (0..10).collect();
+        let crc = crc16(&data).to_le_bytes();
+        let mut sdo = client(&[[0xc6, 0x00, 0x1f, 0x01, 10, 0, 0, 0],
+                               [0x01, 0, 1, 2, 3, 4, 5, 6],
+                               [0x82, 7, 8, 9, 0, 0, 0, 0],
+                               [0xd1, crc[0], crc[1], 0, 0, 0, 0, 0]]);
+        sdo.set_block_size(4);
+        assert_eq!(sdo.block_upload(0x1f00, 1).unwrap(), data);
+
+        let requests = &sdo.transport().requests;
+        assert_eq!(requests[0], [0xa4, 0x00, 0x1f, 0x01, 4, 0, 0, 0]);
+        assert_eq!(requests[1][0], 0xa3);
+        assert_eq!(requests[2], [0xa2, 2, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(requests[3][0], 0xa1);
+    }
+}
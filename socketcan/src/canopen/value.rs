@@ -0,0 +1,343 @@
+//! Data types and values of the object dictionary
+
+use std::fmt;
+
+use super::parse_integer;
+
+/// Basic data types, as used by the `DataType` entries of EDS files
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DataType {
+    Boolean,
+    Integer8,
+    Integer16,
+    Integer24,
+    Integer32,
+    Integer40,
+    Integer48,
+    Integer56,
+    Integer64,
+    Unsigned8,
+    Unsigned16,
+    Unsigned24,
+    Unsigned32,
+    Unsigned40,
+    Unsigned48,
+    Unsigned56,
+    Unsigned64,
+    Real32,
+    Real64,
+    VisibleString,
+    OctetString,
+    UnicodeString,
+    Domain,
+    /// complex and manufacturer specific types, handled as bytes
+    Other(u16),
+}
+
+impl From<u16> for DataType {
+    fn from(index: u16) -> DataType {
+        use self::DataType::*;
+
+        match index {
+            0x0001 => Boolean,
+            0x0002 => Integer8,
+            0x0003 => Integer16,
+            0x0004 => Integer32,
+            0x0005 => Unsigned8,
+            0x0006 => Unsigned16,
+            0x0007 => Unsigned32,
+            0x0008 => Real32,
+            0x0009 => VisibleString,
+            0x000a => OctetString,
+            0x000b => UnicodeString,
+            0x000f => Domain,
+            0x0010 => Integer24,
+            0x0011 => Real64,
+            0x0012 => Integer40,
+            0x0013 => Integer48,
+            0x0014 => Integer56,
+            0x0015 => Integer64,
+            0x0016 => Unsigned24,
+            0x0018 => Unsigned40,
+            0x0019 => Unsigned48,
+            0x001a => Unsigned56,
+            0x001b => Unsigned64,
+            index => Other(index),
+        }
+    }
+}
+
+impl From<DataType> for u16 {
+    fn from(data_type: DataType) -> u16 {
+        use self::DataType::*;
+
+        match data_type {
+            Boolean => 0x0001,
+            Integer8 => 0x0002,
+            Integer16 => 0x0003,
+            Integer32 => 0x0004,
+            Unsigned8 => 0x0005,
+            Unsigned16 => 0x0006,
+            Unsigned32 => 0x0007,
+            Real32 => 0x0008,
+            VisibleString => 0x0009,
+            OctetString => 0x000a,
+            UnicodeString => 0x000b,
+            Domain => 0x000f,
+            Integer24 => 0x0010,
+            Real64 => 0x0011,
+            Integer40 => 0x0012,
+            Integer48 => 0x0013,
+            Integer56 => 0x0014,
+            Integer64 => 0x0015,
+            Unsigned24 => 0x0016,
+            Unsigned40 => 0x0018,
+            Unsigned48 => 0x0019,
+            Unsigned56 => 0x001a,
+            Unsigned64 => 0x001b,
+            Other(index) => index,
+        }
+    }
+}
+
+impl DataType {
+    /// The size of numeric types in bits, `None` for strings and bytes
+    pub fn bits(self) -> Option<u32> {
+        use self::DataType::*;
+
+        match self {
+            Boolean => Some(1),
+            Integer8 | Unsigned8 => Some(8),
+            Integer16 | Unsigned16 => Some(16),
+            Integer24 | Unsigned24 => Some(24),
+            Integer32 | Unsigned32 | Real32 => Some(32),
+            Integer40 | Unsigned40 => Some(40),
+            Integer48 | Unsigned48 => Some(48),
+            Integer56 | Unsigned56 => Some(56),
+            Integer64 | Unsigned64 | Real64 => Some(64),
+            VisibleString | OctetString | UnicodeString | Domain | Other(_) => None,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        use self::DataType::*;
+
+        match self {
+            Integer8 | Integer16 | Integer24 | Integer32 | Integer40 | Integer48 | Integer56 | Integer64 => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_unsigned(self) -> bool {
+        use self::DataType::*;
+
+        match self {
+            Unsigned8 | Unsigned16 | Unsigned24 | Unsigned32 | Unsigned40 | Unsigned48 | Unsigned56 | Unsigned64 => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A typed value of an object
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Integer(i64),
+    Unsigned(u64),
+    Real32(f32),
+    Real64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+/// Sign extend the lower `bits` of `raw`, 0 if there are none.
+fn sign_extend(raw: u64, bits: u32) -> i64 {
+    if bits == 0 {
+        return 0;
+    }
+    let shift = 64 - bits;
+    ((raw << shift) as i64) >> shift
+}
+
+/// The smallest and largest value of an integer type.
+fn integer_range(data_type: DataType) -> Option<(i128, i128)> {
+    let bits = data_type.bits()?;
+    if data_type.is_signed() {
+        Some((-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1))
+    } else if data_type.is_unsigned() {
+        Some((0, (1i128 << bits) - 1))
+    } else {
+        None
+    }
+}
+
+impl Value {
+    /// Decode the little endian representation of a value, `None` if
+    /// `data` is too short for the type.
+    pub fn decode(data_type: DataType, data: &[u8]) -> Option<Value> {
+        let value = match data_type {
+            DataType::VisibleString => {
+                let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                Value::String(String::from_utf8_lossy(&data[..end]).into_owned())
+            }
+            DataType::UnicodeString => {
+                let chars: Vec<u16> = data.chunks(2)
+                    .filter(|c| c.len() == 2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect();
+                Value::String(String::from_utf16_lossy(&chars))
+            }
+            DataType::OctetString | DataType::Domain | DataType::Other(_) => Value::Bytes(data.to_vec()),
+            data_type => {
+                let bits = data_type.bits()?;
+                let len = ((bits + 7) / 8) as usize;
+                if data.len() < len {
+                    return None;
+                }
+                let mut buf = [0u8; 8];
+                buf[..len].copy_from_slice(&data[..len]);
+                Value::from_raw(data_type, u64::from_le_bytes(buf), bits)
+            }
+        };
+        Some(value)
+    }
+
+    /// The value of the lower `bits` of `raw`, as mapped into PDOs.
+    pub fn from_raw(data_type: DataType, raw: u64, bits: u32) -> Value {
+        match data_type {
+            DataType::Boolean => Value::Boolean(raw & 0x01 != 0),
+            DataType::Real32 => Value::Real32(f32::from_bits(raw as u32)),
+            DataType::Real64 => Value::Real64(f64::from_bits(raw)),
+            t if t.is_signed() => Value::Integer(sign_extend(raw, bits.min(t.bits().unwrap_or(64)))),
+            t if t.is_unsigned() => Value::Unsigned(raw),
+            t => Value::decode(t, &raw.to_le_bytes()[..(bits / 8) as usize]).unwrap_or(Value::Bytes(Vec::new())),
+        }
+    }
+
+    /// Encode the value as `data_type`, `None` if it does not fit.
+    ///
+    /// Integers and unsigned integers are converted into each other, if in
+    /// range.
+    pub fn encode(&self, data_type: DataType) -> Option<Vec<u8>> {
+        let data = match (data_type, self) {
+            (DataType::Boolean, Value::Boolean(b)) => vec![*b as u8],
+            (DataType::Real32, Value::Real32(f)) => f.to_bits().to_le_bytes().to_vec(),
+            (DataType::Real64, Value::Real64(f)) => f.to_bits().to_le_bytes().to_vec(),
+            (DataType::VisibleString, Value::String(s)) => s.as_bytes().to_vec(),
+            (DataType::UnicodeString, Value::String(s)) => {
+                s.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+            }
+            (DataType::OctetString, Value::Bytes(b))
+            | (DataType::Domain, Value::Bytes(b))
+            | (DataType::Other(_), Value::Bytes(b)) => b.clone(),
+            (t, v) if t.is_signed() || t.is_unsigned() => {
+                let bits = t.bits()?;
+                let n = match *v {
+                    Value::Integer(i) => i128::from(i),
+                    Value::Unsigned(u) => i128::from(u),
+                    _ => return None,
+                };
+                let (min, max) = integer_range(t)?;
+                if n < min || n > max {
+                    return None;
+                }
+                (n as u64).to_le_bytes()[..(bits / 8) as usize].to_vec()
+            }
+            _ => return None,
+        };
+        Some(data)
+    }
+
+    /// Parse a value of an EDS file, in which integers are decimal,
+    /// hexadecimal with `0x` or octal with a leading `0`, and may add
+    /// `$NODEID`. `None` if an integer is out of the range of its type.
+    pub fn parse(data_type: DataType, s: &str, node: u8) -> Option<Value> {
+        let s = s.trim();
+        let value = match data_type {
+            DataType::Boolean => Value::Boolean(parse_integer(s, node)? != 0),
+            DataType::Real32 => Value::Real32(s.parse().ok()?),
+            DataType::Real64 => Value::Real64(s.parse().ok()?),
+            DataType::VisibleString | DataType::UnicodeString => Value::String(s.to_string()),
+            t if t.is_signed() || t.is_unsigned() => {
+                let n = parse_integer(s, node)?;
+                let (min, max) = integer_range(t)?;
+                if n < min || n > max {
+                    return None;
+                }
+                if t.is_signed() { Value::Integer(n as i64) } else { Value::Unsigned(n as u64) }
+            }
+            _ => {
+                let hex: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+                if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return None;
+                }
+                let bytes = (0..hex.len()).step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()?;
+                Value::Bytes(bytes)
+            }
+        };
+        Some(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Unsigned(u) => write!(f, "{}", u),
+            Value::Real32(r) => write!(f, "{}", r),
+            Value::Real64(r) => write!(f, "{}", r),
+            Value::String(ref s) => write!(f, "{}", s),
+            Value::Bytes(ref b) => {
+                for byte in b {
+                    write!(f, "{:02X}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_encode() {
+        assert_eq!(Value::decode(DataType::Integer16, &[0xfe, 0xff]), Some(Value::Integer(-2)));
+        assert_eq!(Value::decode(DataType::Unsigned24, &[1, 2, 3, 4]), Some(Value::Unsigned(0x030201)));
+        assert_eq!(Value::decode(DataType::Unsigned32, &[1, 2]), None);
+        assert_eq!(Value::decode(DataType::VisibleString, b"node\0\0"), Some(Value::String("node".into())));
+        assert_eq!(Value::from_raw(DataType::Integer8, 0xff, 0), Value::Integer(0));
+
+        assert_eq!(Value::Integer(-2).encode(DataType::Integer16), Some(vec![0xfe, 0xff]));
+        assert_eq!(Value::Integer(300).encode(DataType::Unsigned8), None);
+        assert_eq!(Value::Unsigned(300).encode(DataType::Unsigned16), Some(vec![0x2c, 0x01]));
+        assert_eq!(Value::Real32(1.0).encode(DataType::Real32), Some(vec![0, 0, 0x80, 0x3f]));
+        assert_eq!(Value::Boolean(true).encode(DataType::Unsigned8), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Value::parse(DataType::Unsigned32, "$NODEID+0x180", 5), Some(Value::Unsigned(0x185)));
+        assert_eq!(Value::parse(DataType::Unsigned32, "0x80000000+$NodeId", 5),
+                   Some(Value::Unsigned(0x8000_0005)));
+        assert_eq!(Value::parse(DataType::Integer8, "-12", 0), Some(Value::Integer(-12)));
+        assert_eq!(Value::parse(DataType::Unsigned8, "010", 0), Some(Value::Unsigned(8)));
+        assert_eq!(Value::parse(DataType::OctetString, "01 AB", 0), Some(Value::Bytes(vec![0x01, 0xab])));
+        assert_eq!(Value::parse(DataType::Unsigned8, "x", 0), None);
+        assert_eq!(Value::parse(DataType::Unsigned8, "-1", 0), None);
+        assert_eq!(Value::parse(DataType::Unsigned8, "0x100", 0), None);
+        assert_eq!(Value::parse(DataType::Integer8, "-129", 0), None);
+        assert_eq!(Value::parse(DataType::Unsigned64, "0xffffffffffffffff", 0),
+                   Some(Value::Unsigned(u64::max_value())));
+        assert_eq!(Value::parse(DataType::OctetString, "a\u{e9}b", 0), None);
+        assert_eq!(Value::parse(DataType::Unsigned64, "0x7fffffffffffffffffffffffffffffff+1", 0), None);
+        assert_eq!(Value::parse(DataType::Integer64, "--170141183460469231731687303715884105728", 0), None);
+    }
+}
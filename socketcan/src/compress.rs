@@ -0,0 +1,133 @@
+//! Transparent decompression of logs
+//!
+//! Compressed logs are recognized by their magic bytes rather than the file
+//! extension, anything else is passed through unchanged. gzip is always
+//! supported, xz and zstd depend on the optional `xz2` and `zstd`
+//! features. They are not enabled by default, as they build liblzma and
+//! libzstd from C.
+//!
+//! ```no_run
+//! use socketcan::{compress, dump};
+//!
+//! let mut reader = dump::Reader::new(compress::open("candump.log.gz").unwrap());
+//! for record in reader.records() {
+//!     println!("{:?}", record.unwrap());
+//! }
+//! ```
+
+use flate2::bufread::MultiGzDecoder;
+use std::{fs, io, path};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression formats that are recognized
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression from the first bytes of a file.
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(XZ_MAGIC) {
+            Compression::Xz
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wraps `rdr` in a decoder matching its compression, if any.
+///
+/// Concatenated gzip members and xz streams are read as one, as are multiple
+/// zstd frames. Fails with `InvalidInput` if support for the detected
+/// compression was not compiled in.
+pub fn decompress<R>(mut rdr: R) -> io::Result<Box<dyn io::BufRead + Send>>
+    where R: io::BufRead + Send + 'static
+{
+    let compression = Compression::detect(rdr.fill_buf()?);
+
+    match compression {
+        Compression::None => Ok(Box::new(rdr)),
+        Compression::Gzip => Ok(Box::new(io::BufReader::new(MultiGzDecoder::new(rdr)))),
+        #[cfg(feature = "xz2")]
+        Compression::Xz => Ok(Box::new(io::BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(rdr)))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(io::BufReader::new(zstd::stream::read::Decoder::with_buffer(rdr)?))),
+        #[allow(unreachable_patterns)]
+        _ => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               format!("support for {:?} compressed logs is not enabled", compression)))
+        }
+    }
+}
+
+/// Opens a file, decompressing it if necessary.
+pub fn open<P: AsRef<path::Path>>(path: P) -> io::Result<Box<dyn io::BufRead + Send>> {
+    decompress(io::BufReader::new(fs::File::open(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress, Compression};
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+
+    const LOG: &[u8] = b"(1469439874.299591) can1 080#\n\
+                         (1469439874.299654) can1 701#7F\n";
+
+    fn read_all(compressed: Vec<u8>) -> Vec<u8> {
+        let mut out = Vec::new();
+        decompress(::std::io::Cursor::new(compressed)).unwrap().read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(Compression::detect(LOG), Compression::None);
+        assert_eq!(read_all(LOG.to_vec()), LOG);
+        assert_eq!(read_all(Vec::new()), b"");
+    }
+
+    #[test]
+    fn test_gzip_members() {
+        let mut compressed = Vec::new();
+        for chunk in LOG.chunks(20) {
+            let mut enc = GzEncoder::new(Vec::new(), ::flate2::Compression::default());
+            enc.write_all(chunk).unwrap();
+            compressed.extend(enc.finish().unwrap());
+        }
+
+        assert_eq!(Compression::detect(&compressed), Compression::Gzip);
+        assert_eq!(read_all(compressed), LOG);
+    }
+
+    #[cfg(feature = "xz2")]
+    #[test]
+    fn test_xz() {
+        let mut enc = ::xz2::write::XzEncoder::new(Vec::new(), 6);
+        enc.write_all(LOG).unwrap();
+        let compressed = enc.finish().unwrap();
+
+        assert_eq!(Compression::detect(&compressed), Compression::Xz);
+        assert_eq!(read_all(compressed), LOG);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let compressed = ::zstd::encode_all(LOG, 3).unwrap();
+
+        assert_eq!(Compression::detect(&compressed), Compression::Zstd);
+        assert_eq!(read_all(compressed), LOG);
+    }
+}
@@ -9,12 +9,13 @@
 //! (1469439874.299654) can1 701#7F
 //! ```
 //!
-//! Can be parsed by a `Reader` object. The API is inspired by the
-//! [csv](https://crates.io/crates/csv) crate.
+//! Can be parsed by a `Reader` object and written by a `Writer` object. The
+//! API is inspired by the [csv](https://crates.io/crates/csv) crate.
 
-use crate::CanMessageId;
-use std::{fs, io, path};
-use std::convert::TryFrom;
+use crate::{CanFdFrame, CanFrame, CanMessageId, CanXlFrame, CANXL_PRIO_MASK, EFF_MASK, ERR_MASK};
+use crate::{FdFlags, FrameFlags, XlFlags};
+use crate::compress;
+use std::{error, fmt, fs, io, path};
 use hex::FromHex;
 
 // cannot be generic, because from_str_radix is not part of any Trait
@@ -46,18 +47,141 @@ impl Reader<fs::File> {
     }
 }
 
+impl Reader<Box<dyn io::BufRead + Send>> {
+    /// Open a log file, which may be compressed (see `compress`).
+    pub fn open<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<Box<dyn io::BufRead + Send>>> {
+        Ok(Reader::new(compress::open(path)?))
+    }
+}
+
 /// Record iterator
 #[derive(Debug)]
 pub struct CanDumpRecords<'a, R: 'a> {
     src: &'a mut Reader<R>,
 }
 
+/// Owned record iterator, see `Reader::into_owned_records`
+#[derive(Debug)]
+pub struct OwnedCanDumpRecords<R> {
+    src: Reader<R>,
+}
+
+/// A frame as found in a candump log.
+#[derive(Debug, Clone)]
+pub enum CanDumpFrame {
+    /// A classic CAN frame, including remote frames
+    Classic(CanFrame),
+
+    /// A CAN FD frame
+    Fd(CanFdFrame),
+
+    /// An error frame, see `CanFrame::error`
+    Error(CanFrame),
+
+    /// A CAN XL frame
+    Xl(Box<CanXlFrame>),
+}
+
+impl CanDumpFrame {
+    /// The CAN ID (without flags), or the priority for CAN XL frames. For
+    /// error frames this is the error class.
+    pub fn id(&self) -> u32 {
+        match *self {
+            CanDumpFrame::Classic(ref frame) => frame.id(),
+            CanDumpFrame::Fd(ref frame) => frame.id(),
+            CanDumpFrame::Error(ref frame) => frame.err(),
+            CanDumpFrame::Xl(ref frame) => frame.prio(),
+        }
+    }
+
+    /// The frame payload
+    pub fn data(&self) -> &[u8] {
+        match *self {
+            CanDumpFrame::Classic(ref frame) | CanDumpFrame::Error(ref frame) => frame.data(),
+            CanDumpFrame::Fd(ref frame) => frame.data(),
+            CanDumpFrame::Xl(ref frame) => frame.data(),
+        }
+    }
+
+    /// Check if the frame uses a 29 bit identifier
+    pub fn is_extended(&self) -> bool {
+        match *self {
+            CanDumpFrame::Classic(ref frame) => frame.is_extended(),
+            CanDumpFrame::Fd(ref frame) => frame.is_extended(),
+            _ => false,
+        }
+    }
+
+    /// Check if the frame is a remote transmission request
+    pub fn is_rtr(&self) -> bool {
+        match *self {
+            CanDumpFrame::Classic(ref frame) => frame.is_rtr(),
+            _ => false,
+        }
+    }
+
+    /// Check if the frame is an error frame
+    pub fn is_error(&self) -> bool {
+        match *self {
+            CanDumpFrame::Error(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Whether a frame was received or sent by the logging device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
 /// Recorded CAN frame.
+///
+/// The device name is borrowed from the reader. Use `OwnedCanDumpRecord` to
+/// keep records around or pass them to other threads.
 #[derive(Debug)]
 pub struct CanDumpRecord<'a> {
     pub t_us: u64,
     pub device: &'a str,
-    pub frame: super::CanFrame,
+    pub frame: CanDumpFrame,
+
+    /// Direction, if the log records it
+    pub direction: Option<Direction>,
+}
+
+/// Recorded CAN frame, owning the device name.
+#[derive(Debug, Clone)]
+pub struct OwnedCanDumpRecord {
+    pub t_us: u64,
+    pub device: String,
+    pub frame: CanDumpFrame,
+
+    /// Direction, if the log records it
+    pub direction: Option<Direction>,
+}
+
+impl OwnedCanDumpRecord {
+    /// Borrow as a `CanDumpRecord`, e.g. to pass it to a writer.
+    pub fn as_record(&self) -> CanDumpRecord<'_> {
+        CanDumpRecord {
+            t_us: self.t_us,
+            device: &self.device,
+            frame: self.frame.clone(),
+            direction: self.direction,
+        }
+    }
+}
+
+impl<'a> From<CanDumpRecord<'a>> for OwnedCanDumpRecord {
+    fn from(record: CanDumpRecord<'a>) -> OwnedCanDumpRecord {
+        OwnedCanDumpRecord {
+            t_us: record.t_us,
+            device: record.device.to_owned(),
+            frame: record.frame,
+            direction: record.direction,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -68,9 +192,26 @@ pub enum ParseError {
     InvalidTimestamp,
     InvalidDeviceName,
     InvalidCanFrame,
+    InvalidHeader,
     ConstructionError(super::ConstructionError),
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Io(ref e) => write!(f, "{}", e),
+            ParseError::UnexpectedEndOfLine => write!(f, "unexpected end of line"),
+            ParseError::InvalidTimestamp => write!(f, "invalid timestamp"),
+            ParseError::InvalidDeviceName => write!(f, "invalid device name"),
+            ParseError::InvalidCanFrame => write!(f, "invalid CAN frame"),
+            ParseError::InvalidHeader => write!(f, "invalid header"),
+            ParseError::ConstructionError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
 impl From<io::Error> for ParseError {
     fn from(e: io::Error) -> ParseError {
         ParseError::Io(e)
@@ -83,90 +224,223 @@ impl From<super::ConstructionError> for ParseError {
     }
 }
 
+/// Splits off a trailing `_<dlc>` raw DLC, which candump appends for
+/// frames with eight bytes of data but a DLC above 8. It is not kept.
+fn strip_raw_dlc(raw: &[u8]) -> Result<&[u8], ParseError> {
+    match raw.iter().position(|&c| c == b'_') {
+        Some(idx) => {
+            match parse_raw(&raw[idx + 1..], 16) {
+                Some(9..=15) => Ok(&raw[..idx]),
+                _ => Err(ParseError::InvalidCanFrame),
+            }
+        }
+        None => Ok(raw),
+    }
+}
+
+/// Parses hex encoded data bytes, which may be separated by dots.
+fn parse_data(raw: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut digits = Vec::with_capacity(raw.len());
+    for chunk in raw.split(|&c| c == b'.') {
+        if chunk.len() % 2 != 0 {
+            return Err(ParseError::InvalidCanFrame);
+        }
+        digits.extend_from_slice(chunk);
+    }
+
+    Vec::from_hex(&digits).map_err(|_| ParseError::InvalidCanFrame)
+}
+
+/// Parses the frame field of a candump line, following `parse_canframe()`
+/// of can-utils:
+///
+/// ```text
+/// 123#11.22.33          classic frame, 3 digits for a standard id
+/// 12345678#R3           remote frame with DLC, 8 digits for an extended id
+/// 20000004#0004000000000000
+///                       error frame, the error flag is set in the id
+/// 123##1112233          CAN FD frame, the digit after ## holds the flags
+/// 45123#81:00:12345678#AABB
+///                       CAN XL frame with VCID, priority, flags, SDT and AF
+/// ```
+fn parse_frame(raw: &[u8]) -> Result<CanDumpFrame, ParseError> {
+    let sep_idx = raw.iter()
+        .position(|&c| c == b'#')
+        .ok_or(ParseError::InvalidCanFrame)?;
+    let (can_id, rest) = (&raw[..sep_idx], &raw[sep_idx + 1..]);
+
+    // the number of digits determines the kind of id
+    let id = parse_raw(can_id, 16).ok_or(ParseError::InvalidCanFrame)? as u32;
+    let message_id = match can_id.len() {
+        3 => CanMessageId::SFF(id as u16),
+        5 => return parse_xl_frame(id, rest),
+        8 if id & FrameFlags::ERR_FLAG.bits() != 0 => {
+            let data = parse_data(strip_raw_dlc(rest)?)?;
+            return Ok(CanDumpFrame::Error(CanFrame::new_error(id & ERR_MASK, &data)?));
+        }
+        8 if id <= EFF_MASK => CanMessageId::EFF(id),
+        _ => return Err(ParseError::InvalidCanFrame),
+    };
+
+    match rest.first() {
+        Some(&b'#') => {
+            let flags = rest.get(1)
+                .and_then(|&c| (c as char).to_digit(16))
+                .ok_or(ParseError::InvalidCanFrame)?;
+            let data = parse_data(&rest[2..])?;
+            let frame = CanFdFrame::new(message_id, &data, FdFlags::from_bits_truncate(flags as u8))?;
+            Ok(CanDumpFrame::Fd(frame))
+        }
+        Some(&b'R') | Some(&b'r') => {
+            // the length of the requested data, if given
+            let dlc = match strip_raw_dlc(&rest[1..])? {
+                b"" => 0,
+                dlc => parse_raw(dlc, 10).ok_or(ParseError::InvalidCanFrame)? as usize,
+            };
+            if dlc > 8 {
+                return Err(ParseError::InvalidCanFrame);
+            }
+            Ok(CanDumpFrame::Classic(CanFrame::new(message_id, &[0; 8][..dlc], true, false)?))
+        }
+        _ => {
+            let data = parse_data(strip_raw_dlc(rest)?)?;
+            Ok(CanDumpFrame::Classic(CanFrame::new(message_id, &data, false, false)?))
+        }
+    }
+}
+
+fn parse_xl_frame(id: u32, raw: &[u8]) -> Result<CanDumpFrame, ParseError> {
+    // <flags>:<sdt>:<af>#<data>
+    let sep_idx = raw.iter()
+        .position(|&c| c == b'#')
+        .ok_or(ParseError::InvalidCanFrame)?;
+    let mut header = raw[..sep_idx].split(|&c| c == b':');
+    let mut field = |len| {
+        header.next()
+            .filter(|f| f.len() == len)
+            .and_then(|f| parse_raw(f, 16))
+            .ok_or(ParseError::InvalidCanFrame)
+    };
+    let flags = field(2)? as u8;
+    let sdt = field(2)? as u8;
+    let af = field(8)? as u32;
+
+    let data = parse_data(&raw[sep_idx + 1..])?;
+    if data.is_empty() || id & !(CANXL_PRIO_MASK | 0xff000) != 0 {
+        return Err(ParseError::InvalidCanFrame);
+    }
+
+    // the VCID is written in front of the three priority digits
+    let prio = (id & CANXL_PRIO_MASK) | ((id & 0xff000) << 4);
+    let frame = CanXlFrame::new(prio, sdt, af, &data, XlFlags::from_bits_truncate(flags))?;
+    Ok(CanDumpFrame::Xl(Box::new(frame)))
+}
+
 impl<R: io::BufRead> Reader<R> {
+    /// Create a reader on top of a buffered reader, e.g. a decompressor.
+    pub fn new(rdr: R) -> Reader<R> {
+        Reader {
+            rdr: rdr,
+            line_buf: Vec::new(),
+        }
+    }
+
     /// Returns an iterator over all records
-    pub fn records(&mut self) -> CanDumpRecords<R> {
+    pub fn records(&mut self) -> CanDumpRecords<'_, R> {
         CanDumpRecords { src: self }
     }
 
-    /// Advance state, returning next record.
-    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord>, ParseError> {
-        self.line_buf.clear();
-        let bytes_read = self.rdr.read_until(b'\n', &mut self.line_buf)?;
+    /// Returns an iterator over owned records, consuming the reader.
+    pub fn into_owned_records(self) -> OwnedCanDumpRecords<R> {
+        OwnedCanDumpRecords { src: self }
+    }
 
-        // reached EOF
-        if bytes_read == 0 {
-            return Ok(None);
+    /// Advance state, returning next record.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        // skip empty lines
+        loop {
+            self.line_buf.clear();
+            let bytes_read = self.rdr.read_until(b'\n', &mut self.line_buf)?;
+
+            // reached EOF
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+
+            if self.line_buf.iter().any(|&c| c != b'\n' && c != b'\r') {
+                break;
+            }
         }
 
-        let mut field_iter = self.line_buf.split(|&c| c == b' ');
+        parse_line(&self.line_buf)
+    }
+}
 
-        // parse time field
-        let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+/// Parse a single line of a log, returning `None` for blank lines.
+///
+/// This is what `Reader` is built on, for use with other sources of lines.
+pub fn parse_line(line: &[u8]) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+    let mut line = line;
 
-        if f.len() < 3 || f[0] != b'(' || f[f.len() - 1] != b')' {
-            return Err(ParseError::InvalidTimestamp);
-        }
+    // cut off linefeed
+    while let Some(&b'\n') | Some(&b'\r') = line.last() {
+        line = &line[..line.len() - 1];
+    }
 
-        let inner = &f[1..f.len() - 1];
+    if line.is_empty() {
+        return Ok(None);
+    }
 
-        // split at dot, read both parts
-        let dot = inner.iter()
-            .position(|&c| c == b'.')
-            .ok_or(ParseError::InvalidTimestamp)?;
+    let mut field_iter = line.split(|&c| c == b' ');
 
-        let (num, mant) = inner.split_at(dot);
+    // parse time field
+    let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
 
-        // parse number and multiply
-        let n_num: u64 = parse_raw(num, 10).ok_or(ParseError::InvalidTimestamp)?;
-        let n_mant: u64 = parse_raw(&mant[1..], 10).ok_or(ParseError::InvalidTimestamp)?;
-        let t_us = n_num.saturating_mul(1_000_000).saturating_add(n_mant);
+    if f.len() < 3 || f[0] != b'(' || f[f.len() - 1] != b')' {
+        return Err(ParseError::InvalidTimestamp);
+    }
 
-        let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+    let inner = &f[1..f.len() - 1];
 
-        // device name
-        let device = ::std::str::from_utf8(f).map_err(|_| ParseError::InvalidDeviceName)?;
+    // split at dot, read both parts
+    let dot = inner.iter()
+        .position(|&c| c == b'.')
+        .ok_or(ParseError::InvalidTimestamp)?;
 
-        // parse packet
-        let can_raw = field_iter.next()
-            .ok_or(ParseError::UnexpectedEndOfLine)?;
+    let (num, mant) = inner.split_at(dot);
 
-        let sep_idx = can_raw.iter()
-            .position(|&c| c == b'#')
-            .ok_or(ParseError::InvalidCanFrame)?;
-        let (can_id, mut can_data) = can_raw.split_at(sep_idx);
+    // parse number and multiply
+    let n_num: u64 = parse_raw(num, 10).ok_or(ParseError::InvalidTimestamp)?;
+    let n_mant: u64 = parse_raw(&mant[1..], 10).ok_or(ParseError::InvalidTimestamp)?;
+    let t_us = n_num.saturating_mul(1_000_000).saturating_add(n_mant);
 
-        // cut of linefeed and skip seperator
-        can_data = &can_data[1..];
-        if let Some(&b'\n') = can_data.last() {
-            can_data = &can_data[..can_data.len() - 1];
-        };
+    let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
 
-        let rtr = b"R" == can_data;
+    // device name
+    let device = ::std::str::from_utf8(f).map_err(|_| ParseError::InvalidDeviceName)?;
 
-        let data = if rtr {
-            Vec::new()
-        } else {
-            Vec::from_hex(&can_data).map_err(|_| ParseError::InvalidCanFrame)?
-        };
-        let frame = super::CanFrame::new((parse_raw(can_id, 16)
-                                                  .and_then(|id| CanMessageId::try_from(id as u32).ok()))
-                                                  .ok_or(ParseError::InvalidCanFrame)?,
-                                              &data,
-                                              rtr,
-                                              // FIXME: how are error frames saved?
-                                              false)?;
+    // parse packet
+    let can_raw = field_iter.next()
+        .ok_or(ParseError::UnexpectedEndOfLine)?;
+    let frame = parse_frame(can_raw)?;
 
-        Ok(Some(CanDumpRecord {
-            t_us: t_us,
-            device: device,
-            frame: frame,
-        }))
-    }
+    // optional direction, as logged by `candump -x`
+    let direction = match field_iter.next() {
+        Some(b"R") => Some(Direction::Rx),
+        Some(b"T") => Some(Direction::Tx),
+        _ => None,
+    };
+
+    Ok(Some(CanDumpRecord {
+        t_us: t_us,
+        device: device,
+        frame: frame,
+        direction: direction,
+    }))
 }
 
-impl<'a, R: io::Read> Iterator for CanDumpRecords<'a, io::BufReader<R>> {
-    type Item = Result<(u64, super::CanFrame), ParseError>;
+impl<'a, R: io::BufRead> Iterator for CanDumpRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // lift Option:
@@ -178,9 +452,147 @@ impl<'a, R: io::Read> Iterator for CanDumpRecords<'a, io::BufReader<R>> {
     }
 }
 
+impl<R: io::BufRead> Iterator for OwnedCanDumpRecords<R> {
+    type Item = Result<OwnedCanDumpRecord, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.src.next_record() {
+            Ok(Some(record)) => Some(Ok(record.into())),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A CAN log writer.
+///
+/// Writes frames in the format of `candump -l`, which can be read back by
+/// `Reader` and replayed by `canplayer`.
+pub struct Writer<W> {
+    wtr: W,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer { wtr: wtr }
+    }
+
+    /// Write a classic CAN frame, including remote and error frames.
+    pub fn write_frame(&mut self, t_us: u64, device: &str, frame: &CanFrame) -> io::Result<()> {
+        self.write_prefix(t_us, device)?;
+        self.write_classic(frame)?;
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Write a CAN FD frame.
+    pub fn write_fd_frame(&mut self, t_us: u64, device: &str, frame: &CanFdFrame) -> io::Result<()> {
+        self.write_prefix(t_us, device)?;
+        self.write_fd(frame)?;
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Write a CAN XL frame.
+    pub fn write_xl_frame(&mut self, t_us: u64, device: &str, frame: &CanXlFrame) -> io::Result<()> {
+        self.write_prefix(t_us, device)?;
+        self.write_xl(frame)?;
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Write a record as returned by `Reader::next_record`, including its
+    /// direction if known.
+    pub fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        self.write_prefix(record.t_us, record.device)?;
+        match record.frame {
+            CanDumpFrame::Classic(ref frame) | CanDumpFrame::Error(ref frame) => self.write_classic(frame)?,
+            CanDumpFrame::Fd(ref frame) => self.write_fd(frame)?,
+            CanDumpFrame::Xl(ref frame) => self.write_xl(frame)?,
+        }
+
+        match record.direction {
+            Some(Direction::Rx) => self.wtr.write_all(b" R\n"),
+            Some(Direction::Tx) => self.wtr.write_all(b" T\n"),
+            None => self.wtr.write_all(b"\n"),
+        }
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+
+    fn write_prefix(&mut self, t_us: u64, device: &str) -> io::Result<()> {
+        write!(self.wtr, "({:010}.{:06}) {} ", t_us / 1_000_000, t_us % 1_000_000, device)
+    }
+
+    fn write_classic(&mut self, frame: &CanFrame) -> io::Result<()> {
+        if frame.is_error() {
+            write!(self.wtr, "{:08X}#", frame.id_raw() & (ERR_MASK | FrameFlags::ERR_FLAG.bits()))?;
+        } else {
+            self.write_id(frame.id(), frame.is_extended())?;
+            self.wtr.write_all(b"#")?;
+
+            if frame.is_rtr() {
+                // the requested length is only given if non-zero
+                return match frame.data().len() {
+                    0 => write!(self.wtr, "R"),
+                    len => write!(self.wtr, "R{:X}", len),
+                };
+            }
+        }
+
+        self.write_data(frame.data())
+    }
+
+    fn write_fd(&mut self, frame: &CanFdFrame) -> io::Result<()> {
+        self.write_id(frame.id(), frame.is_extended())?;
+        write!(self.wtr, "##{:X}", frame.flags().bits())?;
+        self.write_data(frame.data())
+    }
+
+    fn write_xl(&mut self, frame: &CanXlFrame) -> io::Result<()> {
+        write!(self.wtr,
+               "{:02X}{:03X}#{:02X}:{:02X}:{:08X}#",
+               frame.vcid(),
+               frame.prio(),
+               frame.flags().bits(),
+               frame.sdt(),
+               frame.af())?;
+        self.write_data(frame.data())
+    }
+
+    // the number of digits tells SFF (3) and EFF (8) apart
+    fn write_id(&mut self, id: u32, extended: bool) -> io::Result<()> {
+        if extended {
+            write!(self.wtr, "{:08X}", id & EFF_MASK)
+        } else {
+            write!(self.wtr, "{:03X}", id)
+        }
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        for byte in data {
+            write!(self.wtr, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Reader;
+    use super::{parse_line, CanDumpFrame, OwnedCanDumpRecord, Reader, Writer};
+    use crate::{CanFdFrame, CanFrame, CanMessageId, FdFlags, XlFlags};
 
     #[test]
     fn test_simple_example() {
@@ -215,5 +627,174 @@ mod test {
         assert!(reader.next_record().unwrap().is_none());
     }
 
+    #[test]
+    fn test_write_frames() {
+        let mut writer = Writer::from_writer(Vec::new());
+
+        let sff = CanFrame::new(CanMessageId::SFF(0x80), &[], false, false).unwrap();
+        let eff = CanFrame::new(CanMessageId::EFF(0x123), &[0xde, 0xad], false, false).unwrap();
+        let rtr = CanFrame::new(CanMessageId::SFF(0x7ff), &[0; 3], true, false).unwrap();
+        let err = CanFrame::new(CanMessageId::SFF(0x004), &[0, 0x04, 0, 0, 0, 0, 0, 0], false, true)
+            .unwrap();
+        let fd = CanFdFrame::new(CanMessageId::SFF(0x701), &[0x7f; 9], FdFlags::BRS).unwrap();
+
+        writer.write_frame(1469439874299591, "can1", &sff).unwrap();
+        writer.write_frame(1469439874299654, "can1", &eff).unwrap();
+        writer.write_frame(5, "vcan0", &rtr).unwrap();
+        writer.write_frame(5, "vcan0", &err).unwrap();
+        writer.write_fd_frame(5, "vcan0", &fd).unwrap();
+
+        assert_eq!(::std::str::from_utf8(&writer.into_inner()).unwrap(),
+                   "(1469439874.299591) can1 080#\n\
+                    (1469439874.299654) can1 00000123#DEAD\n\
+                    (0000000000.000005) vcan0 7FF#R3\n\
+                    (0000000000.000005) vcan0 20000004#0004000000000000\n\
+                    (0000000000.000005) vcan0 701##17F7F7F7F7F7F7F7F7F000000\n");
+    }
+
+    #[test]
+    fn test_write_read_roundtrip() {
+        let frame = CanFrame::new(CanMessageId::EFF(0x1234567), &[1, 2, 3], false, false).unwrap();
+
+        let mut writer = Writer::from_writer(Vec::new());
+        writer.write_frame(1469439874299591, "can0", &frame).unwrap();
+        let buf = writer.into_inner();
+
+        let mut reader = Reader::from_reader(&buf[..]);
+        let rec = reader.next_record().unwrap().unwrap();
+        assert_eq!(rec.t_us, 1469439874299591);
+        assert_eq!(rec.device, "can0");
+        assert_eq!(rec.frame.id(), 0x1234567);
+        assert!(rec.frame.is_extended());
+        assert_eq!(rec.frame.data(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_read_frame_kinds() {
+        let input: &[u8] = b"(1.000001) can0 12345678#R3\r\n\
+                             \n\
+                             (1.000002) can0 123#r\n\
+                             (1.000003) can0 20000004#0004000000000000\n\
+                             (1.000004) can0 701##311.22.33 R\n\
+                             (1.000005) can0 00000123#1122334455667788_C\n\
+                             (1.000006) can0 45123#81:00:12345678#AABB\n";
+
+        let frames: Vec<_> = Reader::from_reader(input).records().map(|r| r.unwrap()).collect();
+        assert_eq!(frames.len(), 6);
+
+        match frames[0] {
+            (1000001, CanDumpFrame::Classic(ref frame)) => {
+                assert!(frame.is_rtr());
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x12345678);
+                assert_eq!(frame.data().len(), 3);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        match frames[1] {
+            (1000002, CanDumpFrame::Classic(ref frame)) => {
+                assert!(frame.is_rtr());
+                assert!(!frame.is_extended());
+                assert_eq!(frame.id(), 0x123);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        match frames[2] {
+            (1000003, CanDumpFrame::Error(ref frame)) => {
+                assert!(frame.is_error());
+                assert_eq!(frame.err(), 0x004);
+                assert_eq!(frame.data(), &[0, 0x04, 0, 0, 0, 0, 0, 0]);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        match frames[3] {
+            (1000004, CanDumpFrame::Fd(ref frame)) => {
+                assert!(frame.is_brs());
+                assert!(frame.is_esi());
+                assert_eq!(frame.id(), 0x701);
+                assert_eq!(frame.data(), &[0x11, 0x22, 0x33]);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        match frames[4] {
+            (1000005, CanDumpFrame::Classic(ref frame)) => {
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x123);
+                assert_eq!(frame.data(), &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        match frames[5] {
+            (1000006, CanDumpFrame::Xl(ref frame)) => {
+                assert_eq!(frame.vcid(), 0x45);
+                assert_eq!(frame.prio(), 0x123);
+                assert_eq!(frame.flags(), XlFlags::XLF | XlFlags::SEC);
+                assert_eq!(frame.af(), 0x12345678);
+                assert_eq!(frame.data(), &[0xaa, 0xbb]);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_invalid_frames() {
+        for line in &["(1.0) can0 1234#11",
+                      "(1.0) can0 123#112",
+                      "(1.0) can0 123#R9",
+                      "(1.0) can0 123##G11",
+                      "(1.0) can0 45123#81:00#AA",
+                      "(1.0) can0 123#11_3"] {
+            let mut reader = Reader::from_reader(line.as_bytes());
+            assert!(reader.next_record().is_err(), "{} was accepted", line);
+        }
+    }
 
+    #[test]
+    fn test_write_read_all_kinds() {
+        let input = "(1469439874.299591) can1 00000123#R\n\
+                     (1469439874.299592) can1 20000004#0004000000000000\n\
+                     (1469439874.299593) can1 701##1112233 T\n\
+                     (1469439874.299594) can1 45123#81:00:12345678#AABB\n";
+
+        let mut reader = Reader::from_reader(input.as_bytes());
+        let mut writer = Writer::from_writer(Vec::new());
+        while let Some(record) = reader.next_record().unwrap() {
+            writer.write_record(&record).unwrap();
+        }
+
+        assert_eq!(::std::str::from_utf8(&writer.into_inner()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_owned_records() {
+        let input: &[u8] = b"(1469439874.299591) can1 080#\n\
+                             \r\n\
+                             (1469439874.299654) can0 701#7F T\n";
+
+        // any `BufRead` will do, the records outlive the reader
+        let records: Vec<OwnedCanDumpRecord> = Reader::new(input)
+            .into_owned_records()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].device, "can1");
+        assert_eq!(records[1].device, "can0");
+        assert_eq!(records[1].frame.data(), &[0x7f]);
+
+        let mut wtr = Writer::from_writer(Vec::new());
+        for record in &records {
+            wtr.write_record(&record.as_record()).unwrap();
+        }
+        assert_eq!(wtr.into_inner(),
+                   &b"(1469439874.299591) can1 080#\n(1469439874.299654) can0 701#7F T\n"[..]);
+
+        assert!(parse_line(b"\r\n").unwrap().is_none());
+        assert_eq!(parse_line(b"(0.000001) vcan0 123#\n").unwrap().unwrap().t_us, 1);
+    }
 }
@@ -0,0 +1,577 @@
+//! CAN gateway
+//!
+//! The kernel's `can-gw` module routes frames between CAN interfaces without
+//! a round trip through userspace. Each rule forwards the frames received on
+//! one interface, optionally filtered, to another one and may modify them
+//! on the way: the id, dlc and data can be combined with operands by AND,
+//! OR, XOR and SET (applied in that order), after which an XOR or CRC8
+//! checksum can be written into the data.
+//!
+//! Rules are configured over netlink like `cangw` from can-utils does, which
+//! requires `CAP_NET_ADMIN`:
+//!
+//! ```no_run
+//! use socketcan::{CanFilter, CanGateway, FrameModification, GatewayRule};
+//!
+//! let mut gw = CanGateway::open().unwrap();
+//!
+//! // forward 0x100 from can0 to can1 as 0x200
+//! let mut rule = GatewayRule::open("can0", "can1").unwrap();
+//! rule.filter = Some(CanFilter::new(0x100, 0x7ff).unwrap());
+//! rule.mod_set = Some(FrameModification { id: Some(0x200), ..Default::default() });
+//! gw.add(&rule).unwrap();
+//!
+//! for entry in gw.list().unwrap() {
+//!     println!("{} -> {}: {} frames", entry.rule.src_if, entry.rule.dst_if, entry.handled);
+//! }
+//! gw.flush().unwrap();
+//! ```
+
+use libc::c_uint;
+use nix::net::if_::if_nametoindex;
+use std::{io, mem};
+use crate::rtnl::{self, MsgBuilder, NlSocket, NLM_F_CREATE};
+use crate::{CanFilter, AF_CAN};
+
+// linux/rtnetlink.h
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTM_GETROUTE: u16 = 26;
+
+// linux/can/gw.h
+const CGW_TYPE_CAN_CAN: u8 = 1;
+
+const CGW_MOD_AND: u16 = 1;
+const CGW_MOD_OR: u16 = 2;
+const CGW_MOD_XOR: u16 = 3;
+const CGW_MOD_SET: u16 = 4;
+const CGW_CS_XOR: u16 = 5;
+const CGW_CS_CRC8: u16 = 6;
+const CGW_HANDLED: u16 = 7;
+const CGW_DROPPED: u16 = 8;
+const CGW_SRC_IF: u16 = 9;
+const CGW_DST_IF: u16 = 10;
+const CGW_FILTER: u16 = 11;
+const CGW_DELETED: u16 = 12;
+const CGW_LIM_HOPS: u16 = 13;
+const CGW_MOD_UID: u16 = 14;
+
+const CGW_FLAGS_CAN_ECHO: u16 = 0x01;
+const CGW_FLAGS_CAN_SRC_TSTAMP: u16 = 0x02;
+const CGW_FLAGS_CAN_IIF_TX_OK: u16 = 0x04;
+
+const CGW_MOD_ID: u8 = 0x01;
+const CGW_MOD_DLC: u8 = 0x02;
+const CGW_MOD_DATA: u8 = 0x04;
+
+const CGW_CRC8PRF_UNSPEC: u8 = 0;
+const CGW_CRC8PRF_1U8: u8 = 1;
+const CGW_CRC8PRF_16U8: u8 = 2;
+const CGW_CRC8PRF_SFFID_XOR: u8 = 3;
+
+// sizes of the packed struct cgw_frame_mod and struct cgw_csum_crc8
+const FRAME_MOD_LEN: usize = 17;
+const CSUM_CRC8_LEN: usize = 282;
+
+/// Mirrors the `struct rtcanmsg` of linux/can/gw.h
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct RtCanMsg {
+    /// Address family, always `AF_CAN`
+    can_family: u8,
+
+    /// Gateway type, only `CGW_TYPE_CAN_CAN` is supported
+    gwtype: u8,
+
+    /// `CGW_FLAGS_CAN_*`
+    flags: u16,
+}
+
+impl RtCanMsg {
+    fn new(flags: u16) -> RtCanMsg {
+        RtCanMsg {
+            can_family: AF_CAN as u8,
+            gwtype: CGW_TYPE_CAN_CAN,
+            flags: flags,
+        }
+    }
+}
+
+/// Operands of a modification, elements left at `None` are not modified
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameModification {
+    /// operand of the CAN id, including the `EFF_FLAG` and `RTR_FLAG` bits
+    pub id: Option<u32>,
+    pub dlc: Option<u8>,
+    pub data: Option<[u8; 8]>,
+}
+
+impl FrameModification {
+    /// Encodes the `struct cgw_frame_mod`, `None` if nothing is modified.
+    fn encode(&self) -> Option<[u8; FRAME_MOD_LEN]> {
+        let mut buf = [0u8; FRAME_MOD_LEN];
+        let mut modtype = 0;
+        if let Some(id) = self.id {
+            buf[0..4].copy_from_slice(&id.to_ne_bytes());
+            modtype |= CGW_MOD_ID;
+        }
+        if let Some(dlc) = self.dlc {
+            buf[4] = dlc;
+            modtype |= CGW_MOD_DLC;
+        }
+        if let Some(data) = self.data {
+            buf[8..16].copy_from_slice(&data);
+            modtype |= CGW_MOD_DATA;
+        }
+        buf[16] = modtype;
+
+        if modtype == 0 {
+            None
+        } else {
+            Some(buf)
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<FrameModification> {
+        if data.len() < FRAME_MOD_LEN {
+            return None;
+        }
+        let modtype = data[16];
+        let mut frame_data = [0u8; 8];
+        frame_data.copy_from_slice(&data[8..16]);
+
+        Some(FrameModification {
+            id: rtnl::attr_u32(data).filter(|_| modtype & CGW_MOD_ID != 0),
+            dlc: Some(data[4]).filter(|_| modtype & CGW_MOD_DLC != 0),
+            data: Some(frame_data).filter(|_| modtype & CGW_MOD_DATA != 0),
+        })
+    }
+}
+
+/// XOR checksum over the data bytes `from` to `to`, written to `result`
+///
+/// Indices are 0-7, negative ones count from the end of the frame's data
+/// (-1 being the last byte), so they work with any dlc.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct XorChecksum {
+    pub from: i8,
+    pub to: i8,
+    pub result: i8,
+    /// initial value the bytes are XORed into
+    pub init: u8,
+}
+
+impl XorChecksum {
+    fn encode(&self) -> [u8; 4] {
+        [self.from as u8, self.to as u8, self.result as u8, self.init]
+    }
+
+    fn decode(data: &[u8]) -> Option<XorChecksum> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(XorChecksum {
+            from: data[0] as i8,
+            to: data[1] as i8,
+            result: data[2] as i8,
+            init: data[3],
+        })
+    }
+}
+
+/// Additional input of a CRC8 checksum, as used by AUTOSAR E2E profiles
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Crc8Profile {
+    /// only the data bytes are used
+    Unspecified,
+
+    /// a constant byte processed after the data
+    OneU8(u8),
+
+    /// a byte processed after the data, selected from the table by the
+    /// lower 4 bits of the data byte 1 (the counter of E2E profile 1)
+    SixteenU8([u8; 16]),
+
+    /// the XOR of the two bytes of an 11 bit CAN id processed after the
+    /// data
+    SffIdXor,
+}
+
+/// CRC8 checksum over the data bytes `from` to `to`, written to `result`
+///
+/// Indices are handled like those of `XorChecksum`. The checksum is computed
+/// MSB first with `polynomial`, starting from `init` and XORed with
+/// `final_xor`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Crc8Checksum {
+    pub from: i8,
+    pub to: i8,
+    pub result: i8,
+    pub polynomial: u8,
+    pub init: u8,
+    pub final_xor: u8,
+    pub profile: Crc8Profile,
+}
+
+/// The lookup table of a CRC8 computed MSB first.
+fn crc8_table(polynomial: u8) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u8;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ polynomial
+            } else {
+                crc << 1
+            };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+impl Crc8Checksum {
+    /// Encodes the `struct cgw_csum_crc8`, with the table the kernel looks
+    /// up the checksum in.
+    fn encode(&self) -> [u8; CSUM_CRC8_LEN] {
+        let mut buf = [0u8; CSUM_CRC8_LEN];
+        buf[0] = self.from as u8;
+        buf[1] = self.to as u8;
+        buf[2] = self.result as u8;
+        buf[3] = self.init;
+        buf[4] = self.final_xor;
+        buf[5..261].copy_from_slice(&crc8_table(self.polynomial));
+
+        let (profile, profile_data) = buf[261..].split_at_mut(1);
+        profile[0] = match self.profile {
+            Crc8Profile::Unspecified => CGW_CRC8PRF_UNSPEC,
+            Crc8Profile::OneU8(b) => {
+                profile_data[0] = b;
+                CGW_CRC8PRF_1U8
+            }
+            Crc8Profile::SixteenU8(ref table) => {
+                profile_data[..16].copy_from_slice(table);
+                CGW_CRC8PRF_16U8
+            }
+            Crc8Profile::SffIdXor => CGW_CRC8PRF_SFFID_XOR,
+        };
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Option<Crc8Checksum> {
+        if data.len() < CSUM_CRC8_LEN {
+            return None;
+        }
+        let profile_data = &data[262..];
+        let profile = match data[261] {
+            CGW_CRC8PRF_1U8 => Crc8Profile::OneU8(profile_data[0]),
+            CGW_CRC8PRF_16U8 => {
+                let mut table = [0u8; 16];
+                table.copy_from_slice(&profile_data[..16]);
+                Crc8Profile::SixteenU8(table)
+            }
+            CGW_CRC8PRF_SFFID_XOR => Crc8Profile::SffIdXor,
+            _ => Crc8Profile::Unspecified,
+        };
+
+        Some(Crc8Checksum {
+            from: data[0] as i8,
+            to: data[1] as i8,
+            result: data[2] as i8,
+            init: data[3],
+            final_xor: data[4],
+            // the entry of 0x01 is the polynomial itself
+            polynomial: data[5 + 1],
+            profile: profile,
+        })
+    }
+}
+
+/// A routing rule between two CAN interfaces
+#[derive(Debug, Clone, PartialEq)]
+pub struct GatewayRule {
+    /// interface index frames are received on
+    pub src_if: c_uint,
+
+    /// interface index frames are sent on
+    pub dst_if: c_uint,
+
+    /// only frames matching the filter are routed, all if `None`
+    pub filter: Option<CanFilter>,
+
+    /// loop routed frames back to the sockets on `dst_if`
+    pub echo: bool,
+
+    /// keep the receive timestamp of the source frame
+    pub src_timestamp: bool,
+
+    /// allow `dst_if` to be the same as `src_if`
+    pub allow_same_if: bool,
+
+    pub mod_and: Option<FrameModification>,
+    pub mod_or: Option<FrameModification>,
+    pub mod_xor: Option<FrameModification>,
+    pub mod_set: Option<FrameModification>,
+
+    pub xor_checksum: Option<XorChecksum>,
+    pub crc8_checksum: Option<Crc8Checksum>,
+
+    /// maximum number of gateway hops a frame may take, the module's
+    /// `max_hops` parameter if `None`
+    pub hop_limit: Option<u8>,
+
+    /// identifies the rule, so its modifications can be updated in place
+    pub uid: Option<u32>,
+}
+
+impl GatewayRule {
+    /// A rule forwarding all frames from `src_if` to `dst_if` unmodified
+    pub fn new(src_if: c_uint, dst_if: c_uint) -> GatewayRule {
+        GatewayRule {
+            src_if: src_if,
+            dst_if: dst_if,
+            filter: None,
+            echo: false,
+            src_timestamp: false,
+            allow_same_if: false,
+            mod_and: None,
+            mod_or: None,
+            mod_xor: None,
+            mod_set: None,
+            xor_checksum: None,
+            crc8_checksum: None,
+            hop_limit: None,
+            uid: None,
+        }
+    }
+
+    /// Like `new`, but looks up the interfaces by name
+    pub fn open(src: &str, dst: &str) -> Result<GatewayRule, nix::Error> {
+        Ok(GatewayRule::new(if_nametoindex(src)?, if_nametoindex(dst)?))
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.echo {
+            flags |= CGW_FLAGS_CAN_ECHO;
+        }
+        if self.src_timestamp {
+            flags |= CGW_FLAGS_CAN_SRC_TSTAMP;
+        }
+        if self.allow_same_if {
+            flags |= CGW_FLAGS_CAN_IIF_TX_OK;
+        }
+        flags
+    }
+
+    /// Builds an `RTM_NEWROUTE` or `RTM_DELROUTE` message for the rule.
+    fn to_msg(&self, msg_type: u16, flags: u16) -> MsgBuilder {
+        let mut msg = MsgBuilder::new(msg_type, flags);
+        msg.header(&RtCanMsg::new(self.flags()));
+
+        let mods = [
+            (CGW_MOD_AND, &self.mod_and),
+            (CGW_MOD_OR, &self.mod_or),
+            (CGW_MOD_XOR, &self.mod_xor),
+            (CGW_MOD_SET, &self.mod_set),
+        ];
+        for (attr_type, modification) in mods.iter() {
+            if let Some(buf) = modification.and_then(|m| m.encode()) {
+                msg.attr(*attr_type, &buf);
+            }
+        }
+
+        if let Some(ref xor) = self.xor_checksum {
+            msg.attr(CGW_CS_XOR, &xor.encode());
+        }
+        if let Some(ref crc8) = self.crc8_checksum {
+            msg.attr(CGW_CS_CRC8, &crc8.encode());
+        }
+        if let Some(uid) = self.uid {
+            msg.attr_u32(CGW_MOD_UID, uid);
+        }
+        if let Some(hops) = self.hop_limit {
+            msg.attr_u8(CGW_LIM_HOPS, hops);
+        }
+        if let Some(ref filter) = self.filter {
+            msg.attr_struct(CGW_FILTER, filter);
+        }
+        msg.attr_u32(CGW_SRC_IF, self.src_if);
+        msg.attr_u32(CGW_DST_IF, self.dst_if);
+        msg
+    }
+}
+
+/// A rule as listed by the kernel, with its frame counters
+#[derive(Debug, Clone, PartialEq)]
+pub struct GatewayEntry {
+    pub rule: GatewayRule,
+
+    /// frames routed
+    pub handled: u32,
+
+    /// frames that could not be sent on the destination interface
+    pub dropped: u32,
+
+    /// frames dropped due to a modification or checksum failing, or to the
+    /// hop limit
+    pub deleted: u32,
+}
+
+impl GatewayEntry {
+    /// Parses the payload of an `RTM_NEWROUTE` message, `None` if it is not
+    /// a CAN to CAN rule.
+    fn from_route_msg(payload: &[u8]) -> Option<GatewayEntry> {
+        let (hdr, attrs) = rtnl::parse_header::<RtCanMsg>(payload)?;
+        if hdr.can_family != AF_CAN as u8 || hdr.gwtype != CGW_TYPE_CAN_CAN {
+            return None;
+        }
+
+        let mut rule = GatewayRule::new(0, 0);
+        rule.echo = hdr.flags & CGW_FLAGS_CAN_ECHO != 0;
+        rule.src_timestamp = hdr.flags & CGW_FLAGS_CAN_SRC_TSTAMP != 0;
+        rule.allow_same_if = hdr.flags & CGW_FLAGS_CAN_IIF_TX_OK != 0;
+
+        let mut entry = GatewayEntry {
+            rule: rule,
+            handled: 0,
+            dropped: 0,
+            deleted: 0,
+        };
+        let rule = &mut entry.rule;
+
+        for (attr_type, data) in attrs {
+            match attr_type {
+                CGW_MOD_AND => rule.mod_and = FrameModification::decode(data),
+                CGW_MOD_OR => rule.mod_or = FrameModification::decode(data),
+                CGW_MOD_XOR => rule.mod_xor = FrameModification::decode(data),
+                CGW_MOD_SET => rule.mod_set = FrameModification::decode(data),
+                CGW_CS_XOR => rule.xor_checksum = XorChecksum::decode(data),
+                CGW_CS_CRC8 => rule.crc8_checksum = Crc8Checksum::decode(data),
+                CGW_HANDLED => entry.handled = rtnl::attr_u32(data)?,
+                CGW_DROPPED => entry.dropped = rtnl::attr_u32(data)?,
+                CGW_DELETED => entry.deleted = rtnl::attr_u32(data)?,
+                CGW_SRC_IF => rule.src_if = rtnl::attr_u32(data)?,
+                CGW_DST_IF => rule.dst_if = rtnl::attr_u32(data)?,
+                CGW_FILTER if data.len() >= mem::size_of::<CanFilter>() => {
+                    rule.filter = Some(rtnl::attr_struct(data))
+                }
+                CGW_LIM_HOPS => rule.hop_limit = rtnl::attr_u8(data),
+                CGW_MOD_UID => rule.uid = rtnl::attr_u32(data),
+                _ => (),
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+/// Configures the rules of the `can-gw` kernel module
+#[derive(Debug)]
+pub struct CanGateway {
+    sock: NlSocket,
+}
+
+impl CanGateway {
+    /// Opens a netlink socket to configure the gateway
+    pub fn open() -> io::Result<CanGateway> {
+        Ok(CanGateway { sock: NlSocket::open(0)? })
+    }
+
+    /// Adds a rule
+    ///
+    /// If the rule has a `uid` that is already in use, the modifications of
+    /// the existing rule are replaced instead and its counters are kept.
+    pub fn add(&mut self, rule: &GatewayRule) -> io::Result<()> {
+        self.sock.request_ack(rule.to_msg(RTM_NEWROUTE, NLM_F_CREATE))
+    }
+
+    /// Deletes a rule
+    ///
+    /// Rules with a `uid` are matched by it alone, others have to match in
+    /// all their settings.
+    pub fn delete(&mut self, rule: &GatewayRule) -> io::Result<()> {
+        self.sock.request_ack(rule.to_msg(RTM_DELROUTE, 0))
+    }
+
+    /// Deletes all rules
+    pub fn flush(&mut self) -> io::Result<()> {
+        // a message without interfaces removes all rules
+        let mut msg = MsgBuilder::new(RTM_DELROUTE, 0);
+        msg.header(&RtCanMsg::new(0));
+
+        self.sock.request_ack(msg)
+    }
+
+    /// Lists all rules, in the order they are applied
+    pub fn list(&mut self) -> io::Result<Vec<GatewayEntry>> {
+        let mut msg = MsgBuilder::new(RTM_GETROUTE, 0);
+        msg.header(&RtCanMsg::new(0));
+        let replies = self.sock.dump(msg)?;
+
+        Ok(replies.iter()
+            .filter_map(|data| GatewayEntry::from_route_msg(data))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rtnl::NLMSG_HDRLEN;
+
+    #[test]
+    fn test_crc8_table() {
+        // SAE J1850
+        let table = crc8_table(0x1d);
+        assert_eq!(table[0], 0);
+        assert_eq!(table[1], 0x1d);
+        assert_eq!(table[0x80], 0x26);
+    }
+
+    #[test]
+    fn test_frame_modification() {
+        let modification = FrameModification {
+            id: Some(0x123),
+            dlc: None,
+            data: Some([1, 2, 3, 4, 5, 6, 7, 8]),
+        };
+        let buf = modification.encode().unwrap();
+        assert_eq!(buf[16], CGW_MOD_ID | CGW_MOD_DATA);
+        assert_eq!(FrameModification::decode(&buf), Some(modification));
+
+        assert_eq!(FrameModification::default().encode(), None);
+    }
+
+    #[test]
+    fn test_rule_round_trip() {
+        let mut rule = GatewayRule::new(3, 4);
+        rule.filter = Some(CanFilter::new(0x100, 0x700).unwrap());
+        rule.echo = true;
+        rule.mod_and = Some(FrameModification { dlc: Some(0x0f), ..Default::default() });
+        rule.mod_set = Some(FrameModification { id: Some(0x200), ..Default::default() });
+        rule.xor_checksum = Some(XorChecksum { from: 0, to: -2, result: -1, init: 0xff });
+        rule.crc8_checksum = Some(Crc8Checksum {
+            from: 1,
+            to: 6,
+            result: 0,
+            polynomial: 0x1d,
+            init: 0xff,
+            final_xor: 0xff,
+            profile: Crc8Profile::SixteenU8([0x42; 16]),
+        });
+        rule.hop_limit = Some(2);
+        rule.uid = Some(0xcafe);
+
+        let mut msg = rule.to_msg(RTM_NEWROUTE, 0);
+        msg.attr_u32(CGW_HANDLED, 10);
+        msg.attr_u32(CGW_DELETED, 1);
+        let buf = msg.finish(1);
+
+        let entry = GatewayEntry::from_route_msg(&buf[NLMSG_HDRLEN..]).unwrap();
+        assert_eq!(entry.rule, rule);
+        assert_eq!(entry.handled, 10);
+        assert_eq!(entry.dropped, 0);
+        assert_eq!(entry.deleted, 1);
+    }
+}
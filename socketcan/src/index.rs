@@ -0,0 +1,562 @@
+//! Indexed access to candump logs
+//!
+//! An index splits a log into blocks of consecutive records and keeps their
+//! byte offsets and time ranges, optionally also which ids appear in which
+//! block. `IndexedReader` uses it to jump to a point in time and to skip
+//! blocks that cannot hold records of interest, so looking at a short window
+//! of a large log does not require reading all of it.
+//!
+//! ```no_run
+//! use socketcan::index::{IndexBuilder, IndexedReader};
+//! use std::fs;
+//! use std::io::BufReader;
+//!
+//! let mut builder = IndexBuilder::new();
+//! builder.set_index_ids(true);
+//! let index = builder.build(BufReader::new(fs::File::open("candump.log").unwrap())).unwrap();
+//!
+//! let mut reader = IndexedReader::new(BufReader::new(fs::File::open("candump.log").unwrap()), index).unwrap();
+//! reader.set_id_filter(&[0x701]);
+//! reader.seek_to_time(1_469_439_874_000_000).unwrap();
+//! reader.set_end_time(Some(1_469_439_876_000_000));
+//! while let Some(record) = reader.next_record().unwrap() {
+//!     println!("{:?}", record);
+//! }
+//! ```
+//!
+//! Indexes only work on uncompressed logs, as they need to seek.
+
+use crate::dump::{parse_line, CanDumpFrame, CanDumpRecord, ParseError};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, SeekFrom};
+
+const INDEX_MAGIC: &[u8; 8] = b"CANIDX\x00\x01";
+
+/// records per block, unless set otherwise
+const DEFAULT_BLOCK_SIZE: u32 = 4096;
+
+/// A block of consecutive records.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// byte offset of the first record
+    pub offset: u64,
+
+    /// earliest and latest timestamp, logs are not required to be ordered
+    pub t_min: u64,
+    pub t_max: u64,
+    pub records: u32,
+}
+
+/// Index of a candump log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogIndex {
+    /// length of the indexed log
+    len: u64,
+    blocks: Vec<Block>,
+
+    /// block numbers by id, if ids were indexed
+    ids: Option<HashMap<u32, Vec<u32>>>,
+}
+
+/// Creates a `LogIndex` by reading a log once.
+#[derive(Debug, Clone)]
+pub struct IndexBuilder {
+    block_size: u32,
+    index_ids: bool,
+}
+
+impl Default for IndexBuilder {
+    fn default() -> IndexBuilder {
+        IndexBuilder::new()
+    }
+}
+
+impl IndexBuilder {
+    pub fn new() -> IndexBuilder {
+        IndexBuilder {
+            block_size: DEFAULT_BLOCK_SIZE,
+            index_ids: false,
+        }
+    }
+
+    /// Set the number of records per block.
+    ///
+    /// Smaller blocks make seeking and id lookups more precise, at the cost
+    /// of a larger index.
+    pub fn set_block_size(&mut self, records: u32) {
+        self.block_size = records.max(1);
+    }
+
+    /// Also record which ids appear in each block.
+    pub fn set_index_ids(&mut self, index_ids: bool) {
+        self.index_ids = index_ids;
+    }
+
+    /// Read a log from start to end, building its index.
+    pub fn build<R: io::BufRead>(&self, mut rdr: R) -> Result<LogIndex, ParseError> {
+        let mut index = LogIndex {
+            len: 0,
+            blocks: Vec::new(),
+            ids: if self.index_ids { Some(HashMap::new()) } else { None },
+        };
+        let mut line_buf = Vec::new();
+
+        loop {
+            line_buf.clear();
+            let bytes_read = rdr.read_until(b'\n', &mut line_buf)?;
+            if bytes_read == 0 {
+                break;
+            }
+
+            let offset = index.len;
+            index.len += bytes_read as u64;
+
+            let record = match parse_line(&line_buf)? {
+                Some(record) => record,
+                None => continue,
+            };
+
+            match index.blocks.last_mut() {
+                Some(block) if block.records < self.block_size => {
+                    block.t_min = block.t_min.min(record.t_us);
+                    block.t_max = block.t_max.max(record.t_us);
+                    block.records += 1;
+                }
+                _ => index.blocks.push(Block {
+                    offset: offset,
+                    t_min: record.t_us,
+                    t_max: record.t_us,
+                    records: 1,
+                }),
+            }
+
+            if let Some(ref mut ids) = index.ids {
+                let block = index.blocks.len() as u32 - 1;
+                let blocks = ids.entry(record.frame.id()).or_insert_with(Vec::new);
+                if blocks.last() != Some(&block) {
+                    blocks.push(block);
+                }
+            }
+        }
+
+        Ok(index)
+    }
+}
+
+impl LogIndex {
+    /// Length of the indexed log in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Earliest and latest timestamp of the log
+    pub fn time_range(&self) -> Option<(u64, u64)> {
+        let t_min = self.blocks.iter().map(|b| b.t_min).min()?;
+        let t_max = self.blocks.iter().map(|b| b.t_max).max()?;
+        Some((t_min, t_max))
+    }
+
+    /// Numbers of the blocks holding frames with `id`, `None` if ids were
+    /// not indexed.
+    pub fn blocks_with_id(&self, id: u32) -> Option<&[u32]> {
+        self.ids.as_ref().map(|ids| ids.get(&id).map_or(&[][..], |b| &b[..]))
+    }
+
+    /// Byte offset of the first block that may hold records at or after
+    /// `t_us`, the end of the log if there is none.
+    pub fn offset_for_time(&self, t_us: u64) -> u64 {
+        self.blocks.iter().find(|b| b.t_max >= t_us).map_or(self.len, |b| b.offset)
+    }
+
+    /// Save the index, e.g. next to the log.
+    pub fn write_to<W: io::Write>(&self, mut wtr: W) -> io::Result<()> {
+        wtr.write_all(INDEX_MAGIC)?;
+        wtr.write_all(&self.len.to_le_bytes())?;
+        wtr.write_all(&(self.blocks.len() as u32).to_le_bytes())?;
+        for block in &self.blocks {
+            wtr.write_all(&block.offset.to_le_bytes())?;
+            wtr.write_all(&block.t_min.to_le_bytes())?;
+            wtr.write_all(&block.t_max.to_le_bytes())?;
+            wtr.write_all(&block.records.to_le_bytes())?;
+        }
+
+        match self.ids {
+            Some(ref ids) => {
+                // sorted, so equal indexes are saved equally
+                let mut sorted: Vec<_> = ids.iter().collect();
+                sorted.sort();
+
+                wtr.write_all(&[1])?;
+                wtr.write_all(&(sorted.len() as u32).to_le_bytes())?;
+                for (id, blocks) in sorted {
+                    wtr.write_all(&id.to_le_bytes())?;
+                    wtr.write_all(&(blocks.len() as u32).to_le_bytes())?;
+                    for block in blocks {
+                        wtr.write_all(&block.to_le_bytes())?;
+                    }
+                }
+            }
+            None => wtr.write_all(&[0])?,
+        }
+        wtr.flush()
+    }
+
+    /// Load an index saved by `write_to`.
+    pub fn read_from<R: io::Read>(mut rdr: R) -> io::Result<LogIndex> {
+        let mut magic = [0; 8];
+        rdr.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a log index"));
+        }
+
+        let len = read_u64(&mut rdr)?;
+        let count = read_u32(&mut rdr)?;
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            blocks.push(Block {
+                offset: read_u64(&mut rdr)?,
+                t_min: read_u64(&mut rdr)?,
+                t_max: read_u64(&mut rdr)?,
+                records: read_u32(&mut rdr)?,
+            });
+        }
+
+        let mut has_ids = [0];
+        rdr.read_exact(&mut has_ids)?;
+        let ids = if has_ids[0] != 0 {
+            let mut ids = HashMap::new();
+            for _ in 0..read_u32(&mut rdr)? {
+                let id = read_u32(&mut rdr)?;
+                let mut id_blocks = Vec::new();
+                for _ in 0..read_u32(&mut rdr)? {
+                    let block = read_u32(&mut rdr)?;
+                    if block >= count {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid block number"));
+                    }
+                    id_blocks.push(block);
+                }
+                ids.insert(id, id_blocks);
+            }
+            Some(ids)
+        } else {
+            None
+        };
+
+        Ok(LogIndex {
+            len: len,
+            blocks: blocks,
+            ids: ids,
+        })
+    }
+}
+
+fn read_u32<R: io::Read>(rdr: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    rdr.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: io::Read>(rdr: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    rdr.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads a candump log with the help of its index.
+///
+/// Records can be limited to a time window, a set of ids and a set of
+/// devices. Blocks that cannot hold matching records are skipped without
+/// reading them.
+#[derive(Debug)]
+pub struct IndexedReader<R> {
+    rdr: R,
+    index: LogIndex,
+    line_buf: Vec<u8>,
+
+    /// current block and byte offset
+    block: usize,
+    pos: u64,
+
+    /// earliest timestamp of the blocks from each block on, to stop early
+    later_t_min: Vec<u64>,
+
+    from_us: u64,
+    until_us: Option<u64>,
+    ids: Option<HashSet<u32>>,
+
+    /// blocks holding any of `ids`, if they were indexed
+    id_blocks: Option<HashSet<u32>>,
+    devices: Option<Vec<String>>,
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct IndexedRecords<'a, R: 'a> {
+    src: &'a mut IndexedReader<R>,
+}
+
+impl<R: io::BufRead + io::Seek> IndexedReader<R> {
+    /// Create a reader for the log `index` was built from.
+    ///
+    /// Fails with `InvalidData` if the length of the log does not match, as
+    /// an index is only valid for the exact log it was built from.
+    pub fn new(mut rdr: R, index: LogIndex) -> io::Result<IndexedReader<R>> {
+        if rdr.seek(SeekFrom::End(0))? != index.len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "index does not match the log"));
+        }
+        rdr.seek(SeekFrom::Start(0))?;
+
+        let mut later_t_min = vec![u64::max_value(); index.blocks.len() + 1];
+        for (i, block) in index.blocks.iter().enumerate().rev() {
+            later_t_min[i] = later_t_min[i + 1].min(block.t_min);
+        }
+
+        Ok(IndexedReader {
+            rdr: rdr,
+            index: index,
+            line_buf: Vec::new(),
+            block: 0,
+            pos: 0,
+            later_t_min: later_t_min,
+            from_us: 0,
+            until_us: None,
+            ids: None,
+            id_blocks: None,
+            devices: None,
+        })
+    }
+
+    pub fn index(&self) -> &LogIndex {
+        &self.index
+    }
+
+    /// Continue with the first record at or after `t_us`.
+    ///
+    /// Records before `t_us` are skipped from here on, even if the log is
+    /// not ordered by time.
+    pub fn seek_to_time(&mut self, t_us: u64) -> io::Result<()> {
+        self.from_us = t_us;
+        self.seek_to_block(0)
+    }
+
+    /// Stop at records after `t_us`, `None` to read up to the end.
+    pub fn set_end_time(&mut self, t_us: Option<u64>) {
+        self.until_us = t_us;
+    }
+
+    /// Only return frames with one of `ids`, an empty slice returns all.
+    ///
+    /// Ids are matched against `CanDumpFrame::id`.
+    pub fn set_id_filter(&mut self, ids: &[u32]) {
+        if ids.is_empty() {
+            self.ids = None;
+            self.id_blocks = None;
+            return;
+        }
+
+        self.id_blocks = if self.index.ids.is_some() {
+            let blocks = ids.iter().filter_map(|&id| self.index.blocks_with_id(id)).flat_map(|b| b.iter().cloned());
+            Some(blocks.collect())
+        } else {
+            None
+        };
+        self.ids = Some(ids.iter().cloned().collect());
+    }
+
+    /// Only return records of one of `devices`, an empty slice returns all.
+    pub fn set_device_filter(&mut self, devices: &[&str]) {
+        self.devices = if devices.is_empty() {
+            None
+        } else {
+            Some(devices.iter().map(|&d| d.to_owned()).collect())
+        };
+    }
+
+    /// Returns an iterator over all matching records
+    pub fn records(&mut self) -> IndexedRecords<'_, R> {
+        IndexedRecords { src: self }
+    }
+
+    /// Advance state, returning the next matching record.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        loop {
+            if !self.enter_block()? {
+                return Ok(None);
+            }
+
+            self.line_buf.clear();
+            let bytes_read = self.rdr.read_until(b'\n', &mut self.line_buf)?;
+            if bytes_read == 0 {
+                // the log was truncated after building the index
+                return Ok(None);
+            }
+            self.pos += bytes_read as u64;
+
+            // the record is parsed again to return it, after the borrow of
+            // the buffer for checking it ended
+            if let Some(record) = parse_line(&self.line_buf)? {
+                if self.matches(&record) {
+                    break;
+                }
+            }
+        }
+
+        parse_line(&self.line_buf)
+    }
+
+    /// Makes sure the position is within a block worth reading, moving on to
+    /// the next one if necessary. Returns `false` at the end.
+    fn enter_block(&mut self) -> io::Result<bool> {
+        let block_end = self.index.blocks.get(self.block + 1).map_or(self.index.len, |b| b.offset);
+        if self.pos < block_end && self.block < self.index.blocks.len() {
+            return Ok(true);
+        }
+        let next = self.block + 1;
+        self.seek_to_block(next)?;
+        Ok(self.block < self.index.blocks.len())
+    }
+
+    /// Seeks to the first block at or after `first` that may hold matching
+    /// records.
+    fn seek_to_block(&mut self, first: usize) -> io::Result<()> {
+        let mut block = first;
+        while block < self.index.blocks.len() {
+            if let Some(until_us) = self.until_us {
+                if self.later_t_min[block] > until_us {
+                    block = self.index.blocks.len();
+                    break;
+                }
+            }
+            if self.may_match(block) {
+                break;
+            }
+            block += 1;
+        }
+
+        let offset = self.index.blocks.get(block).map_or(self.index.len, |b| b.offset);
+        if offset != self.pos || block != self.block {
+            self.rdr.seek(SeekFrom::Start(offset))?;
+        }
+        self.block = block;
+        self.pos = offset;
+        Ok(())
+    }
+
+    fn may_match(&self, block: usize) -> bool {
+        let b = &self.index.blocks[block];
+        if b.t_max < self.from_us || self.until_us.map_or(false, |until_us| b.t_min > until_us) {
+            return false;
+        }
+        self.id_blocks.as_ref().map_or(true, |blocks| blocks.contains(&(block as u32)))
+    }
+
+    fn matches(&self, record: &CanDumpRecord) -> bool {
+        record.t_us >= self.from_us && self.until_us.map_or(true, |until_us| record.t_us <= until_us) &&
+        self.ids.as_ref().map_or(true, |ids| ids.contains(&record.frame.id())) &&
+        self.devices.as_ref().map_or(true, |devices| devices.iter().any(|d| d == record.device))
+    }
+}
+
+impl<'a, R: io::BufRead + io::Seek> Iterator for IndexedRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IndexBuilder, IndexedReader, LogIndex};
+    use std::io::Cursor;
+
+    /// 100 records, one every 10ms, ids cycling through 0x100 to 0x104 and
+    /// 0x7ff only in the middle
+    fn log() -> Vec<u8> {
+        let mut log = String::new();
+        for i in 0..100 {
+            let id = if i == 50 { 0x7ff } else { 0x100 + i % 5 };
+            let device = if i % 2 == 0 { "can0" } else { "can1" };
+            log.push_str(&format!("(1.{:06}) {} {:03X}#{:02X}\n", i * 10_000, device, id, i));
+            if i == 20 {
+                log.push('\n');
+            }
+        }
+        log.into_bytes()
+    }
+
+    fn build(block_size: u32, index_ids: bool) -> LogIndex {
+        let mut builder = IndexBuilder::new();
+        builder.set_block_size(block_size);
+        builder.set_index_ids(index_ids);
+        builder.build(&log()[..]).unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let index = build(8, true);
+        assert_eq!(index.len(), log().len() as u64);
+        assert_eq!(index.blocks().len(), 13);
+        assert_eq!(index.blocks()[1].offset, 8 * 23);
+        assert_eq!(index.blocks()[1].t_min, 1_080_000);
+        assert_eq!(index.time_range(), Some((1_000_000, 1_990_000)));
+        assert_eq!(index.blocks_with_id(0x7ff), Some(&[6][..]));
+        assert_eq!(index.blocks_with_id(0x123), Some(&[][..]));
+        assert_eq!(build(8, false).blocks_with_id(0x7ff), None);
+        assert_eq!(index.offset_for_time(1_085_000), 8 * 23);
+    }
+
+    #[test]
+    fn test_seek_and_filter() {
+        for &(block_size, index_ids) in &[(1, true), (7, true), (7, false), (1000, false)] {
+            let mut reader = IndexedReader::new(Cursor::new(log()), build(block_size, index_ids)).unwrap();
+
+            reader.seek_to_time(1_205_000).unwrap();
+            reader.set_end_time(Some(1_300_000));
+            let data: Vec<u8> = reader.records().map(|r| r.unwrap().1.data()[0]).collect();
+            assert_eq!(data, (21..=30).collect::<Vec<u8>>());
+
+            reader.seek_to_time(0).unwrap();
+            reader.set_end_time(None);
+            reader.set_id_filter(&[0x7ff, 0x101]);
+            reader.set_device_filter(&["can1"]);
+            let data: Vec<u8> = reader.records().map(|r| r.unwrap().1.data()[0]).collect();
+            assert_eq!(data, vec![1, 11, 21, 31, 41, 51, 61, 71, 81, 91]);
+
+            reader.set_device_filter(&[]);
+            reader.seek_to_time(1_400_000).unwrap();
+            let record = reader.next_record().unwrap().unwrap();
+            assert_eq!(record.frame.id(), 0x101);
+            assert_eq!(record.t_us, 1_410_000);
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let index = build(8, true);
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(LogIndex::read_from(&saved[..]).unwrap(), index);
+
+        let index = build(8, false);
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(LogIndex::read_from(&saved[..]).unwrap(), index);
+
+        assert!(LogIndex::read_from(&b"CANIDX\x00\x02"[..]).is_err());
+        assert!(IndexedReader::new(Cursor::new(b"(1.0) can0 123#\n".to_vec()), index).is_err());
+    }
+}
@@ -0,0 +1,552 @@
+//! ISO-TP (ISO 15765-2) transport through the kernel's `CAN_ISOTP` protocol
+//!
+//! ISO-TP segments payloads (PDUs) that do not fit a single CAN frame into a
+//! first frame and consecutive frames, paced by flow control frames of the
+//! receiver. The kernel module `can-isotp` (in-tree since Linux 5.10) does
+//! all of this, an `IsoTpSocket` reads and writes complete PDUs.
+//!
+//! A connection is identified by the pair of CAN ids frames are received
+//! and sent with:
+//!
+//! ```no_run
+//! use socketcan::CanMessageId;
+//! use socketcan::isotp::{IsoTpOptions, IsoTpSocket};
+//!
+//! let mut options = IsoTpOptions::default();
+//! options.tx_padding = Some(0xcc);
+//!
+//! let sock = IsoTpSocket::open("vcan0", CanMessageId::SFF(0x7e8), CanMessageId::SFF(0x7e0),
+//!                              &options).unwrap();
+//! sock.write(&[0x22, 0xf1, 0x90]).unwrap();
+//! println!("{:02x?}", sock.read_pdu().unwrap());
+//! ```
+//!
+//! On kernels without `can-isotp`, `UserIsoTpSocket` implements the protocol
+//! on top of a `CanSocket`. Both implement `IsoTpTransport`, for code that
+//! works with either.
+
+use libc::{
+    bind, c_int, c_short, c_uint, c_void, close, recv, sockaddr, socket, write, MSG_PEEK,
+    MSG_TRUNC, SOL_SOCKET, SO_RCVTIMEO, SO_SNDTIMEO,
+};
+use nix::net::if_::if_nametoindex;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::{io, ptr, time};
+
+mod userspace;
+
+pub use self::userspace::{IsoTpTimeouts, UserIsoTpSocket};
+
+use crate::util::set_socket_option;
+use crate::{
+    c_timeval_new, CanAddr, CanMessageId, CanSocketOpenError, FdFlags, AF_CAN, CANFD_MAX_DLEN,
+    CANFD_MTU, CAN_MTU, PF_CAN, SOCK_DGRAM, SOL_CAN_BASE,
+};
+
+// constants stolen from C headers (linux/can.h, linux/can/isotp.h)
+pub const CAN_ISOTP: c_int = 6;
+pub const SOL_CAN_ISOTP: c_int = SOL_CAN_BASE + CAN_ISOTP;
+const CAN_ISOTP_OPTS: c_int = 1;
+const CAN_ISOTP_RECV_FC: c_int = 2;
+const CAN_ISOTP_TX_STMIN: c_int = 3;
+const CAN_ISOTP_RX_STMIN: c_int = 4;
+const CAN_ISOTP_LL_OPTS: c_int = 5;
+
+/// largest PDU with a 12 bit length, as supported by ISO 15765-2:2004
+pub const ISOTP_MAX_PDU: usize = 4095;
+
+bitflags! {
+    #[derive(Default)]
+    pub struct IsoTpFlags: u32 {
+        /// only listen, do not send flow control frames
+        const LISTEN_MODE = 0x001;
+
+        /// use extended (or mixed) addressing, see `IsoTpOptions::ext_address`
+        const EXTEND_ADDR = 0x002;
+
+        /// pad sent frames to their full length
+        const TX_PADDING = 0x004;
+
+        /// expect received frames to be padded
+        const RX_PADDING = 0x008;
+
+        /// check the length of padded frames
+        const CHK_PAD_LEN = 0x010;
+
+        /// check the padding content of received frames
+        const CHK_PAD_DATA = 0x020;
+
+        /// half duplex error state handling
+        const HALF_DUPLEX = 0x040;
+
+        /// ignore the STmin of received flow control frames
+        const FORCE_TXSTMIN = 0x080;
+
+        /// ignore consecutive frames arriving faster than the set STmin
+        const FORCE_RXSTMIN = 0x100;
+
+        /// use a different extended address for receiving
+        const RX_EXT_ADDR = 0x200;
+
+        /// `write` returns once the PDU has been sent completely
+        const WAIT_TX_DONE = 0x400;
+
+        /// 1-to-N functional addressing with single frames only
+        const SF_BROADCAST = 0x800;
+
+        /// 1-to-N transmission without flow control
+        const CF_BROADCAST = 0x1000;
+    }
+}
+
+/// `struct can_isotp_options`
+#[derive(Debug, Default)]
+#[repr(C)]
+struct IsoTpOptionsRaw {
+    flags: u32,
+    frame_txtime: u32,
+    ext_address: u8,
+    txpad_content: u8,
+    rxpad_content: u8,
+    rx_ext_address: u8,
+}
+
+/// `struct can_isotp_fc_options`
+#[derive(Debug, Default)]
+#[repr(C)]
+struct FlowControlOptionsRaw {
+    bs: u8,
+    stmin: u8,
+    wftmax: u8,
+}
+
+/// `struct can_isotp_ll_options`
+#[derive(Debug, Default)]
+#[repr(C)]
+struct LinkLayerOptionsRaw {
+    mtu: u8,
+    tx_dl: u8,
+    tx_flags: u8,
+}
+
+/// Encodes a separation time as the STmin byte of a flow control frame.
+///
+/// Sub-millisecond times are rounded up to steps of 100µs, times beyond the
+/// maximum of 127ms are clamped.
+pub fn st_min_from_duration(st_min: time::Duration) -> u8 {
+    let us = st_min.as_secs() * 1_000_000 + u64::from(st_min.subsec_micros());
+    match us {
+        0 => 0x00,
+        1..=900 => 0xf0 + ((us + 99) / 100) as u8,
+        _ if us >= 127_000 => 0x7f,
+        _ => ((us + 999) / 1000) as u8,
+    }
+}
+
+/// Decodes the STmin byte of a flow control frame.
+///
+/// Reserved values are treated as the maximum of 127ms, as required by the
+/// standard.
+pub fn st_min_to_duration(st_min: u8) -> time::Duration {
+    match st_min {
+        0x00..=0x7f => time::Duration::from_millis(u64::from(st_min)),
+        0xf1..=0xf9 => time::Duration::from_micros(u64::from(st_min - 0xf0) * 100),
+        _ => time::Duration::from_millis(0x7f),
+    }
+}
+
+/// Flow control parameters sent to the peer when receiving
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FlowControlOptions {
+    /// number of consecutive frames before the next flow control frame,
+    /// 0 sends all of them at once
+    pub block_size: u8,
+
+    /// minimum gap between consecutive frames requested from the sender
+    pub st_min: time::Duration,
+
+    /// maximum number of wait frames the sender accepts, 0 disables them
+    pub wft_max: u8,
+}
+
+/// Link layer parameters, used to send ISO-TP over CAN FD
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LinkLayerOptions {
+    /// MTU of the frames sent and received, `CAN_MTU` or `CANFD_MTU`
+    pub mtu: usize,
+
+    /// data length of sent frames, 8 or one of the CAN FD lengths up to 64
+    pub tx_dl: u8,
+
+    /// flags of sent CAN FD frames
+    pub tx_flags: FdFlags,
+}
+
+impl LinkLayerOptions {
+    /// Options for CAN FD frames with a data length of `tx_dl`.
+    pub fn fd(tx_dl: u8, tx_flags: FdFlags) -> LinkLayerOptions {
+        LinkLayerOptions {
+            mtu: CANFD_MTU,
+            tx_dl: tx_dl,
+            tx_flags: tx_flags,
+        }
+    }
+}
+
+impl Default for LinkLayerOptions {
+    fn default() -> LinkLayerOptions {
+        LinkLayerOptions {
+            mtu: CAN_MTU,
+            tx_dl: 8,
+            tx_flags: FdFlags::empty(),
+        }
+    }
+}
+
+/// Configuration of an `IsoTpSocket`
+///
+/// The defaults are normal addressing without padding, receiving without
+/// flow control restrictions on classic CAN frames.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IsoTpOptions {
+    /// flags, the flags implied by the other options are added when opening
+    pub flags: IsoTpFlags,
+
+    /// gap between sent frames when `CF_BROADCAST` is used, `None` keeps the
+    /// kernel's default
+    pub frame_txtime: Option<time::Duration>,
+
+    /// address byte preceding the data of each frame
+    ///
+    /// Extended addressing uses it with 29 bit or 11 bit ids to select the
+    /// target, mixed addressing with 29 bit ids as the address extension.
+    pub ext_address: Option<u8>,
+
+    /// address byte expected in received frames, if it differs from
+    /// `ext_address`
+    pub rx_ext_address: Option<u8>,
+
+    /// pad sent frames with this byte
+    pub tx_padding: Option<u8>,
+
+    /// expect received frames to be padded with this byte, which is only
+    /// checked with `CHK_PAD_DATA`
+    pub rx_padding: Option<u8>,
+
+    /// send frames at most this often, regardless of the peer's flow control
+    pub force_tx_st_min: Option<time::Duration>,
+
+    /// drop consecutive frames arriving faster than this
+    pub force_rx_st_min: Option<time::Duration>,
+
+    /// flow control sent to the peer
+    pub flow_control: FlowControlOptions,
+
+    /// CAN FD settings, `None` uses classic CAN frames
+    pub link_layer: Option<LinkLayerOptions>,
+}
+
+impl IsoTpOptions {
+    fn raw(&self) -> IsoTpOptionsRaw {
+        let mut raw = IsoTpOptionsRaw {
+            flags: self.flags.bits(),
+            ..IsoTpOptionsRaw::default()
+        };
+
+        if let Some(txtime) = self.frame_txtime {
+            raw.frame_txtime = duration_ns(txtime);
+        }
+        if let Some(ext_address) = self.ext_address {
+            raw.flags |= IsoTpFlags::EXTEND_ADDR.bits();
+            raw.ext_address = ext_address;
+        }
+        if let Some(rx_ext_address) = self.rx_ext_address {
+            raw.flags |= IsoTpFlags::RX_EXT_ADDR.bits();
+            raw.rx_ext_address = rx_ext_address;
+        }
+        if let Some(pad) = self.tx_padding {
+            raw.flags |= IsoTpFlags::TX_PADDING.bits();
+            raw.txpad_content = pad;
+        }
+        if let Some(pad) = self.rx_padding {
+            raw.flags |= IsoTpFlags::RX_PADDING.bits();
+            raw.rxpad_content = pad;
+        }
+        if self.force_tx_st_min.is_some() {
+            raw.flags |= IsoTpFlags::FORCE_TXSTMIN.bits();
+        }
+        if self.force_rx_st_min.is_some() {
+            raw.flags |= IsoTpFlags::FORCE_RXSTMIN.bits();
+        }
+        raw
+    }
+}
+
+/// Durations in the options are passed as nanoseconds in 32 bits.
+fn duration_ns(d: time::Duration) -> u32 {
+    let ns = d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos());
+    if ns > u64::from(u32::max_value()) {
+        u32::max_value()
+    } else {
+        ns as u32
+    }
+}
+
+/// Transfer of whole PDUs over an ISO-TP connection
+pub trait IsoTpTransport {
+    /// Blocking read of a single PDU.
+    fn read_pdu(&mut self) -> io::Result<Vec<u8>>;
+
+    /// Blocking write of a single PDU.
+    fn write_pdu(&mut self, pdu: &[u8]) -> io::Result<()>;
+
+    /// Sets the time `read_pdu` waits for the start of a PDU, zero waits
+    /// forever.
+    fn set_read_timeout(&mut self, duration: time::Duration) -> io::Result<()>;
+}
+
+/// A socket for an ISO-TP connection.
+///
+/// Reads and writes whole PDUs. Like a `CanSocket`, it is closed when
+/// dropped.
+#[derive(Debug)]
+pub struct IsoTpSocket {
+    fd: c_int,
+}
+
+impl IsoTpSocket {
+    /// Open an ISO-TP connection on a named CAN device.
+    ///
+    /// Frames with the id `rx_id` are received, frames with `tx_id` are sent.
+    pub fn open(ifname: &str,
+                rx_id: CanMessageId,
+                tx_id: CanMessageId,
+                options: &IsoTpOptions)
+                -> Result<IsoTpSocket, CanSocketOpenError> {
+        let if_index = if_nametoindex(ifname)?;
+        IsoTpSocket::open_if(if_index, rx_id, tx_id, options)
+    }
+
+    /// Open an ISO-TP connection on a CAN device by interface number.
+    pub fn open_if(if_index: c_uint,
+                   rx_id: CanMessageId,
+                   tx_id: CanMessageId,
+                   options: &IsoTpOptions)
+                   -> Result<IsoTpSocket, CanSocketOpenError> {
+        let addr = CanAddr {
+            _af_can: AF_CAN as c_short,
+            if_index: if_index as c_int,
+            rx_id: rx_id.with_eff_bit(),
+            tx_id: tx_id.with_eff_bit(),
+        };
+
+        let sock_fd = unsafe { socket(PF_CAN, SOCK_DGRAM, CAN_ISOTP) };
+        if sock_fd == -1 {
+            return Err(CanSocketOpenError::from(io::Error::last_os_error()));
+        }
+
+        // the socket is closed on drop if anything fails from here on
+        let sock = IsoTpSocket { fd: sock_fd };
+
+        // options can only be set before binding
+        sock.set_options(options)?;
+
+        let bind_rv = unsafe {
+            let sockaddr_ptr = &addr as *const CanAddr;
+            bind(sock.fd,
+                 sockaddr_ptr as *const sockaddr,
+                 size_of::<CanAddr>() as u32)
+        };
+
+        if bind_rv == -1 {
+            return Err(CanSocketOpenError::from(io::Error::last_os_error()));
+        }
+
+        Ok(sock)
+    }
+
+    fn set_options(&self, options: &IsoTpOptions) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_CAN_ISOTP, CAN_ISOTP_OPTS, &options.raw())?;
+
+        let fc = FlowControlOptionsRaw {
+            bs: options.flow_control.block_size,
+            stmin: st_min_from_duration(options.flow_control.st_min),
+            wftmax: options.flow_control.wft_max,
+        };
+        set_socket_option(self.fd, SOL_CAN_ISOTP, CAN_ISOTP_RECV_FC, &fc)?;
+
+        if let Some(st_min) = options.force_tx_st_min {
+            set_socket_option(self.fd, SOL_CAN_ISOTP, CAN_ISOTP_TX_STMIN, &duration_ns(st_min))?;
+        }
+        if let Some(st_min) = options.force_rx_st_min {
+            set_socket_option(self.fd, SOL_CAN_ISOTP, CAN_ISOTP_RX_STMIN, &duration_ns(st_min))?;
+        }
+
+        if let Some(ll) = options.link_layer {
+            if ll.mtu != CAN_MTU && ll.mtu != CANFD_MTU || ll.tx_dl as usize > CANFD_MAX_DLEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "invalid link layer options"));
+            }
+
+            let ll = LinkLayerOptionsRaw {
+                mtu: ll.mtu as u8,
+                tx_dl: ll.tx_dl,
+                tx_flags: ll.tx_flags.bits(),
+            };
+            set_socket_option(self.fd, SOL_CAN_ISOTP, CAN_ISOTP_LL_OPTS, &ll)?;
+        }
+
+        Ok(())
+    }
+
+    /// Change socket to non-blocking mode
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let mut nonblocking = nonblocking as c_int;
+        let rv = unsafe { libc::ioctl(self.fd, libc::FIONBIO, &mut nonblocking) };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sets the read timeout on the socket
+    ///
+    /// For convenience, the result value can be checked using
+    /// `ShouldRetry::should_retry` when a timeout is set.
+    pub fn set_read_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_SOCKET, SO_RCVTIMEO, &c_timeval_new(duration))
+    }
+
+    /// Sets the write timeout on the socket
+    pub fn set_write_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_SOCKET, SO_SNDTIMEO, &c_timeval_new(duration))
+    }
+
+    /// Blocking read of a single PDU into `buf`, returning its length.
+    ///
+    /// A PDU larger than `buf` is truncated.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let rv = unsafe { recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(rv as usize)
+    }
+
+    /// Blocking read of a single PDU of any length.
+    pub fn read_pdu(&self) -> io::Result<Vec<u8>> {
+        // peeking with MSG_TRUNC returns the full length of the PDU
+        let len = unsafe { recv(self.fd, ptr::null_mut(), 0, MSG_PEEK | MSG_TRUNC) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0; len as usize];
+        let len = self.read(&mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Write a single PDU.
+    ///
+    /// Unless `WAIT_TX_DONE` is set, this returns as soon as the transfer
+    /// started, a following write blocks until it is done.
+    pub fn write(&self, pdu: &[u8]) -> io::Result<()> {
+        let rv = unsafe { write(self.fd, pdu.as_ptr() as *const c_void, pdu.len()) };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if rv as usize != pdu.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Incomplete write"));
+        }
+        Ok(())
+    }
+}
+
+impl IsoTpTransport for IsoTpSocket {
+    fn read_pdu(&mut self) -> io::Result<Vec<u8>> {
+        IsoTpSocket::read_pdu(self)
+    }
+
+    fn write_pdu(&mut self, pdu: &[u8]) -> io::Result<()> {
+        self.write(pdu)
+    }
+
+    fn set_read_timeout(&mut self, duration: time::Duration) -> io::Result<()> {
+        IsoTpSocket::set_read_timeout(self, duration)
+    }
+}
+
+impl AsRawFd for IsoTpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl FromRawFd for IsoTpSocket {
+    unsafe fn from_raw_fd(fd: RawFd) -> IsoTpSocket {
+        IsoTpSocket { fd: fd }
+    }
+}
+
+impl IntoRawFd for IsoTpSocket {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for IsoTpSocket {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_raw_layout() {
+        assert_eq!(size_of::<IsoTpOptionsRaw>(), 12);
+        assert_eq!(size_of::<FlowControlOptionsRaw>(), 3);
+        assert_eq!(size_of::<LinkLayerOptionsRaw>(), 3);
+    }
+
+    #[test]
+    fn test_st_min() {
+        assert_eq!(st_min_from_duration(Duration::from_millis(0)), 0x00);
+        assert_eq!(st_min_from_duration(Duration::from_micros(100)), 0xf1);
+        assert_eq!(st_min_from_duration(Duration::from_micros(850)), 0xf9);
+        assert_eq!(st_min_from_duration(Duration::from_micros(950)), 0x01);
+        assert_eq!(st_min_from_duration(Duration::from_millis(20)), 0x14);
+        assert_eq!(st_min_from_duration(Duration::from_secs(1)), 0x7f);
+
+        assert_eq!(st_min_to_duration(0x14), Duration::from_millis(20));
+        assert_eq!(st_min_to_duration(0xf3), Duration::from_micros(300));
+        assert_eq!(st_min_to_duration(0x80), Duration::from_millis(127));
+        assert_eq!(st_min_to_duration(0xfa), Duration::from_millis(127));
+    }
+
+    #[test]
+    fn test_implied_flags() {
+        let options = IsoTpOptions {
+            ext_address: Some(0xf1),
+            tx_padding: Some(0xcc),
+            force_rx_st_min: Some(Duration::from_millis(1)),
+            ..IsoTpOptions::default()
+        };
+        let raw = options.raw();
+
+        assert_eq!(raw.flags,
+                   (IsoTpFlags::EXTEND_ADDR | IsoTpFlags::TX_PADDING | IsoTpFlags::FORCE_RXSTMIN)
+                       .bits());
+        assert_eq!(raw.ext_address, 0xf1);
+        assert_eq!(raw.txpad_content, 0xcc);
+    }
+}
@@ -0,0 +1,609 @@
+//! ISO-TP implemented in userspace on top of a `CanSocket`
+//!
+//! Sending and receiving block the calling thread for the whole transfer,
+//! including the flow control frames exchanged in between. The timing is as
+//! exact as the scheduler allows, which is good enough for most ECUs.
+
+use libc::ENOBUFS;
+use std::time::{Duration, Instant};
+use std::{cmp, io, thread};
+
+use super::{st_min_from_duration, st_min_to_duration, IsoTpFlags, IsoTpOptions, IsoTpTransport};
+use crate::{
+    canfd_valid_len, raw_id, CanFdFrame, CanFilter, CanFrame, CanMessageId, CanSocket,
+    CanSocketOpenError, FrameFlags, ShouldRetry, CANFD_MAX_DLEN, CANFD_MTU, CAN_MTU, EFF_MASK,
+};
+
+// protocol control information, upper nibble of the first byte
+const PCI_SF: u8 = 0x00;
+const PCI_FF: u8 = 0x10;
+const PCI_CF: u8 = 0x20;
+const PCI_FC: u8 = 0x30;
+
+// flow status of flow control frames
+const FC_CTS: u8 = 0;
+const FC_WAIT: u8 = 1;
+const FC_OVFLW: u8 = 2;
+
+/// padding of CAN FD frames to a valid length if no padding is set
+const FD_PAD: u8 = 0xcc;
+
+/// Network layer timeouts of ISO 15765-2
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IsoTpTimeouts {
+    /// time for a frame to be sent
+    pub n_as: Duration,
+
+    /// time the sender waits for a flow control frame
+    pub n_bs: Duration,
+
+    /// time the receiver waits for the next consecutive frame
+    pub n_cr: Duration,
+}
+
+impl Default for IsoTpTimeouts {
+    fn default() -> IsoTpTimeouts {
+        IsoTpTimeouts {
+            n_as: Duration::from_millis(1000),
+            n_bs: Duration::from_millis(1000),
+            n_cr: Duration::from_millis(1000),
+        }
+    }
+}
+
+/// A received frame, by its protocol control information
+#[derive(Debug, PartialEq, Eq)]
+enum Pci<'a> {
+    Single(&'a [u8]),
+    First { len: usize, data: &'a [u8] },
+    Consecutive { sn: u8, data: &'a [u8] },
+    FlowControl { status: u8, block_size: u8, st_min: u8 },
+}
+
+/// Framing of PDUs, independent of the socket
+#[derive(Debug)]
+struct Codec {
+    ext_address: Option<u8>,
+    rx_ext_address: Option<u8>,
+    padding: Option<u8>,
+
+    /// data length of sent frames
+    tx_dl: usize,
+}
+
+impl Codec {
+    fn new(options: &IsoTpOptions) -> Codec {
+        Codec {
+            ext_address: options.ext_address,
+            rx_ext_address: options.rx_ext_address.or(options.ext_address),
+            padding: options.tx_padding,
+            tx_dl: options.link_layer.map(|ll| ll.tx_dl as usize).unwrap_or(8),
+        }
+    }
+
+    /// length of the address preceding the PCI
+    fn offset(&self) -> usize {
+        if self.ext_address.is_some() { 1 } else { 0 }
+    }
+
+    /// Adds the address and padding to the PCI and payload of a frame.
+    fn frame(&self, pci: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.tx_dl);
+        frame.extend(self.ext_address);
+        frame.extend_from_slice(pci);
+        frame.extend_from_slice(data);
+
+        let len = match self.padding {
+            Some(_) => self.tx_dl,
+            None => canfd_valid_len(frame.len()),
+        };
+        frame.resize(len, self.padding.unwrap_or(FD_PAD));
+        frame
+    }
+
+    /// largest PDU sent in a single frame
+    fn max_single(&self) -> usize {
+        if self.tx_dl > 8 {
+            self.tx_dl - 2 - self.offset()
+        } else {
+            7 - self.offset()
+        }
+    }
+
+    fn single_frame(&self, pdu: &[u8]) -> Vec<u8> {
+        if pdu.len() <= 7 - self.offset() {
+            self.frame(&[PCI_SF | pdu.len() as u8], pdu)
+        } else {
+            // escape sequence of CAN FD, the length follows the PCI
+            self.frame(&[PCI_SF, pdu.len() as u8], pdu)
+        }
+    }
+
+    /// First frame of a segmented PDU, along with the number of bytes of the
+    /// PDU it holds.
+    fn first_frame(&self, pdu: &[u8]) -> (Vec<u8>, usize) {
+        let len = pdu.len();
+        let pci = if len <= super::ISOTP_MAX_PDU {
+            vec![PCI_FF | (len >> 8) as u8, len as u8]
+        } else {
+            // escape sequence, a zero length followed by 32 bits
+            vec![PCI_FF, 0, (len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]
+        };
+
+        let n = self.tx_dl - self.offset() - pci.len();
+        (self.frame(&pci, &pdu[..n]), n)
+    }
+
+    fn consecutive_frame(&self, sn: u8, data: &[u8]) -> Vec<u8> {
+        self.frame(&[PCI_CF | (sn & 0x0f)], data)
+    }
+
+    /// bytes of the PDU held by a consecutive frame
+    fn cf_payload(&self) -> usize {
+        self.tx_dl - 1 - self.offset()
+    }
+
+    fn flow_control(&self, status: u8, block_size: u8, st_min: u8) -> Vec<u8> {
+        self.frame(&[PCI_FC | status, block_size, st_min], &[])
+    }
+
+    /// Parses a received frame, `None` if it is malformed or for another
+    /// address.
+    fn parse<'a>(&self, data: &'a [u8]) -> Option<Pci<'a>> {
+        let data = match self.rx_ext_address {
+            Some(addr) if data.first() == Some(&addr) => &data[1..],
+            Some(_) => return None,
+            None => data,
+        };
+
+        let pci = *data.first()?;
+        match pci & 0xf0 {
+            PCI_SF => {
+                let (len, payload) = match pci & 0x0f {
+                    0 if data.len() > 2 => (data[1] as usize, &data[2..]),
+                    0 => return None,
+                    len => (len as usize, &data[1..]),
+                };
+                if len == 0 || len > payload.len() {
+                    return None;
+                }
+                Some(Pci::Single(&payload[..len]))
+            }
+            PCI_FF if data.len() >= 2 => {
+                let (len, payload) = match ((pci as usize & 0x0f) << 8) | data[1] as usize {
+                    0 if data.len() >= 6 => {
+                        (data[2..6].iter().fold(0, |len, &b| len << 8 | b as usize), &data[6..])
+                    }
+                    0 => return None,
+                    len => (len, &data[2..]),
+                };
+                // a malformed first frame may announce less than it carries
+                let data = &payload[..cmp::min(len, payload.len())];
+                Some(Pci::First { len: len, data: data })
+            }
+            PCI_CF => Some(Pci::Consecutive { sn: pci & 0x0f, data: &data[1..] }),
+            PCI_FC if data.len() >= 3 => {
+                Some(Pci::FlowControl {
+                    status: pci & 0x0f,
+                    block_size: data[1],
+                    st_min: data[2],
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Turns a timeout of the socket into a timeout of the protocol.
+fn protocol_timeout(e: io::Error, timer: &str) -> io::Error {
+    if e.should_retry() {
+        io::Error::new(io::ErrorKind::TimedOut, format!("{} timeout", timer))
+    } else {
+        e
+    }
+}
+
+/// An ISO-TP connection handled in userspace.
+///
+/// Offers the same interface as the kernel based `IsoTpSocket`, for kernels
+/// without `can-isotp`. All options are supported, except for checking the
+/// padding of received frames (`CHK_PAD_LEN` and `CHK_PAD_DATA`).
+#[derive(Debug)]
+pub struct UserIsoTpSocket {
+    sock: CanSocket,
+    tx_id: CanMessageId,
+    options: IsoTpOptions,
+    codec: Codec,
+    timeouts: IsoTpTimeouts,
+
+    /// whether CAN FD frames are used
+    fd: bool,
+
+    /// time `read_pdu` waits for a PDU to start, `None` waits forever
+    read_timeout: Option<Duration>,
+}
+
+impl UserIsoTpSocket {
+    /// Open an ISO-TP connection on a named CAN device.
+    ///
+    /// Frames with the id `rx_id` are received, frames with `tx_id` are sent.
+    pub fn open(ifname: &str,
+                rx_id: CanMessageId,
+                tx_id: CanMessageId,
+                options: &IsoTpOptions)
+                -> Result<UserIsoTpSocket, CanSocketOpenError> {
+        let sock = CanSocket::open(ifname)?;
+        Ok(UserIsoTpSocket::new(sock, rx_id, tx_id, options)?)
+    }
+
+    /// Runs an ISO-TP connection on an open socket, replacing its filters.
+    pub fn new(sock: CanSocket,
+               rx_id: CanMessageId,
+               tx_id: CanMessageId,
+               options: &IsoTpOptions)
+               -> io::Result<UserIsoTpSocket> {
+        if options.flags.intersects(IsoTpFlags::CHK_PAD_LEN | IsoTpFlags::CHK_PAD_DATA) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "checking the padding is not supported"));
+        }
+
+        let fd = match options.link_layer {
+            None => false,
+            Some(ll) if ll.mtu == CAN_MTU && ll.tx_dl == 8 => false,
+            Some(ll) if ll.mtu == CANFD_MTU && ll.tx_dl >= 8 && ll.tx_dl as usize <= CANFD_MAX_DLEN &&
+                        canfd_valid_len(ll.tx_dl as usize) == ll.tx_dl as usize => true,
+            Some(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "invalid link layer options"));
+            }
+        };
+
+        let invalid_id = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        raw_id(tx_id).map_err(invalid_id)?;
+        let filter = CanFilter::new(raw_id(rx_id).map_err(invalid_id)?,
+                                    EFF_MASK | (FrameFlags::EFF_FLAG | FrameFlags::RTR_FLAG).bits())
+            .map_err(invalid_id)?;
+        sock.set_filters(&[filter])?;
+        if fd {
+            sock.set_fd_frames(true)?;
+        }
+
+        let timeouts = IsoTpTimeouts::default();
+        sock.set_write_timeout(timeouts.n_as)?;
+
+        Ok(UserIsoTpSocket {
+            sock: sock,
+            tx_id: tx_id,
+            options: options.clone(),
+            codec: Codec::new(options),
+            timeouts: timeouts,
+            fd: fd,
+            read_timeout: None,
+        })
+    }
+
+    /// Change the network layer timeouts.
+    pub fn set_timeouts(&mut self, timeouts: IsoTpTimeouts) -> io::Result<()> {
+        self.sock.set_write_timeout(timeouts.n_as)?;
+        self.timeouts = timeouts;
+        Ok(())
+    }
+
+    /// Sets the time `read_pdu` waits for the start of a PDU, zero waits
+    /// forever.
+    ///
+    /// Like for sockets, the error can be checked using
+    /// `ShouldRetry::should_retry`.
+    pub fn set_read_timeout(&mut self, duration: Duration) -> io::Result<()> {
+        self.read_timeout = if duration == Duration::from_secs(0) {
+            None
+        } else {
+            Some(duration)
+        };
+        Ok(())
+    }
+
+    /// Receives the data of the next frame, waiting until `deadline`.
+    fn recv(&self, deadline: Option<Instant>) -> io::Result<Vec<u8>> {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                // a zero timeout would block forever
+                if deadline <= now || deadline - now < Duration::from_micros(1) {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                deadline - now
+            }
+            None => Duration::from_secs(0),
+        };
+        self.sock.set_read_timeout(timeout)?;
+
+        if self.fd {
+            Ok(self.sock.read_fd_frame()?.data().to_vec())
+        } else {
+            Ok(self.sock.read_frame()?.data().to_vec())
+        }
+    }
+
+    /// Sends a single frame, retrying while the transmit queue is full.
+    fn send(&self, data: &[u8]) -> io::Result<()> {
+        let deadline = Instant::now() + self.timeouts.n_as;
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+
+        loop {
+            let rv = if self.fd {
+                let tx_flags = self.options.link_layer.map(|ll| ll.tx_flags).unwrap_or_default();
+                self.sock.write_fd_frame(&CanFdFrame::new(self.tx_id, data, tx_flags).map_err(invalid)?)
+            } else {
+                self.sock.write_frame(&CanFrame::new(self.tx_id, data, false, false).map_err(invalid)?)
+            };
+
+            match rv {
+                Ok(()) => return Ok(()),
+                Err(ref e) if e.raw_os_error() == Some(ENOBUFS) && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(ref e) if e.raw_os_error() == Some(ENOBUFS) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "N_As timeout"));
+                }
+                Err(e) => return Err(protocol_timeout(e, "N_As")),
+            }
+        }
+    }
+
+    /// Waits for the flow control of the receiver, returning the block size
+    /// and the separation time.
+    fn recv_flow_control(&self) -> io::Result<(u8, Duration)> {
+        let mut deadline = Instant::now() + self.timeouts.n_bs;
+
+        loop {
+            let data = self.recv(Some(deadline)).map_err(|e| protocol_timeout(e, "N_Bs"))?;
+            match self.codec.parse(&data) {
+                Some(Pci::FlowControl { status: FC_CTS, block_size, st_min }) => {
+                    let st_min = self.options
+                                     .force_tx_st_min
+                                     .unwrap_or_else(|| st_min_to_duration(st_min));
+                    return Ok((block_size, st_min));
+                }
+                Some(Pci::FlowControl { status: FC_WAIT, .. }) => {
+                    deadline = Instant::now() + self.timeouts.n_bs;
+                }
+                Some(Pci::FlowControl { status: FC_OVFLW, .. }) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "PDU too large for the receiver"));
+                }
+                Some(Pci::FlowControl { .. }) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid flow status"));
+                }
+                // frames of other transfers are dropped while sending
+                _ => (),
+            }
+        }
+    }
+
+    /// Blocking write of a single PDU.
+    pub fn write(&mut self, pdu: &[u8]) -> io::Result<()> {
+        let flags = self.options.flags;
+        if flags.contains(IsoTpFlags::LISTEN_MODE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot send in listen mode"));
+        }
+        if pdu.is_empty() || pdu.len() as u64 > u64::from(u32::max_value()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid PDU length"));
+        }
+
+        if pdu.len() <= self.codec.max_single() {
+            return self.send(&self.codec.single_frame(pdu));
+        }
+        if flags.contains(IsoTpFlags::SF_BROADCAST) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "PDU does not fit a single frame"));
+        }
+
+        let (first, mut pos) = self.codec.first_frame(pdu);
+        self.send(&first)?;
+
+        let broadcast = flags.contains(IsoTpFlags::CF_BROADCAST);
+        let (mut block_size, mut st_min) = if broadcast {
+            (0, self.options.frame_txtime.unwrap_or_default())
+        } else {
+            self.recv_flow_control()?
+        };
+
+        let mut sn = 1;
+        let mut sent_in_block = 0;
+        while pos < pdu.len() {
+            if block_size != 0 && sent_in_block == block_size {
+                let fc = self.recv_flow_control()?;
+                block_size = fc.0;
+                st_min = fc.1;
+                sent_in_block = 0;
+            } else if sent_in_block != 0 {
+                thread::sleep(st_min);
+            }
+
+            let end = cmp::min(pos + self.codec.cf_payload(), pdu.len());
+            self.send(&self.codec.consecutive_frame(sn, &pdu[pos..end]))?;
+
+            pos = end;
+            sn = (sn + 1) & 0x0f;
+            sent_in_block += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Blocking read of a single PDU.
+    pub fn read_pdu(&mut self) -> io::Result<Vec<u8>> {
+        let listen = self.options.flags.contains(IsoTpFlags::LISTEN_MODE);
+        let fc = self.options.flow_control;
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+
+        let mut next = self.recv(deadline)?;
+        'pdu: loop {
+            let (len, mut pdu) = match self.codec.parse(&next) {
+                Some(Pci::Single(data)) => return Ok(data.to_vec()),
+                Some(Pci::First { len, data }) => (len, data.to_vec()),
+                _ => {
+                    next = self.recv(deadline)?;
+                    continue;
+                }
+            };
+
+            let flow_control = self.codec.flow_control(FC_CTS, fc.block_size, st_min_from_duration(fc.st_min));
+            if !listen {
+                self.send(&flow_control)?;
+            }
+
+            let mut sn = 1;
+            let mut in_block = 0;
+            let mut last_cf: Option<Instant> = None;
+            while pdu.len() < len {
+                let deadline = Instant::now() + self.timeouts.n_cr;
+                let data = self.recv(Some(deadline)).map_err(|e| protocol_timeout(e, "N_Cr"))?;
+
+                let restart = match self.codec.parse(&data) {
+                    Some(Pci::Consecutive { sn: got, data: payload }) => {
+                        if let (Some(st_min), Some(last)) = (self.options.force_rx_st_min, last_cf) {
+                            if last.elapsed() < st_min {
+                                continue;
+                            }
+                        }
+                        last_cf = Some(Instant::now());
+
+                        if got != sn {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "wrong sequence number"));
+                        }
+
+                        let n = cmp::min(payload.len(), len - pdu.len());
+                        pdu.extend_from_slice(&payload[..n]);
+                        sn = (sn + 1) & 0x0f;
+                        in_block += 1;
+
+                        if fc.block_size != 0 && in_block == fc.block_size && pdu.len() < len {
+                            if !listen {
+                                self.send(&flow_control)?;
+                            }
+                            in_block = 0;
+                        }
+                        false
+                    }
+                    // a new transfer aborts the current one
+                    Some(Pci::Single(_)) | Some(Pci::First { .. }) => true,
+                    _ => false,
+                };
+
+                if restart {
+                    next = data;
+                    continue 'pdu;
+                }
+            }
+
+            return Ok(pdu);
+        }
+    }
+}
+
+impl IsoTpTransport for UserIsoTpSocket {
+    fn read_pdu(&mut self) -> io::Result<Vec<u8>> {
+        UserIsoTpSocket::read_pdu(self)
+    }
+
+    fn write_pdu(&mut self, pdu: &[u8]) -> io::Result<()> {
+        self.write(pdu)
+    }
+
+    fn set_read_timeout(&mut self, duration: Duration) -> io::Result<()> {
+        UserIsoTpSocket::set_read_timeout(self, duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codec, Pci};
+    use crate::isotp::{IsoTpOptions, LinkLayerOptions};
+    use crate::FdFlags;
+
+    fn reassemble(codec: &Codec, pdu: &[u8]) -> Vec<u8> {
+        let (first, mut pos) = codec.first_frame(pdu);
+        let (len, mut out) = match codec.parse(&first) {
+            Some(Pci::First { len, data }) => (len, data.to_vec()),
+            other => panic!("not a first frame: {:?}", other),
+        };
+        assert_eq!(len, pdu.len());
+
+        let mut sn = 1;
+        while pos < pdu.len() {
+            let end = ::std::cmp::min(pos + codec.cf_payload(), pdu.len());
+            let cf = codec.consecutive_frame(sn, &pdu[pos..end]);
+            match codec.parse(&cf) {
+                Some(Pci::Consecutive { sn: got, data }) => {
+                    assert_eq!(got, sn);
+                    let n = ::std::cmp::min(data.len(), len - out.len());
+                    out.extend_from_slice(&data[..n]);
+                }
+                other => panic!("not a consecutive frame: {:?}", other),
+            }
+            pos = end;
+            sn = (sn + 1) & 0x0f;
+        }
+        out
+    }
+
+    #[test]
+    fn test_single_frame() {
+        let codec = Codec::new(&IsoTpOptions::default());
+        assert_eq!(codec.single_frame(&[0x3e, 0x00]), vec![0x02, 0x3e, 0x00]);
+        assert_eq!(codec.parse(&[0x02, 0x3e, 0x00]), Some(Pci::Single(&[0x3e, 0x00])));
+        assert_eq!(codec.parse(&[0x05, 0x3e, 0x00]), None);
+
+        let codec = Codec::new(&IsoTpOptions {
+            ext_address: Some(0xf1),
+            tx_padding: Some(0xaa),
+            ..IsoTpOptions::default()
+        });
+        assert_eq!(codec.max_single(), 6);
+        assert_eq!(codec.single_frame(&[0x3e]), vec![0xf1, 0x01, 0x3e, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa]);
+        assert_eq!(codec.parse(&[0xf1, 0x01, 0x3e, 0xaa]), Some(Pci::Single(&[0x3e])));
+        assert_eq!(codec.parse(&[0xf2, 0x01, 0x3e, 0xaa]), None);
+    }
+
+    #[test]
+    fn test_segmented() {
+        let codec = Codec::new(&IsoTpOptions::default());
+        let pdu: Vec<u8> = (0..100).collect();
+
+        let (first, n) = codec.first_frame(&pdu);
+        assert_eq!(&first[..3], &[0x10, 100, 0]);
+        assert_eq!(n, 6);
+        assert_eq!(reassemble(&codec, &pdu), pdu);
+
+        // the payload of a first frame is limited to the announced length
+        assert_eq!(codec.parse(&[0x10, 0x03, 1, 2, 3, 4, 5, 6]),
+                   Some(Pci::First { len: 3, data: &[1, 2, 3] }));
+
+        let fc = codec.flow_control(0, 8, 0x14);
+        assert_eq!(codec.parse(&fc), Some(Pci::FlowControl { status: 0, block_size: 8, st_min: 0x14 }));
+    }
+
+    #[test]
+    fn test_fd_escape() {
+        let codec = Codec::new(&IsoTpOptions {
+            link_layer: Some(LinkLayerOptions::fd(64, FdFlags::BRS)),
+            ..IsoTpOptions::default()
+        });
+
+        // single frames with more than 7 bytes carry their length separately
+        let pdu: Vec<u8> = (0..20).collect();
+        let sf = codec.single_frame(&pdu);
+        assert_eq!(sf.len(), 24);
+        assert_eq!(&sf[..3], &[0x00, 20, 0]);
+        assert_eq!(codec.parse(&sf), Some(Pci::Single(&pdu[..])));
+
+        // PDUs beyond 4095 bytes use a 32 bit length
+        let pdu: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let (first, n) = codec.first_frame(&pdu);
+        assert_eq!(&first[..6], &[0x10, 0x00, 0x00, 0x00, 0x13, 0x88]);
+        assert_eq!(n, 58);
+        assert_eq!(reassemble(&codec, &pdu), pdu);
+    }
+}
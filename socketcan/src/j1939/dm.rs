@@ -0,0 +1,149 @@
+//! Diagnostic messages of J1939-73
+
+/// DM1, active diagnostic trouble codes
+pub const J1939_PGN_DM1: u32 = 0x0feca;
+
+/// DM2, previously active diagnostic trouble codes
+pub const J1939_PGN_DM2: u32 = 0x0fecb;
+
+/// State of a warning lamp
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LampStatus {
+    Off,
+    On,
+    Error,
+    NotAvailable,
+}
+
+/// Flashing of a warning lamp
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlashStatus {
+    Slow,
+    Fast,
+    Reserved,
+    /// not flashing, or not available
+    Off,
+}
+
+/// A warning lamp
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lamp {
+    pub status: LampStatus,
+    pub flash: FlashStatus,
+}
+
+impl Lamp {
+    /// Decode the two bit fields at `shift`.
+    fn decode(status: u8, flash: u8, shift: u8) -> Lamp {
+        let status = match (status >> shift) & 0x03 {
+            0 => LampStatus::Off,
+            1 => LampStatus::On,
+            2 => LampStatus::Error,
+            _ => LampStatus::NotAvailable,
+        };
+        let flash = match (flash >> shift) & 0x03 {
+            0 => FlashStatus::Slow,
+            1 => FlashStatus::Fast,
+            2 => FlashStatus::Reserved,
+            _ => FlashStatus::Off,
+        };
+        Lamp {
+            status: status,
+            flash: flash,
+        }
+    }
+}
+
+/// A diagnostic trouble code
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Dtc {
+    /// suspect parameter number of the fault
+    pub spn: u32,
+
+    /// failure mode identifier
+    pub fmi: u8,
+
+    /// number of times the fault became active, 127 if not available
+    pub occurrence_count: u8,
+
+    /// SPN conversion method bit, set by ECUs using outdated byte orders
+    pub conversion_method: bool,
+}
+
+impl Dtc {
+    /// Decode the 4 bytes of a DTC.
+    ///
+    /// The SPN is decoded with the current conversion method (version 4),
+    /// regardless of the conversion method bit.
+    pub fn decode(data: &[u8; 4]) -> Dtc {
+        Dtc {
+            spn: u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2] & 0xe0) << 11,
+            fmi: data[2] & 0x1f,
+            occurrence_count: data[3] & 0x7f,
+            conversion_method: data[3] & 0x80 != 0,
+        }
+    }
+}
+
+/// The lamps and trouble codes of DM1 and DM2 messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dm1 {
+    pub malfunction_indicator: Lamp,
+    pub red_stop: Lamp,
+    pub amber_warning: Lamp,
+    pub protect: Lamp,
+    pub dtcs: Vec<Dtc>,
+}
+
+impl Dm1 {
+    /// Decode the data of a DM1 or DM2 message, `None` if it is too short.
+    ///
+    /// Messages with up to one DTC fit a single frame, more are sent with
+    /// the transport protocol, see `J1939Reassembler`. Placeholder DTCs of
+    /// messages without faults (all zeros or all ones) are skipped.
+    pub fn decode(data: &[u8]) -> Option<Dm1> {
+        if data.len() < 2 {
+            return None;
+        }
+
+        let (status, flash) = (data[0], data[1]);
+        let dtcs = data[2..].chunks(4)
+                            .filter(|b| b.len() == 4)
+                            .filter(|b| b.iter().any(|&c| c != 0) && b.iter().any(|&c| c != 0xff))
+                            .map(|b| Dtc::decode(&[b[0], b[1], b[2], b[3]]))
+                            .collect();
+
+        Some(Dm1 {
+            malfunction_indicator: Lamp::decode(status, flash, 6),
+            red_stop: Lamp::decode(status, flash, 4),
+            amber_warning: Lamp::decode(status, flash, 2),
+            protect: Lamp::decode(status, flash, 0),
+            dtcs: dtcs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_faults() {
+        let dm1 = Dm1::decode(&[0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff]).unwrap();
+        assert_eq!(dm1.malfunction_indicator, Lamp { status: LampStatus::Off, flash: FlashStatus::Off });
+        assert!(dm1.dtcs.is_empty());
+        assert_eq!(Dm1::decode(&[0x00]), None);
+    }
+
+    #[test]
+    fn test_dtcs() {
+        // amber warning lamp on, SPN 100 FMI 1 seen 3 times and SPN 524287
+        // FMI 31 once
+        let dm1 = Dm1::decode(&[0x04, 0xff, 0x64, 0x00, 0x01, 0x03, 0xff, 0xff, 0xff, 0x01]).unwrap();
+        assert_eq!(dm1.amber_warning.status, LampStatus::On);
+        assert_eq!(dm1.red_stop.status, LampStatus::Off);
+        assert_eq!(dm1.dtcs,
+                   vec![Dtc { spn: 100, fmi: 1, occurrence_count: 3, conversion_method: false },
+                        Dtc { spn: 0x7ffff, fmi: 31, occurrence_count: 1, conversion_method: false }]);
+    }
+}
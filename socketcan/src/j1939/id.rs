@@ -0,0 +1,230 @@
+//! J1939 identifiers and parameters of raw CAN frames
+
+use super::J1939_NO_ADDR;
+use crate::{CanFrame, CanMessageId, EFF_MASK};
+
+/// first PDU format of PDU2 (broadcast) PGNs
+const PDU2_MIN_PF: u32 = 240;
+
+/// Check if `pgn` is a PDU1 format PGN, which is sent to a destination
+/// address.
+#[inline]
+pub fn pgn_is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xff < PDU2_MIN_PF
+}
+
+/// Fields of a 29 bit J1939 identifier
+///
+/// For PDU1 format PGNs, the lower 8 bits of the PGN hold the destination
+/// address on the bus and are zero in `pgn`. PDU2 format PGNs are always
+/// broadcast, `dst_addr` is `J1939_NO_ADDR` for them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct J1939Id {
+    /// priority, 0 (highest) to 7
+    pub priority: u8,
+
+    /// parameter group number, including the (extended) data page bits
+    pub pgn: u32,
+
+    pub src_addr: u8,
+    pub dst_addr: u8,
+}
+
+impl J1939Id {
+    pub fn new(priority: u8, pgn: u32, src_addr: u8, dst_addr: u8) -> J1939Id {
+        J1939Id {
+            priority: priority,
+            pgn: pgn,
+            src_addr: src_addr,
+            dst_addr: dst_addr,
+        }
+    }
+
+    /// Split a 29 bit identifier into its fields.
+    pub fn from_raw(id: u32) -> J1939Id {
+        let id = id & EFF_MASK;
+        let pgn = (id >> 8) & 0x3ffff;
+
+        let (pgn, dst_addr) = if pgn_is_pdu1(pgn) {
+            (pgn & 0x3ff00, (pgn & 0xff) as u8)
+        } else {
+            (pgn, J1939_NO_ADDR)
+        };
+
+        J1939Id {
+            priority: (id >> 26) as u8 & 0x07,
+            pgn: pgn,
+            src_addr: id as u8,
+            dst_addr: dst_addr,
+        }
+    }
+
+    /// Fields of the identifier of a frame, `None` for 11 bit identifiers.
+    pub fn from_frame(frame: &CanFrame) -> Option<J1939Id> {
+        if frame.is_extended() {
+            Some(J1939Id::from_raw(frame.id()))
+        } else {
+            None
+        }
+    }
+
+    /// Assemble the 29 bit identifier.
+    ///
+    /// The destination address is ignored for PDU2 format PGNs.
+    pub fn to_raw(&self) -> u32 {
+        let pgn = if pgn_is_pdu1(self.pgn) {
+            (self.pgn & 0x3ff00) | u32::from(self.dst_addr)
+        } else {
+            self.pgn & 0x3ffff
+        };
+
+        u32::from(self.priority & 0x07) << 26 | pgn << 8 | u32::from(self.src_addr)
+    }
+
+    /// The identifier for a `CanFrame`
+    pub fn message_id(&self) -> CanMessageId {
+        CanMessageId::EFF(self.to_raw())
+    }
+
+    /// Check if the PGN is sent to a destination address.
+    #[inline]
+    pub fn is_pdu1(&self) -> bool {
+        pgn_is_pdu1(self.pgn)
+    }
+}
+
+/// A decoded suspect parameter
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpnValue {
+    /// a valid value, scaled to its unit
+    Value(f64),
+
+    /// a value in the range reserved for indicators
+    Reserved,
+
+    /// the sender signals an error
+    Error,
+
+    /// the sender does not support the parameter
+    NotAvailable,
+}
+
+/// Location and scaling of a suspect parameter (SPN) in the data of a PGN
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Spn {
+    /// position of the least significant bit, counting from bit 0 of byte 0
+    pub start_bit: usize,
+
+    /// length in bits, up to 64
+    pub bits: usize,
+
+    /// scale of a raw value step
+    pub resolution: f64,
+
+    /// added to the scaled value
+    pub offset: f64,
+}
+
+impl Spn {
+    pub fn new(start_bit: usize, bits: usize, resolution: f64, offset: f64) -> Spn {
+        Spn {
+            start_bit: start_bit,
+            bits: bits,
+            resolution: resolution,
+            offset: offset,
+        }
+    }
+
+    /// The raw bits of the parameter, `None` if `data` is too short.
+    ///
+    /// Parameters are stored little endian (Intel byte order).
+    pub fn raw(&self, data: &[u8]) -> Option<u64> {
+        if self.bits == 0 || self.bits > 64 || self.start_bit + self.bits > data.len() * 8 {
+            return None;
+        }
+
+        let mut raw = 0u64;
+        for i in 0..self.bits {
+            let bit = self.start_bit + i;
+            if data[bit / 8] & (1 << (bit % 8)) != 0 {
+                raw |= 1 << i;
+            }
+        }
+        Some(raw)
+    }
+
+    /// Decode the parameter, `None` if `data` is too short.
+    ///
+    /// The highest values signal errors and missing values: for parameters
+    /// of a byte or more, the most significant byte is `0xff` if not
+    /// available, `0xfe` on errors and `0xfb` to `0xfd` for indicators.
+    /// Shorter (discrete) parameters use all bits set and the value below.
+    pub fn decode(&self, data: &[u8]) -> Option<SpnValue> {
+        let raw = self.raw(data)?;
+        let all = if self.bits == 64 { !0 } else { (1u64 << self.bits) - 1 };
+
+        let value = if self.bits < 8 {
+            match raw {
+                _ if raw == all => SpnValue::NotAvailable,
+                _ if raw == all - 1 && self.bits > 1 => SpnValue::Error,
+                _ => SpnValue::Value(raw as f64 * self.resolution + self.offset),
+            }
+        } else {
+            match raw >> (self.bits - 8) {
+                0xff => SpnValue::NotAvailable,
+                0xfe => SpnValue::Error,
+                0xfb..=0xfd => SpnValue::Reserved,
+                _ => SpnValue::Value(raw as f64 * self.resolution + self.offset),
+            }
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{J1939Id, Spn, SpnValue};
+    use crate::j1939::J1939_NO_ADDR;
+
+    #[test]
+    fn test_id() {
+        // EEC1 from address 0x00
+        let id = J1939Id::from_raw(0x0cf00400);
+        assert_eq!(id, J1939Id::new(3, 0xf004, 0x00, J1939_NO_ADDR));
+        assert!(!id.is_pdu1());
+        assert_eq!(id.to_raw(), 0x0cf00400);
+
+        // request from 0xf9 to 0x00
+        let id = J1939Id::from_raw(0x18ea00f9);
+        assert_eq!(id, J1939Id::new(6, 0xea00, 0xf9, 0x00));
+        assert!(id.is_pdu1());
+        assert_eq!(id.to_raw(), 0x18ea00f9);
+
+        // data page bit
+        let id = J1939Id::from_raw(0x19fef1fe);
+        assert_eq!(id.pgn, 0x1fef1);
+        assert_eq!(id.to_raw(), 0x19fef1fe);
+    }
+
+    #[test]
+    fn test_spn() {
+        // engine speed (SPN 190) of EEC1, 0.125 rpm/bit
+        let data = [0xff, 0xff, 0xff, 0x68, 0x13, 0xff, 0xff, 0xff];
+        let rpm = Spn::new(24, 16, 0.125, 0.0);
+        assert_eq!(rpm.raw(&data), Some(0x1368));
+        assert_eq!(rpm.decode(&data), Some(SpnValue::Value(621.0)));
+        assert_eq!(rpm.decode(&data[..4]), None);
+
+        // engine torque mode (SPN 899), 4 bits
+        let mode = Spn::new(0, 4, 1.0, 0.0);
+        assert_eq!(mode.decode(&data), Some(SpnValue::NotAvailable));
+        assert_eq!(mode.decode(&[0x0e]), Some(SpnValue::Error));
+        assert_eq!(mode.decode(&[0x03]), Some(SpnValue::Value(3.0)));
+
+        // driver's demand torque (SPN 512), -125% offset
+        let torque = Spn::new(8, 8, 1.0, -125.0);
+        assert_eq!(torque.decode(&[0, 0xfe]), Some(SpnValue::Error));
+        assert_eq!(torque.decode(&[0, 0xfc]), Some(SpnValue::Reserved));
+        assert_eq!(torque.decode(&[0, 150]), Some(SpnValue::Value(25.0)));
+    }
+}
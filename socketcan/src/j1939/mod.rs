@@ -0,0 +1,799 @@
+//! SAE J1939 through the kernel's `CAN_J1939` protocol
+//!
+//! The kernel handles address claiming bookkeeping and the transport
+//! protocols (TP and ETP) for messages longer than 8 bytes, a `J1939Socket`
+//! sends and receives whole messages addressed by PGN, NAME and source
+//! address.
+//!
+//! ```no_run
+//! use socketcan::j1939::{J1939Addr, J1939Socket, J1939_NO_ADDR};
+//!
+//! let sock = J1939Socket::open("vcan0", &J1939Addr::with_addr(0x20)).unwrap();
+//! sock.set_broadcast(true).unwrap();
+//! sock.send_to(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], &J1939Addr::new(0, 0xfeca, J1939_NO_ADDR))
+//!     .unwrap();
+//!
+//! let msg = sock.recv_message().unwrap();
+//! println!("PGN {:05x} from {:02x}: {:02x?}", msg.src.pgn, msg.src.addr, msg.data);
+//! ```
+//!
+//! Without kernel support, or to decode logs, `J1939Id` splits the
+//! identifiers of raw CAN frames and `J1939Reassembler` reassembles the
+//! transport protocol sessions:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::j1939::{Dm1, J1939Reassembler, J1939_PGN_DM1};
+//!
+//! let mut sock = CanSocket::open("vcan0").unwrap();
+//! let mut reassembler = J1939Reassembler::new();
+//! loop {
+//!     let (frame, t) = sock.read_frame_with_timestamp().unwrap();
+//!     let t = t.duration_since(std::time::UNIX_EPOCH).unwrap();
+//!     let t_us = t.as_secs() * 1_000_000 + u64::from(t.subsec_micros());
+//!     if let Some(msg) = reassembler.push(&frame, t_us) {
+//!         if msg.src.pgn == J1939_PGN_DM1 {
+//!             println!("{:02x}: {:?}", msg.src.addr, Dm1::decode(&msg.data));
+//!         }
+//!     }
+//! }
+//! ```
+
+use libc::{
+    bind, c_int, c_short, c_uint, c_void, close, cmsghdr, connect, iovec, msghdr, recvmsg,
+    sendto, sockaddr, socket, socklen_t, write, MSG_ERRQUEUE, MSG_TRUNC, SOL_SOCKET,
+    SO_BROADCAST, SO_RCVTIMEO, SO_SNDTIMEO,
+};
+use nix::net::if_::if_nametoindex;
+use std::mem::{size_of, zeroed};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::{io, ptr, time};
+
+mod dm;
+mod id;
+mod transport;
+
+pub use self::dm::{Dm1, Dtc, FlashStatus, Lamp, LampStatus, J1939_PGN_DM1, J1939_PGN_DM2};
+pub use self::id::{pgn_is_pdu1, J1939Id, Spn, SpnValue};
+pub use self::transport::{J1939Reassembler, J1939_PGN_TP_CM, J1939_PGN_TP_DT};
+
+use crate::rtnl::{attr_struct, attr_u32, attr_u64, attr_u8, NlAttrs};
+use crate::util::{set_socket_option, set_socket_option_mult};
+use crate::{c_timeval_new, CanSocketOpenError, AF_CAN, PF_CAN, SOCK_DGRAM, SOL_CAN_BASE};
+
+// constants stolen from C headers (linux/can.h, linux/can/j1939.h)
+pub const CAN_J1939: c_int = 7;
+pub const SOL_CAN_J1939: c_int = SOL_CAN_BASE + CAN_J1939;
+const SO_J1939_FILTER: c_int = 1;
+const SO_J1939_PROMISC: c_int = 2;
+const SO_J1939_SEND_PRIO: c_int = 3;
+const SO_J1939_ERRQUEUE: c_int = 4;
+
+const SCM_J1939_DEST_ADDR: c_int = 1;
+const SCM_J1939_DEST_NAME: c_int = 2;
+const SCM_J1939_PRIO: c_int = 3;
+const SCM_J1939_ERRQUEUE: c_int = 4;
+
+const J1939_NLA_BYTES_ACKED: u16 = 1;
+const J1939_NLA_TOTAL_SIZE: u16 = 2;
+const J1939_NLA_PGN: u16 = 3;
+const J1939_NLA_SRC_NAME: u16 = 4;
+const J1939_NLA_DEST_NAME: u16 = 5;
+const J1939_NLA_SRC_ADDR: u16 = 6;
+const J1939_NLA_DEST_ADDR: u16 = 7;
+
+const J1939_EE_INFO_TX_ABORT: u32 = 1;
+const J1939_EE_INFO_RX_RTS: u32 = 2;
+const J1939_EE_INFO_RX_DPO: u32 = 3;
+const J1939_EE_INFO_RX_ABORT: u32 = 4;
+
+// (linux/net_tstamp.h, linux/errqueue.h, asm-generic/socket.h)
+const SO_TIMESTAMPING: c_int = 37;
+const SCM_TIMESTAMPING_OPT_STATS: c_int = 54;
+const SOF_TIMESTAMPING_OPT_ID: u32 = 1 << 7;
+const SOF_TIMESTAMPING_TX_SCHED: u32 = 1 << 8;
+const SOF_TIMESTAMPING_TX_ACK: u32 = 1 << 9;
+const SOF_TIMESTAMPING_OPT_CMSG: u32 = 1 << 10;
+const SOF_TIMESTAMPING_OPT_TSONLY: u32 = 1 << 11;
+const SOF_TIMESTAMPING_OPT_STATS: u32 = 1 << 12;
+const SO_EE_ORIGIN_LOCAL: u8 = 1;
+const SO_EE_ORIGIN_TIMESTAMPING: u8 = 4;
+const SCM_TSTAMP_SCHED: u32 = 1;
+const SCM_TSTAMP_ACK: u32 = 2;
+
+/// no NAME, e.g. when the socket only uses a static address
+pub const J1939_NO_NAME: u64 = 0;
+
+/// no PGN, receives all PGNs when bound
+pub const J1939_NO_PGN: u32 = 0x40000;
+
+/// no address, or the global (broadcast) address when sending
+pub const J1939_NO_ADDR: u8 = 0xff;
+
+/// the address of a node that could not claim one
+pub const J1939_IDLE_ADDR: u8 = 0xfe;
+
+/// all bits of a PGN
+pub const J1939_PGN_MAX: u32 = 0x3ffff;
+
+/// Request
+pub const J1939_PGN_REQUEST: u32 = 0x0ea00;
+
+/// Address Claimed
+pub const J1939_PGN_ADDRESS_CLAIMED: u32 = 0x0ee00;
+
+/// Commanded Address
+pub const J1939_PGN_ADDRESS_COMMANDED: u32 = 0x0fed8;
+
+/// Largest message of the transport protocol (TP)
+pub const J1939_MAX_TP_PACKET_SIZE: usize = 1785;
+
+/// Largest message of the extended transport protocol (ETP)
+pub const J1939_MAX_ETP_PACKET_SIZE: usize = 7 * 0x00ff_ffff;
+
+/// size of the buffer for control messages of `recvmsg`
+const CONTROL_LEN: usize = 256;
+
+/// Addressing of a J1939 message: NAME, PGN and address
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct J1939Addr {
+    /// 64 bit NAME, `J1939_NO_NAME` if not used
+    pub name: u64,
+
+    /// parameter group number, `J1939_NO_PGN` if not used
+    pub pgn: u32,
+
+    /// 8 bit address, `J1939_NO_ADDR` if not used
+    pub addr: u8,
+}
+
+impl J1939Addr {
+    pub fn new(name: u64, pgn: u32, addr: u8) -> J1939Addr {
+        J1939Addr {
+            name: name,
+            pgn: pgn,
+            addr: addr,
+        }
+    }
+
+    /// A static address, without NAME or PGN.
+    pub fn with_addr(addr: u8) -> J1939Addr {
+        J1939Addr::new(J1939_NO_NAME, J1939_NO_PGN, addr)
+    }
+}
+
+impl Default for J1939Addr {
+    fn default() -> J1939Addr {
+        J1939Addr::new(J1939_NO_NAME, J1939_NO_PGN, J1939_NO_ADDR)
+    }
+}
+
+/// `struct sockaddr_can` with its J1939 part, which does not fit `CanAddr`
+#[derive(Debug)]
+#[repr(C)]
+struct J1939SockAddr {
+    _af_can: c_short,
+    if_index: c_int,
+    name: u64,
+    pgn: u32,
+    addr: u8,
+}
+
+impl J1939SockAddr {
+    fn new(if_index: c_int, addr: &J1939Addr) -> J1939SockAddr {
+        J1939SockAddr {
+            _af_can: AF_CAN as c_short,
+            if_index: if_index,
+            name: addr.name,
+            pgn: addr.pgn,
+            addr: addr.addr,
+        }
+    }
+
+    fn j1939_addr(&self) -> J1939Addr {
+        J1939Addr::new(self.name, self.pgn, self.addr)
+    }
+}
+
+/// `struct sock_extended_err`
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct SockExtendedErr {
+    ee_errno: u32,
+    ee_origin: u8,
+    ee_type: u8,
+    ee_code: u8,
+    ee_pad: u8,
+    ee_info: u32,
+    ee_data: u32,
+}
+
+/// Receive filter of a `J1939Socket`
+///
+/// Matches a message if its NAME, PGN and address match under the masks. The
+/// default filter matches all messages, the builder methods narrow it down.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct J1939Filter {
+    _name: u64,
+    _name_mask: u64,
+    _pgn: u32,
+    _pgn_mask: u32,
+    _addr: u8,
+    _addr_mask: u8,
+}
+
+impl J1939Filter {
+    pub fn new() -> J1939Filter {
+        J1939Filter::default()
+    }
+
+    /// Only match messages of the source NAME `name`.
+    pub fn name(mut self, name: u64) -> J1939Filter {
+        self._name = name;
+        self._name_mask = !0;
+        self
+    }
+
+    /// Only match messages with the PGN `pgn`.
+    pub fn pgn(mut self, pgn: u32) -> J1939Filter {
+        self._pgn = pgn;
+        self._pgn_mask = J1939_PGN_MAX;
+        self
+    }
+
+    /// Only match messages of the source address `addr`.
+    pub fn addr(mut self, addr: u8) -> J1939Filter {
+        self._addr = addr;
+        self._addr_mask = 0xff;
+        self
+    }
+}
+
+/// A received J1939 message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J1939Message {
+    pub data: Vec<u8>,
+
+    /// sender and PGN, the NAME is only known if the sender claimed its
+    /// address
+    pub src: J1939Addr,
+
+    /// destination address, `J1939_NO_ADDR` for broadcasts
+    pub dst_addr: u8,
+
+    /// destination NAME, if known
+    pub dst_name: u64,
+
+    /// priority, 0 (highest) to 7
+    pub priority: u8,
+}
+
+/// Kinds of events reported through the error queue
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum J1939EventKind {
+    /// a message has been queued for sending
+    TxScheduled,
+
+    /// a message has been sent completely
+    TxDone,
+
+    /// sending a message was aborted
+    TxAbort,
+
+    /// a transport session started receiving (RTS or BAM)
+    RxRts,
+
+    /// a data packet offset of an ETP session was received
+    RxDpo,
+
+    /// receiving a message was aborted
+    RxAbort,
+}
+
+/// Progress of a transport session, see `J1939Socket::set_errqueue`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J1939Event {
+    pub kind: J1939EventKind,
+
+    /// counts the messages sent by the socket, starting with 0
+    pub id: u32,
+
+    /// error of aborted sessions, 0 otherwise
+    pub errno: i32,
+
+    /// bytes transferred so far
+    pub bytes_acked: Option<u32>,
+
+    /// size of the whole message
+    pub total_size: Option<u32>,
+
+    pub pgn: Option<u32>,
+    pub src_name: Option<u64>,
+    pub dst_name: Option<u64>,
+    pub src_addr: Option<u8>,
+    pub dst_addr: Option<u8>,
+}
+
+impl J1939Event {
+    fn parse(control: &[u8]) -> io::Result<J1939Event> {
+        let mut err = None;
+        let mut stats = None;
+        for (level, cmsg_type, data) in ControlMessages::new(control) {
+            match (level, cmsg_type) {
+                (SOL_CAN_J1939, SCM_J1939_ERRQUEUE) => err = Some(attr_struct::<SockExtendedErr>(data)),
+                (SOL_SOCKET, SCM_TIMESTAMPING_OPT_STATS) => stats = Some(data),
+                _ => (),
+            }
+        }
+
+        let err = err.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing error queue message"))?;
+        let kind = match (err.ee_origin, err.ee_info) {
+            (SO_EE_ORIGIN_TIMESTAMPING, SCM_TSTAMP_SCHED) => J1939EventKind::TxScheduled,
+            (SO_EE_ORIGIN_TIMESTAMPING, SCM_TSTAMP_ACK) => J1939EventKind::TxDone,
+            (SO_EE_ORIGIN_LOCAL, J1939_EE_INFO_TX_ABORT) => J1939EventKind::TxAbort,
+            (SO_EE_ORIGIN_LOCAL, J1939_EE_INFO_RX_RTS) => J1939EventKind::RxRts,
+            (SO_EE_ORIGIN_LOCAL, J1939_EE_INFO_RX_DPO) => J1939EventKind::RxDpo,
+            (SO_EE_ORIGIN_LOCAL, J1939_EE_INFO_RX_ABORT) => J1939EventKind::RxAbort,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown error queue message")),
+        };
+
+        let mut event = J1939Event {
+            kind: kind,
+            id: err.ee_data,
+            errno: err.ee_errno as i32,
+            bytes_acked: None,
+            total_size: None,
+            pgn: None,
+            src_name: None,
+            dst_name: None,
+            src_addr: None,
+            dst_addr: None,
+        };
+
+        for (attr_type, data) in NlAttrs::new(stats.unwrap_or(&[])) {
+            match attr_type {
+                J1939_NLA_BYTES_ACKED => event.bytes_acked = attr_u32(data),
+                J1939_NLA_TOTAL_SIZE => event.total_size = attr_u32(data),
+                J1939_NLA_PGN => event.pgn = attr_u32(data),
+                J1939_NLA_SRC_NAME => event.src_name = attr_u64(data),
+                J1939_NLA_DEST_NAME => event.dst_name = attr_u64(data),
+                J1939_NLA_SRC_ADDR => event.src_addr = attr_u8(data),
+                J1939_NLA_DEST_ADDR => event.dst_addr = attr_u8(data),
+                _ => (),
+            }
+        }
+
+        Ok(event)
+    }
+}
+
+/// Iterator over `(level, type, data)` of the control messages of `recvmsg`
+struct ControlMessages<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ControlMessages<'a> {
+    fn new(buf: &'a [u8]) -> ControlMessages<'a> {
+        ControlMessages { buf: buf }
+    }
+}
+
+/// `CMSG_ALIGN`
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+impl<'a> Iterator for ControlMessages<'a> {
+    type Item = (c_int, c_int, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hdr_len = cmsg_align(size_of::<cmsghdr>());
+        if self.buf.len() < hdr_len {
+            return None;
+        }
+
+        let hdr: cmsghdr = unsafe { ptr::read_unaligned(self.buf.as_ptr() as *const cmsghdr) };
+        let len = hdr.cmsg_len as usize;
+        if len < hdr_len || len > self.buf.len() {
+            self.buf = &[];
+            return None;
+        }
+
+        let data = &self.buf[hdr_len..len];
+        self.buf = &self.buf[cmsg_align(len).min(self.buf.len())..];
+        Some((hdr.cmsg_level, hdr.cmsg_type, data))
+    }
+}
+
+/// A socket for J1939 messages.
+///
+/// Like a `CanSocket`, it is closed when dropped.
+#[derive(Debug)]
+pub struct J1939Socket {
+    fd: c_int,
+}
+
+impl J1939Socket {
+    /// Open a named CAN device, binding to `addr`.
+    ///
+    /// Messages are sent from the address, or from the address claimed for
+    /// the NAME. If a PGN is given, only messages with that PGN are received.
+    pub fn open(ifname: &str, addr: &J1939Addr) -> Result<J1939Socket, CanSocketOpenError> {
+        let if_index = if_nametoindex(ifname)?;
+        J1939Socket::open_if(if_index, addr)
+    }
+
+    /// Open CAN device by interface number, binding to `addr`.
+    pub fn open_if(if_index: c_uint, addr: &J1939Addr) -> Result<J1939Socket, CanSocketOpenError> {
+        let addr = J1939SockAddr::new(if_index as c_int, addr);
+
+        let sock_fd = unsafe { socket(PF_CAN, SOCK_DGRAM, CAN_J1939) };
+        if sock_fd == -1 {
+            return Err(CanSocketOpenError::from(io::Error::last_os_error()));
+        }
+
+        // the socket is closed on drop if binding fails
+        let sock = J1939Socket { fd: sock_fd };
+
+        let bind_rv = unsafe {
+            let sockaddr_ptr = &addr as *const J1939SockAddr;
+            bind(sock.fd,
+                 sockaddr_ptr as *const sockaddr,
+                 size_of::<J1939SockAddr>() as socklen_t)
+        };
+
+        if bind_rv == -1 {
+            return Err(CanSocketOpenError::from(io::Error::last_os_error()));
+        }
+
+        Ok(sock)
+    }
+
+    /// Sets the default destination of `send`.
+    ///
+    /// Once connected, only messages from the peer are received.
+    pub fn connect(&self, addr: &J1939Addr) -> io::Result<()> {
+        let addr = J1939SockAddr::new(0, addr);
+        let rv = unsafe {
+            connect(self.fd,
+                    &addr as *const J1939SockAddr as *const sockaddr,
+                    size_of::<J1939SockAddr>() as socklen_t)
+        };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Change socket to non-blocking mode
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let mut nonblocking = nonblocking as c_int;
+        let rv = unsafe { libc::ioctl(self.fd, libc::FIONBIO, &mut nonblocking) };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sets the read timeout on the socket
+    ///
+    /// For convenience, the result value can be checked using
+    /// `ShouldRetry::should_retry` when a timeout is set.
+    pub fn set_read_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_SOCKET, SO_RCVTIMEO, &c_timeval_new(duration))
+    }
+
+    /// Sets the write timeout on the socket
+    pub fn set_write_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_SOCKET, SO_SNDTIMEO, &c_timeval_new(duration))
+    }
+
+    /// Sets filters on the socket.
+    ///
+    /// Only messages matching any of the filters are received, an empty list
+    /// removes all filters.
+    pub fn set_filters(&self, filters: &[J1939Filter]) -> io::Result<()> {
+        set_socket_option_mult(self.fd, SOL_CAN_J1939, SO_J1939_FILTER, filters)
+    }
+
+    /// Enable or disable promiscuous mode.
+    ///
+    /// In promiscuous mode, messages to other addresses are received as well.
+    pub fn set_promisc(&self, enabled: bool) -> io::Result<()> {
+        let promisc: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_J1939, SO_J1939_PROMISC, &promisc)
+    }
+
+    /// Sets the priority of sent messages, 0 (highest) to 7.
+    ///
+    /// The default is 6, the priorities 0 and 1 require `CAP_NET_ADMIN`.
+    pub fn set_send_priority(&self, priority: u8) -> io::Result<()> {
+        set_socket_option(self.fd, SOL_CAN_J1939, SO_J1939_SEND_PRIO, &(priority as c_int))
+    }
+
+    /// Enable or disable sending to the global address `J1939_NO_ADDR`.
+    pub fn set_broadcast(&self, enabled: bool) -> io::Result<()> {
+        let broadcast: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_SOCKET, SO_BROADCAST, &broadcast)
+    }
+
+    /// Enable or disable reporting the progress of transport sessions.
+    ///
+    /// When enabled, `recv_event` returns when sent messages are scheduled,
+    /// completed or aborted, and when receiving sessions start or are
+    /// aborted.
+    pub fn set_errqueue(&self, enabled: bool) -> io::Result<()> {
+        let errqueue: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_J1939, SO_J1939_ERRQUEUE, &errqueue)?;
+
+        let timestamping: u32 = if enabled {
+            SOF_TIMESTAMPING_OPT_ID | SOF_TIMESTAMPING_TX_SCHED | SOF_TIMESTAMPING_TX_ACK |
+            SOF_TIMESTAMPING_OPT_CMSG | SOF_TIMESTAMPING_OPT_TSONLY | SOF_TIMESTAMPING_OPT_STATS
+        } else {
+            0
+        };
+        set_socket_option(self.fd, SOL_SOCKET, SO_TIMESTAMPING, &timestamping)
+    }
+
+    /// Send a message to the peer set with `connect`.
+    pub fn send(&self, data: &[u8]) -> io::Result<()> {
+        let rv = unsafe { write(self.fd, data.as_ptr() as *const c_void, data.len()) };
+        check_sent(rv, data.len())
+    }
+
+    /// Send a message to `addr`.
+    ///
+    /// The PGN is taken from `addr` if given, otherwise from the address the
+    /// socket is bound or connected to.
+    pub fn send_to(&self, data: &[u8], addr: &J1939Addr) -> io::Result<()> {
+        let addr = J1939SockAddr::new(0, addr);
+        let rv = unsafe {
+            sendto(self.fd,
+                   data.as_ptr() as *const c_void,
+                   data.len(),
+                   0,
+                   &addr as *const J1939SockAddr as *const sockaddr,
+                   size_of::<J1939SockAddr>() as socklen_t)
+        };
+        check_sent(rv, data.len())
+    }
+
+    /// Calls `recvmsg`, returning the length, the source, the control
+    /// messages and the message flags.
+    fn recvmsg(&self, buf: &mut [u8], flags: c_int) -> io::Result<(usize, J1939SockAddr, Vec<u8>, c_int)> {
+        let mut addr: J1939SockAddr = unsafe { zeroed() };
+        let mut control = vec![0u8; CONTROL_LEN];
+        let mut iov = iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+
+        let mut msg: msghdr = unsafe { zeroed() };
+        msg.msg_name = &mut addr as *mut J1939SockAddr as *mut c_void;
+        msg.msg_namelen = size_of::<J1939SockAddr>() as socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = control.len() as _;
+
+        let rv = unsafe { recvmsg(self.fd, &mut msg, flags) };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        control.truncate(msg.msg_controllen as usize);
+        Ok((rv as usize, addr, control, msg.msg_flags))
+    }
+
+    /// Blocking read of a single message into `buf`, returning its length
+    /// and source.
+    ///
+    /// A message larger than `buf` is truncated.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, J1939Addr)> {
+        let (len, addr, _, _) = self.recvmsg(buf, 0)?;
+        Ok((len, addr.j1939_addr()))
+    }
+
+    /// Blocking read of a single message of up to
+    /// `J1939_MAX_TP_PACKET_SIZE` bytes, along with its addressing.
+    ///
+    /// A longer message is dropped and fails with `InvalidData`, use
+    /// `recv_message_max` to receive messages of the extended transport
+    /// protocol.
+    pub fn recv_message(&self) -> io::Result<J1939Message> {
+        self.recv_message_max(J1939_MAX_TP_PACKET_SIZE)
+    }
+
+    /// Like `recv_message`, but for messages of up to `max_len` bytes, at
+    /// most `J1939_MAX_ETP_PACKET_SIZE`.
+    ///
+    /// A longer message is dropped and fails with `InvalidData`.
+    pub fn recv_message_max(&self, max_len: usize) -> io::Result<J1939Message> {
+        // CAN_J1939 neither peeks nor reports the length of truncated
+        // messages, so the buffer has to fit the largest one accepted
+        let mut data = vec![0; max_len];
+        let (len, addr, control, flags) = self.recvmsg(&mut data, 0)?;
+        if flags & MSG_TRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message exceeds the maximum length"));
+        }
+        data.truncate(len);
+        data.shrink_to_fit();
+
+        let mut msg = J1939Message {
+            data: data,
+            src: addr.j1939_addr(),
+            dst_addr: J1939_NO_ADDR,
+            dst_name: J1939_NO_NAME,
+            priority: 0,
+        };
+
+        for (level, cmsg_type, data) in ControlMessages::new(&control) {
+            if level != SOL_CAN_J1939 {
+                continue;
+            }
+            match cmsg_type {
+                SCM_J1939_DEST_ADDR => msg.dst_addr = attr_u8(data).unwrap_or(J1939_NO_ADDR),
+                SCM_J1939_DEST_NAME => msg.dst_name = attr_u64(data).unwrap_or(J1939_NO_NAME),
+                SCM_J1939_PRIO => msg.priority = attr_u8(data).unwrap_or(0),
+                _ => (),
+            }
+        }
+
+        Ok(msg)
+    }
+
+    /// Blocking read of the next event of the error queue.
+    ///
+    /// Requires `set_errqueue`. The socket becomes readable when events are
+    /// pending, even if no message has been received.
+    pub fn recv_event(&self) -> io::Result<J1939Event> {
+        let (_, _, control, _) = self.recvmsg(&mut [], MSG_ERRQUEUE)?;
+        J1939Event::parse(&control)
+    }
+}
+
+fn check_sent(rv: isize, len: usize) -> io::Result<()> {
+    if rv < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if rv as usize != len {
+        return Err(io::Error::new(io::ErrorKind::Other, "Incomplete write"));
+    }
+    Ok(())
+}
+
+impl AsRawFd for J1939Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl FromRawFd for J1939Socket {
+    unsafe fn from_raw_fd(fd: RawFd) -> J1939Socket {
+        J1939Socket { fd: fd }
+    }
+}
+
+impl IntoRawFd for J1939Socket {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for J1939Socket {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Appends a control message like the kernel does.
+    fn push_cmsg(buf: &mut Vec<u8>, level: c_int, cmsg_type: c_int, data: &[u8]) {
+        let hdr_len = cmsg_align(size_of::<cmsghdr>());
+        let mut hdr: cmsghdr = unsafe { zeroed() };
+        hdr.cmsg_len = (hdr_len + data.len()) as _;
+        hdr.cmsg_level = level;
+        hdr.cmsg_type = cmsg_type;
+
+        let start = buf.len();
+        buf.resize(start + hdr_len, 0);
+        unsafe {
+            ptr::write_unaligned(buf[start..].as_mut_ptr() as *mut cmsghdr, hdr);
+        }
+        buf.extend_from_slice(data);
+        let end = cmsg_align(buf.len());
+        buf.resize(end, 0);
+    }
+
+    fn nla(buf: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+        buf.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        buf.extend_from_slice(&attr_type.to_ne_bytes());
+        buf.extend_from_slice(data);
+        while buf.len() % 4 != 0 {
+            buf.push(0);
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(size_of::<J1939SockAddr>(), 24);
+        assert_eq!(size_of::<J1939Filter>(), 32);
+        assert_eq!(size_of::<SockExtendedErr>(), 16);
+    }
+
+    #[test]
+    fn test_parse_event() {
+        // ee_errno, ee_origin, ee_type, ee_code, ee_pad, ee_info, ee_data
+        let mut err = Vec::new();
+        err.extend_from_slice(&0u32.to_ne_bytes());
+        err.extend_from_slice(&[SO_EE_ORIGIN_TIMESTAMPING, 0, 0, 0]);
+        err.extend_from_slice(&SCM_TSTAMP_ACK.to_ne_bytes());
+        err.extend_from_slice(&3u32.to_ne_bytes());
+
+        let mut stats = Vec::new();
+        nla(&mut stats, J1939_NLA_BYTES_ACKED, &100u32.to_ne_bytes());
+        nla(&mut stats, J1939_NLA_TOTAL_SIZE, &100u32.to_ne_bytes());
+        nla(&mut stats, J1939_NLA_PGN, &0xfecau32.to_ne_bytes());
+        nla(&mut stats, J1939_NLA_DEST_ADDR, &[0x30]);
+
+        let mut control = Vec::new();
+        push_cmsg(&mut control, SOL_SOCKET, SO_TIMESTAMPING, &[0; 48]);
+        push_cmsg(&mut control, SOL_CAN_J1939, SCM_J1939_ERRQUEUE, &err);
+        push_cmsg(&mut control, SOL_SOCKET, SCM_TIMESTAMPING_OPT_STATS, &stats);
+
+        let event = J1939Event::parse(&control).unwrap();
+        assert_eq!(event.kind, J1939EventKind::TxDone);
+        assert_eq!(event.id, 3);
+        assert_eq!(event.errno, 0);
+        assert_eq!(event.bytes_acked, Some(100));
+        assert_eq!(event.total_size, Some(100));
+        assert_eq!(event.pgn, Some(0xfeca));
+        assert_eq!(event.dst_addr, Some(0x30));
+        assert_eq!(event.src_name, None);
+    }
+
+    #[test]
+    fn test_recv_message() {
+        // a datagram socket pair takes the place of the kernel
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_DGRAM, 0, fds.as_mut_ptr()) }, 0);
+        let sock = unsafe { J1939Socket::from_raw_fd(fds[0]) };
+        let peer = unsafe { J1939Socket::from_raw_fd(fds[1]) };
+
+        let data: Vec<u8> = (0..100).collect();
+        peer.send(&data).unwrap();
+        let msg = sock.recv_message().unwrap();
+        assert_eq!(msg.data, data);
+        assert_eq!(msg.dst_addr, J1939_NO_ADDR);
+
+        peer.send(&data).unwrap();
+        assert_eq!(sock.recv_message_max(10).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // the truncated message is gone
+        peer.send(&data[..8]).unwrap();
+        assert_eq!(sock.recv_message_max(10).unwrap().data, &data[..8]);
+
+        // longer than the transport protocol allows
+        let data = vec![0xaa; J1939_MAX_TP_PACKET_SIZE + 1];
+        peer.send(&data).unwrap();
+        assert_eq!(sock.recv_message().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        peer.send(&data).unwrap();
+        assert_eq!(sock.recv_message_max(data.len()).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = J1939Filter::new().pgn(0xfeca).addr(0x20);
+        assert_eq!(filter._pgn_mask, J1939_PGN_MAX);
+        assert_eq!(filter._addr_mask, 0xff);
+        assert_eq!(filter._name_mask, 0);
+    }
+}
@@ -0,0 +1,305 @@
+//! Reassembly of J1939 transport protocol sessions from raw CAN frames
+
+use std::collections::HashMap;
+
+use super::{J1939Addr, J1939Id, J1939Message, J1939_NO_ADDR, J1939_NO_NAME};
+use crate::CanFrame;
+
+/// Transport Protocol - Connection Management
+pub const J1939_PGN_TP_CM: u32 = 0x0ec00;
+
+/// Transport Protocol - Data Transfer
+pub const J1939_PGN_TP_DT: u32 = 0x0eb00;
+
+// control bytes of TP.CM
+const TP_CM_RTS: u8 = 16;
+const TP_CM_CTS: u8 = 17;
+const TP_CM_EOMA: u8 = 19;
+const TP_CM_BAM: u8 = 32;
+const TP_CM_ABORT: u8 = 255;
+
+/// largest message of the transport protocol
+const TP_MAX_SIZE: usize = 1785;
+
+/// T1 of J1939-21, the longest gap between the packets of a broadcast
+const BAM_TIMEOUT_US: u64 = 750_000;
+
+/// T2 of J1939-21, the longest wait for packets after a CTS
+const RTS_TIMEOUT_US: u64 = 1_250_000;
+
+/// A transfer in progress
+#[derive(Debug)]
+struct Session {
+    pgn: u32,
+    priority: u8,
+    size: usize,
+    packets: u8,
+    data: Vec<u8>,
+    broadcast: bool,
+
+    /// packets received so far, by sequence number minus one
+    received: Vec<bool>,
+
+    /// sequence number of the next packet
+    next_seq: u8,
+
+    /// time of the last frame of the session
+    t_us: u64,
+}
+
+impl Session {
+    fn timeout_us(&self) -> u64 {
+        if self.broadcast { BAM_TIMEOUT_US } else { RTS_TIMEOUT_US }
+    }
+}
+
+/// Turns raw CAN frames into J1939 messages, reassembling transport
+/// protocol sessions.
+///
+/// Both broadcast (BAM) and connection mode (RTS/CTS) sessions are
+/// reassembled. The reassembler only listens, the CTS frames of connection
+/// mode sessions have to be sent by the destination node. The extended
+/// transport protocol (ETP) is not supported.
+///
+/// Messages carried by a single frame are returned as they are, the frames
+/// of the transport protocol itself are consumed.
+#[derive(Debug, Default)]
+pub struct J1939Reassembler {
+    /// sessions by source and destination address
+    sessions: HashMap<(u8, u8), Session>,
+}
+
+impl J1939Reassembler {
+    pub fn new() -> J1939Reassembler {
+        J1939Reassembler::default()
+    }
+
+    /// number of sessions in progress
+    pub fn sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Process a frame received at `t_us` microseconds, returning the
+    /// message it completes, if any.
+    ///
+    /// Frames with 11 bit identifiers are ignored. Sessions that timed out
+    /// are dropped before processing the frame.
+    pub fn push(&mut self, frame: &CanFrame, t_us: u64) -> Option<J1939Message> {
+        if frame.is_rtr() || frame.is_error() {
+            return None;
+        }
+        let id = J1939Id::from_frame(frame)?;
+
+        self.sessions.retain(|_, s| t_us.saturating_sub(s.t_us) <= s.timeout_us());
+
+        match id.pgn {
+            J1939_PGN_TP_CM => {
+                self.connection_management(&id, frame.data(), t_us);
+                None
+            }
+            J1939_PGN_TP_DT => self.data_transfer(&id, frame.data(), t_us),
+            _ => {
+                Some(J1939Message {
+                    data: frame.data().to_vec(),
+                    src: J1939Addr::new(J1939_NO_NAME, id.pgn, id.src_addr),
+                    dst_addr: id.dst_addr,
+                    dst_name: J1939_NO_NAME,
+                    priority: id.priority,
+                })
+            }
+        }
+    }
+
+    fn connection_management(&mut self, id: &J1939Id, data: &[u8], t_us: u64) {
+        if data.len() < 8 {
+            return;
+        }
+
+        let size = u16::from_le_bytes([data[1], data[2]]) as usize;
+        let packets = data[3];
+        let pgn = u32::from(data[5]) | u32::from(data[6]) << 8 | u32::from(data[7]) << 16;
+
+        match data[0] {
+            TP_CM_RTS | TP_CM_BAM => {
+                if size < 9 || size > TP_MAX_SIZE || packets as usize != (size + 6) / 7 {
+                    return;
+                }
+
+                // a new session replaces a pending one
+                let broadcast = data[0] == TP_CM_BAM;
+                let dst_addr = if broadcast { J1939_NO_ADDR } else { id.dst_addr };
+                self.sessions.insert((id.src_addr, dst_addr),
+                                     Session {
+                                         pgn: pgn,
+                                         priority: id.priority,
+                                         size: size,
+                                         packets: packets,
+                                         data: vec![0xff; packets as usize * 7],
+                                         broadcast: broadcast,
+                                         received: vec![false; packets as usize],
+                                         next_seq: 1,
+                                         t_us: t_us,
+                                     });
+            }
+            TP_CM_CTS => {
+                // sent by the destination, keeps the session alive
+                if let Some(session) = self.sessions.get_mut(&(id.dst_addr, id.src_addr)) {
+                    session.t_us = t_us;
+                }
+            }
+            TP_CM_ABORT => {
+                // either side may abort
+                self.sessions.remove(&(id.src_addr, id.dst_addr));
+                self.sessions.remove(&(id.dst_addr, id.src_addr));
+            }
+            // the end of message acknowledgement follows the last packet
+            TP_CM_EOMA => (),
+            _ => (),
+        }
+    }
+
+    fn data_transfer(&mut self, id: &J1939Id, data: &[u8], t_us: u64) -> Option<J1939Message> {
+        let key = (id.src_addr, id.dst_addr);
+        let seq = *data.first()?;
+
+        let done = {
+            let session = self.sessions.get_mut(&key)?;
+            // broadcasts have to arrive in order, while packets may be
+            // repeated after a CTS, so they are placed by their sequence
+            // number
+            if seq == 0 || seq > session.packets || session.broadcast && seq != session.next_seq {
+                None
+            } else {
+                let start = (seq as usize - 1) * 7;
+                let payload = &data[1..];
+                let n = payload.len().min(7);
+                session.data[start..start + n].copy_from_slice(&payload[..n]);
+                session.t_us = t_us;
+                session.next_seq = seq.wrapping_add(1);
+                session.received[seq as usize - 1] = true;
+                Some(session.received.iter().all(|&r| r))
+            }
+        };
+
+        match done {
+            Some(false) => None,
+            Some(true) => {
+                let session = self.sessions.remove(&key)?;
+                let mut data = session.data;
+                data.truncate(session.size);
+                Some(J1939Message {
+                    data: data,
+                    src: J1939Addr::new(J1939_NO_NAME, session.pgn, id.src_addr),
+                    dst_addr: id.dst_addr,
+                    dst_name: J1939_NO_NAME,
+                    priority: session.priority,
+                })
+            }
+            None => {
+                // unexpected sequence numbers end the session
+                self.sessions.remove(&key);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CanFrame;
+
+    fn frame(id: J1939Id, data: &[u8]) -> CanFrame {
+        CanFrame::new(id.message_id(), data, false, false).unwrap()
+    }
+
+    /// splits `data` into TP.DT frames
+    fn packets(src: u8, dst: u8, data: &[u8]) -> Vec<CanFrame> {
+        data.chunks(7)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut payload = vec![i as u8 + 1];
+                payload.extend_from_slice(chunk);
+                payload.resize(8, 0xff);
+                frame(J1939Id::new(7, J1939_PGN_TP_DT, src, dst), &payload)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bam() {
+        let mut rsm = J1939Reassembler::new();
+        let data: Vec<u8> = (0..20).collect();
+
+        let bam = frame(J1939Id::new(6, J1939_PGN_TP_CM, 0x00, J1939_NO_ADDR),
+                        &[TP_CM_BAM, 20, 0, 3, 0xff, 0xca, 0xfe, 0x00]);
+        assert_eq!(rsm.push(&bam, 0), None);
+        assert_eq!(rsm.sessions(), 1);
+
+        let dts = packets(0x00, J1939_NO_ADDR, &data);
+        assert_eq!(rsm.push(&dts[0], 50_000), None);
+        assert_eq!(rsm.push(&dts[1], 100_000), None);
+        let msg = rsm.push(&dts[2], 150_000).unwrap();
+
+        assert_eq!(msg.data, data);
+        assert_eq!(msg.src, J1939Addr::new(J1939_NO_NAME, 0xfeca, 0x00));
+        assert_eq!(msg.dst_addr, J1939_NO_ADDR);
+        assert_eq!(msg.priority, 6);
+        assert_eq!(rsm.sessions(), 0);
+    }
+
+    #[test]
+    fn test_rts_cts() {
+        let mut rsm = J1939Reassembler::new();
+        let data: Vec<u8> = (0..16).collect();
+
+        let rts = frame(J1939Id::new(7, J1939_PGN_TP_CM, 0x20, 0x30),
+                        &[TP_CM_RTS, 16, 0, 3, 0xff, 0x00, 0xef, 0x00]);
+        let cts = frame(J1939Id::new(7, J1939_PGN_TP_CM, 0x30, 0x20),
+                        &[TP_CM_CTS, 3, 1, 0xff, 0xff, 0x00, 0xef, 0x00]);
+        assert_eq!(rsm.push(&rts, 0), None);
+        assert_eq!(rsm.push(&cts, 1_000), None);
+
+        let dts = packets(0x20, 0x30, &data);
+        assert_eq!(rsm.push(&dts[0], 2_000), None);
+        // a repeated packet is placed again
+        assert_eq!(rsm.push(&dts[0], 3_000), None);
+        assert_eq!(rsm.push(&dts[1], 4_000), None);
+        let msg = rsm.push(&dts[2], 5_000).unwrap();
+
+        assert_eq!(msg.data, data);
+        assert_eq!(msg.src.pgn, 0xef00);
+        assert_eq!(msg.dst_addr, 0x30);
+
+        // the last packet does not complete a session missing others
+        assert_eq!(rsm.push(&rts, 6_000), None);
+        assert_eq!(rsm.push(&dts[0], 7_000), None);
+        assert_eq!(rsm.push(&dts[2], 8_000), None);
+        assert_eq!(rsm.sessions(), 1);
+        assert_eq!(rsm.push(&dts[1], 9_000).unwrap().data, data);
+    }
+
+    #[test]
+    fn test_abort_and_timeout() {
+        let mut rsm = J1939Reassembler::new();
+        let data: Vec<u8> = (0..16).collect();
+        let dts = packets(0x20, 0x30, &data);
+
+        let rts = frame(J1939Id::new(7, J1939_PGN_TP_CM, 0x20, 0x30),
+                        &[TP_CM_RTS, 16, 0, 3, 0xff, 0x00, 0xef, 0x00]);
+        let abort = frame(J1939Id::new(7, J1939_PGN_TP_CM, 0x30, 0x20),
+                          &[TP_CM_ABORT, 1, 0xff, 0xff, 0xff, 0x00, 0xef, 0x00]);
+        rsm.push(&rts, 0);
+        rsm.push(&abort, 1_000);
+        assert_eq!(rsm.sessions(), 0);
+
+        rsm.push(&rts, 0);
+        rsm.push(&dts[0], 1_000);
+        assert_eq!(rsm.push(&dts[1], 2_000_000), None);
+        assert_eq!(rsm.sessions(), 0);
+
+        // single frame messages pass through
+        let eec1 = frame(J1939Id::from_raw(0x0cf00400), &[0xff; 8]);
+        assert_eq!(rsm.push(&eec1, 0).unwrap().src.pgn, 0xf004);
+    }
+}
@@ -40,6 +40,14 @@
 //! Raw access to the underlying file descriptor and construction through
 //! is available through the `AsRawFd`, `IntoRawFd` and `FromRawFd`
 //! implementations.
+//!
+//! # Features
+//!
+//! * `xz2`: decompression of xz compressed logs in `compress`
+//! * `zstd`: decompression of zstd compressed logs in `compress`
+//! * `vcan_tests`: tests that require a `vcan0` interface
+//!
+//! None are enabled by default, `xz2` and `zstd` build their C libraries.
 
 // clippy: do not warn about things like "SocketCAN" inside the docs
 #![cfg_attr(feature = "cargo-clippy", allow(doc_markdown))]
@@ -48,9 +56,23 @@
 #[macro_use]
 extern crate bitflags;
 
+pub mod asc;
+pub mod blf;
+pub mod canopen;
+pub mod compress;
 mod err;
+pub mod index;
+pub mod isotp;
+pub mod j1939;
 pub mod dump;
+mod gw;
 mod nl;
+pub mod obd;
+pub mod pcap;
+pub mod replay;
+mod rtnl;
+pub mod trc;
+pub mod uds;
 mod util;
 
 #[cfg(test)]
@@ -66,7 +88,11 @@ use libc::{
     SOCK_RAW, SOL_SOCKET, SO_RCVTIMEO, SO_SNDTIMEO,
 };
 use nix::net::if_::if_nametoindex;
-pub use crate::nl::CanInterface;
+pub use crate::gw::{CanGateway, Crc8Checksum, Crc8Profile, FrameModification, GatewayEntry,
+                    GatewayRule, XorChecksum};
+pub use crate::nl::{CanDeviceStats, CanFrameMode, CanInterface, CanInterfaceInfo,
+                    CanInterfaceKind, CanInterfaceStats, CanState, LinkEvent, LinkMonitor,
+                    OperState};
 use std::mem::{size_of, uninitialized};
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::{error, fmt, io, time};
@@ -126,9 +152,21 @@ pub const CAN_RAW_FILTER: c_int = 1;
 pub const CAN_RAW_ERR_FILTER: c_int = 2;
 pub const CAN_RAW_LOOPBACK: c_int = 3;
 pub const CAN_RAW_RECV_OWN_MSGS: c_int = 4;
-// unused:
-// const CAN_RAW_FD_FRAMES: c_int = 5;
+const CAN_RAW_FD_FRAMES: c_int = 5;
 const CAN_RAW_JOIN_FILTERS: c_int = 6;
+const CAN_RAW_XL_FRAMES: c_int = 7;
+
+/// MTU of a classic CAN device, the size of a `struct can_frame`
+pub const CAN_MTU: usize = 16;
+
+/// MTU of a CAN FD capable device, the size of a `struct canfd_frame`
+pub const CANFD_MTU: usize = 72;
+
+/// MTU of a CAN XL capable device, the size of a `struct canxl_frame`
+pub const CANXL_MTU: usize = 2060;
+
+/// size of the `struct canxl_frame` header preceding the data
+const CANXL_HDR_SIZE: usize = 12;
 
 /// datagram (conn.less) socket
 pub const SOCK_DGRAM: c_int = 2;
@@ -153,6 +191,43 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Default)]
+    pub struct FdFlags: u8 {
+        /// bit rate switch, the data phase is sent with the higher bit rate
+        const BRS = 0x01;
+
+        /// error state indicator of the transmitting node
+        const ESI = 0x02;
+    }
+}
+
+/// maximum payload of a CAN FD frame
+pub const CANFD_MAX_DLEN: usize = 64;
+
+bitflags! {
+    #[derive(Default)]
+    pub struct XlFlags: u8 {
+        /// simple extended content (security/segmentation)
+        const SEC = 0x01;
+
+        /// remote request substitution
+        const RRS = 0x02;
+
+        /// marks a CAN XL frame, always set
+        const XLF = 0x80;
+    }
+}
+
+/// maximum payload of a CAN XL frame
+pub const CANXL_MAX_DLEN: usize = 2048;
+
+/// priority (11 bit identifier) bits of a CAN XL frame
+pub const CANXL_PRIO_MASK: u32 = 0x000007ff;
+
+/// virtual CAN network identifier bits of a CAN XL frame
+pub const CANXL_VCID_MASK: u32 = 0x00ff0000;
+
 /// valid bits in standard frame id
 pub const SFF_MASK: u32 = 0x000007ff;
 const SFF_MASK_U16: u16 = 0x07ff;
@@ -283,7 +358,7 @@ impl error::Error for CanSocketOpenError {
 pub enum ConstructionError {
     /// CAN ID was outside the range of valid IDs
     IDTooLarge,
-    /// More than 8 Bytes of payload data were passed in
+    /// More than 8 Bytes (CAN FD: 64 Bytes) of payload data were passed in
     TooMuchData,
 }
 
@@ -292,7 +367,7 @@ impl fmt::Display for ConstructionError {
         match *self {
             ConstructionError::IDTooLarge => write!(f, "CAN ID too large"),
             ConstructionError::TooMuchData => {
-                write!(f, "Payload is larger than CAN maximum of 8 bytes")
+                write!(f, "Payload is larger than CAN maximum of 8 bytes (CAN FD: 64 bytes)")
             }
         }
     }
@@ -451,6 +526,67 @@ impl CanSocket {
         Ok(frame)
     }
 
+    /// Blocking read a single CAN FD or classic frame.
+    ///
+    /// Requires CAN FD frames to be enabled with `set_fd_frames`. Classic
+    /// frames are returned as CAN FD frames without flags.
+    pub fn read_fd_frame(&self) -> io::Result<CanFdFrame> {
+        self.read_fd_frame_mtu().map(|(frame, _)| frame)
+    }
+
+    /// Like `read_fd_frame`, but also returns the number of bytes read,
+    /// `CAN_MTU` for classic frames and `CANFD_MTU` for CAN FD frames.
+    fn read_fd_frame_mtu(&self) -> io::Result<(CanFdFrame, usize)> {
+        let mut frame = CanFdFrame {
+            _id: 0,
+            _data_len: 0,
+            _flags: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: [0; CANFD_MAX_DLEN],
+        };
+
+        let read_rv = unsafe {
+            let frame_ptr = &mut frame as *mut CanFdFrame;
+            read(self.fd, frame_ptr as *mut c_void, size_of::<CanFdFrame>())
+        };
+
+        if read_rv as usize != CANFD_MTU && read_rv as usize != CAN_MTU {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((frame, read_rv as usize))
+    }
+
+    /// Blocking read of a classic, CAN FD or error frame with timestamp, as
+    /// captured into logs.
+    ///
+    /// CAN FD frames are only received if enabled with `set_fd_frames`.
+    fn read_dump_frame_with_timestamp(&mut self) -> io::Result<(dump::CanDumpFrame, time::SystemTime)> {
+        let (frame, mtu) = self.read_fd_frame_mtu()?;
+        let time = self.last_timestamp()?;
+
+        if mtu == CANFD_MTU {
+            return Ok((dump::CanDumpFrame::Fd(frame), time));
+        }
+
+        let mut data = [0; 8];
+        data.copy_from_slice(&frame._data[..8]);
+        let frame = CanFrame {
+            _id: frame._id,
+            _data_len: frame._data_len,
+            _pad: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: data,
+        };
+        if frame.is_error() {
+            Ok((dump::CanDumpFrame::Error(frame), time))
+        } else {
+            Ok((dump::CanDumpFrame::Classic(frame), time))
+        }
+    }
+
     /// Blocking read a single can frame with timestamp
     ///
     /// Note that reading a frame and retrieving the timestamp requires two
@@ -458,7 +594,11 @@ impl CanSocket {
     /// to the socket is enforce through requiring a `mut &self`.
     pub fn read_frame_with_timestamp(&mut self) -> io::Result<(CanFrame, time::SystemTime)> {
         let frame = self.read_frame()?;
+        Ok((frame, self.last_timestamp()?))
+    }
 
+    /// The receive timestamp of the last frame read.
+    fn last_timestamp(&self) -> io::Result<time::SystemTime> {
         let mut ts: timespec;
         let rval = unsafe {
             // we initialize tv calling ioctl, passing this responsibility on
@@ -478,7 +618,7 @@ impl CanSocket {
             return Err(io::Error::last_os_error());
         }
 
-        Ok((frame, util::system_time_from_timespec(ts)))
+        Ok(util::system_time_from_timespec(ts))
     }
 
     /// Write a single can frame.
@@ -503,6 +643,42 @@ impl CanSocket {
         Ok(())
     }
 
+    /// Write a single CAN FD frame.
+    ///
+    /// Requires CAN FD frames to be enabled with `set_fd_frames` and a CAN FD
+    /// capable device.
+    pub fn write_fd_frame(&self, frame: &CanFdFrame) -> io::Result<()> {
+        let write_rv = unsafe {
+            let frame_ptr = frame as *const CanFdFrame;
+            write(self.fd, frame_ptr as *const c_void, size_of::<CanFdFrame>())
+        };
+
+        if write_rv as usize != size_of::<CanFdFrame>() {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Write a single CAN XL frame.
+    ///
+    /// Requires CAN XL frames to be enabled with `set_xl_frames` and a CAN XL
+    /// capable device.
+    pub fn write_xl_frame(&self, frame: &CanXlFrame) -> io::Result<()> {
+        // only the header and the actual data are written
+        let len = CANXL_HDR_SIZE + frame.data().len();
+        let write_rv = unsafe {
+            let frame_ptr = frame as *const CanXlFrame;
+            write(self.fd, frame_ptr as *const c_void, len)
+        };
+
+        if write_rv as usize != len {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
     /// Blocking write a single can frame, retrying until it gets sent
     /// successfully.
     pub fn write_frame_insist(&self, frame: &CanFrame) -> io::Result<()> {
@@ -560,6 +736,22 @@ impl CanSocket {
         set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_RECV_OWN_MSGS, &recv_own_msgs)
     }
 
+    /// Enable or disable sending and receiving of CAN FD frames.
+    ///
+    /// Off by default, in which case `write_fd_frame` fails.
+    pub fn set_fd_frames(&self, enabled: bool) -> io::Result<()> {
+        let fd_frames: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_FD_FRAMES, &fd_frames)
+    }
+
+    /// Enable or disable sending and receiving of CAN XL frames.
+    ///
+    /// Off by default, in which case `write_xl_frame` fails.
+    pub fn set_xl_frames(&self, enabled: bool) -> io::Result<()> {
+        let xl_frames: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_XL_FRAMES, &xl_frames)
+    }
+
     /// Enable or disable join filters.
     ///
     /// By default a frame is accepted if it matches any of the filters set
@@ -595,6 +787,25 @@ impl Drop for CanSocket {
     }
 }
 
+/// Checks an identifier and turns it into the raw id with EFF flag.
+fn raw_id(message_id: CanMessageId) -> Result<u32, ConstructionError> {
+    match message_id {
+        CanMessageId::SFF(id) => {
+            let id = id as u32;
+            if id > SFF_MASK {
+                return Err(ConstructionError::IDTooLarge);
+            }
+            Ok(id)
+        },
+        CanMessageId::EFF(id) => {
+            if id > EFF_MASK {
+                return Err(ConstructionError::IDTooLarge);
+            }
+            Ok(id | FrameFlags::EFF_FLAG.bits())
+        },
+    }
+}
+
 /// CanFrame
 ///
 /// Uses the same memory layout as the underlying kernel struct for performance
@@ -628,21 +839,7 @@ impl CanFrame {
             return Err(ConstructionError::TooMuchData);
         }
 
-        let mut _id = match message_id {
-            CanMessageId::SFF(id) => {
-                let id = id as u32;
-                if id > SFF_MASK {
-                    return Err(ConstructionError::IDTooLarge);
-                }
-                id
-            },
-            CanMessageId::EFF(id) => {
-                if id > EFF_MASK {
-                    return Err(ConstructionError::IDTooLarge);
-                }
-                id | FrameFlags::EFF_FLAG.bits()
-            },
-        };
+        let mut _id = raw_id(message_id)?;
 
         if rtr {
             _id |= FrameFlags::RTR_FLAG.bits();
@@ -669,6 +866,20 @@ impl CanFrame {
         })
     }
 
+    /// Create an error frame.
+    ///
+    /// Unlike `new`, which always sets the EFF flag for larger ids, this
+    /// stores the error class bits (see `ERR_MASK`) as they are.
+    pub fn new_error(err: u32, data: &[u8]) -> Result<CanFrame, ConstructionError> {
+        if err > ERR_MASK {
+            return Err(ConstructionError::IDTooLarge);
+        }
+
+        let mut frame = CanFrame::new(CanMessageId::SFF(0), data, false, true)?;
+        frame._id |= err;
+        Ok(frame)
+    }
+
     /// Return the actual CAN ID (without EFF/RTR/ERR flags)
     #[inline]
     pub fn id(&self) -> u32 {
@@ -740,11 +951,265 @@ impl fmt::UpperHex for CanFrame {
     }
 }
 
+/// CanFdFrame
+///
+/// A CAN FD frame with up to 64 bytes of payload. Uses the same memory layout
+/// as the underlying kernel struct `canfd_frame`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CanFdFrame {
+    /// 32 bit CAN_ID + EFF/RTR/ERR flags
+    _id: u32,
+
+    /// data length. Bytes beyond are not valid
+    _data_len: u8,
+
+    /// additional flags for CAN FD
+    _flags: u8,
+
+    /// reserved
+    _res0: u8,
+
+    /// reserved
+    _res1: u8,
+
+    /// buffer for data
+    _data: [u8; CANFD_MAX_DLEN],
+}
+
+/// Rounds a payload length up to the next length a CAN FD DLC can express.
+fn canfd_valid_len(len: usize) -> usize {
+    match len {
+        0..=8 => len,
+        9..=12 => 12,
+        13..=16 => 16,
+        17..=20 => 20,
+        21..=24 => 24,
+        25..=32 => 32,
+        33..=48 => 48,
+        _ => 64,
+    }
+}
+
+/// Maps a CAN FD payload length to its DLC, rounding up like `canfd_valid_len`.
+fn canfd_len_to_dlc(len: usize) -> u8 {
+    match canfd_valid_len(len) {
+        12 => 9,
+        16 => 10,
+        20 => 11,
+        24 => 12,
+        32 => 13,
+        48 => 14,
+        64 => 15,
+        len => len as u8,
+    }
+}
+
+/// Maps a CAN FD DLC (0 to 15) to its payload length.
+fn canfd_dlc_to_len(dlc: u8) -> usize {
+    const LENS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+    LENS[(dlc & 0x0f) as usize]
+}
+
+impl CanFdFrame {
+    /// Create a new CAN FD frame.
+    ///
+    /// Payloads with a length that cannot be expressed by a CAN FD DLC (e.g.
+    /// 9 bytes) are padded with zeros, like the kernel would when sending.
+    pub fn new(message_id: CanMessageId, data: &[u8], flags: FdFlags) -> Result<CanFdFrame, ConstructionError> {
+        if data.len() > CANFD_MAX_DLEN {
+            return Err(ConstructionError::TooMuchData);
+        }
+
+        let mut full_data = [0; CANFD_MAX_DLEN];
+        full_data[..data.len()].copy_from_slice(data);
+
+        Ok(CanFdFrame {
+            _id: raw_id(message_id)?,
+            _data_len: canfd_valid_len(data.len()) as u8,
+            _flags: flags.bits(),
+            _res0: 0,
+            _res1: 0,
+            _data: full_data,
+        })
+    }
+
+    /// Return the actual CAN ID (without EFF/RTR/ERR flags)
+    #[inline]
+    pub fn id(&self) -> u32 {
+        if self.is_extended() {
+            self._id & EFF_MASK
+        } else {
+            self._id & SFF_MASK
+        }
+    }
+
+    /// Return the raw CAN ID as stored in the frame
+    #[inline]
+    pub fn id_raw(&self) -> u32 {
+        self._id
+    }
+
+    /// Check if frame uses 29 bit extended frame format
+    #[inline]
+    pub fn is_extended(&self) -> bool {
+        self._id & FrameFlags::EFF_FLAG.bits() != 0
+    }
+
+    /// The CAN FD specific flags
+    #[inline]
+    pub fn flags(&self) -> FdFlags {
+        FdFlags::from_bits_truncate(self._flags)
+    }
+
+    /// Check if the data phase is sent with the higher bit rate
+    #[inline]
+    pub fn is_brs(&self) -> bool {
+        self.flags().contains(FdFlags::BRS)
+    }
+
+    /// Check if the transmitting node was error passive
+    #[inline]
+    pub fn is_esi(&self) -> bool {
+        self.flags().contains(FdFlags::ESI)
+    }
+
+    /// A slice into the actual data. Slice will always be <= 64 bytes in length
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self._data[..(self._data_len as usize)]
+    }
+}
+
+impl fmt::Debug for CanFdFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CanFdFrame")
+            .field("id", &self._id)
+            .field("flags", &self.flags())
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+impl fmt::UpperHex for CanFdFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:X}##", self.id())?;
+
+        let mut parts = self.data().iter().map(|v| format!("{:02X}", v));
+
+        let sep = if f.alternate() { " " } else { "" };
+        write!(f, "{}", parts.join(sep))
+    }
+}
+
+/// CanXlFrame
+///
+/// A CAN XL frame with 1 to 2048 bytes of payload. Uses the same memory
+/// layout as the underlying kernel struct `canxl_frame`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CanXlFrame {
+    /// 11 bit priority and 8 bit VCID
+    _prio: u32,
+
+    /// additional flags for CAN XL
+    _flags: u8,
+
+    /// SDU (service data unit) type
+    _sdt: u8,
+
+    /// data length. Bytes beyond are not valid
+    _len: u16,
+
+    /// acceptance field
+    _af: u32,
+
+    /// buffer for data
+    _data: [u8; CANXL_MAX_DLEN],
+}
+
+impl CanXlFrame {
+    /// Create a new CAN XL frame.
+    ///
+    /// `prio` holds the 11 bit priority and the VCID (see `CANXL_VCID_MASK`),
+    /// the `XLF` flag is always set.
+    pub fn new(prio: u32, sdt: u8, af: u32, data: &[u8], flags: XlFlags) -> Result<CanXlFrame, ConstructionError> {
+        if prio & !(CANXL_PRIO_MASK | CANXL_VCID_MASK) != 0 {
+            return Err(ConstructionError::IDTooLarge);
+        }
+
+        if data.len() > CANXL_MAX_DLEN {
+            return Err(ConstructionError::TooMuchData);
+        }
+
+        let mut full_data = [0; CANXL_MAX_DLEN];
+        full_data[..data.len()].copy_from_slice(data);
+
+        Ok(CanXlFrame {
+            _prio: prio,
+            _flags: (flags | XlFlags::XLF).bits(),
+            _sdt: sdt,
+            _len: data.len() as u16,
+            _af: af,
+            _data: full_data,
+        })
+    }
+
+    /// The 11 bit priority, which takes the place of the CAN ID
+    #[inline]
+    pub fn prio(&self) -> u32 {
+        self._prio & CANXL_PRIO_MASK
+    }
+
+    /// The virtual CAN network identifier
+    #[inline]
+    pub fn vcid(&self) -> u8 {
+        ((self._prio & CANXL_VCID_MASK) >> 16) as u8
+    }
+
+    /// The CAN XL specific flags
+    #[inline]
+    pub fn flags(&self) -> XlFlags {
+        XlFlags::from_bits_truncate(self._flags)
+    }
+
+    /// The SDU type
+    #[inline]
+    pub fn sdt(&self) -> u8 {
+        self._sdt
+    }
+
+    /// The acceptance field
+    #[inline]
+    pub fn af(&self) -> u32 {
+        self._af
+    }
+
+    /// A slice into the actual data
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self._data[..(self._len as usize)]
+    }
+}
+
+impl fmt::Debug for CanXlFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CanXlFrame")
+            .field("prio", &self.prio())
+            .field("vcid", &self.vcid())
+            .field("flags", &self.flags())
+            .field("sdt", &self._sdt)
+            .field("af", &self._af)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
 /// CanFilter
 ///
 /// Contains an internal id and mask. Packets are considered to be matched by
 /// a filter if `received_id & mask == filter_id & mask` holds true.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct CanFilter {
     _id: u32,
@@ -10,22 +10,42 @@
 //! > domain sockets.
 //!
 //!
-//! The netlink module currently relies on the
-//! [netlink-rs](https://crates.io/crates/netlink-rs), which has some
-//! deficiencies. If those are not fixed, a reimplmentation of netlink-rs'
-//! functionality might be required.
-
-use byte_conv::As as AsBytes;
-use libc::{self, c_char, c_ushort, c_int, c_uint};
-use netlink_rs::socket::{Msg as NetlinkMessage, Socket as NetlinkSocket, NetlinkAddr,
-                         Payload as NetlinkPayload, NlMsgHeader};
-use netlink_rs::Protocol as NetlinkProtocol;
-use nix;
+//! Messages are built and parsed by the crate's own rtnetlink implementation
+//! in the `rtnl` module.
+
+use libc::{c_char, c_ushort, c_int, c_uint};
 use nix::net::if_::if_nametoindex;
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::{mem, io};
+use crate::rtnl::{self, MsgBuilder, NlAttrs, NlMessages, NlSocket};
+use crate::{CAN_MTU, CANFD_MTU, CANXL_MTU};
 
 // linux/rtnetlink.h
 const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+const RTMGRP_LINK: u32 = 1;
+
+// linux/if_link.h
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_TXQLEN: u16 = 13;
+const IFLA_OPERSTATE: u16 = 16;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_STATS64: u16 = 23;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const IFLA_INFO_XSTATS: u16 = 3;
+
+// linux/can/netlink.h
+const IFLA_CAN_STATE: u16 = 4;
+
+// linux/can.h; smallest MTU of a CAN XL device, header plus 64 data bytes
+const CANXL_MIN_MTU: usize = 76;
+
+// linux/if_arp.h
+const ARPHRD_CAN: c_ushort = 280;
 
 // linux/socket.h
 const AF_UNSPEC: c_char = 0;
@@ -74,66 +94,477 @@ impl IfInfoMsg {
 }
 
 
-/// Sends a netlink message down a netlink socket, and checks if an ACK was
-/// properly received.
-fn send_and_read_ack(sock: &mut NetlinkSocket,
-                     msg: NetlinkMessage,
-                     dest: &NetlinkAddr)
-                     -> io::Result<()> {
+/// The kind of a CAN network device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CanInterfaceKind {
+    /// A physical CAN controller, driven through the `can-dev` framework
+    Can,
+    /// A virtual CAN device
+    Vcan,
+    /// A virtual CAN tunnel, always created in pairs
+    Vxcan,
+    /// A serial line CAN adapter (e.g. USBtin, CANable with slcan firmware)
+    Slcan,
+    /// Any other link kind reported by the kernel
+    Other(String),
+}
 
-    let msg_len = msg.header().msg_length() as usize;
-    let bytes_sent = sock.send(msg, dest)?;
-    if bytes_sent != msg_len {
-        return Err(io::Error::new(io::ErrorKind::Other, "Incomplete write"));
+impl CanInterfaceKind {
+    fn from_link_kind(kind: &str) -> CanInterfaceKind {
+        match kind {
+            "can" => CanInterfaceKind::Can,
+            "vcan" => CanInterfaceKind::Vcan,
+            "vxcan" => CanInterfaceKind::Vxcan,
+            "slcan" => CanInterfaceKind::Slcan,
+            other => CanInterfaceKind::Other(other.to_owned()),
+        }
     }
+}
 
-    // receive all pending messages
-    let (addr, msgs) = sock.recv()?;
+/// Operational state of a network device (RFC 2863), see `IFLA_OPERSTATE`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OperState {
+    Unknown,
+    NotPresent,
+    Down,
+    LowerLayerDown,
+    Testing,
+    Dormant,
+    Up,
+}
 
-    match msgs.into_iter().nth(0) {
-        Some(msg) => {
-            println!("Received Address: {:?}", addr);
-            println!("Received Message: {:?}", msg);
-            match *msg.payload() {
-                NetlinkPayload::Ack(_) => (),
-                NetlinkPayload::Err(errno, _) => {
-                    return Err(io::Error::from_raw_os_error(-errno));
-                }
-                NetlinkPayload::None => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData,
-                                              "Received no payload when
-                                              expecting an ACK"));
+impl From<u8> for OperState {
+    fn from(state: u8) -> OperState {
+        match state {
+            1 => OperState::NotPresent,
+            2 => OperState::Down,
+            3 => OperState::LowerLayerDown,
+            4 => OperState::Testing,
+            5 => OperState::Dormant,
+            6 => OperState::Up,
+            _ => OperState::Unknown,
+        }
+    }
+}
 
+/// State of a CAN controller, see `IFLA_CAN_STATE`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CanState {
+    /// RX/TX error count < 96
+    ErrorActive,
+    /// RX/TX error count < 128
+    ErrorWarning,
+    /// RX/TX error count < 256
+    ErrorPassive,
+    /// RX/TX error count >= 256
+    BusOff,
+    /// Device is stopped
+    Stopped,
+    /// Device is sleeping
+    Sleeping,
+}
+
+impl CanState {
+    fn from_u32(state: u32) -> Option<CanState> {
+        match state {
+            0 => Some(CanState::ErrorActive),
+            1 => Some(CanState::ErrorWarning),
+            2 => Some(CanState::ErrorPassive),
+            3 => Some(CanState::BusOff),
+            4 => Some(CanState::Stopped),
+            5 => Some(CanState::Sleeping),
+            _ => None,
+        }
+    }
+}
+
+/// Which frames a CAN device accepts, selected through its MTU.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CanFrameMode {
+    /// Classic CAN 2.0 frames only (`CAN_MTU`)
+    Classic,
+    /// Classic and CAN FD frames (`CANFD_MTU`)
+    Fd,
+    /// Classic, CAN FD and CAN XL frames (`CANXL_MTU`)
+    Xl,
+}
+
+impl CanFrameMode {
+    /// The MTU a device needs to use this mode
+    pub fn mtu(&self) -> u32 {
+        match *self {
+            CanFrameMode::Classic => CAN_MTU as u32,
+            CanFrameMode::Fd => CANFD_MTU as u32,
+            CanFrameMode::Xl => CANXL_MTU as u32,
+        }
+    }
+
+    /// The mode selected by a device MTU, `None` if the MTU is not valid for
+    /// a CAN device.
+    pub fn from_mtu(mtu: u32) -> Option<CanFrameMode> {
+        match mtu as usize {
+            CAN_MTU => Some(CanFrameMode::Classic),
+            CANFD_MTU => Some(CanFrameMode::Fd),
+            CANXL_MIN_MTU..=CANXL_MTU => Some(CanFrameMode::Xl),
+            _ => None,
+        }
+    }
+}
+
+/// Information about a CAN network device, as returned by
+/// `CanInterface::list`.
+#[derive(Clone, Debug)]
+pub struct CanInterfaceInfo {
+    /// Interface name, e.g. "can0"
+    pub name: String,
+
+    /// Kernel interface index
+    pub index: c_uint,
+
+    /// Device kind, `None` if the kernel did not report one
+    pub kind: Option<CanInterfaceKind>,
+
+    /// Whether the interface is administratively up (`IFF_UP`)
+    pub is_up: bool,
+
+    /// Operational state. Note that virtual devices report `Unknown` while up.
+    pub oper_state: OperState,
+
+    /// Controller state, only reported by CAN hardware devices
+    pub can_state: Option<CanState>,
+
+    /// Maximum transfer unit, `CAN_MTU` for classic CAN and `CANFD_MTU` for
+    /// CAN FD capable devices
+    pub mtu: u32,
+}
+
+impl CanInterfaceInfo {
+    /// Parses an `RTM_NEWLINK` message, returns `None` if it does not
+    /// describe a CAN device.
+    fn from_link_msg(data: &[u8]) -> Option<CanInterfaceInfo> {
+        let (info, attrs) = rtnl::parse_header::<IfInfoMsg>(data)?;
+        if info.dev_type != ARPHRD_CAN {
+            return None;
+        }
+
+        let mut name = None;
+        let mut kind = None;
+        let mut oper_state = OperState::Unknown;
+        let mut can_state = None;
+        let mut mtu = 0;
+
+        for (attr_type, payload) in attrs {
+            match attr_type {
+                IFLA_IFNAME => name = rtnl::attr_str(payload),
+                IFLA_MTU => mtu = rtnl::attr_u32(payload).unwrap_or(0),
+                IFLA_OPERSTATE => {
+                    oper_state = rtnl::attr_u8(payload).map(OperState::from).unwrap_or(oper_state)
                 }
-                NetlinkPayload::Data(_) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData,
-                                              "Received data when expecting an ACK"));
+                IFLA_LINKINFO => {
+                    for (info_type, info) in NlAttrs::new(payload) {
+                        match info_type {
+                            IFLA_INFO_KIND => {
+                                kind = rtnl::attr_str(info)
+                                    .map(|k| CanInterfaceKind::from_link_kind(&k))
+                            }
+                            IFLA_INFO_DATA => {
+                                can_state = NlAttrs::new(info)
+                                    .get(IFLA_CAN_STATE)
+                                    .and_then(rtnl::attr_u32)
+                                    .and_then(CanState::from_u32)
+                            }
+                            _ => (),
+                        }
+                    }
                 }
+                _ => (),
             }
         }
-        None => {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                      "ACK expected, but got nothing instead"))
+
+        let name = name?;
+
+        // slcan does not register link operations, so it never reports a kind
+        if kind.is_none() && name.starts_with("slcan") {
+            kind = Some(CanInterfaceKind::Slcan);
         }
+
+        Some(CanInterfaceInfo {
+            name: name,
+            index: info.index as c_uint,
+            kind: kind,
+            is_up: info.flags & IFF_UP != 0,
+            oper_state: oper_state,
+            can_state: can_state,
+            mtu: mtu,
+        })
     }
 
-    Ok(())
+    /// The frame mode selected by the device's MTU
+    pub fn frame_mode(&self) -> Option<CanFrameMode> {
+        CanFrameMode::from_mtu(self.mtu)
+    }
 
+    /// Returns a handle to control this interface.
+    pub fn interface(&self) -> CanInterface {
+        CanInterface::open_if(self.index)
+    }
 }
 
-/// Opens a new netlink socket, bound to this process' PID
-fn open_nl_route_socket() -> io::Result<NetlinkSocket> {
-    let sock = NetlinkSocket::new(NetlinkProtocol::Route)?;
+/// Mirrors the leading, stable part of `struct rtnl_link_stats64`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct RtnlLinkStats64 {
+    rx_packets: u64,
+    tx_packets: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_errors: u64,
+    tx_errors: u64,
+    rx_dropped: u64,
+    tx_dropped: u64,
+}
+
+/// CAN controller error counters, mirrors `struct can_device_stats`.
+///
+/// These are only reported by devices using the `can-dev` framework, i.e.
+/// real CAN hardware.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CanDeviceStats {
+    /// Bus errors
+    pub bus_error: u32,
+    /// Changes to error warning state
+    pub error_warning: u32,
+    /// Changes to error passive state
+    pub error_passive: u32,
+    /// Changes to bus off state
+    pub bus_off: u32,
+    /// Arbitration lost errors
+    pub arbitration_lost: u32,
+    /// CAN controller re-starts
+    pub restarts: u32,
+}
 
-    // retrieve PID
-    let pid = unsafe { libc::getpid() } as u32;
+/// Traffic and error counters of a CAN interface, see
+/// `CanInterface::stats`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CanInterfaceStats {
+    /// Frames received
+    pub rx_packets: u64,
+    /// Frames sent
+    pub tx_packets: u64,
+    /// Payload bytes received
+    pub rx_bytes: u64,
+    /// Payload bytes sent
+    pub tx_bytes: u64,
+    /// Receive errors
+    pub rx_errors: u64,
+    /// Transmit errors
+    pub tx_errors: u64,
+    /// Received frames dropped, e.g. due to full buffers
+    pub rx_dropped: u64,
+    /// Frames dropped before they could be sent
+    pub tx_dropped: u64,
+    /// Controller error counters, `None` for virtual devices
+    pub can: Option<CanDeviceStats>,
+}
 
-    // after opening the socket, bind it to be able to receive messages back
-    // groups is set to 0, because we want no notifications
-    let bind_addr = NetlinkAddr::new(pid, 0);
-    sock.bind(bind_addr)?;
+impl CanInterfaceStats {
+    /// Parses the statistics out of an `RTM_NEWLINK` message.
+    fn from_link_msg(data: &[u8]) -> Option<CanInterfaceStats> {
+        let (_, attrs) = rtnl::parse_header::<IfInfoMsg>(data)?;
+
+        let mut stats = CanInterfaceStats::default();
+        for (attr_type, payload) in attrs {
+            match attr_type {
+                IFLA_STATS64 => {
+                    let link: RtnlLinkStats64 = rtnl::attr_struct(payload);
+                    stats.rx_packets = link.rx_packets;
+                    stats.tx_packets = link.tx_packets;
+                    stats.rx_bytes = link.rx_bytes;
+                    stats.tx_bytes = link.tx_bytes;
+                    stats.rx_errors = link.rx_errors;
+                    stats.tx_errors = link.tx_errors;
+                    stats.rx_dropped = link.rx_dropped;
+                    stats.tx_dropped = link.tx_dropped;
+                }
+                IFLA_LINKINFO => {
+                    stats.can = NlAttrs::new(payload)
+                        .get(IFLA_INFO_XSTATS)
+                        .map(rtnl::attr_struct::<CanDeviceStats>);
+                }
+                _ => (),
+            }
+        }
 
-    Ok(sock)
+        Some(stats)
+    }
+}
+
+/// A change of a CAN network device, reported by a `LinkMonitor`.
+///
+/// Every event carries the state of the device after the change.
+#[derive(Clone, Debug)]
+pub enum LinkEvent {
+    /// A new CAN device appeared, e.g. because an adapter was plugged in
+    Added(CanInterfaceInfo),
+    /// A CAN device was removed
+    Removed(CanInterfaceInfo),
+    /// A device was brought up
+    Up(CanInterfaceInfo),
+    /// A device was brought down
+    Down(CanInterfaceInfo),
+    /// The controller state of a device changed, e.g. it went bus-off
+    CanStateChanged(CanInterfaceInfo),
+}
+
+/// Link state monitor
+///
+/// Subscribes to the `RTNLGRP_LINK` multicast group and turns the kernel's
+/// link notifications into `LinkEvent`s for CAN devices. Iterating over a
+/// monitor blocks until the next event arrives.
+///
+/// If the kernel drops notifications because they are not read fast enough,
+/// the monitor resynchronizes with the current list of interfaces and reports
+/// the differences.
+pub struct LinkMonitor {
+    sock: NlSocket,
+    links: HashMap<c_uint, CanInterfaceInfo>,
+    pending: VecDeque<LinkEvent>,
+    buf: Vec<u8>,
+}
+
+impl LinkMonitor {
+    /// Start monitoring CAN devices
+    pub fn open() -> io::Result<LinkMonitor> {
+        // subscribe before listing, so no change between the two is lost
+        let sock = NlSocket::open(RTMGRP_LINK)?;
+
+        let links = CanInterface::list()?
+            .into_iter()
+            .map(|info| (info.index, info))
+            .collect();
+
+        Ok(LinkMonitor {
+            sock: sock,
+            links: links,
+            pending: VecDeque::new(),
+            buf: Vec::new(),
+        })
+    }
+
+    /// The CAN devices currently known to the monitor
+    pub fn interfaces(&self) -> Vec<CanInterfaceInfo> {
+        self.links.values().cloned().collect()
+    }
+
+    /// Change the monitor to non-blocking mode
+    ///
+    /// In non-blocking mode, `next_event` fails with `WouldBlock` if no event
+    /// is available.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+
+    /// Wait for the next link event.
+    pub fn next_event(&mut self) -> io::Result<LinkEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            let mut buf = mem::replace(&mut self.buf, Vec::new());
+            let rv = self.sock.recv(&mut buf);
+            match rv {
+                Ok(()) => {
+                    for msg in NlMessages::new(&buf) {
+                        let info = match CanInterfaceInfo::from_link_msg(msg.payload) {
+                            Some(info) => info,
+                            None => continue,
+                        };
+                        match msg.msg_type {
+                            RTM_NEWLINK => self.update(info),
+                            RTM_DELLINK => self.remove(info),
+                            _ => (),
+                        }
+                    }
+                    self.buf = buf;
+                }
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    self.buf = buf;
+                    self.resync()?;
+                }
+                Err(e) => {
+                    self.buf = buf;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, info: CanInterfaceInfo) {
+        let old = match self.links.insert(info.index, info.clone()) {
+            Some(old) => old,
+            None => {
+                self.pending.push_back(LinkEvent::Added(info));
+                return;
+            }
+        };
+
+        if old.is_up != info.is_up {
+            self.pending.push_back(if info.is_up {
+                LinkEvent::Up(info.clone())
+            } else {
+                LinkEvent::Down(info.clone())
+            });
+        }
+
+        if old.can_state != info.can_state && info.can_state.is_some() {
+            self.pending.push_back(LinkEvent::CanStateChanged(info));
+        }
+    }
+
+    fn remove(&mut self, info: CanInterfaceInfo) {
+        if self.links.remove(&info.index).is_some() {
+            self.pending.push_back(LinkEvent::Removed(info));
+        }
+    }
+
+    /// Compares the known links with a fresh dump, after notifications were
+    /// lost.
+    fn resync(&mut self) -> io::Result<()> {
+        let current = CanInterface::list()?;
+
+        let removed: Vec<CanInterfaceInfo> = self.links
+            .values()
+            .filter(|known| current.iter().all(|info| info.index != known.index))
+            .cloned()
+            .collect();
+
+        for info in removed {
+            self.remove(info);
+        }
+
+        for info in current {
+            self.update(info);
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for LinkMonitor {
+    type Item = io::Result<LinkEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
 }
 
 /// SocketCAN interface
@@ -161,37 +592,197 @@ impl CanInterface {
         CanInterface { if_index: if_index }
     }
 
+    /// List all CAN interfaces
+    ///
+    /// Dumps all network links through netlink and returns those of type
+    /// `ARPHRD_CAN`, in the order reported by the kernel.
+    pub fn list() -> io::Result<Vec<CanInterfaceInfo>> {
+        let mut nl = NlSocket::open(0)?;
+
+        let mut msg = MsgBuilder::new(RTM_GETLINK, 0);
+        msg.header(&IfInfoMsg::new(0, 0, 0));
+        let replies = nl.dump(msg)?;
+
+        Ok(replies.iter()
+            .filter_map(|data| CanInterfaceInfo::from_link_msg(data))
+            .collect())
+    }
+
+    /// Interface index of this CAN interface
+    pub fn if_index(&self) -> c_uint {
+        self.if_index
+    }
+
+    /// Retrieve interface statistics
+    ///
+    /// Returns the kernel's 64 bit link counters and, for CAN hardware, the
+    /// controller error counters (`ip -s -d link show`).
+    pub fn stats(&self) -> io::Result<CanInterfaceStats> {
+        let reply = self.get_link()?;
+        CanInterfaceStats::from_link_msg(&reply)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed link message"))
+    }
+
+    /// Requests the `RTM_NEWLINK` message describing this interface.
+    fn get_link(&self) -> io::Result<Vec<u8>> {
+        let mut nl = NlSocket::open(0)?;
+
+        let mut msg = MsgBuilder::new(RTM_GETLINK, 0);
+        msg.header(&IfInfoMsg::new(self.if_index as i32, 0, 0));
+
+        nl.request(msg)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "No reply to link request"))
+    }
+
+    /// Changes the interface flags selected by `change` to `flags`.
+    fn set_flags(&self, flags: c_uint, change: c_uint) -> io::Result<()> {
+        let mut nl = NlSocket::open(0)?;
+
+        let mut msg = MsgBuilder::new(RTM_NEWLINK, 0);
+        msg.header(&IfInfoMsg::new(self.if_index as i32, flags, change));
+
+        nl.request_ack(msg)
+    }
+
+    /// Sets a single `u32` link attribute.
+    fn set_link_u32(&self, attr_type: u16, val: u32) -> io::Result<()> {
+        let mut nl = NlSocket::open(0)?;
+
+        let mut msg = MsgBuilder::new(RTM_NEWLINK, 0);
+        msg.header(&IfInfoMsg::new(self.if_index as i32, 0, 0));
+        msg.attr_u32(attr_type, val);
+
+        nl.request_ack(msg)
+    }
+
+    /// Set the MTU
+    ///
+    /// CAN devices only accept the MTUs of the frame types they support, see
+    /// `set_frame_mode`. Most drivers, including `vcan`, refuse to change the
+    /// MTU while the interface is up.
+    pub fn set_mtu(&self, mtu: u32) -> io::Result<()> {
+        self.set_link_u32(IFLA_MTU, mtu)
+    }
+
+    /// Switch between classic CAN, CAN FD and CAN XL
+    ///
+    /// Sets the MTU matching `mode`, the interface has to be down.
+    pub fn set_frame_mode(&self, mode: CanFrameMode) -> io::Result<()> {
+        self.set_mtu(mode.mtu())
+    }
+
+    /// Set the transmit queue length, in frames
+    pub fn set_txqueuelen(&self, len: u32) -> io::Result<()> {
+        self.set_link_u32(IFLA_TXQLEN, len)
+    }
+
     /// Bring down CAN interface
     ///
     /// Use a netlink control socket to set the interface status to "down".
     pub fn bring_down(&self) -> io::Result<()> {
-        let mut nl = open_nl_route_socket()?;
-
-        // prepare message
-        let mut header = NlMsgHeader::user_defined(RTM_NEWLINK, mem::size_of::<IfInfoMsg>() as u32);
-        header.ack();
-
         // settings flags to 0 and change to IFF_UP will disable the IFF_UP flag
-        let info = IfInfoMsg::new(self.if_index as i32, 0, IFF_UP);
-        let msg = NetlinkMessage::new(header, NetlinkPayload::Data(info.as_bytes()));
-
-        // send the message
-        send_and_read_ack(&mut nl, msg, &NetlinkAddr::new(0, 0))
+        self.set_flags(0, IFF_UP)
     }
 
     /// Bring up CAN interface
     ///
     /// Brings the interface up by settings its "up" flag enabled via netlink.
     pub fn bring_up(&self) -> io::Result<()> {
-        let mut nl = open_nl_route_socket()?;
+        self.set_flags(IFF_UP, IFF_UP)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rtnl::NLMSG_HDRLEN;
+
+    fn link_msg(dev_type: c_ushort) -> MsgBuilder {
+        let mut info = IfInfoMsg::new(7, IFF_UP, 0);
+        info.dev_type = dev_type;
+
+        let mut msg = MsgBuilder::new(RTM_NEWLINK, 0);
+        msg.header(&info);
+        msg.attr_str(IFLA_IFNAME, "can0");
+        msg.attr_u32(IFLA_MTU, 72);
+        msg.attr_u8(IFLA_OPERSTATE, 6);
+        msg
+    }
 
-        let mut header = NlMsgHeader::user_defined(RTM_NEWLINK, mem::size_of::<IfInfoMsg>() as u32);
-        header.ack();
+    #[test]
+    fn test_parse_link_info() {
+        let mut msg = link_msg(ARPHRD_CAN);
+        msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "can");
+        msg.nest_start(IFLA_INFO_DATA);
+        msg.attr_u32(IFLA_CAN_STATE, 3);
+        msg.nest_end();
+        msg.nest_end();
+        let buf = msg.finish(1);
+
+        let info = CanInterfaceInfo::from_link_msg(&buf[NLMSG_HDRLEN..]).unwrap();
+        assert_eq!(info.name, "can0");
+        assert_eq!(info.index, 7);
+        assert_eq!(info.kind, Some(CanInterfaceKind::Can));
+        assert!(info.is_up);
+        assert_eq!(info.oper_state, OperState::Up);
+        assert_eq!(info.can_state, Some(CanState::BusOff));
+        assert_eq!(info.mtu, 72);
+    }
+
+    #[test]
+    fn test_frame_mode_from_mtu() {
+        assert_eq!(CanFrameMode::from_mtu(16), Some(CanFrameMode::Classic));
+        assert_eq!(CanFrameMode::from_mtu(72), Some(CanFrameMode::Fd));
+        assert_eq!(CanFrameMode::from_mtu(2060), Some(CanFrameMode::Xl));
+        assert_eq!(CanFrameMode::from_mtu(100), Some(CanFrameMode::Xl));
+        assert_eq!(CanFrameMode::from_mtu(1500), Some(CanFrameMode::Xl));
+        assert_eq!(CanFrameMode::from_mtu(20), None);
+        assert_eq!(CanFrameMode::from_mtu(4000), None);
+    }
 
-        let info = IfInfoMsg::new(self.if_index as i32, IFF_UP, IFF_UP);
-        let msg = NetlinkMessage::new(header, NetlinkPayload::Data(info.as_bytes()));
+    #[test]
+    fn test_ignore_non_can_links() {
+        let buf = link_msg(1).finish(1);
+        assert!(CanInterfaceInfo::from_link_msg(&buf[NLMSG_HDRLEN..]).is_none());
+    }
 
-        // send the message
-        send_and_read_ack(&mut nl, msg, &NetlinkAddr::new(0, 0))
+    #[test]
+    fn test_parse_stats() {
+        let link = RtnlLinkStats64 {
+            rx_packets: 1,
+            tx_packets: 2,
+            rx_bytes: 3,
+            tx_bytes: 4,
+            rx_errors: 5,
+            tx_errors: 6,
+            rx_dropped: 7,
+            tx_dropped: 8,
+        };
+        let can = CanDeviceStats {
+            bus_error: 9,
+            error_warning: 10,
+            error_passive: 11,
+            bus_off: 12,
+            arbitration_lost: 13,
+            restarts: 14,
+        };
+
+        let mut msg = link_msg(ARPHRD_CAN);
+        msg.attr_struct(IFLA_STATS64, &link);
+        msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "can");
+        msg.attr_struct(IFLA_INFO_XSTATS, &can);
+        msg.nest_end();
+        let buf = msg.finish(1);
+
+        let stats = CanInterfaceStats::from_link_msg(&buf[NLMSG_HDRLEN..]).unwrap();
+        assert_eq!(stats.rx_packets, 1);
+        assert_eq!(stats.tx_bytes, 4);
+        assert_eq!(stats.tx_dropped, 8);
+        assert_eq!(stats.can, Some(can));
     }
 }
@@ -0,0 +1,462 @@
+//! On-board diagnostics (SAE J1979, ISO 15765-4)
+//!
+//! `ObdClient` queries current data (service 01), freeze frames (02), trouble
+//! codes (03, 07 and 0A) and vehicle information such as the VIN (09). It
+//! works on an `ObdSocket`, which sends requests to all ECUs and collects
+//! their responses from a `CanSocket`, or on any `IsoTpTransport` connected
+//! to a single ECU:
+//!
+//! ```no_run
+//! use socketcan::obd::{ObdAddressing, ObdClient, ObdSocket, PID_ENGINE_SPEED};
+//!
+//! let sock = ObdSocket::open("vcan0", ObdAddressing::Standard).unwrap();
+//! let mut client = ObdClient::new(sock);
+//!
+//! for response in client.current_data(PID_ENGINE_SPEED).unwrap() {
+//!     println!("{:?}: {:?}", response.ecu, response.data);
+//! }
+//! for response in client.stored_dtcs().unwrap() {
+//!     for dtc in response.data {
+//!         println!("{}", dtc);
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use std::{error, fmt, io};
+
+mod pid;
+mod socket;
+
+pub use self::pid::*;
+pub use self::socket::{ObdAddressing, ObdSocket};
+
+use crate::isotp::IsoTpTransport;
+use crate::uds::NegativeResponseCode;
+use crate::ShouldRetry;
+
+// services of SAE J1979
+pub const SERVICE_CURRENT_DATA: u8 = 0x01;
+pub const SERVICE_FREEZE_FRAME_DATA: u8 = 0x02;
+pub const SERVICE_STORED_DTCS: u8 = 0x03;
+pub const SERVICE_PENDING_DTCS: u8 = 0x07;
+pub const SERVICE_VEHICLE_INFORMATION: u8 = 0x09;
+pub const SERVICE_PERMANENT_DTCS: u8 = 0x0a;
+
+// info types of service 09
+pub const INFO_VIN: u8 = 0x02;
+pub const INFO_ECU_NAME: u8 = 0x0a;
+
+/// time to wait for responses, P2 of ISO 15765-4 is 50ms
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// time to wait after a pending response
+const OBD_P2_STAR: Duration = Duration::from_millis(5000);
+
+/// Check if a response tells the ECU needs more time.
+fn is_response_pending(response: &[u8]) -> bool {
+    response.len() == 3 && response[0] == 0x7f && NegativeResponseCode::from(response[2]) ==
+                                                  NegativeResponseCode::ResponsePending
+}
+
+/// A diagnostic trouble code, as shown by `Display` like `P0301`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObdDtc(pub u16);
+
+impl fmt::Display for ObdDtc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let system = match self.0 >> 14 {
+            0 => 'P',
+            1 => 'C',
+            2 => 'B',
+            _ => 'U',
+        };
+        write!(f, "{}{:04X}", system, self.0 & 0x3fff)
+    }
+}
+
+/// A response of an ECU
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObdResponse<T> {
+    /// CAN id of the ECU, `None` on ISO-TP transports
+    pub ecu: Option<u32>,
+    pub data: T,
+}
+
+/// Errors of OBD requests
+#[derive(Debug)]
+pub enum ObdError {
+    /// The transport failed
+    IOError(io::Error),
+
+    /// No ECU responded
+    NoResponse,
+
+    /// The ECUs rejected the request
+    NegativeResponse(NegativeResponseCode),
+}
+
+impl fmt::Display for ObdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObdError::IOError(ref e) => write!(f, "IO: {}", e),
+            ObdError::NoResponse => write!(f, "no response"),
+            ObdError::NegativeResponse(code) => write!(f, "negative response: {:?}", code),
+        }
+    }
+}
+
+impl error::Error for ObdError {}
+
+impl From<io::Error> for ObdError {
+    fn from(e: io::Error) -> ObdError {
+        ObdError::IOError(e)
+    }
+}
+
+/// Sends a request and collects the responses
+pub trait ObdTransport {
+    /// Send a request, returning the responses received within `timeout`.
+    ///
+    /// Pending responses (0x78) extend the wait and are not returned.
+    fn query(&mut self, request: &[u8], timeout: Duration) -> io::Result<Vec<ObdResponse<Vec<u8>>>>;
+}
+
+impl<T: IsoTpTransport> ObdTransport for T {
+    /// Returns the single response of the ECU at the other end.
+    fn query(&mut self, request: &[u8], timeout: Duration) -> io::Result<Vec<ObdResponse<Vec<u8>>>> {
+        self.write_pdu(request)?;
+
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            // a zero timeout would block forever
+            if now >= deadline || deadline - now < Duration::from_micros(1) {
+                return Ok(Vec::new());
+            }
+            self.set_read_timeout(deadline - now)?;
+
+            match self.read_pdu() {
+                Ok(ref response) if is_response_pending(response) => deadline = Instant::now() + OBD_P2_STAR,
+                Ok(response) => {
+                    return Ok(vec![ObdResponse {
+                                       ecu: None,
+                                       data: response,
+                                   }])
+                }
+                Err(ref e) if e.should_retry() => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Decode the DTCs of a response to service 03, 07 or 0A.
+///
+/// On CAN, the number of DTCs precedes them.
+fn decode_dtcs(data: &[u8]) -> Vec<ObdDtc> {
+    data.get(1..)
+        .unwrap_or(&[])
+        .chunks(2)
+        .filter(|c| c.len() == 2 && (c[0] != 0 || c[1] != 0))
+        .map(|c| ObdDtc(u16::from_be_bytes([c[0], c[1]])))
+        .collect()
+}
+
+/// Decode the PIDs of responses, which start at `offset`, skipping those
+/// too short.
+fn decode_pids(pid: u8, responses: Vec<ObdResponse<Vec<u8>>>, offset: usize) -> Vec<ObdResponse<PidValue>> {
+    responses.into_iter()
+             .filter_map(|r| {
+                 let value = PidValue::decode(pid, &r.data[offset..])?;
+                 Some(ObdResponse {
+                     ecu: r.ecu,
+                     data: value,
+                 })
+             })
+             .collect()
+}
+
+/// Decode a string of service 09, dropping the padding.
+fn decode_string(data: &[u8]) -> String {
+    let data: Vec<u8> = data.iter().cloned().filter(|&b| b != 0).collect();
+    String::from_utf8_lossy(&data).into_owned()
+}
+
+/// An OBD client
+#[derive(Debug)]
+pub struct ObdClient<T> {
+    transport: T,
+    timeout: Duration,
+}
+
+impl<T: ObdTransport> ObdClient<T> {
+    pub fn new(transport: T) -> ObdClient<T> {
+        ObdClient {
+            transport: transport,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Sets the time to wait for responses.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Send a raw request, returning the positive responses following the
+    /// service.
+    ///
+    /// Responses that do not echo the parameters of the request are
+    /// skipped. Fails with a negative response only if no ECU responded
+    /// positively.
+    pub fn query(&mut self, service: u8, params: &[u8]) -> Result<Vec<ObdResponse<Vec<u8>>>, ObdError> {
+        let mut request = vec![service];
+        request.extend_from_slice(params);
+
+        let mut negative = None;
+        let mut positive = Vec::new();
+        for response in self.transport.query(&request, self.timeout)? {
+            let data = &response.data;
+            if data.len() >= 3 && data[0] == 0x7f && data[1] == service {
+                negative = Some(NegativeResponseCode::from(data[2]));
+            } else if data.len() > params.len() && data[0] == service.wrapping_add(0x40) &&
+                      data[1..=params.len()] == *params {
+                positive.push(ObdResponse {
+                    ecu: response.ecu,
+                    data: data[1..].to_vec(),
+                });
+            }
+        }
+
+        match negative {
+            _ if !positive.is_empty() => Ok(positive),
+            Some(code) => Err(ObdError::NegativeResponse(code)),
+            None => Err(ObdError::NoResponse),
+        }
+    }
+
+    /// Read a PID of the current data.
+    pub fn current_data(&mut self, pid: u8) -> Result<Vec<ObdResponse<PidValue>>, ObdError> {
+        let responses = self.query(SERVICE_CURRENT_DATA, &[pid])?;
+        Ok(decode_pids(pid, responses, 1))
+    }
+
+    /// Read a PID of a freeze frame.
+    pub fn freeze_frame_data(&mut self, pid: u8, frame: u8) -> Result<Vec<ObdResponse<PidValue>>, ObdError> {
+        let responses = self.query(SERVICE_FREEZE_FRAME_DATA, &[pid, frame])?;
+        Ok(decode_pids(pid, responses, 2))
+    }
+
+    /// The PIDs of the current data each ECU supports.
+    ///
+    /// Queries the ranges of supported PIDs as long as any ECU supports the
+    /// next range.
+    pub fn supported_pids(&mut self) -> Result<Vec<ObdResponse<Vec<u8>>>, ObdError> {
+        let mut supported: BTreeMap<Option<u32>, Vec<u8>> = BTreeMap::new();
+
+        let mut range = PID_SUPPORTED_01_20;
+        loop {
+            let responses = match self.current_data(range) {
+                Ok(responses) => responses,
+                // later ranges may be rejected by all ECUs
+                Err(ObdError::NoResponse) | Err(ObdError::NegativeResponse(_)) if range != 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            let mut next = false;
+            for response in responses {
+                if let PidValue::Supported(pids) = response.data {
+                    next |= pids.contains(&range.wrapping_add(0x20));
+                    supported.entry(response.ecu).or_default().extend(pids);
+                }
+            }
+
+            if !next || range == 0xe0 {
+                break;
+            }
+            range += 0x20;
+        }
+
+        Ok(supported.into_iter()
+                    .map(|(ecu, pids)| {
+                        ObdResponse {
+                            ecu: ecu,
+                            data: pids,
+                        }
+                    })
+                    .collect())
+    }
+
+    fn dtcs(&mut self, service: u8) -> Result<Vec<ObdResponse<Vec<ObdDtc>>>, ObdError> {
+        Ok(self.query(service, &[])?
+               .into_iter()
+               .map(|r| {
+                   ObdResponse {
+                       ecu: r.ecu,
+                       data: decode_dtcs(&r.data),
+                   }
+               })
+               .collect())
+    }
+
+    /// Read the confirmed DTCs.
+    pub fn stored_dtcs(&mut self) -> Result<Vec<ObdResponse<Vec<ObdDtc>>>, ObdError> {
+        self.dtcs(SERVICE_STORED_DTCS)
+    }
+
+    /// Read the DTCs detected during the current or last driving cycle.
+    pub fn pending_dtcs(&mut self) -> Result<Vec<ObdResponse<Vec<ObdDtc>>>, ObdError> {
+        self.dtcs(SERVICE_PENDING_DTCS)
+    }
+
+    /// Read the DTCs that cannot be cleared by a scan tool.
+    pub fn permanent_dtcs(&mut self) -> Result<Vec<ObdResponse<Vec<ObdDtc>>>, ObdError> {
+        self.dtcs(SERVICE_PERMANENT_DTCS)
+    }
+
+    /// Read vehicle information, returning the data following the number
+    /// of data items.
+    pub fn vehicle_information(&mut self, info_type: u8) -> Result<Vec<ObdResponse<Vec<u8>>>, ObdError> {
+        Ok(self.query(SERVICE_VEHICLE_INFORMATION, &[info_type])?
+               .into_iter()
+               .filter(|r| r.data.len() >= 2)
+               .map(|r| {
+                   ObdResponse {
+                       ecu: r.ecu,
+                       data: r.data[2..].to_vec(),
+                   }
+               })
+               .collect())
+    }
+
+    /// Read the vehicle identification number.
+    pub fn vin(&mut self) -> Result<Vec<ObdResponse<String>>, ObdError> {
+        self.string(INFO_VIN)
+    }
+
+    /// Read the names of the ECUs.
+    pub fn ecu_name(&mut self) -> Result<Vec<ObdResponse<String>>, ObdError> {
+        self.string(INFO_ECU_NAME)
+    }
+
+    fn string(&mut self, info_type: u8) -> Result<Vec<ObdResponse<String>>, ObdError> {
+        Ok(self.vehicle_information(info_type)?
+               .into_iter()
+               .map(|r| {
+                   ObdResponse {
+                       ecu: r.ecu,
+                       data: decode_string(&r.data),
+                   }
+               })
+               .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Answers requests with the responses of several ECUs.
+    struct Vehicle {
+        responses: HashMap<Vec<u8>, Vec<ObdResponse<Vec<u8>>>>,
+    }
+
+    impl Vehicle {
+        fn new(responses: &[(&[u8], u32, &[u8])]) -> Vehicle {
+            let mut vehicle = Vehicle { responses: HashMap::new() };
+            for &(request, ecu, response) in responses {
+                vehicle.responses
+                       .entry(request.to_vec())
+                       .or_default()
+                       .push(ObdResponse {
+                           ecu: Some(ecu),
+                           data: response.to_vec(),
+                       });
+            }
+            vehicle
+        }
+    }
+
+    impl ObdTransport for Vehicle {
+        fn query(&mut self, request: &[u8], _timeout: Duration) -> io::Result<Vec<ObdResponse<Vec<u8>>>> {
+            Ok(self.responses.get(request).cloned().unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_dtc_display() {
+        assert_eq!(ObdDtc(0x0301).to_string(), "P0301");
+        assert_eq!(ObdDtc(0x4123).to_string(), "C0123");
+        assert_eq!(ObdDtc(0xc1a2).to_string(), "U01A2");
+    }
+
+    #[test]
+    fn test_current_data() {
+        let mut client = ObdClient::new(Vehicle::new(&[(&[0x01, 0x0c], 0x7e8, &[0x41, 0x0c, 0x1a, 0xf8]),
+                                                       (&[0x01, 0x0c], 0x7e9, &[0x7f, 0x01, 0x12]),
+                                                       (&[0x01, 0x0d], 0x7e9, &[0x7f, 0x01, 0x31])]));
+        assert_eq!(client.current_data(PID_ENGINE_SPEED).unwrap(),
+                   vec![ObdResponse {
+                            ecu: Some(0x7e8),
+                            data: PidValue::EngineSpeed(1726.0),
+                        }]);
+
+        match client.current_data(PID_VEHICLE_SPEED) {
+            Err(ObdError::NegativeResponse(NegativeResponseCode::RequestOutOfRange)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match client.current_data(PID_COOLANT_TEMPERATURE) {
+            Err(ObdError::NoResponse) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_service_beyond_j1979() {
+        // the positive response id of 0xc0 does not fit into a byte
+        let mut client = ObdClient::new(Vehicle::new(&[(&[0xc0], 0x7e8, &[0x7f, 0xc0, 0x11]),
+                                                       (&[0xc0], 0x7e9, &[0x01])]));
+        match client.query(0xc0, &[]) {
+            Err(ObdError::NegativeResponse(NegativeResponseCode::ServiceNotSupported)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_supported_pids() {
+        let mut client = ObdClient::new(Vehicle::new(&[(&[0x01, 0x00], 0x7e8, &[0x41, 0x00, 0x80, 0, 0, 1]),
+                                                       (&[0x01, 0x00], 0x7e9, &[0x41, 0x00, 0x00, 0x10, 0, 0]),
+                                                       (&[0x01, 0x20], 0x7e8, &[0x41, 0x20, 0, 0, 0x20, 0])]));
+        assert_eq!(client.supported_pids().unwrap(),
+                   vec![ObdResponse {
+                            ecu: Some(0x7e8),
+                            data: vec![0x01, 0x20, 0x33],
+                        },
+                        ObdResponse {
+                            ecu: Some(0x7e9),
+                            data: vec![0x0c],
+                        }]);
+    }
+
+    #[test]
+    fn test_dtcs_and_vin() {
+        let mut client = ObdClient::new(Vehicle::new(&[(&[0x03], 0x7e8, &[0x43, 0x02, 0x03, 0x01, 0xc1, 0xa2]),
+                                                       (&[0x03], 0x7e9, &[0x43, 0x00]),
+                                                       (&[0x09, 0x02], 0x7e8, b"\x49\x02\x01W0L0000000000000X")]));
+        let dtcs = client.stored_dtcs().unwrap();
+        assert_eq!(dtcs[0].data, vec![ObdDtc(0x0301), ObdDtc(0xc1a2)]);
+        assert!(dtcs[1].data.is_empty());
+        assert_eq!(client.vin().unwrap()[0].data, "W0L0000000000000X");
+    }
+}
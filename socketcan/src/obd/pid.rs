@@ -0,0 +1,229 @@
+//! Parameter IDs of service 01 (current data) and 02 (freeze frame data)
+
+use super::ObdDtc;
+
+// PIDs of SAE J1979
+pub const PID_SUPPORTED_01_20: u8 = 0x00;
+pub const PID_MONITOR_STATUS: u8 = 0x01;
+pub const PID_FREEZE_DTC: u8 = 0x02;
+pub const PID_FUEL_SYSTEM_STATUS: u8 = 0x03;
+pub const PID_ENGINE_LOAD: u8 = 0x04;
+pub const PID_COOLANT_TEMPERATURE: u8 = 0x05;
+pub const PID_SHORT_TERM_FUEL_TRIM_BANK1: u8 = 0x06;
+pub const PID_LONG_TERM_FUEL_TRIM_BANK1: u8 = 0x07;
+pub const PID_SHORT_TERM_FUEL_TRIM_BANK2: u8 = 0x08;
+pub const PID_LONG_TERM_FUEL_TRIM_BANK2: u8 = 0x09;
+pub const PID_FUEL_PRESSURE: u8 = 0x0a;
+pub const PID_INTAKE_MANIFOLD_PRESSURE: u8 = 0x0b;
+pub const PID_ENGINE_SPEED: u8 = 0x0c;
+pub const PID_VEHICLE_SPEED: u8 = 0x0d;
+pub const PID_TIMING_ADVANCE: u8 = 0x0e;
+pub const PID_INTAKE_AIR_TEMPERATURE: u8 = 0x0f;
+pub const PID_MAF_AIR_FLOW_RATE: u8 = 0x10;
+pub const PID_THROTTLE_POSITION: u8 = 0x11;
+pub const PID_OBD_STANDARD: u8 = 0x1c;
+pub const PID_RUN_TIME: u8 = 0x1f;
+pub const PID_DISTANCE_WITH_MIL: u8 = 0x21;
+pub const PID_FUEL_TANK_LEVEL: u8 = 0x2f;
+pub const PID_DISTANCE_SINCE_CLEARED: u8 = 0x31;
+pub const PID_BAROMETRIC_PRESSURE: u8 = 0x33;
+pub const PID_CONTROL_MODULE_VOLTAGE: u8 = 0x42;
+pub const PID_AMBIENT_AIR_TEMPERATURE: u8 = 0x46;
+pub const PID_FUEL_TYPE: u8 = 0x51;
+pub const PID_ENGINE_OIL_TEMPERATURE: u8 = 0x5c;
+pub const PID_ENGINE_FUEL_RATE: u8 = 0x5e;
+
+/// Check if `pid` asks for the PIDs supported, 0x00, 0x20 and so on.
+#[inline]
+pub fn pid_is_supported_range(pid: u8) -> bool {
+    pid % 0x20 == 0
+}
+
+/// A decoded PID, in the units of SAE J1979
+#[derive(Debug, Clone, PartialEq)]
+pub enum PidValue {
+    /// the supported PIDs of the next 32 PIDs
+    Supported(Vec<u8>),
+
+    /// the malfunction indicator lamp and the number of stored DTCs
+    MonitorStatus { mil: bool, dtc_count: u8 },
+
+    /// the DTC that caused the freeze frame
+    FreezeDtc(ObdDtc),
+
+    /// status of fuel system 1 and 2
+    FuelSystemStatus(u8, u8),
+
+    /// calculated engine load in %
+    EngineLoad(f64),
+
+    /// in °C
+    CoolantTemperature(i16),
+
+    /// in %, negative is leaner
+    ShortTermFuelTrim { bank: u8, percent: f64 },
+    LongTermFuelTrim { bank: u8, percent: f64 },
+
+    /// gauge pressure in kPa
+    FuelPressure(u16),
+
+    /// absolute pressure in kPa
+    IntakeManifoldPressure(u8),
+
+    /// in rpm
+    EngineSpeed(f64),
+
+    /// in km/h
+    VehicleSpeed(u8),
+
+    /// in ° before top dead center
+    TimingAdvance(f64),
+
+    /// in °C
+    IntakeAirTemperature(i16),
+
+    /// in g/s
+    MafAirFlowRate(f64),
+
+    /// in %
+    ThrottlePosition(f64),
+
+    /// the OBD standard the vehicle conforms to
+    ObdStandard(u8),
+
+    /// in s
+    RunTime(u16),
+
+    /// in km
+    DistanceWithMil(u16),
+
+    /// in %
+    FuelTankLevel(f64),
+
+    /// in km
+    DistanceSinceCleared(u16),
+
+    /// in kPa
+    BarometricPressure(u8),
+
+    /// in V
+    ControlModuleVoltage(f64),
+
+    /// in °C
+    AmbientAirTemperature(i16),
+
+    /// the fuel type code
+    FuelType(u8),
+
+    /// in °C
+    EngineOilTemperature(i16),
+
+    /// in l/h
+    EngineFuelRate(f64),
+
+    /// PIDs without a variant
+    Raw(Vec<u8>),
+}
+
+impl PidValue {
+    /// Decode the data of `pid`, `None` if it is too short.
+    pub fn decode(pid: u8, data: &[u8]) -> Option<PidValue> {
+        let a = || data.first().map(|&a| f64::from(a));
+        let ab = || {
+            if data.len() >= 2 {
+                Some(u16::from_be_bytes([data[0], data[1]]))
+            } else {
+                None
+            }
+        };
+        let temperature = || data.first().map(|&a| i16::from(a) - 40);
+        let percent = || a().map(|a| a * 100.0 / 255.0);
+        let trim = || a().map(|a| a * 100.0 / 128.0 - 100.0);
+
+        let value = match pid {
+            _ if pid_is_supported_range(pid) => {
+                if data.len() < 4 {
+                    return None;
+                }
+                let bits = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                let pids = (0..32).filter(|i| bits & (0x8000_0000 >> i) != 0)
+                                  .map(|i| pid.wrapping_add(i as u8 + 1))
+                                  .collect();
+                PidValue::Supported(pids)
+            }
+            PID_MONITOR_STATUS => {
+                let a = *data.first()?;
+                PidValue::MonitorStatus {
+                    mil: a & 0x80 != 0,
+                    dtc_count: a & 0x7f,
+                }
+            }
+            PID_FREEZE_DTC => PidValue::FreezeDtc(ObdDtc(ab()?)),
+            PID_FUEL_SYSTEM_STATUS => PidValue::FuelSystemStatus(*data.first()?, *data.get(1)?),
+            PID_ENGINE_LOAD => PidValue::EngineLoad(percent()?),
+            PID_COOLANT_TEMPERATURE => PidValue::CoolantTemperature(temperature()?),
+            PID_SHORT_TERM_FUEL_TRIM_BANK1 | PID_SHORT_TERM_FUEL_TRIM_BANK2 => {
+                PidValue::ShortTermFuelTrim {
+                    bank: (pid - PID_SHORT_TERM_FUEL_TRIM_BANK1) / 2 + 1,
+                    percent: trim()?,
+                }
+            }
+            PID_LONG_TERM_FUEL_TRIM_BANK1 | PID_LONG_TERM_FUEL_TRIM_BANK2 => {
+                PidValue::LongTermFuelTrim {
+                    bank: (pid - PID_LONG_TERM_FUEL_TRIM_BANK1) / 2 + 1,
+                    percent: trim()?,
+                }
+            }
+            PID_FUEL_PRESSURE => PidValue::FuelPressure(u16::from(*data.first()?) * 3),
+            PID_INTAKE_MANIFOLD_PRESSURE => PidValue::IntakeManifoldPressure(*data.first()?),
+            PID_ENGINE_SPEED => PidValue::EngineSpeed(f64::from(ab()?) / 4.0),
+            PID_VEHICLE_SPEED => PidValue::VehicleSpeed(*data.first()?),
+            PID_TIMING_ADVANCE => PidValue::TimingAdvance(a()? / 2.0 - 64.0),
+            PID_INTAKE_AIR_TEMPERATURE => PidValue::IntakeAirTemperature(temperature()?),
+            PID_MAF_AIR_FLOW_RATE => PidValue::MafAirFlowRate(f64::from(ab()?) / 100.0),
+            PID_THROTTLE_POSITION => PidValue::ThrottlePosition(percent()?),
+            PID_OBD_STANDARD => PidValue::ObdStandard(*data.first()?),
+            PID_RUN_TIME => PidValue::RunTime(ab()?),
+            PID_DISTANCE_WITH_MIL => PidValue::DistanceWithMil(ab()?),
+            PID_FUEL_TANK_LEVEL => PidValue::FuelTankLevel(percent()?),
+            PID_DISTANCE_SINCE_CLEARED => PidValue::DistanceSinceCleared(ab()?),
+            PID_BAROMETRIC_PRESSURE => PidValue::BarometricPressure(*data.first()?),
+            PID_CONTROL_MODULE_VOLTAGE => PidValue::ControlModuleVoltage(f64::from(ab()?) / 1000.0),
+            PID_AMBIENT_AIR_TEMPERATURE => PidValue::AmbientAirTemperature(temperature()?),
+            PID_FUEL_TYPE => PidValue::FuelType(*data.first()?),
+            PID_ENGINE_OIL_TEMPERATURE => PidValue::EngineOilTemperature(temperature()?),
+            PID_ENGINE_FUEL_RATE => PidValue::EngineFuelRate(f64::from(ab()?) / 20.0),
+            _ => PidValue::Raw(data.to_vec()),
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(PidValue::decode(PID_ENGINE_SPEED, &[0x1a, 0xf8]), Some(PidValue::EngineSpeed(1726.0)));
+        assert_eq!(PidValue::decode(PID_ENGINE_SPEED, &[0x1a]), None);
+        assert_eq!(PidValue::decode(PID_COOLANT_TEMPERATURE, &[0x23]),
+                   Some(PidValue::CoolantTemperature(-5)));
+        assert_eq!(PidValue::decode(PID_LONG_TERM_FUEL_TRIM_BANK2, &[0x80]),
+                   Some(PidValue::LongTermFuelTrim { bank: 2, percent: 0.0 }));
+        assert_eq!(PidValue::decode(PID_MONITOR_STATUS, &[0x83, 0x07, 0x65, 0x04]),
+                   Some(PidValue::MonitorStatus { mil: true, dtc_count: 3 }));
+        assert_eq!(PidValue::decode(0xa6, &[1, 2, 3, 4]), Some(PidValue::Raw(vec![1, 2, 3, 4])));
+    }
+
+    #[test]
+    fn test_supported() {
+        assert_eq!(PidValue::decode(PID_SUPPORTED_01_20, &[0xbe, 0x1f, 0xa8, 0x13]),
+                   Some(PidValue::Supported(vec![0x01, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0c, 0x0d, 0x0e, 0x0f,
+                                                 0x10, 0x11, 0x13, 0x15, 0x1c, 0x1f, 0x20])));
+        match PidValue::decode(0x20, &[0x80, 0, 0, 1]) {
+            Some(PidValue::Supported(pids)) => assert_eq!(pids, vec![0x21, 0x40]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
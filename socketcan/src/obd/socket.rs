@@ -0,0 +1,310 @@
+//! OBD requests over a raw `CanSocket`, collecting the responses of all ECUs
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::{is_response_pending, ObdResponse, ObdTransport, OBD_P2_STAR};
+use crate::{
+    CanFilter, CanFrame, CanMessageId, CanSocket, CanSocketOpenError, FrameFlags, ShouldRetry,
+    EFF_MASK,
+};
+
+// identifiers of ISO 15765-4
+const FUNCTIONAL_SFF: u32 = 0x7df;
+const PHYSICAL_SFF: u32 = 0x7e0;
+const RESPONSE_SFF: u32 = 0x7e8;
+const FUNCTIONAL_EFF: u32 = 0x18db_33f1;
+const PHYSICAL_EFF: u32 = 0x18da_00f1;
+const RESPONSE_EFF: u32 = 0x18da_f100;
+
+/// ISO-TP frame types
+const PCI_SINGLE: u8 = 0x00;
+const PCI_FIRST: u8 = 0x10;
+const PCI_CONSECUTIVE: u8 = 0x20;
+
+/// flow control, continue to send all frames without delay
+const FLOW_CONTINUE: [u8; 3] = [0x30, 0x00, 0x00];
+
+/// Identifiers of OBD requests and responses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ObdAddressing {
+    /// 11 bit identifiers, requests to 0x7df and 0x7e0-0x7e7, responses from
+    /// 0x7e8-0x7ef
+    Standard,
+
+    /// 29 bit identifiers, requests to 0x18db33f1 and 0x18daxxf1, responses
+    /// from 0x18daf1xx
+    Extended,
+}
+
+impl ObdAddressing {
+    /// The identifier to send requests to `ecu` with, where `ecu` is the
+    /// number (0-7) of a standard or the address of an extended ECU.
+    fn request_id(self, ecu: Option<u8>) -> CanMessageId {
+        match (self, ecu) {
+            (ObdAddressing::Standard, None) => CanMessageId::SFF(FUNCTIONAL_SFF as u16),
+            (ObdAddressing::Standard, Some(n)) => CanMessageId::SFF((PHYSICAL_SFF + u32::from(n & 0x07)) as u16),
+            (ObdAddressing::Extended, None) => CanMessageId::EFF(FUNCTIONAL_EFF),
+            (ObdAddressing::Extended, Some(addr)) => CanMessageId::EFF(PHYSICAL_EFF | u32::from(addr) << 8),
+        }
+    }
+
+    /// The ECU a response identifier belongs to, in the same form as
+    /// `request_id` takes.
+    fn ecu(self, frame: &CanFrame) -> Option<u8> {
+        match self {
+            ObdAddressing::Standard if !frame.is_extended() && frame.id() & !0x07 == RESPONSE_SFF => {
+                Some((frame.id() & 0x07) as u8)
+            }
+            ObdAddressing::Extended if frame.is_extended() && frame.id() & !0xff == RESPONSE_EFF => {
+                Some(frame.id() as u8)
+            }
+            _ => None,
+        }
+    }
+
+    fn filter(self) -> CanFilter {
+        let flags = (FrameFlags::EFF_FLAG | FrameFlags::RTR_FLAG).bits();
+        match self {
+            ObdAddressing::Standard => CanFilter::new(RESPONSE_SFF, flags | (EFF_MASK & !0x07)),
+            ObdAddressing::Extended => {
+                CanFilter::new(RESPONSE_EFF | FrameFlags::EFF_FLAG.bits(), flags | (EFF_MASK & !0xff))
+            }
+        }
+        .unwrap()
+    }
+}
+
+/// Pads frames to 8 bytes, as ISO 15765-4 requires.
+fn padded(id: CanMessageId, data: &[u8]) -> io::Result<CanFrame> {
+    let mut buf = [0u8; 8];
+    buf[..data.len()].copy_from_slice(data);
+    CanFrame::new(id, &buf, false, false).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// A response spanning several frames
+#[derive(Debug)]
+struct Partial {
+    data: Vec<u8>,
+    len: usize,
+    next_sn: u8,
+}
+
+/// What to do with a received frame
+#[derive(Debug, PartialEq)]
+enum Received {
+    Ignore,
+    /// the ECU sends a multi-frame response, reply with flow control
+    FirstFrame,
+    ConsecutiveFrame,
+    Pending,
+    Complete(Vec<u8>),
+}
+
+/// Reassembles the responses of several ECUs at once.
+#[derive(Debug, Default)]
+struct Collector {
+    partials: HashMap<u8, Partial>,
+}
+
+impl Collector {
+    fn push(&mut self, ecu: u8, data: &[u8]) -> Received {
+        let pci = match data.first() {
+            Some(&pci) => pci,
+            None => return Received::Ignore,
+        };
+
+        match pci & 0xf0 {
+            PCI_SINGLE => {
+                let len = (pci & 0x0f) as usize;
+                if len == 0 || len >= data.len() {
+                    return Received::Ignore;
+                }
+                let payload = &data[1..=len];
+                if is_response_pending(payload) {
+                    Received::Pending
+                } else {
+                    Received::Complete(payload.to_vec())
+                }
+            }
+            PCI_FIRST if data.len() == 8 => {
+                let len = ((pci & 0x0f) as usize) << 8 | data[1] as usize;
+                if len < 8 {
+                    return Received::Ignore;
+                }
+                self.partials.insert(ecu,
+                                     Partial {
+                                         data: data[2..].to_vec(),
+                                         len: len,
+                                         next_sn: 1,
+                                     });
+                Received::FirstFrame
+            }
+            PCI_CONSECUTIVE => {
+                let done = match self.partials.get_mut(&ecu) {
+                    Some(ref mut partial) if pci & 0x0f == partial.next_sn => {
+                        partial.data.extend_from_slice(&data[1..]);
+                        partial.next_sn = (partial.next_sn + 1) & 0x0f;
+                        partial.data.len() >= partial.len
+                    }
+                    Some(_) => {
+                        // out of sequence, the response is lost
+                        self.partials.remove(&ecu);
+                        return Received::Ignore;
+                    }
+                    None => return Received::Ignore,
+                };
+
+                if done {
+                    let mut partial = self.partials.remove(&ecu).unwrap();
+                    partial.data.truncate(partial.len);
+                    Received::Complete(partial.data)
+                } else {
+                    Received::ConsecutiveFrame
+                }
+            }
+            _ => Received::Ignore,
+        }
+    }
+}
+
+/// OBD requests over a `CanSocket`, without the `can-isotp` kernel module.
+///
+/// Requests are sent to all ECUs (functional addressing) unless a target
+/// is set. Responses of several frames are reassembled, flow control
+/// frames are sent back to each ECU.
+#[derive(Debug)]
+pub struct ObdSocket {
+    sock: CanSocket,
+    addressing: ObdAddressing,
+    target: Option<u8>,
+}
+
+impl ObdSocket {
+    pub fn open(ifname: &str, addressing: ObdAddressing) -> Result<ObdSocket, CanSocketOpenError> {
+        let sock = CanSocket::open(ifname)?;
+        Ok(ObdSocket::new(sock, addressing)?)
+    }
+
+    /// Use an open socket, replacing its filters.
+    pub fn new(sock: CanSocket, addressing: ObdAddressing) -> io::Result<ObdSocket> {
+        sock.set_filters(&[addressing.filter()])?;
+        Ok(ObdSocket {
+            sock: sock,
+            addressing: addressing,
+            target: None,
+        })
+    }
+
+    /// Send requests to a single ECU, `None` sends them to all.
+    ///
+    /// ECUs are numbered 0-7 with standard addressing and identified by
+    /// their address with extended addressing.
+    pub fn set_target(&mut self, ecu: Option<u8>) {
+        self.target = ecu;
+    }
+
+    /// The underlying socket
+    pub fn socket(&self) -> &CanSocket {
+        &self.sock
+    }
+}
+
+impl ObdTransport for ObdSocket {
+    /// Requests have to fit a single frame. Responses are collected until
+    /// no frame arrived for `timeout`, or until the first response with a
+    /// target set.
+    fn query(&mut self, request: &[u8], timeout: Duration) -> io::Result<Vec<ObdResponse<Vec<u8>>>> {
+        if request.is_empty() || request.len() > 7 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "requests are 1 to 7 bytes"));
+        }
+
+        let mut sf = vec![request.len() as u8];
+        sf.extend_from_slice(request);
+        self.sock.write_frame_insist(&padded(self.addressing.request_id(self.target), &sf)?)?;
+
+        let mut collector = Collector::default();
+        let mut responses = Vec::new();
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            // a zero timeout would block forever
+            if now >= deadline || deadline - now < Duration::from_micros(1) {
+                return Ok(responses);
+            }
+            self.sock.set_read_timeout(deadline - now)?;
+
+            let frame = match self.sock.read_frame() {
+                Ok(frame) => frame,
+                Err(ref e) if e.should_retry() => return Ok(responses),
+                Err(e) => return Err(e),
+            };
+            let ecu = match self.addressing.ecu(&frame) {
+                Some(ecu) if self.target.is_none() || self.target == Some(ecu) => ecu,
+                _ => continue,
+            };
+
+            match collector.push(ecu, frame.data()) {
+                Received::Ignore => (),
+                Received::FirstFrame => {
+                    let fc = padded(self.addressing.request_id(Some(ecu)), &FLOW_CONTINUE)?;
+                    self.sock.write_frame_insist(&fc)?;
+                    deadline = Instant::now() + timeout;
+                }
+                Received::ConsecutiveFrame => deadline = Instant::now() + timeout,
+                Received::Pending => deadline = Instant::now() + OBD_P2_STAR,
+                Received::Complete(data) => {
+                    responses.push(ObdResponse {
+                        ecu: Some(frame.id()),
+                        data: data,
+                    });
+                    if self.target.is_some() {
+                        return Ok(responses);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addressing() {
+        let standard = ObdAddressing::Standard;
+        assert_eq!(standard.request_id(None), CanMessageId::SFF(0x7df));
+        assert_eq!(standard.request_id(Some(1)), CanMessageId::SFF(0x7e1));
+        let frame = CanFrame::new(CanMessageId::SFF(0x7e9), &[0; 8], false, false).unwrap();
+        assert_eq!(standard.ecu(&frame), Some(1));
+
+        let extended = ObdAddressing::Extended;
+        assert_eq!(extended.request_id(Some(0x10)), CanMessageId::EFF(0x18da10f1));
+        let frame = CanFrame::new(CanMessageId::EFF(0x18daf110), &[0; 8], false, false).unwrap();
+        assert_eq!(extended.ecu(&frame), Some(0x10));
+        assert_eq!(standard.ecu(&frame), None);
+    }
+
+    #[test]
+    fn test_collector() {
+        let mut collector = Collector::default();
+        assert_eq!(collector.push(0, &[0x03, 0x7f, 0x09, 0x78, 0, 0, 0, 0]), Received::Pending);
+
+        // two ECUs answering a VIN request at once
+        let vin = b"\x49\x02\x01W0L0000000000000X";
+        assert_eq!(collector.push(0, &[0x10, 20, 0x49, 0x02, 0x01, b'W', b'0', b'L']), Received::FirstFrame);
+        assert_eq!(collector.push(1, &[0x04, 0x41, 0x0d, 0x32, 0x00, 0, 0, 0]),
+                   Received::Complete(vec![0x41, 0x0d, 0x32, 0x00]));
+        assert_eq!(collector.push(0, &[0x21, b'0', b'0', b'0', b'0', b'0', b'0', b'0']),
+                   Received::ConsecutiveFrame);
+        assert_eq!(collector.push(0, &[0x22, b'0', b'0', b'0', b'0', b'0', b'0', b'X']),
+                   Received::Complete(vin.to_vec()));
+
+        // out of sequence
+        collector.push(0, &[0x10, 20, 0x49, 0x02, 0x01, b'W', b'0', b'L']);
+        assert_eq!(collector.push(0, &[0x22, 0, 0, 0, 0, 0, 0, 0]), Received::Ignore);
+        assert_eq!(collector.push(0, &[0x21, 0, 0, 0, 0, 0, 0, 0]), Received::Ignore);
+    }
+}
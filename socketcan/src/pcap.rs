@@ -0,0 +1,664 @@
+//! PCAP and PCAPNG capture files
+//!
+//! Reads and writes captures using the SocketCAN link type
+//! (`LINKTYPE_CAN_SOCKETCAN`, 227), which Wireshark can dissect along with
+//! higher layer protocols such as CANopen.
+//!
+//! Captures are written as pcapng, with an interface description block per
+//! device and nanosecond timestamps. Both pcapng and the classic pcap format
+//! can be read; packets of other link types are skipped.
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::pcap::Writer;
+//!
+//! let mut socket = CanSocket::open("can0").unwrap();
+//! let mut writer = Writer::from_file("can0.pcapng").unwrap();
+//! for _ in 0..1000 {
+//!     writer.capture_frame(&mut socket, "can0").unwrap();
+//! }
+//! writer.finish().unwrap();
+//! ```
+
+use crate::dump::{CanDumpFrame, CanDumpRecord, ParseError};
+use crate::{CanFdFrame, CanFrame, CanMessageId, CanSocket, CanXlFrame, FdFlags, FrameFlags, XlFlags};
+use crate::{EFF_MASK, ERR_MASK, SFF_MASK};
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+use std::{fs, io, path};
+
+/// `LINKTYPE_CAN_SOCKETCAN`
+pub const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+// pcapng block types
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+// pcapng options
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+
+// classic pcap magic numbers, microsecond and nanosecond resolution
+const PCAP_MAGIC_US: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+
+/// marks a CAN FD frame in the flags byte
+const CANFD_FDF: u8 = 0x04;
+
+/// size of the CAN XL header preceding the data
+const CANXL_HDR_SIZE: usize = 12;
+
+/// Encodes a frame as a `LINKTYPE_CAN_SOCKETCAN` packet.
+///
+/// The id of classic and CAN FD frames is in network byte order, the CAN XL
+/// header in little endian, as in captures taken by the kernel.
+pub fn encode_frame(frame: &CanDumpFrame) -> Vec<u8> {
+    match *frame {
+        CanDumpFrame::Classic(ref frame) | CanDumpFrame::Error(ref frame) => {
+            let mut buf = vec![0; 16];
+            buf[..4].copy_from_slice(&frame.id_raw().to_be_bytes());
+            buf[4] = frame.data().len() as u8;
+            if !frame.is_rtr() {
+                buf[8..8 + frame.data().len()].copy_from_slice(frame.data());
+            }
+            buf
+        }
+        CanDumpFrame::Fd(ref frame) => {
+            let mut buf = vec![0; 72];
+            buf[..4].copy_from_slice(&frame.id_raw().to_be_bytes());
+            buf[4] = frame.data().len() as u8;
+            buf[5] = frame.flags().bits() | CANFD_FDF;
+            buf[8..8 + frame.data().len()].copy_from_slice(frame.data());
+            buf
+        }
+        CanDumpFrame::Xl(ref frame) => {
+            let prio = frame.prio() | u32::from(frame.vcid()) << 16;
+            let mut buf = Vec::with_capacity(CANXL_HDR_SIZE + frame.data().len());
+            buf.extend_from_slice(&prio.to_le_bytes());
+            buf.extend_from_slice(&[frame.flags().bits(), frame.sdt()]);
+            buf.extend_from_slice(&(frame.data().len() as u16).to_le_bytes());
+            buf.extend_from_slice(&frame.af().to_le_bytes());
+            buf.extend_from_slice(frame.data());
+            buf
+        }
+    }
+}
+
+/// Decodes a `LINKTYPE_CAN_SOCKETCAN` packet.
+pub fn decode_frame(packet: &[u8]) -> Result<CanDumpFrame, ParseError> {
+    if packet.len() < 8 {
+        return Err(ParseError::InvalidCanFrame);
+    }
+
+    // the XLF flag takes the place of the length of other frames
+    if packet[4] & XlFlags::XLF.bits() != 0 {
+        if packet.len() < CANXL_HDR_SIZE {
+            return Err(ParseError::InvalidCanFrame);
+        }
+
+        let prio = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        let len = usize::from(u16::from_le_bytes([packet[6], packet[7]]));
+        let af = u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]);
+        let data = packet.get(CANXL_HDR_SIZE..CANXL_HDR_SIZE + len).ok_or(ParseError::InvalidCanFrame)?;
+        let frame = CanXlFrame::new(prio, packet[5], af, data, XlFlags::from_bits_truncate(packet[4]))?;
+        return Ok(CanDumpFrame::Xl(Box::new(frame)));
+    }
+
+    let raw_id = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+    let len = usize::from(packet[4]);
+    let fd = packet[5] & CANFD_FDF != 0 || packet.len() == crate::CANFD_MTU;
+    let rtr = raw_id & FrameFlags::RTR_FLAG.bits() != 0;
+    let data = if rtr { &[0; 8][..] } else { &packet[8..] };
+    let data = data.get(..len).ok_or(ParseError::InvalidCanFrame)?;
+
+    if raw_id & FrameFlags::ERR_FLAG.bits() != 0 {
+        return Ok(CanDumpFrame::Error(CanFrame::new_error(raw_id & ERR_MASK, data)?));
+    }
+
+    let id = if raw_id & FrameFlags::EFF_FLAG.bits() != 0 {
+        CanMessageId::EFF(raw_id & EFF_MASK)
+    } else {
+        CanMessageId::SFF((raw_id & SFF_MASK) as u16)
+    };
+
+    if fd {
+        let flags = FdFlags::from_bits_truncate(packet[5]);
+        Ok(CanDumpFrame::Fd(CanFdFrame::new(id, data, flags)?))
+    } else {
+        Ok(CanDumpFrame::Classic(CanFrame::new(id, data, rtr, false)?))
+    }
+}
+
+#[derive(Debug)]
+struct Interface {
+    link_type: u16,
+
+    /// index into `Reader::devices`
+    device: usize,
+
+    /// timestamp units per second
+    units_per_sec: u64,
+}
+
+/// What the next block or record of a capture held
+enum Next {
+    /// time, device index and range of the packet within the buffer
+    Packet(u64, usize, usize, usize),
+
+    /// a packet of another link type or a block without packet
+    Skip,
+    End,
+}
+
+#[derive(Debug)]
+enum Format {
+    /// not determined yet
+    Unknown,
+    Pcap { link_type: u16, units_per_sec: u64 },
+    PcapNg { interfaces: Vec<Interface> },
+}
+
+#[derive(Debug)]
+/// A pcap or pcapng capture reader.
+pub struct Reader<R> {
+    rdr: R,
+    format: Format,
+    big_endian: bool,
+    buf: Vec<u8>,
+
+    /// device names as found in the capture
+    devices: Vec<String>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            format: Format::Unknown,
+            big_endian: false,
+            buf: Vec::new(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl Reader<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<fs::File>>> {
+        Ok(Reader::from_reader(fs::File::open(path)?))
+    }
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct PcapRecords<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+impl<R: io::BufRead> Reader<R> {
+    /// Returns an iterator over all records
+    pub fn records(&mut self) -> PcapRecords<'_, R> {
+        PcapRecords { src: self }
+    }
+
+    fn u16_at(&self, b: &[u8], off: usize) -> u16 {
+        let bytes = [b[off], b[off + 1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32_at(&self, b: &[u8], off: usize) -> u32 {
+        let bytes = [b[off], b[off + 1], b[off + 2], b[off + 3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    fn device_index(&mut self, name: String) -> usize {
+        match self.devices.iter().position(|d| *d == name) {
+            Some(idx) => idx,
+            None => {
+                self.devices.push(name);
+                self.devices.len() - 1
+            }
+        }
+    }
+
+    // reads exactly `len` bytes into `buf`, `false` on a clean EOF
+    fn fill(&mut self, len: usize) -> Result<bool, ParseError> {
+        self.buf.clear();
+        (&mut self.rdr).take(len as u64).read_to_end(&mut self.buf)?;
+        match self.buf.len() {
+            0 if len > 0 => Ok(false),
+            n if n == len => Ok(true),
+            _ => Err(ParseError::UnexpectedEndOfLine),
+        }
+    }
+
+    /// Advance state, returning next record.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        loop {
+            let next = match self.format {
+                Format::Unknown => {
+                    if !self.fill(4)? {
+                        return Ok(None);
+                    }
+                    self.read_file_header()?;
+                    continue;
+                }
+                Format::Pcap { .. } => self.next_pcap_packet()?,
+                Format::PcapNg { .. } => self.next_pcapng_packet()?,
+            };
+
+            match next {
+                Next::Packet(t_us, device, start, end) => {
+                    let frame = decode_frame(&self.buf[start..end])?;
+                    return Ok(Some(CanDumpRecord {
+                        t_us: t_us,
+                        device: &self.devices[device],
+                        frame: frame,
+                        direction: None,
+                    }));
+                }
+                Next::Skip => continue,
+                Next::End => return Ok(None),
+            }
+        }
+    }
+
+    // called with the first four bytes in `buf`
+    fn read_file_header(&mut self) -> Result<(), ParseError> {
+        let magic = [self.buf[0], self.buf[1], self.buf[2], self.buf[3]];
+
+        if u32::from_le_bytes(magic) == BLOCK_SHB {
+            self.format = Format::PcapNg { interfaces: Vec::new() };
+            return self.read_section_header();
+        }
+
+        let (big_endian, units_per_sec) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_US, _) => (false, 1_000_000),
+            (PCAP_MAGIC_NS, _) => (false, 1_000_000_000),
+            (_, PCAP_MAGIC_US) => (true, 1_000_000),
+            (_, PCAP_MAGIC_NS) => (true, 1_000_000_000),
+            _ => return Err(ParseError::InvalidHeader),
+        };
+        self.big_endian = big_endian;
+
+        if !self.fill(20)? {
+            return Err(ParseError::InvalidHeader);
+        }
+        let link_type = self.u32_at(&self.buf, 16) as u16;
+        self.device_index("can0".to_owned());
+        self.format = Format::Pcap {
+            link_type: link_type,
+            units_per_sec: units_per_sec,
+        };
+        Ok(())
+    }
+
+    // reads the rest of a section header block, its type already consumed
+    fn read_section_header(&mut self) -> Result<(), ParseError> {
+        if !self.fill(8)? {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let magic = [self.buf[4], self.buf[5], self.buf[6], self.buf[7]];
+        self.big_endian = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (BYTE_ORDER_MAGIC, _) => false,
+            (_, BYTE_ORDER_MAGIC) => true,
+            _ => return Err(ParseError::InvalidHeader),
+        };
+
+        let total_len = self.u32_at(&self.buf, 0) as usize;
+        if total_len < 28 || total_len % 4 != 0 {
+            return Err(ParseError::InvalidHeader);
+        }
+        if !self.fill(total_len - 12)? {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        // interfaces are numbered per section
+        if let Format::PcapNg { ref mut interfaces } = self.format {
+            interfaces.clear();
+        }
+        Ok(())
+    }
+
+    fn next_pcap_packet(&mut self) -> Result<Next, ParseError> {
+        let (link_type, units_per_sec) = match self.format {
+            Format::Pcap { link_type, units_per_sec } => (link_type, units_per_sec),
+            _ => unreachable!(),
+        };
+
+        if !self.fill(16)? {
+            return Ok(Next::End);
+        }
+        let secs = u64::from(self.u32_at(&self.buf, 0));
+        let frac = u64::from(self.u32_at(&self.buf, 4));
+        let len = self.u32_at(&self.buf, 8) as usize;
+        if !self.fill(len)? && len > 0 {
+            return Err(ParseError::UnexpectedEndOfLine);
+        }
+
+        if link_type != LINKTYPE_CAN_SOCKETCAN {
+            return Ok(Next::Skip);
+        }
+        let t_us = secs * 1_000_000 + frac * 1_000_000 / units_per_sec;
+        Ok(Next::Packet(t_us, 0, 0, len))
+    }
+
+    fn next_pcapng_packet(&mut self) -> Result<Next, ParseError> {
+        if !self.fill(4)? {
+            return Ok(Next::End);
+        }
+        let block_type = self.u32_at(&self.buf, 0);
+        if block_type == BLOCK_SHB {
+            self.read_section_header()?;
+            return Ok(Next::Skip);
+        }
+
+        if !self.fill(4)? {
+            return Err(ParseError::UnexpectedEndOfLine);
+        }
+        let total_len = self.u32_at(&self.buf, 0) as usize;
+        if total_len < 12 || total_len % 4 != 0 {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        // body and trailing length
+        if !self.fill(total_len - 8)? {
+            return Err(ParseError::UnexpectedEndOfLine);
+        }
+        let body_len = total_len - 12;
+
+        match block_type {
+            BLOCK_IDB => {
+                if body_len < 8 {
+                    return Err(ParseError::InvalidHeader);
+                }
+                let link_type = self.u16_at(&self.buf, 0);
+                let mut name = None;
+                let mut units_per_sec = 1_000_000;
+
+                let mut off = 8;
+                while off + 4 <= body_len {
+                    let code = self.u16_at(&self.buf, off);
+                    let len = usize::from(self.u16_at(&self.buf, off + 2));
+                    let value = self.buf.get(off + 4..off + 4 + len).ok_or(ParseError::InvalidHeader)?;
+
+                    match code {
+                        OPT_ENDOFOPT => break,
+                        OPT_IF_NAME => name = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_owned()),
+                        OPT_IF_TSRESOL if len == 1 => {
+                            // the high bit selects a power of two
+                            let exp = u32::from(value[0] & 0x7f);
+                            units_per_sec = if value[0] & 0x80 != 0 {
+                                2u64.checked_pow(exp)
+                            } else {
+                                10u64.checked_pow(exp)
+                            }.ok_or(ParseError::InvalidHeader)?;
+                        }
+                        _ => (),
+                    }
+                    off += 4 + (len + 3) / 4 * 4;
+                }
+
+                let count = match self.format {
+                    Format::PcapNg { ref interfaces } => interfaces.len(),
+                    _ => unreachable!(),
+                };
+                let device = self.device_index(name.unwrap_or_else(|| format!("can{}", count)));
+                if let Format::PcapNg { ref mut interfaces } = self.format {
+                    interfaces.push(Interface {
+                        link_type: link_type,
+                        device: device,
+                        units_per_sec: units_per_sec,
+                    });
+                }
+                Ok(Next::Skip)
+            }
+            BLOCK_EPB => {
+                if body_len < 20 {
+                    return Err(ParseError::InvalidHeader);
+                }
+                let if_id = self.u32_at(&self.buf, 0) as usize;
+                let ts = u64::from(self.u32_at(&self.buf, 4)) << 32 | u64::from(self.u32_at(&self.buf, 8));
+                let len = self.u32_at(&self.buf, 12) as usize;
+                if 20 + len > body_len {
+                    return Err(ParseError::InvalidHeader);
+                }
+
+                let interface = match self.format {
+                    Format::PcapNg { ref interfaces } => interfaces.get(if_id).ok_or(ParseError::InvalidHeader)?,
+                    _ => unreachable!(),
+                };
+                if interface.link_type != LINKTYPE_CAN_SOCKETCAN {
+                    return Ok(Next::Skip);
+                }
+
+                let t_us = (u128::from(ts) * 1_000_000 / u128::from(interface.units_per_sec)) as u64;
+                Ok(Next::Packet(t_us, interface.device, 20, 20 + len))
+            }
+            // statistics, name resolution and others
+            _ => Ok(Next::Skip),
+        }
+    }
+}
+
+impl<'a, R: io::BufRead> Iterator for PcapRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A pcapng capture writer.
+///
+/// Blocks are written in host byte order. Each device gets an interface
+/// description block when it first appears. Call `finish` to get a valid
+/// file even if no packet was written.
+pub struct Writer<W> {
+    wtr: W,
+    header_written: bool,
+
+    /// interface ids are indices
+    devices: Vec<String>,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            header_written: false,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Write a record as returned by one of the readers.
+    pub fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        self.write_frame(record.t_us.saturating_mul(1000), record.device, &record.frame)
+    }
+
+    /// Write a frame with a timestamp in nanoseconds since the epoch.
+    pub fn write_frame(&mut self, t_ns: u64, device: &str, frame: &CanDumpFrame) -> io::Result<()> {
+        let if_id = self.interface(device)?;
+        let packet = encode_frame(frame);
+
+        let mut body = Vec::with_capacity(20 + packet.len() + 3);
+        body.extend_from_slice(&(if_id as u32).to_ne_bytes());
+        body.extend_from_slice(&((t_ns >> 32) as u32).to_ne_bytes());
+        body.extend_from_slice(&(t_ns as u32).to_ne_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&packet);
+        self.write_block(BLOCK_EPB, &body)
+    }
+
+    /// Read a single frame from `socket` and write it, along with the time
+    /// it was received.
+    ///
+    /// CAN FD frames are captured as well if enabled on the socket with
+    /// `set_fd_frames`.
+    pub fn capture_frame(&mut self, socket: &mut CanSocket, device: &str) -> io::Result<()> {
+        let (frame, time) = socket.read_dump_frame_with_timestamp()?;
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let t_ns = since_epoch.as_secs() * 1_000_000_000 + u64::from(since_epoch.subsec_nanos());
+
+        self.write_frame(t_ns, device, &frame)
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    /// Write the section header if nothing was written yet, returning the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_section_header()?;
+        self.wtr.flush()?;
+        Ok(self.wtr)
+    }
+
+    fn write_section_header(&mut self) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+
+        // version 1.0, section length unknown
+        body.extend_from_slice(&1u16.to_ne_bytes());
+        body.extend_from_slice(&0u16.to_ne_bytes());
+        body.extend_from_slice(&(-1i64).to_ne_bytes());
+        self.write_block(BLOCK_SHB, &body)
+    }
+
+    // returns the interface id of a device, describing it if necessary
+    fn interface(&mut self, device: &str) -> io::Result<usize> {
+        self.write_section_header()?;
+        if let Some(idx) = self.devices.iter().position(|d| d == device) {
+            return Ok(idx);
+        }
+
+        let mut body = Vec::with_capacity(32 + device.len());
+        body.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_ne_bytes());
+        body.extend_from_slice(&0u16.to_ne_bytes());
+
+        // no snapshot length limit
+        body.extend_from_slice(&0u32.to_ne_bytes());
+        push_option(&mut body, OPT_IF_NAME, device.as_bytes());
+        push_option(&mut body, OPT_IF_TSRESOL, &[9]);
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        self.write_block(BLOCK_IDB, &body)?;
+
+        self.devices.push(device.to_owned());
+        Ok(self.devices.len() - 1)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let padding = (4 - body.len() % 4) % 4;
+        let total_len = (12 + body.len() + padding) as u32;
+
+        self.wtr.write_all(&block_type.to_ne_bytes())?;
+        self.wtr.write_all(&total_len.to_ne_bytes())?;
+        self.wtr.write_all(body)?;
+        self.wtr.write_all(&[0; 3][..padding])?;
+        self.wtr.write_all(&total_len.to_ne_bytes())
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_ne_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+    buf.extend_from_slice(value);
+    buf.extend_from_slice(&[0; 3][..(4 - value.len() % 4) % 4]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_frame, encode_frame, Reader, Writer};
+    use crate::dump::{self, CanDumpFrame};
+
+    const CANDUMP: &str = "(1719413181.750991) can0 123#0102\n\
+                           (1719413181.751002) vcan1 12345678#R4\n\
+                           (1719413181.752000) can0 20000004#0004000000000000\n\
+                           (1719413181.753000) can0 701##30102030405060708090A0B0C\n\
+                           (1719413181.754000) vcan1 45123#81:00:12345678#AABB\n";
+
+    #[test]
+    fn test_encode_classic() {
+        let mut reader = dump::Reader::from_reader(CANDUMP.as_bytes());
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(encode_frame(&record.frame),
+                   vec![0, 0, 0x01, 0x23, 2, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0]);
+
+        match decode_frame(&[0x92, 0x34, 0x56, 0x78, 8, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap() {
+            CanDumpFrame::Classic(frame) => {
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x12345678);
+                assert_eq!(frame.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+
+        assert!(decode_frame(&[0, 0, 1, 0x23, 9, 0, 0, 0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_candump_to_pcapng_and_back() {
+        let mut writer = Writer::from_writer(Vec::new());
+        let mut reader = dump::Reader::from_reader(CANDUMP.as_bytes());
+        while let Some(record) = reader.next_record().unwrap() {
+            writer.write_record(&record).unwrap();
+        }
+        let pcapng = writer.finish().unwrap();
+        assert_eq!(pcapng.len() % 4, 0);
+
+        let mut dump = dump::Writer::from_writer(Vec::new());
+        let mut reader = Reader::from_reader(&pcapng[..]);
+        while let Some(record) = reader.next_record().unwrap() {
+            dump.write_record(&record).unwrap();
+        }
+
+        assert_eq!(::std::str::from_utf8(&dump.into_inner()).unwrap(), CANDUMP);
+    }
+
+    #[test]
+    fn test_read_classic_pcap() {
+        // big endian, microsecond resolution
+        let mut pcap = vec![0xa1, 0xb2, 0xc3, 0xd4, 0, 2, 0, 4];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&[0, 0, 0xff, 0xff, 0, 0, 0, 227]);
+        pcap.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 16, 0, 0, 0, 16]);
+        pcap.extend_from_slice(&[0, 0, 0x07, 0x01, 1, 0, 0, 0, 0x7f, 0, 0, 0, 0, 0, 0, 0]);
+
+        let records: Vec<_> = Reader::from_reader(&pcap[..]).records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, 1_000_002);
+        assert_eq!(records[0].1.id(), 0x701);
+        assert_eq!(records[0].1.data(), &[0x7f]);
+    }
+
+    #[test]
+    fn test_empty_capture() {
+        let pcapng = Writer::from_writer(Vec::new()).finish().unwrap();
+        assert_eq!(pcapng.len(), 28);
+        assert!(Reader::from_reader(&pcapng[..]).next_record().unwrap().is_none());
+        assert!(Reader::from_reader(&b"nope"[..]).next_record().is_err());
+    }
+}
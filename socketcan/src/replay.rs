@@ -0,0 +1,479 @@
+//! Log replay
+//!
+//! Sends the records of a candump log with their original timing, like the
+//! `canplayer` utility of [can-utils](https://github.com/linux-can/can-utils).
+//!
+//! ```no_run
+//! use socketcan::dump::Reader;
+//! use socketcan::replay::Player;
+//!
+//! let mut reader = Reader::from_file("candump.log").unwrap();
+//! let mut player = Player::from_reader(&mut reader).unwrap();
+//!
+//! // twice as fast, three times, everything recorded on can0 goes to vcan1
+//! player.set_speed(2.0);
+//! player.set_loops(Some(3));
+//! player.add_mapping("vcan1=can0").unwrap();
+//!
+//! player.play().unwrap();
+//! ```
+//!
+//! `Player::schedule` exposes the timing without sending anything, which is
+//! what the async variant in `socketcan-tokio` is built on.
+
+use crate::dump::{CanDumpFrame, CanDumpRecord, ParseError, Reader};
+use crate::{CanSocket, CanSocketOpenError, ShouldRetry};
+use libc::ENOBUFS;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, io, thread};
+
+#[derive(Debug)]
+/// Replay error
+pub enum ReplayError {
+    /// The log could not be read
+    Parse(ParseError),
+
+    /// An interface mapping is not of the form `target=logged`
+    InvalidMapping(String),
+
+    /// A target interface could not be opened
+    Open(CanSocketOpenError),
+
+    /// Sending a frame failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Parse(ref e) => write!(f, "invalid log: {:?}", e),
+            ReplayError::InvalidMapping(ref m) => write!(f, "invalid interface mapping {:?}", m),
+            ReplayError::Open(ref e) => write!(f, "{}", e),
+            ReplayError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ParseError> for ReplayError {
+    fn from(e: ParseError) -> ReplayError {
+        ReplayError::Parse(e)
+    }
+}
+
+impl From<CanSocketOpenError> for ReplayError {
+    fn from(e: CanSocketOpenError) -> ReplayError {
+        ReplayError::Open(e)
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> ReplayError {
+        ReplayError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    t_us: u64,
+    device: usize,
+    frame: CanDumpFrame,
+}
+
+/// Replays a recorded log.
+///
+/// The records are kept in memory, so they can be played several times.
+#[derive(Debug)]
+pub struct Player {
+    entries: Arc<Vec<Entry>>,
+
+    /// device names as found in the log
+    devices: Vec<String>,
+
+    /// logged device -> target interface
+    mapping: HashMap<String, String>,
+    speed: f64,
+    loops: Option<u32>,
+    skip_gaps: Option<Duration>,
+    loopback: bool,
+}
+
+impl Player {
+    /// Create a player without any records.
+    pub fn new() -> Player {
+        Player {
+            entries: Arc::new(Vec::new()),
+            devices: Vec::new(),
+            mapping: HashMap::new(),
+            speed: 1.0,
+            loops: Some(1),
+            skip_gaps: None,
+            loopback: true,
+        }
+    }
+
+    /// Read all remaining records of a log.
+    pub fn from_reader<R: io::BufRead>(reader: &mut Reader<R>) -> Result<Player, ParseError> {
+        let mut player = Player::new();
+        while let Some(record) = reader.next_record()? {
+            player.push_record(&record);
+        }
+        Ok(player)
+    }
+
+    /// Append a record.
+    ///
+    /// Records are played in the order they were added. A timestamp earlier
+    /// than that of the previous record is sent without delay.
+    pub fn push_record(&mut self, record: &CanDumpRecord) {
+        let device = match self.devices.iter().position(|d| d == record.device) {
+            Some(idx) => idx,
+            None => {
+                self.devices.push(record.device.to_owned());
+                self.devices.len() - 1
+            }
+        };
+
+        Arc::make_mut(&mut self.entries).push(Entry {
+            t_us: record.t_us,
+            device: device,
+            frame: record.frame.clone(),
+        });
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no records to play.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Set the replay speed.
+    ///
+    /// A factor of `2.0` halves all gaps between frames, `0.5` doubles them.
+    pub fn set_speed(&mut self, factor: f64) {
+        assert!(factor > 0.0, "replay speed must be positive");
+        self.speed = factor;
+    }
+
+    /// Set how often the log is played, `None` repeats it forever.
+    ///
+    /// Every pass starts right after the last frame of the previous one.
+    pub fn set_loops(&mut self, loops: Option<u32>) {
+        self.loops = loops;
+    }
+
+    /// Skip gaps between frames that are longer than `max_gap`.
+    ///
+    /// The frame after such a gap is sent immediately, which shortens
+    /// recordings with long idle periods.
+    pub fn set_skip_gaps(&mut self, max_gap: Option<Duration>) {
+        self.skip_gaps = max_gap;
+    }
+
+    /// Enable or disable loopback on the sockets opened for the replay.
+    ///
+    /// Enabled by default, see `CanSocket::set_loopback`.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled;
+    }
+
+    /// Send frames recorded on `logged` to the interface `target`.
+    ///
+    /// Without any mapping, frames are sent to the interfaces they were
+    /// recorded on. Once a mapping exists, frames recorded on interfaces
+    /// without one are skipped.
+    pub fn map_interface(&mut self, target: &str, logged: &str) {
+        self.mapping.insert(logged.to_owned(), target.to_owned());
+    }
+
+    /// Add a mapping given as `target=logged`, e.g. `vcan0=can1`, which is
+    /// the syntax `canplayer` uses.
+    pub fn add_mapping(&mut self, mapping: &str) -> Result<(), ReplayError> {
+        let mut parts = mapping.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(target), Some(logged)) if !target.is_empty() && !logged.is_empty() => {
+                self.map_interface(target, logged);
+                Ok(())
+            }
+            _ => Err(ReplayError::InvalidMapping(mapping.to_owned())),
+        }
+    }
+
+    /// The order and timing frames will be sent with.
+    pub fn schedule(&self) -> Schedule {
+        let mut interfaces: Vec<String> = Vec::new();
+        let targets = self.devices
+            .iter()
+            .map(|device| {
+                let target = if self.mapping.is_empty() {
+                    device
+                } else {
+                    self.mapping.get(device)?
+                };
+
+                Some(match interfaces.iter().position(|i| i == target) {
+                    Some(idx) => idx,
+                    None => {
+                        interfaces.push(target.clone());
+                        interfaces.len() - 1
+                    }
+                })
+            })
+            .collect();
+
+        Schedule {
+            entries: self.entries.clone(),
+            targets: targets,
+            interfaces: interfaces,
+            speed: self.speed,
+            loops: self.loops,
+            skip_gaps: self.skip_gaps.map(|gap| gap.as_secs() * 1_000_000 + u64::from(gap.subsec_micros())),
+            pos: 0,
+            pass: 0,
+            elapsed_us: 0,
+            prev_t_us: None,
+            loopback: self.loopback,
+        }
+    }
+
+    /// Play the log, blocking until all frames have been sent.
+    pub fn play(&self) -> Result<(), ReplayError> {
+        let schedule = self.schedule();
+        let sockets = schedule.open_sockets()?;
+
+        let start = Instant::now();
+        for scheduled in schedule {
+            let deadline = start + scheduled.at;
+            let now = Instant::now();
+            if deadline > now {
+                thread::sleep(deadline - now);
+            }
+
+            write_frame_insist(&sockets[scheduled.interface], &scheduled.frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Player {
+    fn default() -> Player {
+        Player::new()
+    }
+}
+
+/// A frame due to be sent.
+#[derive(Debug, Clone)]
+pub struct ScheduledFrame {
+    /// time since the start of the replay
+    pub at: Duration,
+
+    /// index into `Schedule::interfaces`
+    pub interface: usize,
+    pub frame: CanDumpFrame,
+}
+
+/// Iterator over the frames of a replay, see `Player::schedule`.
+///
+/// Frames are yielded as soon as they are requested; waiting for `at` is up
+/// to the caller.
+#[derive(Debug)]
+pub struct Schedule {
+    entries: Arc<Vec<Entry>>,
+
+    /// logged device -> index into interfaces, `None` if skipped
+    targets: Vec<Option<usize>>,
+    interfaces: Vec<String>,
+    speed: f64,
+    loops: Option<u32>,
+    skip_gaps: Option<u64>,
+    pos: usize,
+    pass: u32,
+
+    /// log time passed, after skipping gaps
+    elapsed_us: u64,
+    prev_t_us: Option<u64>,
+    loopback: bool,
+}
+
+impl Schedule {
+    /// Names of the interfaces frames are sent to.
+    pub fn interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    /// Open a socket for each interface, in the order of `interfaces`.
+    ///
+    /// CAN FD and CAN XL frames are enabled on the sockets if the log
+    /// contains any.
+    pub fn open_sockets(&self) -> Result<Vec<CanSocket>, ReplayError> {
+        let has_fd = self.entries.iter().any(|e| match e.frame {
+            CanDumpFrame::Fd(_) => true,
+            _ => false,
+        });
+        let has_xl = self.entries.iter().any(|e| match e.frame {
+            CanDumpFrame::Xl(_) => true,
+            _ => false,
+        });
+
+        let mut sockets = Vec::with_capacity(self.interfaces.len());
+        for interface in &self.interfaces {
+            let socket = CanSocket::open(interface)?;
+            socket.set_loopback(self.loopback)?;
+            if has_fd {
+                socket.set_fd_frames(true)?;
+            }
+            if has_xl {
+                socket.set_xl_frames(true)?;
+            }
+            sockets.push(socket);
+        }
+        Ok(sockets)
+    }
+
+    fn scale(&self, us: u64) -> Duration {
+        let scaled = (us as f64 / self.speed) as u64;
+        Duration::new(scaled / 1_000_000, (scaled % 1_000_000) as u32 * 1000)
+    }
+}
+
+impl Iterator for Schedule {
+    type Item = ScheduledFrame;
+
+    fn next(&mut self) -> Option<ScheduledFrame> {
+        if self.entries.is_empty() || self.targets.iter().all(Option::is_none) {
+            return None;
+        }
+
+        loop {
+            if self.pos == self.entries.len() {
+                self.pass += 1;
+                if self.loops.map_or(false, |loops| self.pass >= loops) {
+                    return None;
+                }
+
+                // the next pass starts without a gap
+                self.pos = 0;
+                self.prev_t_us = None;
+            }
+
+            let (t_us, device) = {
+                let entry = &self.entries[self.pos];
+                (entry.t_us, entry.device)
+            };
+            self.pos += 1;
+
+            let gap = match self.prev_t_us {
+                Some(prev) => t_us.saturating_sub(prev),
+                None => 0,
+            };
+            self.prev_t_us = Some(t_us);
+
+            if self.skip_gaps.map_or(true, |max| gap <= max) {
+                self.elapsed_us += gap;
+            }
+
+            if let Some(interface) = self.targets[device] {
+                return Some(ScheduledFrame {
+                    at: self.scale(self.elapsed_us),
+                    interface: interface,
+                    frame: self.entries[self.pos - 1].frame.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Write any kind of frame found in a log.
+///
+/// Error frames are sent as they are, like `canplayer` does.
+pub fn write_frame(socket: &CanSocket, frame: &CanDumpFrame) -> io::Result<()> {
+    match *frame {
+        CanDumpFrame::Classic(ref frame) | CanDumpFrame::Error(ref frame) => socket.write_frame(frame),
+        CanDumpFrame::Fd(ref frame) => socket.write_fd_frame(frame),
+        CanDumpFrame::Xl(ref frame) => socket.write_xl_frame(frame),
+    }
+}
+
+// retries while the transmit queue of the device is full
+fn write_frame_insist(socket: &CanSocket, frame: &CanDumpFrame) -> io::Result<()> {
+    loop {
+        match write_frame(socket, frame) {
+            Ok(()) => return Ok(()),
+            Err(ref e) if e.raw_os_error() == Some(ENOBUFS) => thread::sleep(Duration::from_millis(1)),
+            Err(ref e) if e.should_retry() => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Player;
+    use crate::dump::Reader;
+    use std::time::Duration;
+
+    const LOG: &[u8] = b"(100.000000) can0 001#01\n\
+                         (100.010000) can1 002#02\n\
+                         (100.030000) can0 003#03\n\
+                         (105.030000) can0 004#04\n";
+
+    fn player() -> Player {
+        Player::from_reader(&mut Reader::from_reader(LOG)).unwrap()
+    }
+
+    fn timing(player: &Player) -> Vec<(u64, usize, u32)> {
+        player.schedule()
+            .map(|s| (s.at.as_secs() * 1_000_000 + u64::from(s.at.subsec_micros()), s.interface, s.frame.id()))
+            .collect()
+    }
+
+    #[test]
+    fn test_original_timing() {
+        let player = player();
+        assert_eq!(player.len(), 4);
+        assert_eq!(player.schedule().interfaces(), &["can0".to_owned(), "can1".to_owned()]);
+        assert_eq!(timing(&player),
+                   vec![(0, 0, 1), (10_000, 1, 2), (30_000, 0, 3), (5_030_000, 0, 4)]);
+    }
+
+    #[test]
+    fn test_speed_and_gaps() {
+        let mut player = player();
+        player.set_speed(2.0);
+        player.set_skip_gaps(Some(Duration::from_secs(1)));
+        assert_eq!(timing(&player),
+                   vec![(0, 0, 1), (5_000, 1, 2), (15_000, 0, 3), (15_000, 0, 4)]);
+    }
+
+    #[test]
+    fn test_loops() {
+        let mut player = player();
+        player.set_loops(Some(2));
+        player.set_skip_gaps(Some(Duration::from_secs(1)));
+        let t = timing(&player);
+        assert_eq!(t.len(), 8);
+        assert_eq!(t[4], (30_000, 0, 1));
+        assert_eq!(t[7], (60_000, 0, 4));
+
+        player.set_loops(None);
+        assert_eq!(player.schedule().take(100).count(), 100);
+    }
+
+    #[test]
+    fn test_mapping() {
+        let mut player = player();
+        player.add_mapping("vcan0=can1").unwrap();
+        assert!(player.add_mapping("vcan0").is_err());
+        assert!(player.add_mapping("=can0").is_err());
+
+        let schedule = player.schedule();
+        assert_eq!(schedule.interfaces(), &["vcan0".to_owned()]);
+        assert_eq!(timing(&player), vec![(10_000, 0, 2)]);
+    }
+}
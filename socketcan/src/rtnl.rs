@@ -0,0 +1,539 @@
+//! rtnetlink protocol implementation
+//!
+//! Implements just enough of netlink (see netlink(7) and rtnetlink(7)) to
+//! manage CAN interfaces: building requests with (nested) attributes,
+//! splitting received datagrams into messages, parsing attributes and
+//! decoding acknowledgements and errors into `io::Error`s.
+//!
+//! All integers on a netlink socket are in host byte order, every message
+//! and attribute is padded to a multiple of 4 bytes.
+
+use libc::{self, c_int, c_void, sa_family_t, sockaddr, sockaddr_nl};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::{io, mem, ptr, slice};
+
+// linux/netlink.h
+pub const NETLINK_ROUTE: c_int = 0;
+
+pub const NLM_F_REQUEST: u16 = 0x01;
+pub const NLM_F_ACK: u16 = 0x04;
+pub const NLM_F_ROOT: u16 = 0x100;
+pub const NLM_F_MATCH: u16 = 0x200;
+pub const NLM_F_DUMP: u16 = NLM_F_ROOT | NLM_F_MATCH;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+pub const NLMSG_NOOP: u16 = 1;
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+pub const NLMSG_OVERRUN: u16 = 4;
+
+const NLA_F_NESTED: u16 = 1 << 15;
+const NLA_F_NET_BYTEORDER: u16 = 1 << 14;
+const NLA_TYPE_MASK: u16 = !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+
+/// Size of a `struct nlmsghdr`
+pub const NLMSG_HDRLEN: usize = 16;
+
+/// Size of a `struct nlattr`
+pub const NLA_HDRLEN: usize = 4;
+
+// receive buffer size, large enough for a multipart dump datagram
+const RECV_BUF_LEN: usize = 32768;
+
+/// Rounds `len` up to the 4 byte netlink alignment (`NLMSG_ALIGN`/`NLA_ALIGN`).
+#[inline]
+pub fn nl_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[inline]
+fn as_bytes<T: Copy>(val: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(val as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Netlink message builder
+///
+/// Starts with a `struct nlmsghdr`, followed by an optional family specific
+/// header (e.g. `struct ifinfomsg`) and any number of attributes. Nested
+/// attributes are opened with `nest_start` and closed with `nest_end`, which
+/// fills in their length.
+#[derive(Debug)]
+pub struct MsgBuilder {
+    buf: Vec<u8>,
+    nests: Vec<usize>,
+}
+
+impl MsgBuilder {
+    /// Start a new message. `NLM_F_REQUEST` is always set.
+    pub fn new(msg_type: u16, flags: u16) -> MsgBuilder {
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // length, set in finish
+        buf.extend_from_slice(&msg_type.to_ne_bytes());
+        buf.extend_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // sequence number
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // port id, filled in by kernel
+
+        MsgBuilder {
+            buf: buf,
+            nests: Vec::new(),
+        }
+    }
+
+    /// Add further `NLM_F_*` flags.
+    pub fn add_flags(&mut self, flags: u16) {
+        let old = u16::from_ne_bytes([self.buf[6], self.buf[7]]);
+        self.buf[6..8].copy_from_slice(&(old | flags).to_ne_bytes());
+    }
+
+    fn pad(&mut self) {
+        let len = nl_align(self.buf.len());
+        self.buf.resize(len, 0);
+    }
+
+    /// Append a family specific header, which must be a `#[repr(C)]` struct.
+    pub fn header<T: Copy>(&mut self, hdr: &T) {
+        self.buf.extend_from_slice(as_bytes(hdr));
+        self.pad();
+    }
+
+    /// Append an attribute with a raw payload.
+    pub fn attr(&mut self, attr_type: u16, data: &[u8]) {
+        let len = NLA_HDRLEN + data.len();
+        self.buf.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&attr_type.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.pad();
+    }
+
+    pub fn attr_u8(&mut self, attr_type: u16, val: u8) {
+        self.attr(attr_type, &[val]);
+    }
+
+    pub fn attr_u32(&mut self, attr_type: u16, val: u32) {
+        self.attr(attr_type, &val.to_ne_bytes());
+    }
+
+    /// Append a NUL-terminated string attribute.
+    #[cfg(test)]
+    pub fn attr_str(&mut self, attr_type: u16, val: &str) {
+        let mut data = Vec::with_capacity(val.len() + 1);
+        data.extend_from_slice(val.as_bytes());
+        data.push(0);
+        self.attr(attr_type, &data);
+    }
+
+    /// Append a `#[repr(C)]` struct as attribute payload.
+    pub fn attr_struct<T: Copy>(&mut self, attr_type: u16, val: &T) {
+        self.attr(attr_type, as_bytes(val));
+    }
+
+    /// Open a nested attribute, all following attributes are part of it until
+    /// `nest_end` is called.
+    #[cfg(test)]
+    pub fn nest_start(&mut self, attr_type: u16) {
+        self.nests.push(self.buf.len());
+        self.attr(attr_type, &[]);
+    }
+
+    /// Close the innermost nested attribute.
+    #[cfg(test)]
+    pub fn nest_end(&mut self) {
+        let start = self.nests.pop().expect("nest_end without nest_start");
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    /// Finish the message, setting its total length and sequence number.
+    pub fn finish(mut self, seq: u32) -> Vec<u8> {
+        assert!(self.nests.is_empty(), "unterminated nested attribute");
+
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buf
+    }
+}
+
+/// A single message received from a netlink socket.
+#[derive(Debug)]
+pub struct NlMsg<'a> {
+    pub msg_type: u16,
+    pub seq: u32,
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the netlink messages contained in a datagram.
+pub struct NlMessages<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> NlMessages<'a> {
+    pub fn new(buf: &'a [u8]) -> NlMessages<'a> {
+        NlMessages { buf: buf }
+    }
+}
+
+impl<'a> Iterator for NlMessages<'a> {
+    type Item = NlMsg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < NLMSG_HDRLEN {
+            return None;
+        }
+
+        let b = self.buf;
+        let len = u32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as usize;
+        if len < NLMSG_HDRLEN || len > b.len() {
+            // truncated message, nothing sensible follows
+            self.buf = &[];
+            return None;
+        }
+
+        let msg = NlMsg {
+            msg_type: u16::from_ne_bytes([b[4], b[5]]),
+            seq: u32::from_ne_bytes([b[8], b[9], b[10], b[11]]),
+            payload: &b[NLMSG_HDRLEN..len],
+        };
+
+        self.buf = &b[nl_align(len).min(b.len())..];
+        Some(msg)
+    }
+}
+
+/// Iterator over `(type, payload)` of a sequence of netlink attributes.
+///
+/// The `NLA_F_NESTED` and `NLA_F_NET_BYTEORDER` flags are stripped from the
+/// type.
+pub struct NlAttrs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> NlAttrs<'a> {
+    pub fn new(buf: &'a [u8]) -> NlAttrs<'a> {
+        NlAttrs { buf: buf }
+    }
+
+    /// Payload of the first attribute of type `attr_type`.
+    pub fn get(mut self, attr_type: u16) -> Option<&'a [u8]> {
+        self.find(|&(t, _)| t == attr_type).map(|(_, data)| data)
+    }
+}
+
+impl<'a> Iterator for NlAttrs<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < NLA_HDRLEN {
+            return None;
+        }
+
+        let b = self.buf;
+        let len = u16::from_ne_bytes([b[0], b[1]]) as usize;
+        let attr_type = u16::from_ne_bytes([b[2], b[3]]) & NLA_TYPE_MASK;
+        if len < NLA_HDRLEN || len > b.len() {
+            self.buf = &[];
+            return None;
+        }
+
+        self.buf = &b[nl_align(len).min(b.len())..];
+        Some((attr_type, &b[NLA_HDRLEN..len]))
+    }
+}
+
+/// Interprets an attribute payload as a NUL-terminated string.
+pub fn attr_str(data: &[u8]) -> Option<String> {
+    let end = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    ::std::str::from_utf8(&data[..end]).ok().map(|s| s.to_owned())
+}
+
+pub fn attr_u8(data: &[u8]) -> Option<u8> {
+    data.first().cloned()
+}
+
+pub fn attr_u32(data: &[u8]) -> Option<u32> {
+    data.get(..4).map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn attr_u64(data: &[u8]) -> Option<u64> {
+    data.get(..8).map(|b| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(b);
+        u64::from_ne_bytes(bytes)
+    })
+}
+
+/// Reads a `#[repr(C)]` struct from the start of an attribute payload.
+///
+/// Payloads shorter than `T` (sent by older kernels) are zero-extended.
+pub fn attr_struct<T: Copy>(data: &[u8]) -> T {
+    let mut val: T = unsafe { mem::zeroed() };
+    let len = data.len().min(mem::size_of::<T>());
+    unsafe {
+        ptr::copy_nonoverlapping(data.as_ptr(), &mut val as *mut T as *mut u8, len);
+    }
+    val
+}
+
+/// Splits a message payload into its family specific header and the
+/// attributes following it.
+pub fn parse_header<'a, T: Copy>(payload: &'a [u8]) -> Option<(T, NlAttrs<'a>)> {
+    if payload.len() < mem::size_of::<T>() {
+        return None;
+    }
+    let hdr = unsafe { ptr::read_unaligned(payload.as_ptr() as *const T) };
+    let attrs = &payload[nl_align(mem::size_of::<T>()).min(payload.len())..];
+    Some((hdr, NlAttrs::new(attrs)))
+}
+
+/// Decodes the payload of an `NLMSG_ERROR` message. An error code of zero
+/// is an acknowledgement.
+pub fn decode_error(payload: &[u8]) -> io::Result<()> {
+    let errno = payload.get(..4)
+        .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid_data("Truncated netlink error message"))?;
+
+    if errno == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(-errno))
+    }
+}
+
+/// A `NETLINK_ROUTE` socket.
+#[derive(Debug)]
+pub struct NlSocket {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl NlSocket {
+    /// Opens a new route socket, subscribed to the multicast `groups`.
+    ///
+    /// The port id is left for the kernel to assign, so multiple sockets can
+    /// be opened by the same process.
+    pub fn open(groups: u32) -> io::Result<NlSocket> {
+        let fd = unsafe {
+            libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, NETLINK_ROUTE)
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let sock = NlSocket { fd: fd, seq: 0 };
+
+        let mut addr: sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as sa_family_t;
+        addr.nl_groups = groups;
+
+        let rv = unsafe {
+            libc::bind(fd,
+                       &addr as *const sockaddr_nl as *const sockaddr,
+                       mem::size_of::<sockaddr_nl>() as u32)
+        };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(sock)
+    }
+
+    /// Change socket to non-blocking mode
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let mut nonblocking = nonblocking as c_int;
+        let rv = unsafe { libc::ioctl(self.fd, libc::FIONBIO, &mut nonblocking) };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sends a message to the kernel, returning its sequence number.
+    pub fn send(&mut self, msg: MsgBuilder) -> io::Result<u32> {
+        self.seq = self.seq.wrapping_add(1);
+        let buf = msg.finish(self.seq);
+
+        let rv = unsafe { libc::send(self.fd, buf.as_ptr() as *const c_void, buf.len(), 0) };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if rv as usize != buf.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Incomplete write"));
+        }
+        Ok(self.seq)
+    }
+
+    /// Receives a single datagram, which may contain several messages.
+    pub fn recv(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.resize(RECV_BUF_LEN, 0);
+        let rv = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(rv as usize);
+        Ok(())
+    }
+
+    /// Sends a request with `NLM_F_ACK` and collects the payloads of all
+    /// replies to it, up to the acknowledgement.
+    pub fn request(&mut self, mut msg: MsgBuilder) -> io::Result<Vec<Vec<u8>>> {
+        msg.add_flags(NLM_F_ACK);
+        self.transact(msg)
+    }
+
+    /// Sends a request and waits for its acknowledgement.
+    pub fn request_ack(&mut self, msg: MsgBuilder) -> io::Result<()> {
+        self.request(msg).map(|_| ())
+    }
+
+    /// Sends a dump request, collecting the payloads of all replies.
+    pub fn dump(&mut self, mut msg: MsgBuilder) -> io::Result<Vec<Vec<u8>>> {
+        msg.add_flags(NLM_F_DUMP);
+        self.transact(msg)
+    }
+
+    /// Sends a message and collects replies until either an `NLMSG_DONE` or
+    /// an `NLMSG_ERROR` (which includes acknowledgements) is received.
+    /// Replies to other sequence numbers are skipped.
+    fn transact(&mut self, msg: MsgBuilder) -> io::Result<Vec<Vec<u8>>> {
+        let seq = self.send(msg)?;
+
+        let mut buf = Vec::new();
+        let mut replies = Vec::new();
+        loop {
+            self.recv(&mut buf)?;
+
+            for msg in NlMessages::new(&buf) {
+                if msg.seq != seq {
+                    continue;
+                }
+
+                match msg.msg_type {
+                    NLMSG_NOOP => (),
+                    NLMSG_OVERRUN => return Err(invalid_data("Netlink message overrun")),
+                    NLMSG_ERROR => return decode_error(msg.payload).map(|_| replies),
+                    NLMSG_DONE => {
+                        // an interrupted dump may carry an error code
+                        return match msg.payload.get(..4) {
+                            Some(_) => decode_error(msg.payload).map(|_| replies),
+                            None => Ok(replies),
+                        };
+                    }
+                    _ => replies.push(msg.payload.to_vec()),
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for NlSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for NlSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nlmsg(msg_type: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+        buf.extend_from_slice(&msg_type.to_ne_bytes());
+        buf.extend_from_slice(&0x02u16.to_ne_bytes()); // NLM_F_MULTI
+        buf.extend_from_slice(&seq.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(payload);
+        buf.resize(nl_align(buf.len()), 0);
+        buf
+    }
+
+    #[test]
+    fn test_builder_nested_attributes() {
+        let mut msg = MsgBuilder::new(16, 0);
+        msg.header(&[1u8, 2, 3]);
+        msg.nest_start(18);
+        msg.attr_str(1, "can");
+        msg.nest_end();
+        msg.attr_u8(16, 6);
+        let buf = msg.finish(7);
+
+        assert_eq!(buf.len(), 16 + 4 + 4 + 8 + 8);
+        assert_eq!(&buf[0..4], &(40u32).to_ne_bytes());
+        assert_eq!(&buf[6..8], &NLM_F_REQUEST.to_ne_bytes());
+        assert_eq!(&buf[8..12], &7u32.to_ne_bytes());
+
+        // header, padded
+        assert_eq!(&buf[16..20], &[1, 2, 3, 0]);
+
+        // nest covers its own header and the padded inner attribute
+        assert_eq!(&buf[20..22], &12u16.to_ne_bytes());
+        assert_eq!(&buf[22..24], &18u16.to_ne_bytes());
+        assert_eq!(&buf[24..26], &8u16.to_ne_bytes());
+        assert_eq!(&buf[28..32], b"can\0");
+
+        // u8 attribute keeps its unpadded length
+        assert_eq!(&buf[32..34], &5u16.to_ne_bytes());
+        assert_eq!(buf[36], 6);
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let mut msg = MsgBuilder::new(16, 0);
+        msg.attr_u8(1, 0xaa);
+        msg.nest_start(2 | NLA_F_NESTED);
+        msg.attr_u32(3, 0x1234_5678);
+        msg.nest_end();
+        msg.attr_str(4, "vcan0");
+        let buf = msg.finish(1);
+
+        let attrs: Vec<_> = NlAttrs::new(&buf[NLMSG_HDRLEN..]).collect();
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[0].0, 1);
+        assert_eq!(attr_u8(attrs[0].1), Some(0xaa));
+        assert_eq!(attrs[1].0, 2);
+        assert_eq!(NlAttrs::new(attrs[1].1).get(3).and_then(attr_u32),
+                   Some(0x1234_5678));
+        assert_eq!(attr_str(attrs[2].1), Some("vcan0".to_owned()));
+    }
+
+    #[test]
+    fn test_truncated_attribute_stops_iteration() {
+        let buf = [8u8, 0, 1, 0, 0xff];
+        assert_eq!(NlAttrs::new(&buf).count(), 0);
+    }
+
+    #[test]
+    fn test_split_multipart_datagram() {
+        let mut buf = nlmsg(16, 3, &[1, 2, 3, 4, 5]);
+        buf.extend(nlmsg(16, 3, &[6]));
+        buf.extend(nlmsg(NLMSG_DONE, 3, &0i32.to_ne_bytes()));
+
+        let msgs: Vec<_> = NlMessages::new(&buf).collect();
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0].payload, &[1, 2, 3, 4, 5]);
+        assert_eq!(msgs[1].payload, &[6]);
+        assert_eq!(msgs[2].msg_type, NLMSG_DONE);
+        assert_eq!(msgs[2].seq, 3);
+    }
+
+    #[test]
+    fn test_decode_error() {
+        assert!(decode_error(&0i32.to_ne_bytes()).is_ok());
+
+        let err = decode_error(&(-libc::ENODEV).to_ne_bytes()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+
+        assert_eq!(decode_error(&[0]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -48,9 +48,9 @@ fn can_message_id_too_large() {
 
 #[cfg(feature = "vcan_tests")]
 mod vcan_tests {
-    use {CanFrame, CanInterface, CanSocket, ERR_MASK_ALL, ERR_MASK_NONE};
+    use crate::{CanFrame, CanFrameMode, CanInterface, CanInterfaceKind, CanSocket, ShouldRetry,
+                ERR_MASK_ALL, ERR_MASK_NONE};
     use std::time;
-    use ShouldRetry;
 
     #[test]
     fn vcan0_timeout() {
@@ -73,13 +73,33 @@ mod vcan_tests {
         cs.set_loopback(true).unwrap();
         cs.set_recv_own_msgs(true).unwrap();
 
-        let frame = CanFrame::new(0x123, &[], true, false).unwrap();
+        let frame = CanFrame::new(0x123u16.into(), &[], true, false).unwrap();
 
         cs.write_frame(&frame).unwrap();
 
         cs.read_frame().unwrap();
     }
 
+    #[test]
+    fn vcan0_is_listed() {
+        let interfaces = CanInterface::list().unwrap();
+        let vcan0 = interfaces.iter().find(|i| i.name == "vcan0").unwrap();
+        assert_eq!(vcan0.kind, Some(CanInterfaceKind::Vcan));
+    }
+
+    #[test]
+    fn vcan0_switch_frame_mode() {
+        let can_if = CanInterface::open("vcan0").unwrap();
+        can_if.bring_down().unwrap();
+
+        can_if.set_frame_mode(CanFrameMode::Fd).unwrap();
+        let vcan0 = CanInterface::list().unwrap().into_iter().find(|i| i.name == "vcan0").unwrap();
+        assert_eq!(vcan0.frame_mode(), Some(CanFrameMode::Fd));
+        can_if.set_frame_mode(CanFrameMode::Classic).unwrap();
+
+        can_if.bring_up().unwrap();
+    }
+
     #[test]
     fn vcan0_set_down() {
         let can_if = CanInterface::open("vcan0").unwrap();
@@ -94,4 +114,97 @@ mod vcan_tests {
         // no timeout set, but should return immediately
         assert!(cs.read_frame().should_retry());
     }
+
+    #[test]
+    fn vcan0_isotp_segmented_pdu() {
+        use crate::isotp::{IsoTpOptions, IsoTpSocket};
+
+        let options = IsoTpOptions::default();
+        let tx = IsoTpSocket::open("vcan0", 0x7e8.into(), 0x7e0.into(), &options).unwrap();
+        let rx = IsoTpSocket::open("vcan0", 0x7e0.into(), 0x7e8.into(), &options).unwrap();
+
+        let pdu: Vec<u8> = (0..4095).map(|i| i as u8).collect();
+        tx.write(&pdu).unwrap();
+        assert_eq!(rx.read_pdu().unwrap(), pdu);
+    }
+
+    #[test]
+    fn vcan0_userspace_isotp_to_kernel() {
+        use crate::isotp::{IsoTpOptions, IsoTpSocket, UserIsoTpSocket};
+
+        let mut options = IsoTpOptions::default();
+        options.flow_control.block_size = 4;
+        let kernel = IsoTpSocket::open("vcan0", 0x7e8.into(), 0x7e0.into(), &options).unwrap();
+        let mut user = UserIsoTpSocket::open("vcan0", 0x7e0.into(), 0x7e8.into(), &options).unwrap();
+
+        let pdu: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let sent = pdu.clone();
+        let sender = ::std::thread::spawn(move || kernel.write(&sent).unwrap());
+        assert_eq!(user.read_pdu().unwrap(), pdu);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn vcan0_uds_client_to_server() {
+        use crate::isotp::{IsoTpOptions, IsoTpSocket};
+        use crate::uds::{DiagnosticSession, UdsClient, UdsServer};
+
+        let options = IsoTpOptions::default();
+        let ecu = IsoTpSocket::open("vcan0", 0x7e0.into(), 0x7e8.into(), &options).unwrap();
+        let tester = IsoTpSocket::open("vcan0", 0x7e8.into(), 0x7e0.into(), &options).unwrap();
+
+        let mut server = UdsServer::new(ecu);
+        server.set_did(0xf190, b"W0L0000000000000".to_vec());
+        let ecu = ::std::thread::spawn(move || {
+            while server.serve_one(time::Duration::from_millis(500)).unwrap() {}
+        });
+
+        let mut client = UdsClient::new(tester);
+        client.diagnostic_session_control(DiagnosticSession::Extended).unwrap();
+        assert_eq!(client.read_data_by_identifier(0xf190).unwrap(), b"W0L0000000000000");
+        ecu.join().unwrap();
+    }
+
+    #[test]
+    fn vcan0_obd_vin() {
+        use crate::isotp::{IsoTpOptions, IsoTpSocket};
+        use crate::obd::{ObdAddressing, ObdClient, ObdSocket};
+        use crate::uds::UdsServer;
+
+        let ecu = IsoTpSocket::open("vcan0", 0x7e0.into(), 0x7e8.into(), &IsoTpOptions::default()).unwrap();
+        let mut server = UdsServer::new(ecu);
+        server.on_service(0x09, |_, request| {
+            let mut response = vec![request[1], 0x01];
+            response.extend_from_slice(b"W0L0000000000000X");
+            Ok(response)
+        });
+        let ecu = ::std::thread::spawn(move || server.serve_one(time::Duration::from_secs(1)).unwrap());
+
+        // the VIN spans several frames, flow control is sent by ObdSocket
+        let mut sock = ObdSocket::open("vcan0", ObdAddressing::Standard).unwrap();
+        sock.set_target(Some(0));
+        let mut client = ObdClient::new(sock);
+        let vin = client.vin().unwrap();
+        assert_eq!(vin[0].ecu, Some(0x7e8));
+        assert_eq!(vin[0].data, "W0L0000000000000X");
+        ecu.join().unwrap();
+    }
+
+    #[test]
+    fn vcan0_j1939_transport_protocol() {
+        use crate::j1939::{J1939Addr, J1939Socket};
+
+        let rx = J1939Socket::open("vcan0", &J1939Addr::with_addr(0x30)).unwrap();
+        let tx = J1939Socket::open("vcan0", &J1939Addr::with_addr(0x20)).unwrap();
+
+        // longer than 8 bytes, sent with RTS/CTS
+        let data: Vec<u8> = (0..100).collect();
+        tx.send_to(&data, &J1939Addr::new(0, 0x0ef00, 0x30)).unwrap();
+
+        let msg = rx.recv_message().unwrap();
+        assert_eq!(msg.data, data);
+        assert_eq!(msg.src.addr, 0x20);
+        assert_eq!(msg.src.pgn & !0xff, 0x0ef00);
+        assert_eq!(msg.dst_addr, 0x30);
+    }
 }
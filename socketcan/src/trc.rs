@@ -0,0 +1,766 @@
+//! PEAK TRC format parsing
+//!
+//! Parses the trace files written by PCAN-View and other PEAK-System tools,
+//! usually found in files with a `.trc` extension. Versions 1.x and 2.x are
+//! read, version 2.1 is written.
+//!
+//! Example:
+//!
+//! ```text
+//! ;$FILEVERSION=2.1
+//! ;$STARTTIME=45469.6152982060
+//! ;$COLUMNS=N,O,T,B,I,d,R,L,D
+//! ;
+//!       1         0.000 DT 1     0123 Rx -  2    01 02
+//!       2        15.991 RR 2 12345678 Tx -  4
+//!       3        17.000 ER 1        - Rx -  5    02 01 0A 00 80
+//!       4        18.000 FB 1     0701 Rx -  9    01 02 03 04 05 06 07 08 09 0A 0B 0C
+//! ```
+//!
+//! Records are returned as `dump::CanDumpRecord`s. Buses are named `can0`
+//! for bus 1, `can1` for bus 2 and so forth, unless named otherwise using
+//! `Reader::set_channel_name`. Timestamps are microseconds since the epoch,
+//! taken from the `$STARTTIME` header plus the offset of each message; traces
+//! without a start time start at zero.
+//!
+//! Error frames (`ER`) are mapped to SocketCAN protocol errors carrying the
+//! error type, location and the error counters.
+
+use crate::dump::{CanDumpFrame, CanDumpRecord, Direction, ParseError};
+use crate::util::civil_from_days;
+use crate::{canfd_dlc_to_len, canfd_len_to_dlc, CanFdFrame, CanFrame, CanMessageId, FdFlags, CANFD_MAX_DLEN};
+use std::collections::BTreeMap;
+use std::{fs, io, path};
+
+/// days from 1899-12-30, the base of `$STARTTIME`, to the epoch
+const EPOCH_DAYS: u64 = 25_569;
+const US_PER_DAY: u64 = 86_400_000_000;
+
+/// digits written after the decimal point of `$STARTTIME`, enough to keep
+/// microseconds
+const START_TIME_DIGITS: u32 = 12;
+
+/// columns of version 2.0 traces, unless given by `$COLUMNS`
+const COLUMNS_2_0: &str = "NOTIdlD";
+const COLUMNS_2_1: &str = "N,O,T,B,I,d,R,L,D";
+
+// from linux/can/error.h
+const CAN_ERR_PROT: u32 = 0x0000_0008;
+const CAN_ERR_CNT: u32 = 0x0000_0200;
+const CAN_ERR_PROT_BIT: u8 = 0x01;
+const CAN_ERR_PROT_FORM: u8 = 0x02;
+const CAN_ERR_PROT_STUFF: u8 = 0x04;
+const CAN_ERR_PROT_TX: u8 = 0x80;
+
+/// Parses `$STARTTIME`, fractional days since 1899-12-30, into microseconds
+/// since the epoch.
+fn parse_start_time(s: &str) -> Option<u64> {
+    let mut parts = s.trim().splitn(2, '.');
+    let days: u64 = parts.next()?.parse().ok()?;
+    let frac = match parts.next() {
+        Some(digits) if digits.bytes().all(|c| c.is_ascii_digit()) => {
+            // more digits than a microsecond needs are ignored
+            let digits = &digits[..digits.len().min(18)];
+            let scale = 10u128.pow(digits.len() as u32);
+            let frac: u128 = if digits.is_empty() { 0 } else { digits.parse().ok()? };
+            ((frac * u128::from(US_PER_DAY) + scale / 2) / scale) as u64
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    days.checked_sub(EPOCH_DAYS)
+        .and_then(|days| days.checked_mul(US_PER_DAY))
+        .map(|us| us + frac)
+}
+
+/// Formats microseconds since the epoch as `$STARTTIME`.
+fn format_start_time(t_us: u64) -> String {
+    let scale = 10u128.pow(START_TIME_DIGITS);
+    let rem = u128::from(t_us % US_PER_DAY);
+    let frac = (rem * scale + u128::from(US_PER_DAY) / 2) / u128::from(US_PER_DAY);
+    format!("{}.{:0width$}", t_us / US_PER_DAY + EPOCH_DAYS, frac, width = START_TIME_DIGITS as usize)
+}
+
+/// Formats microseconds since the epoch like PCAN-View does in comments,
+/// e.g. `26.06.2024 14:46:21.735.0`.
+fn format_date(t_us: u64) -> String {
+    let secs = t_us / 1_000_000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+
+    format!("{:02}.{:02}.{} {:02}:{:02}:{:02}.{:03}.{}",
+            day,
+            month,
+            year,
+            (secs % 86_400) / 3600,
+            (secs % 3600) / 60,
+            secs % 60,
+            (t_us % 1_000_000) / 1000,
+            (t_us % 1000) / 100)
+}
+
+/// Parses milliseconds with an optional fraction into microseconds.
+fn parse_offset(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(2, '.');
+    let ms: u64 = parts.next()?.parse().ok()?;
+    let frac = match parts.next() {
+        Some(digits) if digits.bytes().all(|c| c.is_ascii_digit()) => {
+            // normalize to three digits
+            let mut us = 0;
+            for i in 0..3 {
+                us = us * 10 + digits.as_bytes().get(i).map_or(0, |&c| u64::from(c - b'0'));
+            }
+            us
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    ms.checked_mul(1000).map(|us| us + frac)
+}
+
+/// Parses a hex identifier, eight digits mark an extended one.
+fn parse_id(s: &str) -> Option<CanMessageId> {
+    let id = u32::from_str_radix(s, 16).ok()?;
+    if s.len() > 4 || id > crate::SFF_MASK {
+        if id > crate::EFF_MASK {
+            return None;
+        }
+        Some(CanMessageId::EFF(id))
+    } else {
+        Some(CanMessageId::SFF(id as u16))
+    }
+}
+
+fn parse_direction(s: &str) -> Option<Direction> {
+    match s {
+        "Rx" => Some(Direction::Rx),
+        "Tx" => Some(Direction::Tx),
+        _ => None,
+    }
+}
+
+fn parse_data<'a, I>(fields: &mut I, len: usize) -> Result<Vec<u8>, ParseError>
+    where I: Iterator<Item = &'a str>
+{
+    let mut data = Vec::with_capacity(len);
+    for _ in 0..len {
+        let byte = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        data.push(u8::from_str_radix(byte, 16).map_err(|_| ParseError::InvalidCanFrame)?);
+    }
+    Ok(data)
+}
+
+/// Converts the data of an `ER` message, error type, direction, ECC and the
+/// receive and transmit error counters, into an error frame.
+fn error_frame(data: &[u8]) -> Result<CanFrame, ParseError> {
+    if data.len() < 5 {
+        return Ok(CanFrame::new_error(0, &[])?);
+    }
+
+    let mut prot = match data[0] {
+        0 => CAN_ERR_PROT_BIT,
+        1 => CAN_ERR_PROT_FORM,
+        2 => CAN_ERR_PROT_STUFF,
+        _ => 0,
+    };
+    if data[1] == 0 {
+        prot |= CAN_ERR_PROT_TX;
+    }
+
+    // the segment code of the ECC matches the SocketCAN location
+    let err_data = [0, 0, prot, data[2] & 0x1f, 0, 0, data[4], data[3]];
+    Ok(CanFrame::new_error(CAN_ERR_PROT | CAN_ERR_CNT, &err_data)?)
+}
+
+/// The inverse of `error_frame`.
+fn error_data(frame: &CanFrame) -> [u8; 5] {
+    let data = frame.data();
+    let byte = |idx: usize| data.get(idx).cloned().unwrap_or(0);
+    let prot = byte(2);
+
+    let error_type = if prot & CAN_ERR_PROT_BIT != 0 {
+        0
+    } else if prot & CAN_ERR_PROT_FORM != 0 {
+        1
+    } else if prot & CAN_ERR_PROT_STUFF != 0 {
+        2
+    } else {
+        3
+    };
+    let direction = if prot & CAN_ERR_PROT_TX != 0 { 0 } else { 1 };
+
+    [error_type, direction, byte(3), byte(7), byte(6)]
+}
+
+#[derive(Debug)]
+/// A TRC trace reader.
+pub struct Reader<R> {
+    rdr: R,
+    line_buf: String,
+
+    /// column letters of version 2 traces, `None` for version 1
+    columns: Option<Vec<char>>,
+    start_us: u64,
+
+    /// device names, by bus number
+    channels: BTreeMap<u32, String>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            line_buf: String::new(),
+            columns: None,
+            start_us: 0,
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+impl Reader<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<fs::File>>> {
+        Ok(Reader::from_reader(fs::File::open(path)?))
+    }
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct TrcRecords<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+type Line = (u64, u32, CanDumpFrame, Option<Direction>);
+
+impl<R: io::BufRead> Reader<R> {
+    /// Returns an iterator over all records
+    pub fn records(&mut self) -> TrcRecords<'_, R> {
+        TrcRecords { src: self }
+    }
+
+    /// Set the device name used for records of `bus`, which starts at 1.
+    ///
+    /// Fails with `InvalidDeviceName` for bus 0.
+    pub fn set_channel_name(&mut self, bus: u32, name: &str) -> Result<(), ParseError> {
+        *self.channel_name(bus)? = name.to_owned();
+        Ok(())
+    }
+
+    fn channel_name(&mut self, bus: u32) -> Result<&mut String, ParseError> {
+        let idx = bus.checked_sub(1).ok_or(ParseError::InvalidDeviceName)?;
+        Ok(self.channels.entry(bus).or_insert_with(|| format!("can{}", idx)))
+    }
+
+    /// Advance state, returning next record.
+    ///
+    /// Headers are evaluated as they appear, comments and messages that do
+    /// not hold a frame (status, error counter changes, events) are skipped.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord<'_>>, ParseError> {
+        loop {
+            self.line_buf.clear();
+            if self.rdr.read_line(&mut self.line_buf)? == 0 {
+                return Ok(None);
+            }
+
+            let line = ::std::mem::replace(&mut self.line_buf, String::new());
+            let parsed = self.parse_line(&line);
+            self.line_buf = line;
+
+            if let Some((t_us, bus, frame, direction)) = parsed? {
+                return Ok(Some(CanDumpRecord {
+                    t_us: t_us,
+                    device: self.channel_name(bus)?,
+                    frame: frame,
+                    direction: direction,
+                }));
+            }
+        }
+    }
+
+    // returns `None` for lines without a frame
+    fn parse_line(&mut self, line: &str) -> Result<Option<Line>, ParseError> {
+        let line = line.trim();
+
+        if line.starts_with(';') {
+            self.parse_header(&line[1..])?;
+            return Ok(None);
+        }
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let parsed = match self.columns {
+            Some(ref columns) => parse_v2(columns, line)?,
+            None => parse_v1(line)?,
+        };
+
+        Ok(parsed.map(|(offset, bus, frame, direction)| {
+            (self.start_us.saturating_add(offset), bus, frame, direction)
+        }))
+    }
+
+    fn parse_header(&mut self, header: &str) -> Result<(), ParseError> {
+        let header = header.trim();
+        if !header.starts_with('$') {
+            return Ok(());
+        }
+
+        let mut parts = header[1..].splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().ok_or(ParseError::InvalidHeader)?.trim();
+
+        match key {
+            "FILEVERSION" => {
+                self.columns = match value.split('.').next() {
+                    Some("1") => None,
+                    Some("2") => Some(COLUMNS_2_0.chars().collect()),
+                    _ => return Err(ParseError::InvalidHeader),
+                };
+            }
+            "STARTTIME" => {
+                self.start_us = parse_start_time(value).ok_or(ParseError::InvalidHeader)?;
+            }
+            "COLUMNS" => {
+                let columns: Vec<char> = value.split(',').filter_map(|c| c.trim().chars().next()).collect();
+                if !columns.contains(&'O') || !columns.contains(&'T') || !columns.contains(&'I') {
+                    return Err(ParseError::InvalidHeader);
+                }
+                self.columns = Some(columns);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+// N) O [B] [T] I [-] L <data>... | N) O [B] [T] I [-] L RTR
+fn parse_v1(line: &str) -> Result<Option<Line>, ParseError> {
+    let mut fields = line.split_whitespace().peekable();
+
+    match fields.next() {
+        Some(number) if number.ends_with(')') => (),
+        _ => return Ok(None),
+    }
+    let offset = fields.next().and_then(parse_offset).ok_or(ParseError::InvalidTimestamp)?;
+
+    // version 1.3 adds the bus before the type
+    let mut bus = 1;
+    if let Some(b) = fields.peek().and_then(|b| b.parse::<u32>().ok()) {
+        bus = b;
+        fields.next();
+    }
+    if bus == 0 {
+        return Err(ParseError::InvalidDeviceName);
+    }
+
+    // version 1.0 lacks the type
+    let direction = match fields.peek().cloned() {
+        Some("Rx") | Some("Tx") => fields.next().and_then(parse_direction),
+        Some("Error") => {
+            let frame = CanFrame::new_error(0, &[])?;
+            return Ok(Some((offset, bus, CanDumpFrame::Error(frame), None)));
+        }
+        Some("Warng") => return Ok(None),
+        Some(_) => None,
+        None => return Err(ParseError::UnexpectedEndOfLine),
+    };
+
+    let id = fields.next()
+        .and_then(parse_id)
+        .ok_or(ParseError::InvalidCanFrame)?;
+    if fields.peek() == Some(&"-") {
+        fields.next();
+    }
+
+    let dlc: usize = fields.next()
+        .and_then(|dlc| dlc.parse().ok())
+        .filter(|&dlc| dlc <= 8)
+        .ok_or(ParseError::InvalidCanFrame)?;
+
+    let frame = if fields.peek() == Some(&"RTR") {
+        CanFrame::new(id, &[0; 8][..dlc], true, false)?
+    } else {
+        CanFrame::new(id, &parse_data(&mut fields, dlc)?, false, false)?
+    };
+    Ok(Some((offset, bus, CanDumpFrame::Classic(frame), direction)))
+}
+
+// columns as given by `$COLUMNS`, data is always last
+fn parse_v2(columns: &[char], line: &str) -> Result<Option<Line>, ParseError> {
+    let mut fields = line.split_whitespace();
+
+    let mut offset = None;
+    let mut msg_type = None;
+    let mut bus = None;
+    let mut id = None;
+    let mut direction = None;
+    let mut len = None;
+    let mut dlc = None;
+
+    for &column in columns {
+        if column == 'D' {
+            break;
+        }
+
+        let field = fields.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+        match column {
+            'O' => offset = Some(field),
+            'T' => msg_type = Some(field),
+            'B' => bus = Some(field),
+            'I' => id = Some(field),
+            'd' => direction = parse_direction(field),
+            'l' => len = Some(field),
+            'L' => dlc = Some(field),
+            // number, reserved and unknown columns
+            _ => (),
+        }
+    }
+
+    let fd_flags = match msg_type.ok_or(ParseError::UnexpectedEndOfLine)? {
+        "DT" | "RR" | "ER" => None,
+        "FD" => Some(FdFlags::empty()),
+        "FB" => Some(FdFlags::BRS),
+        "FE" => Some(FdFlags::ESI),
+        "BI" => Some(FdFlags::BRS | FdFlags::ESI),
+        // status, error counter changes, events
+        _ => return Ok(None),
+    };
+
+    let offset = offset.and_then(parse_offset).ok_or(ParseError::InvalidTimestamp)?;
+    let bus = match bus {
+        Some(bus) => bus.parse().ok().filter(|&b| b > 0).ok_or(ParseError::InvalidDeviceName)?,
+        None => 1,
+    };
+
+    // the data length, unless only the code is given
+    let max_len = if fd_flags.is_some() { CANFD_MAX_DLEN } else { 8 };
+    let len = match (len, dlc.map(|dlc| dlc.parse::<u8>().ok().filter(|&d| d <= 15))) {
+        (Some(len), _) => len.parse().ok().filter(|&len| len <= max_len).ok_or(ParseError::InvalidCanFrame)?,
+        (None, Some(Some(dlc))) if fd_flags.is_some() => canfd_dlc_to_len(dlc),
+        (None, Some(Some(dlc))) => usize::from(dlc).min(8),
+        _ => return Err(ParseError::InvalidCanFrame),
+    };
+
+    if msg_type == Some("ER") {
+        let data = parse_data(&mut fields, len)?;
+        return Ok(Some((offset, bus, CanDumpFrame::Error(error_frame(&data)?), direction)));
+    }
+
+    let id = id.and_then(parse_id).ok_or(ParseError::InvalidCanFrame)?;
+    let frame = match fd_flags {
+        Some(flags) => CanDumpFrame::Fd(CanFdFrame::new(id, &parse_data(&mut fields, len)?, flags)?),
+        None if msg_type == Some("RR") => CanDumpFrame::Classic(CanFrame::new(id, &[0; 8][..len.min(8)], true, false)?),
+        None => CanDumpFrame::Classic(CanFrame::new(id, &parse_data(&mut fields, len)?, false, false)?),
+    };
+    Ok(Some((offset, bus, frame, direction)))
+}
+
+impl<'a, R: io::BufRead> Iterator for TrcRecords<'a, R> {
+    type Item = Result<(u64, CanDumpFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A TRC trace writer.
+///
+/// Writes version 2.1 traces. The header is written along with the first
+/// record, whose time is taken as the start of the trace.
+pub struct Writer<W> {
+    wtr: W,
+    start_us: Option<u64>,
+    number: u64,
+
+    /// device names, by bus number minus one
+    channels: Vec<String>,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            start_us: None,
+            number: 0,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Assign a device the next free bus number.
+    ///
+    /// Devices get bus numbers in the order they first appear unless they
+    /// are assigned one in advance.
+    pub fn add_channel(&mut self, device: &str) -> u32 {
+        match self.channels.iter().position(|d| d == device) {
+            Some(idx) => idx as u32 + 1,
+            None => {
+                self.channels.push(device.to_owned());
+                self.channels.len() as u32
+            }
+        }
+    }
+
+    /// Write a record as returned by one of the readers.
+    ///
+    /// Records without a direction are written as received. TRC traces
+    /// cannot hold CAN XL frames, trying to write one fails with
+    /// `InvalidInput`.
+    pub fn write_record(&mut self, record: &CanDumpRecord) -> io::Result<()> {
+        if let CanDumpFrame::Xl(_) = record.frame {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN XL frames cannot be written to TRC traces"));
+        }
+
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                self.write_header(record.t_us)?;
+                record.t_us
+            }
+        };
+
+        self.number += 1;
+        let bus = self.add_channel(record.device);
+        let offset = record.t_us.saturating_sub(start_us);
+        let dir = match record.direction {
+            Some(Direction::Tx) => "Tx",
+            _ => "Rx",
+        };
+
+        write!(self.wtr, "{:>7} {:>9}.{:03} ", self.number, offset / 1000, offset % 1000)?;
+
+        match record.frame {
+            CanDumpFrame::Error(ref frame) => {
+                write!(self.wtr, "ER {} {:>8} {} -  5", bus, "-", dir)?;
+                self.write_data(&error_data(frame))?;
+            }
+            CanDumpFrame::Classic(ref frame) => {
+                let msg_type = if frame.is_rtr() { "RR" } else { "DT" };
+                write!(self.wtr,
+                       "{} {} {:>8} {} - {:>2}",
+                       msg_type,
+                       bus,
+                       format_id(frame.id(), frame.is_extended()),
+                       dir,
+                       frame.data().len())?;
+                if !frame.is_rtr() {
+                    self.write_data(frame.data())?;
+                }
+            }
+            CanDumpFrame::Fd(ref frame) => {
+                let msg_type = match (frame.is_brs(), frame.is_esi()) {
+                    (false, false) => "FD",
+                    (true, false) => "FB",
+                    (false, true) => "FE",
+                    (true, true) => "BI",
+                };
+                write!(self.wtr,
+                       "{} {} {:>8} {} - {:>2}",
+                       msg_type,
+                       bus,
+                       format_id(frame.id(), frame.is_extended()),
+                       dir,
+                       canfd_len_to_dlc(frame.data().len()))?;
+                self.write_data(frame.data())?;
+            }
+            CanDumpFrame::Xl(_) => unreachable!(),
+        }
+
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.wtr.flush()?;
+        Ok(self.wtr)
+    }
+
+    fn write_header(&mut self, start_us: u64) -> io::Result<()> {
+        write!(self.wtr,
+               ";$FILEVERSION=2.1\n\
+                ;$STARTTIME={}\n\
+                ;$COLUMNS={}\n\
+                ;\n\
+                ;   Start time: {}\n\
+                ;-------------------------------------------------------------------------------\n\
+                ;   Message   Time    Type Bus ID    Rx/Tx\n\
+                ;   Number    Offset  |    |   [hex] |  Reserved\n\
+                ;   |         [ms]    |    |   |     |  |  Data Length Code\n\
+                ;   |         |       |    |   |     |  |  |    Data [hex] ...\n\
+                ;   |         |       |    |   |     |  |  |    |\n\
+                ;---+-- ------+------ +- -+ --+----- +- +- +- -- -- -- -- -- -- -- --\n",
+               format_start_time(start_us),
+               COLUMNS_2_1,
+               format_date(start_us))?;
+        self.start_us = Some(start_us);
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if !data.is_empty() {
+            self.wtr.write_all(b"   ")?;
+        }
+        for byte in data {
+            write!(self.wtr, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+fn format_id(id: u32, extended: bool) -> String {
+    if extended {
+        format!("{:08X}", id)
+    } else {
+        format!("{:04X}", id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_start_time, parse_start_time, Reader, Writer};
+    use crate::dump::{self, CanDumpFrame, Direction};
+
+    const START_US: u64 = 1_719_413_181_735_000;
+
+    #[test]
+    fn test_start_time() {
+        assert_eq!(parse_start_time("25569"), Some(0));
+        assert_eq!(parse_start_time("25569.5"), Some(43_200_000_000));
+        assert_eq!(parse_start_time("43749.5432153472"), Some(1_570_798_933_805_998));
+        assert_eq!(parse_start_time("1.5"), None);
+        assert_eq!(format_start_time(0), "25569.000000000000");
+
+        for &t_us in &[START_US, START_US + 1, START_US + 999_999, 1] {
+            assert_eq!(parse_start_time(&format_start_time(t_us)), Some(t_us));
+        }
+    }
+
+    #[test]
+    fn test_read_v1_1() {
+        let input = ";$FILEVERSION=1.1\n\
+                     ;$STARTTIME=25569.5\n\
+                     ;\n\
+                     ;   Message Number\n\
+                     ;---+--   ----+----  --+--  ----+---  +  -+ -- -- -- -- -- -- --\n     \
+                     1)         1.9  Rx         0300  8  00 00 00 00 04 00 00 00\n     \
+                     2)       511.3  Tx     00000100  2  AA BB\n     \
+                     3)      1000.0  Rx         0701  4  RTR\n     \
+                     4)      1500.0  Warng  FFFFFFFF  4  00 00 00 08  BUSHEAVY\n";
+
+        let mut reader = Reader::from_reader(input.as_bytes());
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec.t_us, 43_200_000_000 + 1_900);
+            assert_eq!(rec.device, "can0");
+            assert_eq!(rec.direction, Some(Direction::Rx));
+            assert_eq!(rec.frame.id(), 0x300);
+            assert_eq!(rec.frame.data(), &[0, 0, 0, 0, 4, 0, 0, 0]);
+        }
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec.direction, Some(Direction::Tx));
+            assert!(rec.frame.is_extended());
+            assert_eq!(rec.frame.id(), 0x100);
+        }
+
+        {
+            let rec = reader.next_record().unwrap().unwrap();
+            assert!(rec.frame.is_rtr());
+            assert_eq!(rec.frame.data().len(), 4);
+        }
+
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_v2_0() {
+        let input = ";$FILEVERSION=2.0\n\
+                     ;$STARTTIME=25569\n\
+                     ;$COLUMNS=N,O,T,I,d,l,D\n\
+                     ;\n      \
+                     1      1059.900 DT     0300 Rx  7  00 00 00 00 04 00 00\n      \
+                     2      1283.231 FB 18FF0102 Tx 12  01 02 03 04 05 06 07 08 09 0A 0B 0C\n      \
+                     3      1300.000 ST          Rx     00 00 00 08\n      \
+                     4      1400.000 ER        - Rx  5  02 01 0A 00 80\n";
+
+        let records: Vec<_> = Reader::from_reader(input.as_bytes()).records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].0, 1_059_900);
+        assert_eq!(records[0].1.data().len(), 7);
+
+        match records[1].1 {
+            CanDumpFrame::Fd(ref frame) => {
+                assert!(frame.is_brs());
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x18ff0102);
+                assert_eq!(frame.data().len(), 12);
+            }
+            ref other => panic!("unexpected frame {:?}", other),
+        }
+
+        // stuff error on reception, transmit error counter 128
+        assert!(records[2].1.is_error());
+        assert_eq!(records[2].1.id(), 0x208);
+        assert_eq!(records[2].1.data(), &[0, 0, 0x04, 0x0a, 0, 0, 0x80, 0]);
+
+        assert!(Reader::from_reader(";$FILEVERSION=3.0\n".as_bytes()).next_record().is_err());
+        assert!(Reader::from_reader(";$FILEVERSION=2.0\n1 1.0 DT 0300 Rx 2 00\n".as_bytes())
+                    .next_record()
+                    .is_err());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let header = ";$FILEVERSION=2.0\n;$COLUMNS=N,O,T,I,d,l,D\n";
+        for line in &["1 1.0 DT 0300 Rx 18446744073709551615 00\n", "1 1.0 DT 0300 Rx 9 00\n",
+                      "1 1.0 FD 0300 Rx 65 00\n"] {
+            let input = format!("{}{}", header, line);
+            assert!(Reader::from_reader(input.as_bytes()).next_record().is_err());
+        }
+    }
+
+    #[test]
+    fn test_channel_zero() {
+        let mut reader = Reader::from_reader(&b""[..]);
+        assert!(reader.set_channel_name(0, "vcan0").is_err());
+        reader.set_channel_name(u32::max_value(), "vcan9").unwrap();
+        reader.set_channel_name(1, "vcan0").unwrap();
+    }
+
+    #[test]
+    fn test_candump_to_trc_and_back() {
+        let candump = "(1719413181.750991) can0 123#0102 R\n\
+                       (1719413181.751002) can1 12345678#R4 T\n\
+                       (1719413181.752000) can0 20000208#0000040A00008000 R\n\
+                       (1719413181.753000) can0 701##1010203040506070809000000 R\n\
+                       (1719413181.754000) can1 00000702##3AABB T\n";
+
+        let mut trc = Writer::from_writer(Vec::new());
+        let mut reader = dump::Reader::from_reader(candump.as_bytes());
+        while let Some(record) = reader.next_record().unwrap() {
+            trc.write_record(&record).unwrap();
+        }
+        let trc = trc.finish().unwrap();
+
+        let mut dump = dump::Writer::from_writer(Vec::new());
+        let mut reader = Reader::from_reader(&trc[..]);
+        while let Some(record) = reader.next_record().unwrap() {
+            dump.write_record(&record).unwrap();
+        }
+
+        assert_eq!(::std::str::from_utf8(&dump.into_inner()).unwrap(), candump);
+    }
+}
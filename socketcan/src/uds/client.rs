@@ -0,0 +1,462 @@
+//! Blocking UDS client
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::{
+    be_uint, DiagnosticSession, DtcRecord, MemoryLocation, NegativeResponseCode, ResetType,
+    RoutineControlType, UdsError, DEFAULT_P2, DEFAULT_P2_STAR, POSITIVE_RESPONSE_OFFSET,
+    REPORT_DTC_BY_STATUS_MASK, REPORT_NUMBER_OF_DTC_BY_STATUS_MASK,
+    SID_CLEAR_DIAGNOSTIC_INFORMATION, SID_DIAGNOSTIC_SESSION_CONTROL, SID_ECU_RESET,
+    SID_NEGATIVE_RESPONSE, SID_READ_DATA_BY_IDENTIFIER, SID_READ_DTC_INFORMATION,
+    SID_REQUEST_DOWNLOAD, SID_REQUEST_TRANSFER_EXIT, SID_ROUTINE_CONTROL, SID_SECURITY_ACCESS,
+    SID_TESTER_PRESENT, SID_TRANSFER_DATA, SID_WRITE_DATA_BY_IDENTIFIER,
+    SUPPRESS_POSITIVE_RESPONSE,
+};
+use crate::isotp::IsoTpTransport;
+use crate::ShouldRetry;
+
+/// default time without requests after which the session times out on the
+/// ECU (S3), TesterPresent is sent well before
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_millis(2000);
+
+/// A UDS client, sending requests over an ISO-TP transport.
+///
+/// Each request blocks until its response arrives. Responses are awaited
+/// for P2, negative responses with the code `ResponsePending` extend the
+/// wait to P2* each.
+#[derive(Debug)]
+pub struct UdsClient<T> {
+    transport: T,
+    p2: Duration,
+    p2_star: Duration,
+
+    keep_alive: Option<Duration>,
+    last_request: Instant,
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    pub fn new(transport: T) -> UdsClient<T> {
+        UdsClient {
+            transport: transport,
+            p2: DEFAULT_P2,
+            p2_star: DEFAULT_P2_STAR,
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+            last_request: Instant::now(),
+        }
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Sets the response timings.
+    ///
+    /// Called by `diagnostic_session_control` with the timings reported by
+    /// the ECU. Allow for some delay of the network when setting them.
+    pub fn set_timing(&mut self, p2: Duration, p2_star: Duration) {
+        self.p2 = p2;
+        self.p2_star = p2_star;
+    }
+
+    /// Current P2 and P2*
+    pub fn timing(&self) -> (Duration, Duration) {
+        (self.p2, self.p2_star)
+    }
+
+    /// Sets the interval of `keep_alive`, `None` disables it.
+    pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+        self.keep_alive = interval;
+    }
+
+    /// Send a TesterPresent if no request has been sent for the keep-alive
+    /// interval.
+    ///
+    /// Call this periodically to keep a non-default session alive. The
+    /// response is suppressed, so this does not wait.
+    pub fn keep_alive(&mut self) -> Result<(), UdsError> {
+        match self.keep_alive {
+            Some(interval) if self.last_request.elapsed() >= interval => self.tester_present(true),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a request without waiting for a response.
+    pub fn send(&mut self, request: &[u8]) -> Result<(), UdsError> {
+        self.transport.write_pdu(request)?;
+        self.last_request = Instant::now();
+        Ok(())
+    }
+
+    /// Send a raw request, returning the positive response.
+    ///
+    /// Responses to other services are skipped.
+    pub fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let sid = *request.first()
+                          .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty request"))?;
+        self.send(request)?;
+
+        let mut deadline = Instant::now() + self.p2;
+        loop {
+            let now = Instant::now();
+            // a zero timeout would block forever
+            if now >= deadline || deadline - now < Duration::from_micros(1) {
+                return Err(UdsError::Timeout);
+            }
+            self.transport.set_read_timeout(deadline - now)?;
+
+            let response = match self.transport.read_pdu() {
+                Ok(response) => response,
+                Err(ref e) if e.should_retry() => return Err(UdsError::Timeout),
+                Err(e) => return Err(e.into()),
+            };
+
+            match response.first() {
+                Some(&SID_NEGATIVE_RESPONSE) if response.len() >= 3 && response[1] == sid => {
+                    match NegativeResponseCode::from(response[2]) {
+                        NegativeResponseCode::ResponsePending => deadline = Instant::now() + self.p2_star,
+                        code => return Err(UdsError::NegativeResponse(sid, code)),
+                    }
+                }
+                Some(&rsid) if rsid == sid.wrapping_add(POSITIVE_RESPONSE_OFFSET) => return Ok(response),
+                _ => (),
+            }
+        }
+    }
+
+    /// Send a request, checking that the response echoes the first `echo`
+    /// bytes following the service identifier.
+    fn request_echo(&mut self, request: &[u8], echo: usize) -> Result<Vec<u8>, UdsError> {
+        let response = self.request(request)?;
+        if response.len() < 1 + echo || response[1..=echo] != request[1..=echo] {
+            return Err(UdsError::InvalidResponse(response));
+        }
+        Ok(response)
+    }
+
+    /// Change the session, returning P2 and P2* of the ECU.
+    ///
+    /// The client uses the returned timings from then on.
+    pub fn diagnostic_session_control(&mut self,
+                                      session: DiagnosticSession)
+                                      -> Result<(Duration, Duration), UdsError> {
+        let response = self.request_echo(&[SID_DIAGNOSTIC_SESSION_CONTROL, session.into()], 1)?;
+        if response.len() < 6 {
+            return Ok(self.timing());
+        }
+
+        // P2 is given in ms, P2* in units of 10ms
+        let p2 = Duration::from_millis(be_uint(&response[2..4]));
+        let p2_star = Duration::from_millis(be_uint(&response[4..6]) * 10);
+        self.set_timing(p2, p2_star);
+        Ok((p2, p2_star))
+    }
+
+    /// Reset the ECU, returning the power down time for
+    /// `EnableRapidPowerShutDown`.
+    pub fn ecu_reset(&mut self, reset: ResetType) -> Result<Option<u8>, UdsError> {
+        let response = self.request_echo(&[SID_ECU_RESET, reset.into()], 1)?;
+        Ok(response.get(2).cloned())
+    }
+
+    /// Request the seed of a security level, which is odd.
+    pub fn request_seed(&mut self, level: u8) -> Result<Vec<u8>, UdsError> {
+        let response = self.request_echo(&[SID_SECURITY_ACCESS, level], 1)?;
+        Ok(response[2..].to_vec())
+    }
+
+    /// Send the key for a security level, which is the level of the seed.
+    pub fn send_key(&mut self, level: u8, key: &[u8]) -> Result<(), UdsError> {
+        let mut request = vec![SID_SECURITY_ACCESS, level.wrapping_add(1)];
+        request.extend_from_slice(key);
+        self.request_echo(&request, 1)?;
+        Ok(())
+    }
+
+    /// Unlock a security level, computing the key from the seed with
+    /// `compute_key`.
+    ///
+    /// A seed of all zeros means the level is already unlocked, no key is
+    /// sent then.
+    pub fn security_access<F>(&mut self, level: u8, compute_key: F) -> Result<(), UdsError>
+        where F: FnOnce(&[u8]) -> Vec<u8>
+    {
+        let seed = self.request_seed(level)?;
+        if seed.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        let key = compute_key(&seed);
+        self.send_key(level, &key)
+    }
+
+    /// Read the data identified by `did`.
+    pub fn read_data_by_identifier(&mut self, did: u16) -> Result<Vec<u8>, UdsError> {
+        let did = did.to_be_bytes();
+        let response = self.request_echo(&[SID_READ_DATA_BY_IDENTIFIER, did[0], did[1]], 2)?;
+        Ok(response[3..].to_vec())
+    }
+
+    /// Write the data identified by `did`.
+    pub fn write_data_by_identifier(&mut self, did: u16, data: &[u8]) -> Result<(), UdsError> {
+        let mut request = vec![SID_WRITE_DATA_BY_IDENTIFIER];
+        request.extend_from_slice(&did.to_be_bytes());
+        request.extend_from_slice(data);
+        self.request_echo(&request, 2)?;
+        Ok(())
+    }
+
+    /// Start, stop or get the results of a routine, returning the status
+    /// record of the response.
+    pub fn routine_control(&mut self,
+                           control: RoutineControlType,
+                           routine: u16,
+                           options: &[u8])
+                           -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_ROUTINE_CONTROL, control.into()];
+        request.extend_from_slice(&routine.to_be_bytes());
+        request.extend_from_slice(options);
+        let response = self.request_echo(&request, 3)?;
+        Ok(response[4..].to_vec())
+    }
+
+    /// Request a download to `location`, returning the largest request of
+    /// TransferData the ECU accepts, including its header.
+    ///
+    /// `data_format` tells the compression and encryption methods, 0 for
+    /// neither. Fails with `InvalidInput` if the address or size of
+    /// `location` does not take 1 to 8 bytes.
+    pub fn request_download(&mut self,
+                            location: &MemoryLocation,
+                            data_format: u8)
+                            -> Result<usize, UdsError> {
+        let mut request = vec![SID_REQUEST_DOWNLOAD, data_format];
+        location.encode(&mut request)?;
+
+        let response = self.request(&request)?;
+        let len = response.get(1).map(|b| (b >> 4) as usize).unwrap_or(0);
+        if len == 0 || len > 8 || response.len() < 2 + len {
+            return Err(UdsError::InvalidResponse(response));
+        }
+        Ok(be_uint(&response[2..2 + len]) as usize)
+    }
+
+    /// Transfer a block, returning the parameters of the response.
+    pub fn transfer_data(&mut self, block_sequence_counter: u8, data: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_TRANSFER_DATA, block_sequence_counter];
+        request.extend_from_slice(data);
+        let response = self.request_echo(&request, 1)?;
+        Ok(response[2..].to_vec())
+    }
+
+    /// End a transfer, returning the parameters of the response.
+    pub fn request_transfer_exit(&mut self, params: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_REQUEST_TRANSFER_EXIT];
+        request.extend_from_slice(params);
+        let response = self.request(&request)?;
+        Ok(response[1..].to_vec())
+    }
+
+    /// Download `data` to `location`, in blocks as large as the ECU accepts.
+    pub fn download(&mut self, location: &MemoryLocation, data_format: u8, data: &[u8]) -> Result<(), UdsError> {
+        let max_len = self.request_download(location, data_format)?;
+        if max_len <= 2 {
+            return Err(UdsError::InvalidResponse(vec![]));
+        }
+
+        // the counter starts at 1 and wraps around to 0
+        for (i, block) in data.chunks(max_len - 2).enumerate() {
+            self.transfer_data((i + 1) as u8, block)?;
+        }
+
+        self.request_transfer_exit(&[])?;
+        Ok(())
+    }
+
+    /// Send a raw ReadDTCInformation request, returning the response
+    /// following the sub-function.
+    pub fn read_dtc_information(&mut self, sub_function: u8, params: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_READ_DTC_INFORMATION, sub_function];
+        request.extend_from_slice(params);
+        let response = self.request_echo(&request, 1)?;
+        Ok(response[2..].to_vec())
+    }
+
+    /// Count the DTCs matching `status_mask`.
+    pub fn read_number_of_dtc_by_status_mask(&mut self, status_mask: u8) -> Result<u16, UdsError> {
+        let response = self.read_dtc_information(REPORT_NUMBER_OF_DTC_BY_STATUS_MASK, &[status_mask])?;
+        // status availability mask, format identifier and count
+        if response.len() < 4 {
+            return Err(UdsError::InvalidResponse(response));
+        }
+        Ok(u16::from_be_bytes([response[2], response[3]]))
+    }
+
+    /// Read the DTCs matching `status_mask`.
+    pub fn read_dtc_by_status_mask(&mut self, status_mask: u8) -> Result<Vec<DtcRecord>, UdsError> {
+        let response = self.read_dtc_information(REPORT_DTC_BY_STATUS_MASK, &[status_mask])?;
+
+        // the status availability mask precedes the records
+        Ok(response.get(1..)
+                   .unwrap_or(&[])
+                   .chunks(4)
+                   .filter(|r| r.len() == 4)
+                   .map(|r| {
+                       DtcRecord {
+                           dtc: be_uint(&r[..3]) as u32,
+                           status: r[3],
+                       }
+                   })
+                   .collect())
+    }
+
+    /// Clear the DTCs of a group, 0xffffff for all.
+    pub fn clear_diagnostic_information(&mut self, group: u32) -> Result<(), UdsError> {
+        let group = group.to_be_bytes();
+        self.request(&[SID_CLEAR_DIAGNOSTIC_INFORMATION, group[1], group[2], group[3]])?;
+        Ok(())
+    }
+
+    /// Send a TesterPresent, optionally without waiting for a response.
+    pub fn tester_present(&mut self, suppress_response: bool) -> Result<(), UdsError> {
+        if suppress_response {
+            return self.send(&[SID_TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE]);
+        }
+        self.request_echo(&[SID_TESTER_PRESENT, 0x00], 1)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Records requests and replays canned responses.
+    struct Mock {
+        requests: Vec<Vec<u8>>,
+        responses: VecDeque<Vec<u8>>,
+        timeouts: Vec<Duration>,
+    }
+
+    impl Mock {
+        fn new(responses: &[&[u8]]) -> Mock {
+            Mock {
+                requests: Vec::new(),
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+                timeouts: Vec::new(),
+            }
+        }
+    }
+
+    impl IsoTpTransport for Mock {
+        fn read_pdu(&mut self) -> io::Result<Vec<u8>> {
+            self.responses.pop_front().ok_or_else(|| io::ErrorKind::WouldBlock.into())
+        }
+
+        fn write_pdu(&mut self, pdu: &[u8]) -> io::Result<()> {
+            self.requests.push(pdu.to_vec());
+            Ok(())
+        }
+
+        fn set_read_timeout(&mut self, duration: Duration) -> io::Result<()> {
+            self.timeouts.push(duration);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_session_control() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x50, 0x03, 0x00, 0x32, 0x01, 0xf4]]));
+        let timing = client.diagnostic_session_control(DiagnosticSession::Extended).unwrap();
+
+        assert_eq!(timing, (Duration::from_millis(50), Duration::from_millis(5000)));
+        assert_eq!(client.transport().requests, vec![vec![0x10, 0x03]]);
+    }
+
+    #[test]
+    fn test_response_pending() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x7f, 0x22, 0x78],
+                                                    &[0x7f, 0x22, 0x78],
+                                                    &[0x62, 0xf1, 0x90, b'W', b'0', b'L']]));
+        assert_eq!(client.read_data_by_identifier(0xf190).unwrap(), b"W0L");
+
+        // a single pending response does not satisfy the request
+        let mut client = UdsClient::new(Mock::new(&[&[0x7f, 0x22, 0x78]]));
+        match client.read_data_by_identifier(0xf190) {
+            Err(UdsError::Timeout) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sub_microsecond_timeout() {
+        // would be a zero timeout, blocking forever
+        let mut client = UdsClient::new(Mock::new(&[&[0x7e, 0x00]]));
+        client.set_timing(Duration::from_nanos(500), Duration::from_nanos(500));
+        match client.request(&[0x3e, 0x00]) {
+            Err(UdsError::Timeout) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(client.transport().timeouts.iter().all(|t| *t >= Duration::from_micros(1)));
+    }
+
+    #[test]
+    fn test_negative_response() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x7f, 0x2e, 0x31]]));
+        match client.write_data_by_identifier(0x1234, &[1]) {
+            Err(UdsError::NegativeResponse(0x2e, NegativeResponseCode::RequestOutOfRange)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // responses to other DIDs are invalid
+        let mut client = UdsClient::new(Mock::new(&[&[0x62, 0xf1, 0x91, 0x00]]));
+        match client.read_data_by_identifier(0xf190) {
+            Err(UdsError::InvalidResponse(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_security_access() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x67, 0x01, 0x12, 0x34], &[0x67, 0x02]]));
+        client.security_access(0x01, |seed| seed.iter().map(|b| !b).collect()).unwrap();
+        assert_eq!(client.transport().requests[1], vec![0x27, 0x02, 0xed, 0xcb]);
+    }
+
+    #[test]
+    fn test_download() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x74, 0x20, 0x00, 0x06],
+                                                    &[0x76, 0x01],
+                                                    &[0x76, 0x02],
+                                                    &[0x77]]));
+        let location = MemoryLocation::new(0x0800_0000, 6);
+        client.download(&location, 0x00, &[1, 2, 3, 4, 5, 6]).unwrap();
+
+        let requests = &client.transport().requests;
+        assert_eq!(requests[0],
+                   vec![0x34, 0x00, 0x44, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06]);
+        assert_eq!(requests[1], vec![0x36, 0x01, 1, 2, 3, 4]);
+        assert_eq!(requests[2], vec![0x36, 0x02, 5, 6]);
+        assert_eq!(requests[3], vec![0x37]);
+    }
+
+    #[test]
+    fn test_read_dtcs() {
+        let mut client = UdsClient::new(Mock::new(&[&[0x59, 0x02, 0xff, 0x12, 0x34, 0x56, 0x09]]));
+        assert_eq!(client.read_dtc_by_status_mask(0x08).unwrap(),
+                   vec![DtcRecord { dtc: 0x123456, status: 0x09 }]);
+    }
+
+    #[test]
+    fn test_tester_present() {
+        let mut client = UdsClient::new(Mock::new(&[]));
+        client.set_keep_alive(Some(Duration::from_millis(0)));
+        client.keep_alive().unwrap();
+        assert_eq!(client.transport().requests, vec![vec![0x3e, 0x80]]);
+    }
+}
@@ -0,0 +1,380 @@
+//! Unified Diagnostic Services (ISO 14229) over ISO-TP
+//!
+//! `UdsClient` sends requests to an ECU over any `IsoTpTransport`, either
+//! the kernel's or the userspace implementation, while `UdsServer` answers
+//! them like an ECU would:
+//!
+//! ```no_run
+//! use socketcan::CanMessageId;
+//! use socketcan::isotp::{IsoTpOptions, IsoTpSocket};
+//! use socketcan::uds::{DiagnosticSession, UdsClient};
+//!
+//! let sock = IsoTpSocket::open("vcan0", CanMessageId::SFF(0x7e8), CanMessageId::SFF(0x7e0),
+//!                              &IsoTpOptions::default()).unwrap();
+//! let mut client = UdsClient::new(sock);
+//!
+//! client.diagnostic_session_control(DiagnosticSession::Extended).unwrap();
+//! let vin = client.read_data_by_identifier(0xf190).unwrap();
+//! println!("VIN {}", String::from_utf8_lossy(&vin));
+//! ```
+
+use std::{error, fmt, io};
+use std::time::Duration;
+
+mod client;
+mod server;
+
+pub use self::client::UdsClient;
+pub use self::server::{ServerState, UdsServer};
+
+// service identifiers of requests, responses add `POSITIVE_RESPONSE_OFFSET`
+pub const SID_DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+pub const SID_ECU_RESET: u8 = 0x11;
+pub const SID_CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+pub const SID_READ_DTC_INFORMATION: u8 = 0x19;
+pub const SID_READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+pub const SID_SECURITY_ACCESS: u8 = 0x27;
+pub const SID_WRITE_DATA_BY_IDENTIFIER: u8 = 0x2e;
+pub const SID_ROUTINE_CONTROL: u8 = 0x31;
+pub const SID_REQUEST_DOWNLOAD: u8 = 0x34;
+pub const SID_TRANSFER_DATA: u8 = 0x36;
+pub const SID_REQUEST_TRANSFER_EXIT: u8 = 0x37;
+pub const SID_TESTER_PRESENT: u8 = 0x3e;
+
+/// first byte of negative responses
+pub const SID_NEGATIVE_RESPONSE: u8 = 0x7f;
+
+/// added to the service identifier in positive responses
+pub const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// set in the sub-function of a request to suppress the positive response
+pub const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+// sub-functions of ReadDTCInformation
+const REPORT_NUMBER_OF_DTC_BY_STATUS_MASK: u8 = 0x01;
+const REPORT_DTC_BY_STATUS_MASK: u8 = 0x02;
+
+/// default P2, the time the ECU takes to respond
+const DEFAULT_P2: Duration = Duration::from_millis(50);
+
+/// default P2*, the time the ECU takes after a pending response
+const DEFAULT_P2_STAR: Duration = Duration::from_millis(5000);
+
+/// A trouble code along with its status, as reported by ReadDTCInformation
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DtcRecord {
+    /// 24 bit trouble code
+    pub dtc: u32,
+
+    /// status bits, e.g. 0x01 for testFailed and 0x08 for confirmedDTC
+    pub status: u8,
+}
+
+/// Memory area of RequestDownload
+///
+/// The address and size are sent with the given number of bytes, as told
+/// by the `addressAndLengthFormatIdentifier`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryLocation {
+    pub address: u64,
+    pub size: u64,
+
+    /// bytes of the address, 1 to 8
+    pub address_len: u8,
+
+    /// bytes of the size, 1 to 8
+    pub size_len: u8,
+}
+
+impl MemoryLocation {
+    /// A location with a 32 bit address and size.
+    pub fn new(address: u32, size: u32) -> MemoryLocation {
+        MemoryLocation {
+            address: u64::from(address),
+            size: u64::from(size),
+            address_len: 4,
+            size_len: 4,
+        }
+    }
+
+    /// Append the format identifier, address and size, failing with
+    /// `InvalidInput` if the lengths are not 1 to 8.
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let (a, s) = (self.address_len as usize, self.size_len as usize);
+        if a == 0 || a > 8 || s == 0 || s > 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "address and size take 1 to 8 bytes"));
+        }
+
+        buf.push(self.size_len << 4 | self.address_len);
+        buf.extend_from_slice(&self.address.to_be_bytes()[8 - a..]);
+        buf.extend_from_slice(&self.size.to_be_bytes()[8 - s..]);
+        Ok(())
+    }
+
+    /// Decode the format identifier and the following address and size,
+    /// `None` if `data` is too short or the lengths are invalid.
+    fn decode(data: &[u8]) -> Option<MemoryLocation> {
+        let format = *data.first()?;
+        let (address_len, size_len) = (format & 0x0f, format >> 4);
+        let (a, s) = (address_len as usize, size_len as usize);
+        if a == 0 || a > 8 || s == 0 || s > 8 || data.len() != 1 + a + s {
+            return None;
+        }
+
+        Some(MemoryLocation {
+            address: be_uint(&data[1..1 + a]),
+            size: be_uint(&data[1 + a..]),
+            address_len: address_len,
+            size_len: size_len,
+        })
+    }
+}
+
+/// Reads a big endian number of up to 8 bytes.
+fn be_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |n, &b| n << 8 | u64::from(b))
+}
+
+/// Negative response codes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NegativeResponseCode {
+    GeneralReject,
+    ServiceNotSupported,
+    SubFunctionNotSupported,
+    IncorrectMessageLengthOrInvalidFormat,
+    ResponseTooLong,
+    BusyRepeatRequest,
+    ConditionsNotCorrect,
+    RequestSequenceError,
+    NoResponseFromSubnetComponent,
+    FailurePreventsExecutionOfRequestedAction,
+    RequestOutOfRange,
+    SecurityAccessDenied,
+    InvalidKey,
+    ExceededNumberOfAttempts,
+    RequiredTimeDelayNotExpired,
+    UploadDownloadNotAccepted,
+    TransferDataSuspended,
+    GeneralProgrammingFailure,
+    WrongBlockSequenceCounter,
+    /// the request was received, the response follows within P2*
+    ResponsePending,
+    SubFunctionNotSupportedInActiveSession,
+    ServiceNotSupportedInActiveSession,
+    /// codes without a variant, such as manufacturer specific ones
+    Other(u8),
+}
+
+impl From<u8> for NegativeResponseCode {
+    fn from(code: u8) -> NegativeResponseCode {
+        use self::NegativeResponseCode::*;
+
+        match code {
+            0x10 => GeneralReject,
+            0x11 => ServiceNotSupported,
+            0x12 => SubFunctionNotSupported,
+            0x13 => IncorrectMessageLengthOrInvalidFormat,
+            0x14 => ResponseTooLong,
+            0x21 => BusyRepeatRequest,
+            0x22 => ConditionsNotCorrect,
+            0x24 => RequestSequenceError,
+            0x25 => NoResponseFromSubnetComponent,
+            0x26 => FailurePreventsExecutionOfRequestedAction,
+            0x31 => RequestOutOfRange,
+            0x33 => SecurityAccessDenied,
+            0x35 => InvalidKey,
+            0x36 => ExceededNumberOfAttempts,
+            0x37 => RequiredTimeDelayNotExpired,
+            0x70 => UploadDownloadNotAccepted,
+            0x71 => TransferDataSuspended,
+            0x72 => GeneralProgrammingFailure,
+            0x73 => WrongBlockSequenceCounter,
+            0x78 => ResponsePending,
+            0x7e => SubFunctionNotSupportedInActiveSession,
+            0x7f => ServiceNotSupportedInActiveSession,
+            code => Other(code),
+        }
+    }
+}
+
+impl From<NegativeResponseCode> for u8 {
+    fn from(code: NegativeResponseCode) -> u8 {
+        use self::NegativeResponseCode::*;
+
+        match code {
+            GeneralReject => 0x10,
+            ServiceNotSupported => 0x11,
+            SubFunctionNotSupported => 0x12,
+            IncorrectMessageLengthOrInvalidFormat => 0x13,
+            ResponseTooLong => 0x14,
+            BusyRepeatRequest => 0x21,
+            ConditionsNotCorrect => 0x22,
+            RequestSequenceError => 0x24,
+            NoResponseFromSubnetComponent => 0x25,
+            FailurePreventsExecutionOfRequestedAction => 0x26,
+            RequestOutOfRange => 0x31,
+            SecurityAccessDenied => 0x33,
+            InvalidKey => 0x35,
+            ExceededNumberOfAttempts => 0x36,
+            RequiredTimeDelayNotExpired => 0x37,
+            UploadDownloadNotAccepted => 0x70,
+            TransferDataSuspended => 0x71,
+            GeneralProgrammingFailure => 0x72,
+            WrongBlockSequenceCounter => 0x73,
+            ResponsePending => 0x78,
+            SubFunctionNotSupportedInActiveSession => 0x7e,
+            ServiceNotSupportedInActiveSession => 0x7f,
+            Other(code) => code,
+        }
+    }
+}
+
+/// Sessions of DiagnosticSessionControl
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DiagnosticSession {
+    Default,
+    Programming,
+    Extended,
+    SafetySystem,
+    /// manufacturer or supplier specific sessions
+    Other(u8),
+}
+
+impl From<u8> for DiagnosticSession {
+    fn from(session: u8) -> DiagnosticSession {
+        match session {
+            0x01 => DiagnosticSession::Default,
+            0x02 => DiagnosticSession::Programming,
+            0x03 => DiagnosticSession::Extended,
+            0x04 => DiagnosticSession::SafetySystem,
+            session => DiagnosticSession::Other(session),
+        }
+    }
+}
+
+impl From<DiagnosticSession> for u8 {
+    fn from(session: DiagnosticSession) -> u8 {
+        match session {
+            DiagnosticSession::Default => 0x01,
+            DiagnosticSession::Programming => 0x02,
+            DiagnosticSession::Extended => 0x03,
+            DiagnosticSession::SafetySystem => 0x04,
+            DiagnosticSession::Other(session) => session,
+        }
+    }
+}
+
+/// Reset types of ECUReset
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResetType {
+    Hard,
+    KeyOffOn,
+    Soft,
+    EnableRapidPowerShutDown,
+    DisableRapidPowerShutDown,
+    Other(u8),
+}
+
+impl From<u8> for ResetType {
+    fn from(reset: u8) -> ResetType {
+        match reset {
+            0x01 => ResetType::Hard,
+            0x02 => ResetType::KeyOffOn,
+            0x03 => ResetType::Soft,
+            0x04 => ResetType::EnableRapidPowerShutDown,
+            0x05 => ResetType::DisableRapidPowerShutDown,
+            reset => ResetType::Other(reset),
+        }
+    }
+}
+
+impl From<ResetType> for u8 {
+    fn from(reset: ResetType) -> u8 {
+        match reset {
+            ResetType::Hard => 0x01,
+            ResetType::KeyOffOn => 0x02,
+            ResetType::Soft => 0x03,
+            ResetType::EnableRapidPowerShutDown => 0x04,
+            ResetType::DisableRapidPowerShutDown => 0x05,
+            ResetType::Other(reset) => reset,
+        }
+    }
+}
+
+/// Sub-functions of RoutineControl
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RoutineControlType {
+    Start,
+    Stop,
+    RequestResults,
+}
+
+impl From<RoutineControlType> for u8 {
+    fn from(control: RoutineControlType) -> u8 {
+        match control {
+            RoutineControlType::Start => 0x01,
+            RoutineControlType::Stop => 0x02,
+            RoutineControlType::RequestResults => 0x03,
+        }
+    }
+}
+
+/// Errors of UDS requests
+#[derive(Debug)]
+pub enum UdsError {
+    /// The transport failed
+    IOError(io::Error),
+
+    /// No response within P2, or P2* after a pending response
+    Timeout,
+
+    /// The ECU rejected the request of the service
+    NegativeResponse(u8, NegativeResponseCode),
+
+    /// The response does not match the request
+    InvalidResponse(Vec<u8>),
+}
+
+impl fmt::Display for UdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UdsError::IOError(ref e) => write!(f, "IO: {}", e),
+            UdsError::Timeout => write!(f, "no response"),
+            UdsError::NegativeResponse(sid, code) => {
+                write!(f, "negative response to service {:02x}: {:?}", sid, code)
+            }
+            UdsError::InvalidResponse(ref response) => write!(f, "invalid response {:02x?}", response),
+        }
+    }
+}
+
+impl error::Error for UdsError {}
+
+impl From<io::Error> for UdsError {
+    fn from(e: io::Error) -> UdsError {
+        UdsError::IOError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory_location() {
+        let mut location = MemoryLocation::new(0x0800_0000, 0x100);
+        location.size_len = 2;
+        let mut buf = Vec::new();
+        location.encode(&mut buf).unwrap();
+        assert_eq!(buf, &[0x24, 0x08, 0, 0, 0, 0x01, 0x00]);
+        assert_eq!(MemoryLocation::decode(&buf), Some(location));
+
+        for &(address_len, size_len) in &[(0, 4), (4, 0), (9, 4), (4, 9)] {
+            location.address_len = address_len;
+            location.size_len = size_len;
+            let err = location.encode(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(MemoryLocation::decode(&[0x94, 0, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,841 @@
+//! UDS server, answering requests like an ECU
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+use super::{
+    DiagnosticSession, DtcRecord, MemoryLocation, NegativeResponseCode, ResetType,
+    RoutineControlType, DEFAULT_P2, DEFAULT_P2_STAR, POSITIVE_RESPONSE_OFFSET,
+    REPORT_DTC_BY_STATUS_MASK, REPORT_NUMBER_OF_DTC_BY_STATUS_MASK,
+    SID_CLEAR_DIAGNOSTIC_INFORMATION, SID_DIAGNOSTIC_SESSION_CONTROL, SID_ECU_RESET,
+    SID_NEGATIVE_RESPONSE, SID_READ_DATA_BY_IDENTIFIER, SID_READ_DTC_INFORMATION,
+    SID_REQUEST_DOWNLOAD, SID_REQUEST_TRANSFER_EXIT, SID_ROUTINE_CONTROL, SID_SECURITY_ACCESS,
+    SID_TESTER_PRESENT, SID_TRANSFER_DATA, SID_WRITE_DATA_BY_IDENTIFIER,
+    SUPPRESS_POSITIVE_RESPONSE,
+};
+use crate::isotp::IsoTpTransport;
+use crate::ShouldRetry;
+use self::NegativeResponseCode::*;
+
+/// default S3, the time without requests after which a non-default session
+/// ends
+const DEFAULT_S3: Duration = Duration::from_millis(5000);
+
+/// default largest TransferData request, 1024 bytes of data
+const DEFAULT_MAX_BLOCK_LEN: u16 = 1026;
+
+/// failed keys until SecurityAccess is refused
+const MAX_KEY_ATTEMPTS: u8 = 3;
+
+/// format identifier of ReadDTCInformation, ISO 14229-1 DTCs
+const DTC_FORMAT_ISO14229_1: u8 = 0x01;
+
+type ReadHandler = Box<dyn FnMut(&ServerState) -> Result<Vec<u8>, NegativeResponseCode> + Send>;
+type WriteHandler = Box<dyn FnMut(&ServerState, &[u8]) -> Result<(), NegativeResponseCode> + Send>;
+type RoutineHandler =
+    Box<dyn FnMut(&ServerState, RoutineControlType, &[u8]) -> Result<Vec<u8>, NegativeResponseCode> + Send>;
+type ServiceHandler = Box<dyn FnMut(&ServerState, &[u8]) -> Result<Vec<u8>, NegativeResponseCode> + Send>;
+type SeedHandler = Box<dyn FnMut() -> Vec<u8> + Send>;
+type KeyHandler = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+type DownloadHandler = Box<dyn FnMut(&MemoryLocation, &[u8]) -> Result<(), NegativeResponseCode> + Send>;
+
+/// Session and security state of a server, passed to the handlers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServerState {
+    pub session: DiagnosticSession,
+
+    /// the unlocked security level, 0 if locked
+    pub security_level: u8,
+}
+
+impl Default for ServerState {
+    fn default() -> ServerState {
+        ServerState {
+            session: DiagnosticSession::Default,
+            security_level: 0,
+        }
+    }
+}
+
+/// A data identifier, either a stored value or handled by closures
+#[derive(Default)]
+struct DataIdentifier {
+    value: Vec<u8>,
+    read: Option<ReadHandler>,
+    write: Option<WriteHandler>,
+
+    /// security level required to write the stored value, `None` if it is
+    /// read only
+    write_level: Option<u8>,
+}
+
+struct SecurityLevel {
+    seed: SeedHandler,
+    key: KeyHandler,
+}
+
+/// A download in progress
+struct Download {
+    location: MemoryLocation,
+    data: Vec<u8>,
+
+    /// block sequence counter of the last block
+    counter: u8,
+}
+
+/// A UDS server, simulating an ECU on an ISO-TP transport.
+///
+/// The server answers DiagnosticSessionControl, ECUReset, TesterPresent
+/// and SecurityAccess by itself, keeping the state of the session. Data
+/// identifiers, routines, security levels, downloads and trouble codes are
+/// registered before serving, other services can be added with
+/// `on_service`. Unknown identifiers are answered with `RequestOutOfRange`,
+/// unknown services with `ServiceNotSupported`.
+///
+/// Non-default sessions end after S3 without requests, which also locks
+/// the security levels again.
+pub struct UdsServer<T> {
+    transport: T,
+    state: ServerState,
+
+    p2: Duration,
+    p2_star: Duration,
+    s3: Duration,
+    last_request: Instant,
+
+    sessions: Vec<DiagnosticSession>,
+    dids: HashMap<u16, DataIdentifier>,
+    routines: HashMap<u16, RoutineHandler>,
+    services: HashMap<u8, ServiceHandler>,
+
+    security: HashMap<u8, SecurityLevel>,
+    /// level and seed of the last seed request
+    pending_seed: Option<(u8, Vec<u8>)>,
+    key_attempts: u8,
+
+    dtcs: BTreeMap<u32, u8>,
+    dtc_status_availability: u8,
+
+    on_download: Option<DownloadHandler>,
+    download: Option<Download>,
+    max_block_len: u16,
+}
+
+impl<T> fmt::Debug for UdsServer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UdsServer")
+         .field("state", &self.state)
+         .field("dids", &self.dids.len())
+         .field("routines", &self.routines.len())
+         .field("dtcs", &self.dtcs.len())
+         .finish()
+    }
+}
+
+/// The positive response to `sid`, starting with `params`.
+fn positive(sid: u8, params: &[u8]) -> Vec<u8> {
+    let mut response = vec![sid.wrapping_add(POSITIVE_RESPONSE_OFFSET)];
+    response.extend_from_slice(params);
+    response
+}
+
+impl<T> UdsServer<T> {
+    pub fn new(transport: T) -> UdsServer<T> {
+        UdsServer {
+            transport: transport,
+            state: ServerState::default(),
+            p2: DEFAULT_P2,
+            p2_star: DEFAULT_P2_STAR,
+            s3: DEFAULT_S3,
+            last_request: Instant::now(),
+            sessions: vec![DiagnosticSession::Default,
+                           DiagnosticSession::Programming,
+                           DiagnosticSession::Extended],
+            dids: HashMap::new(),
+            routines: HashMap::new(),
+            services: HashMap::new(),
+            security: HashMap::new(),
+            pending_seed: None,
+            key_attempts: 0,
+            dtcs: BTreeMap::new(),
+            dtc_status_availability: 0xff,
+            on_download: None,
+            download: None,
+            max_block_len: DEFAULT_MAX_BLOCK_LEN,
+        }
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// The current session and security state
+    pub fn state(&self) -> ServerState {
+        self.state
+    }
+
+    /// Sets the timings reported to DiagnosticSessionControl.
+    ///
+    /// P2* is sent in units of 10ms.
+    pub fn set_timing(&mut self, p2: Duration, p2_star: Duration) {
+        self.p2 = p2;
+        self.p2_star = p2_star;
+    }
+
+    /// Sets S3, the time after the last request until a non-default
+    /// session ends.
+    pub fn set_session_timeout(&mut self, s3: Duration) {
+        self.s3 = s3;
+    }
+
+    /// Sets the sessions DiagnosticSessionControl accepts.
+    ///
+    /// Default, programming and extended sessions are accepted unless
+    /// changed.
+    pub fn set_sessions(&mut self, sessions: &[DiagnosticSession]) {
+        self.sessions = sessions.to_vec();
+    }
+
+    /// Add a read only data identifier with a stored value, or change the
+    /// value of an existing one.
+    pub fn set_did(&mut self, did: u16, value: Vec<u8>) {
+        self.dids.entry(did).or_default().value = value;
+    }
+
+    /// The stored value of a data identifier
+    pub fn did(&self, did: u16) -> Option<&[u8]> {
+        self.dids.get(&did).map(|d| &d.value[..])
+    }
+
+    /// Allow WriteDataByIdentifier to change the stored value of `did`.
+    ///
+    /// Writing requires `security_level` to be unlocked, unless it is 0.
+    /// The written data has to be as long as the value.
+    pub fn set_did_writable(&mut self, did: u16, security_level: u8) {
+        self.dids.entry(did).or_default().write_level = Some(security_level);
+    }
+
+    /// Answer ReadDataByIdentifier of `did` with `handler`, instead of a
+    /// stored value.
+    pub fn on_read_did<F>(&mut self, did: u16, handler: F)
+        where F: FnMut(&ServerState) -> Result<Vec<u8>, NegativeResponseCode> + Send + 'static
+    {
+        self.dids.entry(did).or_default().read = Some(Box::new(handler));
+    }
+
+    /// Answer WriteDataByIdentifier of `did` with `handler`, instead of
+    /// changing a stored value.
+    pub fn on_write_did<F>(&mut self, did: u16, handler: F)
+        where F: FnMut(&ServerState, &[u8]) -> Result<(), NegativeResponseCode> + Send + 'static
+    {
+        self.dids.entry(did).or_default().write = Some(Box::new(handler));
+    }
+
+    /// Answer RoutineControl of `routine` with `handler`, which returns the
+    /// status record of the response.
+    pub fn on_routine<F>(&mut self, routine: u16, handler: F)
+        where F: FnMut(&ServerState, RoutineControlType, &[u8]) -> Result<Vec<u8>, NegativeResponseCode>
+                     + Send
+                     + 'static
+    {
+        self.routines.insert(routine, Box::new(handler));
+    }
+
+    /// Answer requests of the service `sid` with `handler`, which gets the
+    /// request and returns the response following the service identifier.
+    ///
+    /// Replaces the server's own handling of the service, if any.
+    pub fn on_service<F>(&mut self, sid: u8, handler: F)
+        where F: FnMut(&ServerState, &[u8]) -> Result<Vec<u8>, NegativeResponseCode> + Send + 'static
+    {
+        self.services.insert(sid, Box::new(handler));
+    }
+
+    /// Add the odd security level `level`, unlocked with the key `key`
+    /// computes from a seed of `seed`.
+    ///
+    /// After 3 invalid keys, seeds are refused until the session changes.
+    pub fn add_security_level<S, K>(&mut self, level: u8, seed: S, key: K)
+        where S: FnMut() -> Vec<u8> + Send + 'static,
+              K: FnMut(&[u8]) -> Vec<u8> + Send + 'static
+    {
+        self.security.insert(level,
+                             SecurityLevel {
+                                 seed: Box::new(seed),
+                                 key: Box::new(key),
+                             });
+    }
+
+    /// Accept downloads, passing the location and data to `handler` when
+    /// the transfer ends.
+    ///
+    /// Downloads require the programming session.
+    pub fn on_download<F>(&mut self, handler: F)
+        where F: FnMut(&MemoryLocation, &[u8]) -> Result<(), NegativeResponseCode> + Send + 'static
+    {
+        self.on_download = Some(Box::new(handler));
+    }
+
+    /// Sets the largest TransferData request, including its header.
+    pub fn set_max_block_len(&mut self, len: u16) {
+        self.max_block_len = len;
+    }
+
+    /// Store a trouble code, or change its status. Only the lower 24 bits
+    /// of `dtc` are used.
+    pub fn set_dtc(&mut self, dtc: u32, status: u8) {
+        self.dtcs.insert(dtc & 0xffffff, status);
+    }
+
+    pub fn remove_dtc(&mut self, dtc: u32) {
+        self.dtcs.remove(&(dtc & 0xffffff));
+    }
+
+    /// The stored trouble codes
+    pub fn dtcs(&self) -> Vec<DtcRecord> {
+        self.dtcs
+            .iter()
+            .map(|(&dtc, &status)| {
+                DtcRecord {
+                    dtc: dtc,
+                    status: status,
+                }
+            })
+            .collect()
+    }
+
+    /// Sets the status bits the server supports, reported along with the
+    /// trouble codes. Statuses are masked with them.
+    pub fn set_dtc_status_availability(&mut self, mask: u8) {
+        self.dtc_status_availability = mask;
+    }
+
+    /// Return to the default session, locking the security levels.
+    fn reset_session(&mut self) {
+        self.state = ServerState::default();
+        self.pending_seed = None;
+        self.key_attempts = 0;
+        self.download = None;
+    }
+
+    /// End a non-default session after S3 without requests.
+    fn check_session_timeout(&mut self) {
+        if self.state.session != DiagnosticSession::Default && self.last_request.elapsed() > self.s3 {
+            self.reset_session();
+        }
+    }
+
+    /// Answer a request, returning the response to send, if any.
+    ///
+    /// Positive responses are suppressed if the request sets
+    /// `SUPPRESS_POSITIVE_RESPONSE` in its sub-function, negative ones are
+    /// always returned.
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let sid = *request.first()?;
+        self.check_session_timeout();
+        self.last_request = Instant::now();
+
+        // the suppress bit is only valid for services with sub-functions
+        let mut request = request.to_vec();
+        let suppress = match sid {
+            SID_DIAGNOSTIC_SESSION_CONTROL |
+            SID_ECU_RESET |
+            SID_SECURITY_ACCESS |
+            SID_ROUTINE_CONTROL |
+            SID_TESTER_PRESENT if request.len() >= 2 => {
+                let suppress = request[1] & SUPPRESS_POSITIVE_RESPONSE != 0;
+                request[1] &= !SUPPRESS_POSITIVE_RESPONSE;
+                suppress
+            }
+            _ => false,
+        };
+
+        match self.dispatch(sid, &request) {
+            Ok(_) if suppress => None,
+            Ok(params) => Some(positive(sid, &params)),
+            Err(code) => Some(vec![SID_NEGATIVE_RESPONSE, sid, code.into()]),
+        }
+    }
+
+    /// Answer a request, returning the positive response following the
+    /// service identifier.
+    fn dispatch(&mut self, sid: u8, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if let Some(handler) = self.services.get_mut(&sid) {
+            return handler(&self.state, request);
+        }
+
+        match sid {
+            SID_DIAGNOSTIC_SESSION_CONTROL => self.diagnostic_session_control(request),
+            SID_ECU_RESET => self.ecu_reset(request),
+            SID_SECURITY_ACCESS => self.security_access(request),
+            SID_TESTER_PRESENT => {
+                match request.len() {
+                    2 if request[1] == 0 => Ok(vec![0x00]),
+                    2 => Err(SubFunctionNotSupported),
+                    _ => Err(IncorrectMessageLengthOrInvalidFormat),
+                }
+            }
+            SID_READ_DATA_BY_IDENTIFIER => self.read_data_by_identifier(request),
+            SID_WRITE_DATA_BY_IDENTIFIER => self.write_data_by_identifier(request),
+            SID_ROUTINE_CONTROL => self.routine_control(request),
+            SID_REQUEST_DOWNLOAD => self.request_download(request),
+            SID_TRANSFER_DATA => self.transfer_data(request),
+            SID_REQUEST_TRANSFER_EXIT => self.request_transfer_exit(request),
+            SID_READ_DTC_INFORMATION => self.read_dtc_information(request),
+            SID_CLEAR_DIAGNOSTIC_INFORMATION => self.clear_diagnostic_information(request),
+            _ => Err(ServiceNotSupported),
+        }
+    }
+
+    fn diagnostic_session_control(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() != 2 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        let session = DiagnosticSession::from(request[1]);
+        if !self.sessions.contains(&session) {
+            return Err(SubFunctionNotSupported);
+        }
+
+        // changing the session, even to the same one, locks the server
+        self.reset_session();
+        self.state.session = session;
+
+        let p2 = (self.p2.as_secs() * 1000 + u64::from(self.p2.subsec_millis())).min(0xffff) as u16;
+        let p2_star =
+            ((self.p2_star.as_secs() * 1000 + u64::from(self.p2_star.subsec_millis())) / 10).min(0xffff) as u16;
+
+        let mut response = vec![request[1]];
+        response.extend_from_slice(&p2.to_be_bytes());
+        response.extend_from_slice(&p2_star.to_be_bytes());
+        Ok(response)
+    }
+
+    fn ecu_reset(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() != 2 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        match ResetType::from(request[1]) {
+            ResetType::Hard | ResetType::KeyOffOn | ResetType::Soft => {
+                self.reset_session();
+                Ok(vec![request[1]])
+            }
+            _ => Err(SubFunctionNotSupported),
+        }
+    }
+
+    fn security_access(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() < 2 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        let sub_function = request[1];
+
+        if sub_function % 2 == 1 {
+            // request seed
+            if request.len() != 2 {
+                return Err(IncorrectMessageLengthOrInvalidFormat);
+            }
+            if self.key_attempts >= MAX_KEY_ATTEMPTS {
+                return Err(RequiredTimeDelayNotExpired);
+            }
+            let level = self.security.get_mut(&sub_function).ok_or(SubFunctionNotSupported)?;
+            let mut seed = (level.seed)();
+
+            // a zero seed tells the level is unlocked already
+            if self.state.security_level == sub_function {
+                seed.iter_mut().for_each(|b| *b = 0);
+                self.pending_seed = None;
+            } else {
+                self.pending_seed = Some((sub_function, seed.clone()));
+            }
+
+            let mut response = vec![sub_function];
+            response.extend_from_slice(&seed);
+            Ok(response)
+        } else {
+            // send key
+            let level = sub_function.wrapping_sub(1);
+            let security = self.security.get_mut(&level).ok_or(SubFunctionNotSupported)?;
+            let seed = match self.pending_seed.take() {
+                Some((seed_level, seed)) if seed_level == level => seed,
+                _ => return Err(RequestSequenceError),
+            };
+
+            let key = (security.key)(&seed);
+            if key[..] != request[2..] {
+                self.key_attempts += 1;
+                return Err(if self.key_attempts >= MAX_KEY_ATTEMPTS { ExceededNumberOfAttempts } else { InvalidKey });
+            }
+
+            self.key_attempts = 0;
+            self.state.security_level = level;
+            Ok(vec![sub_function])
+        }
+    }
+
+    fn read_data_by_identifier(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        // several identifiers may be read at once
+        if request.len() < 3 || request.len() % 2 == 0 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        let mut response = Vec::new();
+        for did in request[1..].chunks(2) {
+            let entry = self.dids
+                            .get_mut(&u16::from_be_bytes([did[0], did[1]]))
+                            .ok_or(RequestOutOfRange)?;
+            response.extend_from_slice(did);
+            match entry.read {
+                Some(ref mut read) => response.extend(read(&self.state)?),
+                None => response.extend_from_slice(&entry.value),
+            }
+        }
+        Ok(response)
+    }
+
+    fn write_data_by_identifier(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() < 4 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        let entry = self.dids
+                        .get_mut(&u16::from_be_bytes([request[1], request[2]]))
+                        .ok_or(RequestOutOfRange)?;
+        let data = &request[3..];
+
+        if let Some(ref mut write) = entry.write {
+            write(&self.state, data)?;
+        } else {
+            match entry.write_level {
+                None => return Err(RequestOutOfRange),
+                Some(level) if level != 0 && level != self.state.security_level => {
+                    return Err(SecurityAccessDenied)
+                }
+                Some(_) if data.len() != entry.value.len() => return Err(IncorrectMessageLengthOrInvalidFormat),
+                Some(_) => entry.value.copy_from_slice(data),
+            }
+        }
+        Ok(request[1..3].to_vec())
+    }
+
+    fn routine_control(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() < 4 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        let control = match request[1] {
+            0x01 => RoutineControlType::Start,
+            0x02 => RoutineControlType::Stop,
+            0x03 => RoutineControlType::RequestResults,
+            _ => return Err(SubFunctionNotSupported),
+        };
+        let handler = self.routines
+                          .get_mut(&u16::from_be_bytes([request[2], request[3]]))
+                          .ok_or(RequestOutOfRange)?;
+
+        let mut response = request[1..4].to_vec();
+        response.extend(handler(&self.state, control, &request[4..])?);
+        Ok(response)
+    }
+
+    fn request_download(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if self.on_download.is_none() {
+            return Err(ServiceNotSupported);
+        }
+        if self.state.session != DiagnosticSession::Programming {
+            return Err(ServiceNotSupportedInActiveSession);
+        }
+        if request.len() < 3 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        if self.download.is_some() {
+            return Err(ConditionsNotCorrect);
+        }
+        // no compression or encryption
+        if request[1] != 0x00 {
+            return Err(RequestOutOfRange);
+        }
+        let location = MemoryLocation::decode(&request[2..]).ok_or(RequestOutOfRange)?;
+
+        self.download = Some(Download {
+            location: location,
+            data: Vec::new(),
+            counter: 0,
+        });
+
+        // the length of maxNumberOfBlockLength, 2 bytes
+        let mut response = vec![0x20];
+        response.extend_from_slice(&self.max_block_len.to_be_bytes());
+        Ok(response)
+    }
+
+    fn transfer_data(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        let max_len = self.max_block_len as usize;
+        let download = self.download.as_mut().ok_or(RequestSequenceError)?;
+        if request.len() < 2 || request.len() > max_len {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        let counter = request[1];
+        if counter == download.counter.wrapping_add(1) {
+            if (download.data.len() + request.len() - 2) as u64 > download.location.size {
+                return Err(TransferDataSuspended);
+            }
+            download.data.extend_from_slice(&request[2..]);
+            download.counter = counter;
+        } else if counter != download.counter || download.data.is_empty() {
+            // the last block may be repeated if its response got lost
+            return Err(WrongBlockSequenceCounter);
+        }
+        Ok(vec![counter])
+    }
+
+    fn request_transfer_exit(&mut self, _request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        match self.download.take() {
+            Some(ref download) if download.data.len() as u64 == download.location.size => {
+                let handler = self.on_download.as_mut().ok_or(RequestSequenceError)?;
+                handler(&download.location, &download.data)?;
+                Ok(Vec::new())
+            }
+            Some(download) => {
+                // incomplete, the transfer may continue
+                self.download = Some(download);
+                Err(RequestSequenceError)
+            }
+            None => Err(RequestSequenceError),
+        }
+    }
+
+    fn read_dtc_information(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() < 2 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+        let available = self.dtc_status_availability;
+
+        match request[1] {
+            REPORT_NUMBER_OF_DTC_BY_STATUS_MASK | REPORT_DTC_BY_STATUS_MASK if request.len() != 3 => {
+                Err(IncorrectMessageLengthOrInvalidFormat)
+            }
+            REPORT_NUMBER_OF_DTC_BY_STATUS_MASK => {
+                let mask = request[2] & available;
+                let count = self.dtcs.values().filter(|&&s| s & mask != 0).count().min(0xffff) as u16;
+
+                let mut response = vec![request[1], available, DTC_FORMAT_ISO14229_1];
+                response.extend_from_slice(&count.to_be_bytes());
+                Ok(response)
+            }
+            REPORT_DTC_BY_STATUS_MASK => {
+                let mask = request[2] & available;
+                let mut response = vec![request[1], available];
+                for (&dtc, &status) in self.dtcs.iter().filter(|&(_, &s)| s & mask != 0) {
+                    response.extend_from_slice(&dtc.to_be_bytes()[1..]);
+                    response.push(status & available);
+                }
+                Ok(response)
+            }
+            _ => Err(SubFunctionNotSupported),
+        }
+    }
+
+    fn clear_diagnostic_information(&mut self, request: &[u8]) -> Result<Vec<u8>, NegativeResponseCode> {
+        if request.len() != 4 {
+            return Err(IncorrectMessageLengthOrInvalidFormat);
+        }
+
+        match u32::from_be_bytes([0, request[1], request[2], request[3]]) {
+            0xffffff => self.dtcs.clear(),
+            group => {
+                self.dtcs.remove(&group).ok_or(RequestOutOfRange)?;
+            }
+        }
+        Ok(Vec::new())
+    }
+}
+
+impl<T: IsoTpTransport> UdsServer<T> {
+    /// Wait up to `timeout` for a request and answer it.
+    ///
+    /// Returns `false` if no request arrived.
+    pub fn serve_one(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.transport.set_read_timeout(timeout)?;
+
+        let request = match self.transport.read_pdu() {
+            Ok(request) => request,
+            Err(ref e) if e.should_retry() => {
+                self.check_session_timeout();
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(response) = self.handle(&request) {
+            self.transport.write_pdu(&response)?;
+        }
+        Ok(true)
+    }
+
+    /// Answer requests until the transport fails.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.serve_one(Duration::from_millis(100))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::uds::{UdsClient, UdsError};
+    use std::collections::VecDeque;
+
+    /// Connects a client directly to a server.
+    struct Loopback {
+        server: UdsServer<()>,
+        responses: VecDeque<Vec<u8>>,
+    }
+
+    impl IsoTpTransport for Loopback {
+        fn read_pdu(&mut self) -> io::Result<Vec<u8>> {
+            self.responses.pop_front().ok_or_else(|| io::ErrorKind::WouldBlock.into())
+        }
+
+        fn write_pdu(&mut self, pdu: &[u8]) -> io::Result<()> {
+            self.responses.extend(self.server.handle(pdu));
+            Ok(())
+        }
+
+        fn set_read_timeout(&mut self, _duration: Duration) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client(server: UdsServer<()>) -> UdsClient<Loopback> {
+        UdsClient::new(Loopback {
+            server: server,
+            responses: VecDeque::new(),
+        })
+    }
+
+    fn expect_nrc<R: fmt::Debug>(result: Result<R, UdsError>, expected: NegativeResponseCode) {
+        match result {
+            Err(UdsError::NegativeResponse(_, code)) if code == expected => (),
+            other => panic!("expected {:?}, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn test_dids() {
+        let mut server = UdsServer::new(());
+        server.set_did(0xf190, b"W0L0000000000000".to_vec());
+        server.set_did(0x0100, vec![0, 0]);
+        server.set_did_writable(0x0100, 0x01);
+        server.on_read_did(0x0200, |state| Ok(vec![u8::from(state.session)]));
+
+        let mut client = client(server);
+        assert_eq!(client.read_data_by_identifier(0xf190).unwrap(), b"W0L0000000000000");
+        assert_eq!(client.read_data_by_identifier(0x0200).unwrap(), vec![0x01]);
+        expect_nrc(client.read_data_by_identifier(0x0300), RequestOutOfRange);
+        expect_nrc(client.write_data_by_identifier(0xf190, b"x"), RequestOutOfRange);
+        expect_nrc(client.write_data_by_identifier(0x0100, &[1, 2]), SecurityAccessDenied);
+    }
+
+    #[test]
+    fn test_session_and_security() {
+        let mut server = UdsServer::new(());
+        server.set_did(0x0100, vec![0, 0]);
+        server.set_did_writable(0x0100, 0x01);
+        server.add_security_level(0x01, || vec![0x12, 0x34], |seed| seed.iter().map(|b| !b).collect());
+
+        let mut client = client(server);
+        client.diagnostic_session_control(DiagnosticSession::Extended).unwrap();
+        assert_eq!(client.send_key(0x01, &[0xed, 0xcb]).unwrap_err().to_string(),
+                   "negative response to service 27: RequestSequenceError");
+
+        client.security_access(0x01, |seed| seed.iter().map(|b| !b).collect()).unwrap();
+        client.write_data_by_identifier(0x0100, &[1, 2]).unwrap();
+        // unlocked already
+        assert_eq!(client.request_seed(0x01).unwrap(), vec![0, 0]);
+
+        let server = &mut client.transport().server;
+        assert_eq!(server.did(0x0100), Some(&[1, 2][..]));
+        assert_eq!(server.state().security_level, 0x01);
+
+        // the session ends after S3
+        server.set_session_timeout(Duration::from_millis(0));
+        ::std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(server.handle(&[0x3e, 0x80]), None);
+        assert_eq!(server.state(), ServerState::default());
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let mut server = UdsServer::new(());
+        server.add_security_level(0x03, || vec![0x01], |_| vec![0x02]);
+
+        assert_eq!(server.handle(&[0x27, 0x05]), Some(vec![0x7f, 0x27, 0x12]));
+        assert_eq!(server.handle(&[0x27, 0x03]), Some(vec![0x67, 0x03, 0x01]));
+        assert_eq!(server.handle(&[0x27, 0x04, 0x00]), Some(vec![0x7f, 0x27, 0x35]));
+        server.handle(&[0x27, 0x03]);
+        server.handle(&[0x27, 0x04, 0x00]);
+        server.handle(&[0x27, 0x03]);
+        assert_eq!(server.handle(&[0x27, 0x04, 0x00]), Some(vec![0x7f, 0x27, 0x36]));
+        assert_eq!(server.handle(&[0x27, 0x03]), Some(vec![0x7f, 0x27, 0x37]));
+
+        // until the session changes
+        server.handle(&[0x10, 0x01]);
+        assert_eq!(server.handle(&[0x27, 0x03]), Some(vec![0x67, 0x03, 0x01]));
+    }
+
+    #[test]
+    fn test_download() {
+        use std::sync::{Arc, Mutex};
+
+        let downloaded = Arc::new(Mutex::new(Vec::new()));
+        let mut server = UdsServer::new(());
+        server.set_max_block_len(6);
+        let sink = downloaded.clone();
+        server.on_download(move |location, data| {
+            assert_eq!(location.address, 0x0800_0000);
+            sink.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        });
+
+        let mut client = client(server);
+        let location = MemoryLocation::new(0x0800_0000, 10);
+        expect_nrc(client.download(&location, 0x00, &[0; 10]), ServiceNotSupportedInActiveSession);
+
+        client.diagnostic_session_control(DiagnosticSession::Programming).unwrap();
+        let data: Vec<u8> = (0..10).collect();
+        client.download(&location, 0x00, &data).unwrap();
+        assert_eq!(*downloaded.lock().unwrap(), data);
+    }
+
+    #[test]
+    fn test_dtcs_and_routines() {
+        let mut server = UdsServer::new(());
+        server.set_dtc(0x123456, 0x09);
+        server.set_dtc(0x654321, 0x04);
+        server.set_dtc(0x01abcdef, 0x01);
+        server.remove_dtc(0x02abcdef);
+        server.on_routine(0xff00, |_, control, options| {
+            match control {
+                RoutineControlType::Start => Ok(options.to_vec()),
+                _ => Err(RequestSequenceError),
+            }
+        });
+
+        let mut client = client(server);
+        assert_eq!(client.read_number_of_dtc_by_status_mask(0xff).unwrap(), 2);
+        assert_eq!(client.read_dtc_by_status_mask(0x08).unwrap(),
+                   vec![DtcRecord { dtc: 0x123456, status: 0x09 }]);
+        client.clear_diagnostic_information(0x123456).unwrap();
+        assert_eq!(client.read_number_of_dtc_by_status_mask(0xff).unwrap(), 1);
+
+        assert_eq!(client.routine_control(RoutineControlType::Start, 0xff00, &[1, 2]).unwrap(), vec![1, 2]);
+        expect_nrc(client.routine_control(RoutineControlType::Stop, 0xff00, &[]), RequestSequenceError);
+        expect_nrc(client.routine_control(RoutineControlType::Start, 0x0001, &[]), RequestOutOfRange);
+    }
+}